@@ -0,0 +1,66 @@
+use crate::middleware::FrameMiddleware;
+
+/// Payloads are padded to a multiple of this many bytes
+pub const WORD_LEN: usize = 4;
+/// Payload length (big endian), padding length and a reserved byte
+const HEADER_LEN: usize = WORD_LEN;
+
+/// # WordAlignment
+///
+/// Pads the payload of every frame to whole words, so that firmware on the other side
+/// can DMA it directly into word-aligned buffers.
+///
+/// The payload is preceded by a header word `[len_hi, len_lo, padding, 0]`,
+/// so that it starts word-aligned as well and the padding can be removed again.
+#[derive(Debug, Default)]
+pub struct WordAlignment;
+
+impl FrameMiddleware for WordAlignment {
+    fn on_send(&mut self, payload: &mut Vec<u8>) {
+        let padding = (WORD_LEN - payload.len() % WORD_LEN) % WORD_LEN;
+        let [len_hi, len_lo] = (payload.len() as u16).to_be_bytes();
+        payload.splice(0..0, [len_hi, len_lo, padding as u8, 0]);
+        payload.resize(payload.len() + padding, 0);
+    }
+
+    fn on_receive(&mut self, payload: &mut Vec<u8>) {
+        let Some(&[len_hi, len_lo, padding, _]) = payload.first_chunk::<HEADER_LEN>() else {
+            return;
+        };
+        let len = u16::from_be_bytes([len_hi, len_lo]) as usize;
+        if HEADER_LEN + len + padding as usize > payload.len() {
+            eprintln!("Aligned payload of {len} bytes does not fit into the frame, keeping it");
+            return;
+        }
+        payload.drain(..HEADER_LEN);
+        payload.truncate(len);
+    }
+
+    fn max_growth(&self) -> usize {
+        // escaped header bytes still count as a single byte of the frame
+        HEADER_LEN + WORD_LEN - 1
+    }
+}
+
+#[test]
+fn word_alignment() {
+    let mut payload = vec![0xc0, 0xc1, 0xc2, 0xc3, 0xc4];
+    WordAlignment.on_send(&mut payload);
+    assert_eq!(
+        payload,
+        [0x00, 0x05, 0x03, 0x00, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0x00, 0x00, 0x00]
+    );
+
+    // received frames are filled up with zeros
+    payload.resize(16, 0);
+    WordAlignment.on_receive(&mut payload);
+    assert_eq!(payload, [0xc0, 0xc1, 0xc2, 0xc3, 0xc4]);
+
+    // the raw bytes leave room for the header and padding
+    let mut stages: Vec<Box<dyn FrameMiddleware>> = vec![Box::new(WordAlignment)];
+    let data = (0..32).map(|byte| Ok(0xc0 | (byte as u8 % 16)));
+    let mut source = crate::escape::Escaped::new(data);
+    let (frame, _) = crate::encode_transformed_frame(&mut source, 16, &mut stages).unwrap();
+    assert_eq!(frame[1..5], [0x00, 0x09, 0x03, 0x00]);
+    assert_eq!(frame[5..9], [0xc0, 0xc1, 0xc2, 0xc3]);
+}
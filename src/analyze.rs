@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use crate::sniff::{self, Sniffed};
+use crate::stream::{ControlMsg, InputEvent, Strictness};
+use crate::wire::WireDecoder;
+
+/// Which pins of PINA carry the nibbles of the other side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pins {
+    /// Pins 4 to 7, the lower ones are outputs of the board
+    #[default]
+    High,
+    /// Pins 0 to 3, if the board only listened
+    Low,
+}
+
+impl Pins {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "high" => Some(Self::High),
+            "low" => Some(Self::Low),
+            _ => None,
+        }
+    }
+
+    fn nibble(self, pina: u8) -> u8 {
+        match self {
+            Self::High => pina >> 4,
+            Self::Low => pina & 0x0f,
+        }
+    }
+}
+
+/// One sample of PINA and the line of the dump it has been read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub line: usize,
+    pub pina: u8,
+}
+
+/// Reads a PINA dump of the B15F tools, one sample per line.
+///
+/// The value is the last word of the line, so that lines may start with a timestamp,
+/// and is written as `0x3f`, `0b00111111` or `63`. Empty lines and `#` comments are skipped.
+///
+/// Returns the samples and the numbers of the lines that could not be read.
+pub fn parse_pina_dump(text: &str) -> (Vec<Sample>, Vec<usize>) {
+    let mut samples = Vec::new();
+    let mut invalid = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let Some(value) = line.split_whitespace().last() else {
+            continue;
+        };
+        let pina = if let Some(hex) = value.strip_prefix("0x") {
+            u8::from_str_radix(hex, 16)
+        } else if let Some(binary) = value.strip_prefix("0b") {
+            u8::from_str_radix(binary, 2)
+        } else {
+            value.parse()
+        };
+        match pina {
+            Ok(pina) => samples.push(Sample {
+                line: line_number,
+                pina,
+            }),
+            Err(_) => invalid.push(line_number),
+        }
+    }
+    (samples, invalid)
+}
+
+/// Something that has been decoded from a trace, or where decoding failed
+#[derive(Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub line: usize,
+    pub failed: bool,
+    pub description: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = if self.failed { "FAILED " } else { "" };
+        write!(f, "line {:>6}: {status}{}", self.line, self.description)
+    }
+}
+
+/// # Report
+///
+/// Result of running the [`WireDecoder`] over a captured trace, see [`analyze`].
+#[derive(Debug, Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+    pub samples: usize,
+    pub frames: u32,
+}
+
+impl Report {
+    pub fn failures(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.failed)
+            .count()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{finding}")?;
+        }
+        writeln!(
+            f,
+            "{} samples, {} frames decoded, {} failures",
+            self.samples,
+            self.frames,
+            self.failures()
+        )
+    }
+}
+
+/// Decodes the samples like the receiving side of a connection would
+pub fn analyze(samples: &[Sample], pins: Pins, strictness: Strictness) -> Report {
+    let mut decoder = WireDecoder::with_strictness(strictness);
+    let mut report = Report {
+        samples: samples.len(),
+        ..Report::default()
+    };
+    for sample in samples {
+        let slips = decoder.slips();
+        let event = decoder.push(pins.nibble(sample.pina));
+        if decoder.slips() > slips {
+            report.findings.push(Finding {
+                line: sample.line,
+                failed: true,
+                description: "nibble slip".into(),
+            });
+        }
+        if matches!(event, InputEvent::DataFrame { .. }) {
+            report.frames += 1;
+        }
+        let failed = matches!(
+            event,
+            InputEvent::Control(ControlMsg::FrameOverrun | ControlMsg::MalformedFrame)
+        );
+        if let Some(Sniffed { description, .. }) = sniff::describe(event) {
+            report.findings.push(Finding {
+                line: sample.line,
+                failed: failed || description.starts_with("invalid"),
+                description,
+            });
+        }
+    }
+    report
+}
+
+#[test]
+fn analyze_pina_dump() {
+    use crate::escape::{EscapeCode, Escaped};
+
+    let (frame, len) = crate::encode_frame(&mut Escaped::new([0xab].into_iter().map(Ok))).unwrap();
+    let mut bytes = frame[..len].to_vec();
+    bytes.extend([0xf0, EscapeCode::CorrectFrameData as u8, 0xf0]);
+    let mut dump = String::from("# time pina\n");
+    for (time, nibble) in crate::conformance::wire_nibbles(&bytes).iter().enumerate() {
+        dump.push_str(&format!("{time} 0x{:02x}\n", nibble << 4 | 0x5));
+    }
+    dump.push_str("oops\n");
+
+    let (samples, invalid) = parse_pina_dump(&dump);
+    assert_eq!(invalid, [samples.len() + 2]);
+    let report = analyze(&samples, Pins::High, Strictness::Strict);
+    assert_eq!(report.frames, 1);
+    assert_eq!(report.failures(), 0);
+    assert_eq!(report.findings.last().unwrap().description, "CFD");
+}
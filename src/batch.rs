@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+
+use crate::device::Device;
+
+/// # Batch
+///
+/// Sends and reads the nibbles of many polls with a single call to the device,
+/// for devices like [`crate::device::TcpDevice`] where every call has a fixed cost.
+///
+/// The nibbles are still decoded one per poll, so the protocol does not change.
+#[derive(Debug)]
+pub struct Batch {
+    max_batch: usize,
+    /// Nibbles that have not been sent yet
+    tx: Vec<u8>,
+    /// Nibbles that have been read, but not decoded yet
+    rx: VecDeque<u8>,
+    /// Value of the lines, if nothing new has arrived
+    last_rx: u8,
+}
+
+impl Batch {
+    pub fn new(max_batch: usize) -> Self {
+        Self {
+            max_batch: max_batch.max(1),
+            tx: Vec::with_capacity(max_batch),
+            rx: VecDeque::with_capacity(max_batch),
+            last_rx: 0,
+        }
+    }
+
+    pub fn max_batch(&self) -> usize {
+        self.max_batch
+    }
+
+    /// Queues the nibble and returns the next received one.
+    ///
+    /// The queued nibbles are sent once the batch is full,
+    /// or when every received nibble has been decoded, as the other side might wait for them.
+    pub fn exchange(&mut self, device: &mut impl Device, nibble_out: u8) -> u8 {
+        self.tx.push(nibble_out);
+        if self.rx.is_empty() || self.tx.len() >= self.max_batch {
+            self.flush(device);
+        }
+        if self.rx.is_empty() {
+            let mut buffer = vec![0; self.max_batch];
+            let len = device.read_many(&mut buffer);
+            self.rx.extend(&buffer[..len]);
+        }
+        if let Some(nibble) = self.rx.pop_front() {
+            self.last_rx = nibble;
+        }
+        self.last_rx
+    }
+
+    /// Sends every queued nibble
+    pub fn flush(&mut self, device: &mut impl Device) {
+        if !self.tx.is_empty() {
+            device.send_many(&self.tx);
+            self.tx.clear();
+        }
+    }
+}
+
+#[test]
+fn batch_over_sim_port() {
+    use crate::device::{DeviceRx, DeviceTx};
+    use crate::sim::SimPort;
+
+    let (mut port, mut other) = SimPort::pair(true);
+    let mut batch = Batch::new(4);
+    // nothing has been received yet, so the first nibble is sent right away
+    assert_eq!(batch.exchange(&mut port, 0x1), 0x0);
+    assert_eq!(other.read(), 0x1);
+    other.send(0x7);
+    assert_eq!(batch.exchange(&mut port, 0x2), 0x7);
+    assert_eq!(other.read(), 0x2);
+}
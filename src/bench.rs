@@ -0,0 +1,275 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::cost::frame_nibbles;
+use crate::escape::EscapeCode;
+use crate::soak::Prbs;
+
+/// Retransmissions after which a frame is given up, so that hopeless configurations end
+const MAX_RETRIES: u32 = 100;
+
+/// # SimChannel
+///
+/// Simulated cable that corrupts every nibble with the same probability,
+/// deterministic for a seed so that configurations are compared on the same errors.
+pub struct SimChannel {
+    prbs: Prbs,
+    nibble_error_rate: f64,
+}
+
+impl SimChannel {
+    pub fn new(seed: u64, nibble_error_rate: f64) -> Self {
+        Self {
+            prbs: Prbs::new(seed),
+            nibble_error_rate,
+        }
+    }
+
+    fn is_corrupted(&mut self) -> bool {
+        let random = u32::from_be_bytes([0; 4].map(|_| self.prbs.next_byte()));
+        (random as f64 / u32::MAX as f64) < self.nibble_error_rate
+    }
+
+    /// Flips at least one bit of the nibble
+    fn flip(&mut self) -> u8 {
+        (self.prbs.next_byte() % 15) + 1
+    }
+
+    /// Sends the bytes over the channel and returns what arrives,
+    /// `None` if one of the buffer nibbles was corrupted and the framing broke
+    fn transmit(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        let buffers = frame_nibbles(bytes) - 2 * bytes.len();
+        let mut broken = false;
+        for _ in 0..buffers {
+            broken |= self.is_corrupted();
+        }
+        let mut received = bytes.to_vec();
+        for byte in &mut received {
+            for shift in [4, 0] {
+                if self.is_corrupted() {
+                    *byte ^= self.flip() << shift;
+                }
+            }
+        }
+        (!broken).then_some(received)
+    }
+}
+
+/// One configuration of the benchmark matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchConfig {
+    pub frame_data_len: usize,
+    pub checksum: ChecksumAlgorithm,
+    pub checksum_len: usize,
+    pub pacing: Duration,
+}
+
+impl BenchConfig {
+    /// Frame sizes × checksums × pacing
+    pub fn matrix() -> Vec<Self> {
+        let checksums = [
+            (ChecksumAlgorithm::Xor, 1),
+            (ChecksumAlgorithm::Sum, 2),
+            (ChecksumAlgorithm::Crc32, 4),
+        ];
+        let mut configs = Vec::new();
+        for frame_data_len in [8, 16, 32, 64] {
+            for (checksum, checksum_len) in checksums {
+                for pacing in [1, 5].map(Duration::from_millis) {
+                    configs.push(Self {
+                        frame_data_len,
+                        checksum,
+                        checksum_len,
+                        pacing,
+                    });
+                }
+            }
+        }
+        configs
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchResult {
+    pub config: BenchConfig,
+    pub payload_bytes: usize,
+    /// Frames that were sent, including retransmissions
+    pub frames: u32,
+    pub retransmissions: u32,
+    /// Frames that were accepted with corrupted data, because the checksum matched anyway
+    pub undetected: u32,
+    /// Frames that were given up after [`MAX_RETRIES`]
+    pub lost: u32,
+    /// Nibbles sent in both directions, one per poll
+    pub nibbles: usize,
+}
+
+impl BenchResult {
+    /// Simulated time it takes to send every nibble
+    pub fn elapsed(&self) -> Duration {
+        self.config.pacing * self.nibbles as u32
+    }
+
+    /// Payload bytes per second that arrived intact
+    pub fn goodput(&self) -> f64 {
+        let seconds = self.elapsed().as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.payload_bytes as f64 / seconds
+    }
+
+    pub fn retransmit_rate(&self) -> f64 {
+        if self.frames == 0 {
+            0.0
+        } else {
+            self.retransmissions as f64 / self.frames as f64
+        }
+    }
+}
+
+/// Sends the payload stop-and-wait over the channel:
+/// every frame is answered with CFD or IFD, and resent until CFD arrives intact.
+pub fn run(config: BenchConfig, payload: &[u8], channel: &mut SimChannel) -> BenchResult {
+    let mut result = BenchResult {
+        config,
+        payload_bytes: 0,
+        frames: 0,
+        retransmissions: 0,
+        undetected: 0,
+        lost: 0,
+        nibbles: 0,
+    };
+    let ack = [EscapeCode::CorrectFrameData as u8];
+
+    for data in payload.chunks(config.frame_data_len.max(1)) {
+        let checksum = config.checksum.checksum(data, config.checksum_len);
+        let mut frame = vec![EscapeCode::StartOfFrame as u8];
+        frame.extend(escape(data));
+        frame.extend(escape(&checksum));
+        frame.push(EscapeCode::EndOfFrame as u8);
+
+        let mut delivered = false;
+        for attempt in 0..=MAX_RETRIES {
+            result.frames += 1;
+            if attempt > 0 {
+                result.retransmissions += 1;
+            }
+            result.nibbles += frame_nibbles(&frame) + frame_nibbles(&ack);
+
+            let received = channel.transmit(&frame).and_then(|received| {
+                let mut received = unescape(&received)?;
+                let checksum = received.split_off(data.len().min(received.len()));
+                Some((received, checksum))
+            });
+            let accepted = received.filter(|(received, received_checksum)| {
+                config.checksum.checksum(received, config.checksum_len) == *received_checksum
+            });
+            // a corrupted ack is noticed by the sender, which resends the frame
+            let acked = channel
+                .transmit(&ack)
+                .is_some_and(|received| received == ack);
+            if let Some((received, _)) = &accepted {
+                if received != data {
+                    result.undetected += 1;
+                }
+            }
+            if accepted.is_some() && acked {
+                delivered = true;
+                break;
+            }
+        }
+        if delivered {
+            result.payload_bytes += data.len();
+        } else {
+            result.lost += 1;
+        }
+    }
+    result
+}
+
+/// Doubles every escape code, like [`crate::escape::Escaped`]
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .flat_map(|&byte| {
+            let repeat = if EscapeCode::from_byte(byte).is_some() {
+                2
+            } else {
+                1
+            };
+            std::iter::repeat_n(byte, repeat)
+        })
+        .collect()
+}
+
+/// Data and checksum of a received frame, `None` if it is not framed by SOF and EOF
+/// or contains an escape code that is not doubled
+fn unescape(frame: &[u8]) -> Option<Vec<u8>> {
+    let (first, rest) = frame.split_first()?;
+    let (last, escaped) = rest.split_last()?;
+    if *first != EscapeCode::StartOfFrame as u8 || *last != EscapeCode::EndOfFrame as u8 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut iter = escaped.iter();
+    while let Some(&byte) = iter.next() {
+        if EscapeCode::from_byte(byte).is_some() && iter.next() != Some(&byte) {
+            return None;
+        }
+        bytes.push(byte);
+    }
+    Some(bytes)
+}
+
+/// Formats the results as a table, one configuration per line
+pub fn table(results: &[BenchResult]) -> String {
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:>5} {:>8} {:>6} {:>12} {:>11} {:>10} {:>4}",
+        "frame", "checksum", "pacing", "goodput B/s", "retransmits", "undetected", "lost"
+    );
+    for result in results {
+        let config = result.config;
+        let _ = writeln!(
+            table,
+            "{:>5} {:>8} {:>6} {:>12.1} {:>10.1}% {:>10} {:>4}",
+            config.frame_data_len,
+            format!("{}x{}", config.checksum.name(), config.checksum_len),
+            format!("{:?}", config.pacing),
+            result.goodput(),
+            100.0 * result.retransmit_rate(),
+            result.undetected,
+            result.lost
+        );
+    }
+    table
+}
+
+#[test]
+fn bench_on_clean_and_noisy_channel() {
+    let config = BenchConfig {
+        frame_data_len: 4,
+        checksum: ChecksumAlgorithm::Crc32,
+        checksum_len: 4,
+        pacing: Duration::from_millis(1),
+    };
+    let payload = [0x01, 0x12, 0xbc, 0xab, 0xcd];
+
+    let clean = run(config, &payload, &mut SimChannel::new(1, 0.0));
+    assert_eq!(clean.frames, 2);
+    assert_eq!(clean.retransmissions, 0);
+    assert_eq!(clean.payload_bytes, payload.len());
+    assert_eq!(clean.elapsed(), Duration::from_millis(clean.nibbles as u64));
+
+    let noisy = run(config, &payload, &mut SimChannel::new(1, 0.05));
+    assert!(noisy.retransmissions > 0);
+    assert!(noisy.goodput() < clean.goodput());
+    assert_eq!(
+        unescape(&[0x12, 0x23, 0x23, 0x01, 0x23]),
+        Some(vec![0x23, 0x01])
+    );
+    assert_eq!(unescape(&[0x12, 0x23, 0x01, 0x23]), None);
+}
@@ -0,0 +1,118 @@
+//! Low level helpers for working with nibbles, the symbols sent over the wire.
+
+/// Number of bits in a symbol on the wire
+pub const SYMBOL_BITS: u32 = 4;
+/// Number of symbols needed to send a byte
+pub const SYMBOLS_PER_BYTE: usize = (u8::BITS / SYMBOL_BITS) as usize;
+/// Mask of the bits that are used by a symbol
+pub const SYMBOL_MASK: u8 = (1 << SYMBOL_BITS) - 1;
+
+/// Swaps the higher and lower nibble, `0x12` becomes `0x21`.
+pub const fn swap_nibbles(byte: u8) -> u8 {
+    byte.rotate_left(SYMBOL_BITS)
+}
+
+pub const fn higher_nibble(byte: u8) -> u8 {
+    byte >> SYMBOL_BITS
+}
+
+pub const fn lower_nibble(byte: u8) -> u8 {
+    byte & SYMBOL_MASK
+}
+
+/// Splits the byte into its higher and lower nibble, in the order they are sent.
+pub const fn split(byte: u8) -> [u8; 2] {
+    [higher_nibble(byte), lower_nibble(byte)]
+}
+
+/// Joins a higher and lower nibble into a byte, unused bits of the nibbles are ignored.
+pub const fn join(higher: u8, lower: u8) -> u8 {
+    lower_nibble(higher) << SYMBOL_BITS | lower_nibble(lower)
+}
+
+/// Order in which the two nibbles of a byte are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NibbleOrder {
+    #[default]
+    HighFirst,
+    LowFirst,
+}
+
+impl NibbleOrder {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "high-first" => Some(Self::HighFirst),
+            "low-first" => Some(Self::LowFirst),
+            _ => None,
+        }
+    }
+
+    pub const fn swapped(self) -> Self {
+        match self {
+            Self::HighFirst => Self::LowFirst,
+            Self::LowFirst => Self::HighFirst,
+        }
+    }
+
+    /// Splits the byte into its nibbles, in the order they are sent.
+    pub const fn split(self, byte: u8) -> [u8; 2] {
+        let [higher, lower] = split(byte);
+        match self {
+            Self::HighFirst => [higher, lower],
+            Self::LowFirst => [lower, higher],
+        }
+    }
+
+    /// Joins two nibbles, in the order they have been received, into a byte.
+    pub const fn join(self, first: u8, second: u8) -> u8 {
+        match self {
+            Self::HighFirst => join(first, second),
+            Self::LowFirst => join(second, first),
+        }
+    }
+
+    /// Bit offset of the nibble with the index, in the byte it belongs to
+    pub const fn shift(self, index: usize) -> usize {
+        match self {
+            Self::HighFirst => (1 + index) % 2 * SYMBOL_BITS as usize,
+            Self::LowFirst => index % 2 * SYMBOL_BITS as usize,
+        }
+    }
+}
+
+/// Number of symbols needed to send the bytes, without any inserted buffer codes.
+pub const fn symbols(bytes: usize) -> usize {
+    bytes * SYMBOLS_PER_BYTE
+}
+
+#[test]
+fn split_and_join() {
+    for byte in u8::MIN..=u8::MAX {
+        let [higher, lower] = split(byte);
+        assert!(higher <= SYMBOL_MASK && lower <= SYMBOL_MASK);
+        assert_eq!(join(higher, lower), byte);
+        assert_eq!(join(higher | 0xf0, lower | 0xf0), byte);
+    }
+}
+
+#[test]
+fn nibble_order() {
+    for order in [NibbleOrder::HighFirst, NibbleOrder::LowFirst] {
+        for byte in u8::MIN..=u8::MAX {
+            let [first, second] = order.split(byte);
+            assert_eq!(order.join(first, second), byte);
+            assert_eq!(first << order.shift(0) | second << order.shift(1), byte);
+        }
+    }
+    assert_eq!(NibbleOrder::LowFirst.split(0x12), [0x2, 0x1]);
+}
+
+#[test]
+fn swap() {
+    assert_eq!(swap_nibbles(0x12), 0x21);
+    for byte in u8::MIN..=u8::MAX {
+        let [higher, lower] = split(byte);
+        assert_eq!(swap_nibbles(byte), join(lower, higher));
+        assert_eq!(swap_nibbles(swap_nibbles(byte)), byte);
+    }
+}
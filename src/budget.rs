@@ -0,0 +1,122 @@
+use std::fmt;
+use std::mem;
+
+use crate::retransmit::FixedRetransmitCache;
+use crate::stream::{InputStream, OutputStream};
+use crate::FRAME_DATA_LEN;
+
+/// Frames the embedded build keeps for retransmission
+pub const EMBEDDED_RETRANSMIT_FRAMES: usize = 4;
+
+/// Bytes every buffer of the embedded build together may use
+pub const EMBEDDED_BUDGET: usize = 2048;
+
+const _: () = assert!(
+    MemoryBudget::fixed::<EMBEDDED_RETRANSMIT_FRAMES>().total() <= EMBEDDED_BUDGET,
+    "buffers of the embedded build exceed EMBEDDED_BUDGET"
+);
+
+/// # MemoryBudget
+///
+/// Bytes used by the buffers of a connection, which do not grow with the amount of data sent.
+///
+/// Frames are sent stop-and-wait, so they always arrive in order and no reorder buffer
+/// is needed, and at most the data of one unacked frame has to be replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub input_stream: usize,
+    pub output_stream: usize,
+    pub retransmit_cache: usize,
+    /// Data of the frame that has not been acked yet, see [`crate::source::ReplaySource`]
+    pub replay_buffer: usize,
+}
+
+impl MemoryBudget {
+    /// Buffers of a connection that caches `N` frames in a [`FixedRetransmitCache`]
+    pub const fn fixed<const N: usize>() -> Self {
+        Self {
+            input_stream: mem::size_of::<InputStream>(),
+            output_stream: mem::size_of::<OutputStream>(),
+            retransmit_cache: FixedRetransmitCache::<N>::BYTES_RESERVED,
+            replay_buffer: FRAME_DATA_LEN,
+        }
+    }
+
+    /// Buffers of the embedded build, checked against [`EMBEDDED_BUDGET`] at compile time
+    pub const fn embedded() -> Self {
+        Self::fixed::<EMBEDDED_RETRANSMIT_FRAMES>()
+    }
+
+    pub const fn total(&self) -> usize {
+        self.input_stream + self.output_stream + self.retransmit_cache + self.replay_buffer
+    }
+}
+
+impl fmt::Display for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "input stream     {:>6} B", self.input_stream)?;
+        writeln!(f, "output stream    {:>6} B", self.output_stream)?;
+        writeln!(f, "retransmit cache {:>6} B", self.retransmit_cache)?;
+        writeln!(f, "replay buffer    {:>6} B", self.replay_buffer)?;
+        writeln!(f, "total            {:>6} B", self.total())
+    }
+}
+
+#[test]
+fn budget_grows_with_cached_frames() {
+    let one = MemoryBudget::fixed::<1>();
+    let two = MemoryBudget::fixed::<2>();
+    assert!(two.retransmit_cache >= one.retransmit_cache + crate::FRAME_LEN);
+    assert_eq!(
+        two.total() - one.total(),
+        two.retransmit_cache - one.retransmit_cache
+    );
+    assert!(MemoryBudget::embedded().total() <= EMBEDDED_BUDGET);
+}
+
+/// Counts the allocations of every thread, so that a test can check that its own code
+/// does not allocate, while other tests run in parallel
+#[cfg(test)]
+struct CountingAllocator;
+
+#[cfg(test)]
+thread_local! {
+    static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        // the thread local is gone while the thread shuts down
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { std::alloc::System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        unsafe { std::alloc::System.dealloc(ptr, layout) }
+    }
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn bounded_buffers_do_not_allocate() {
+    use crate::retransmit::FrameCache;
+
+    let mut cache = FixedRetransmitCache::<EMBEDDED_RETRANSMIT_FRAMES>::new();
+    let mut o_stream = OutputStream::new();
+    let frame = [0xab; crate::FRAME_LEN];
+    let before = ALLOCATIONS.with(std::cell::Cell::get);
+    for seq in 1..=4 * EMBEDDED_RETRANSMIT_FRAMES as u32 {
+        cache.insert(seq, frame, crate::FRAME_LEN).unwrap();
+        let (cached, len) = cache.get(seq).unwrap();
+        o_stream.send_frame(cached, len);
+        for _ in 0..2 * len {
+            o_stream.next();
+        }
+        cache.ack_through(seq);
+    }
+    assert_eq!(ALLOCATIONS.with(std::cell::Cell::get), before);
+}
@@ -0,0 +1,110 @@
+use crate::ping::Echo;
+
+/// Sequence number of the echo frames that carry the calibration pattern,
+/// next to [`crate::resume::SESSION_ECHO_SEQ`]
+pub const CALIBRATION_ECHO_SEQ: u32 = u32::MAX - 1;
+
+/// Rates in bits per second that are tried, slowest first
+pub const CALIBRATION_RATES: [u64; 6] = [100, 250, 500, 1_000, 2_500, 5_000];
+
+/// Polls without a reply, after which the rate is considered too fast
+const REPLY_TIMEOUT_POLLS: u32 = 2_000;
+
+/// Mixed into the rate, so that the pattern contains every nibble from 0x0 to 0xf
+const PATTERN: u64 = 0x0f1e_2d3c_4b5a_6978;
+
+/// Echo carrying the calibration pattern for the rate
+pub fn calibration_echo(rate: u64, reply: bool) -> Echo {
+    Echo {
+        reply,
+        seq: CALIBRATION_ECHO_SEQ,
+        timestamp: rate ^ PATTERN,
+    }
+}
+
+/// The rate of the calibration echo, `None` if the pattern has not been decoded correctly
+pub fn calibration_rate(echo: &Echo) -> Option<u64> {
+    let rate = echo.timestamp ^ PATTERN;
+    CALIBRATION_RATES.contains(&rate).then_some(rate)
+}
+
+/// # Calibration
+///
+/// Finds the highest rate the other side decodes without errors.
+///
+/// The calibration pattern is sent at increasing rates, the other side only replies
+/// if it has decoded the pattern intact. The first rate that is not replied to in time
+/// ends the calibration, the rate before it is adopted for the transfer.
+#[derive(Debug, Clone, Default)]
+pub struct Calibration {
+    /// Index of the rate in [`CALIBRATION_RATES`] that is being tried
+    index: usize,
+    /// Polls since the pattern has been sent with the current rate
+    waiting: u32,
+    /// Highest rate that has been replied to
+    confirmed: Option<u64>,
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rate that is being tried, `None` once the calibration is done
+    pub fn rate(&self) -> Option<u64> {
+        CALIBRATION_RATES.get(self.index).copied()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.rate().is_none()
+    }
+
+    /// Highest rate that the other side decoded without errors
+    pub fn result(&self) -> Option<u64> {
+        self.confirmed
+    }
+
+    /// Counts a poll, returns whether the reply took too long, which ends the calibration
+    pub fn poll(&mut self) -> bool {
+        if self.is_done() {
+            return false;
+        }
+        self.waiting += 1;
+        if self.waiting >= REPLY_TIMEOUT_POLLS {
+            self.index = CALIBRATION_RATES.len();
+            return true;
+        }
+        false
+    }
+
+    /// Handles a reply, returns whether it was the one for the current rate
+    pub fn replied(&mut self, rate: u64) -> bool {
+        if self.rate() != Some(rate) {
+            return false;
+        }
+        self.confirmed = Some(rate);
+        self.index += 1;
+        self.waiting = 0;
+        true
+    }
+}
+
+#[test]
+fn calibration_stops_at_first_timeout() {
+    let echo = calibration_echo(500, false);
+    assert_eq!(calibration_rate(&echo), Some(500));
+    let broken = Echo {
+        timestamp: echo.timestamp ^ 0x10,
+        ..echo
+    };
+    assert_eq!(calibration_rate(&broken), None);
+
+    let mut calibration = Calibration::new();
+    assert!(calibration.replied(100));
+    assert!(!calibration.replied(100));
+    assert!(calibration.replied(250));
+    assert_eq!(calibration.rate(), Some(500));
+    while !calibration.poll() {}
+    assert!(calibration.is_done());
+    assert_eq!(calibration.result(), Some(250));
+}
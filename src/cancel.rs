@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// # CancellationToken
+///
+/// Can be cloned and moved to another thread, e.g. a signal handler or a GUI,
+/// to stop a [`crate::Connection`]. The connection then sends ABT to the other side
+/// and stops polling once it has been sent, so that the other side is not left waiting.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn cancel_from_other_thread() {
+    let token = CancellationToken::new();
+    let remote = token.clone();
+    std::thread::spawn(move || remote.cancel()).join().unwrap();
+    assert!(token.is_cancelled());
+}
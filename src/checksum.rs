@@ -0,0 +1,110 @@
+/// Algorithms that can be used to calculate the checksum of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// Every checksum byte is the xor of every `len`th data byte
+    Xor,
+    /// Wrapping sum of all data bytes, big endian
+    Sum,
+    /// CRC-32 (IEEE), big endian
+    Crc32,
+}
+
+impl ChecksumAlgorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xor" => Some(Self::Xor),
+            "sum" => Some(Self::Sum),
+            "crc32" => Some(Self::Crc32),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Xor => "xor",
+            Self::Sum => "sum",
+            Self::Crc32 => "crc32",
+        }
+    }
+
+    /// Calculates a checksum with `len` bytes,
+    /// checksums longer than the algorithm produces are padded with zeros.
+    pub fn checksum(self, data: &[u8], len: usize) -> Vec<u8> {
+        let mut checksum = vec![0; len];
+        if len == 0 {
+            return checksum;
+        }
+        match self {
+            Self::Xor => {
+                for (index, byte) in data.iter().enumerate() {
+                    checksum[index % len] ^= byte;
+                }
+            }
+            Self::Sum => {
+                let sum = data
+                    .iter()
+                    .fold(0u64, |sum, byte| sum.wrapping_add(*byte as u64));
+                let bytes = sum.to_be_bytes();
+                let used = len.min(bytes.len());
+                checksum[..used].copy_from_slice(&bytes[(bytes.len() - used)..]);
+            }
+            Self::Crc32 => {
+                let bytes = crc32(data).to_be_bytes();
+                let used = len.min(bytes.len());
+                checksum[..used].copy_from_slice(&bytes[(bytes.len() - used)..]);
+            }
+        }
+        checksum
+    }
+}
+
+/// CRC-8 (polynomial 0x07), small enough to protect single fields
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Feeds more data into a CRC-32 register, which starts at `!0`
+/// and is inverted once all data has been fed in
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    crc
+}
+
+#[test]
+fn checksums() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc8(b"123456789"), 0xf4);
+    assert_eq!(
+        ChecksumAlgorithm::Xor.checksum(&[0x01, 0x02, 0x04, 0x08, 0x10], 2),
+        [0x15, 0x0a]
+    );
+    assert_eq!(
+        ChecksumAlgorithm::Sum.checksum(&[0xff, 0xff, 0x02], 2),
+        [0x02, 0x00]
+    );
+    assert_eq!(
+        ChecksumAlgorithm::Crc32.checksum(b"123456789", 6),
+        [0xcb, 0xf4, 0x39, 0x26, 0, 0]
+    );
+}
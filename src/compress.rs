@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+use std::io;
+
+use crate::FRAME_DATA_LEN;
+
+/// Bytes that are compressed together, no more than a frame holds,
+/// so that the source is not read further ahead than without compression
+const BLOCK_LEN: usize = FRAME_DATA_LEN;
+/// Header of the longest literal, its bytes follow it unchanged
+const MAX_LITERAL: u8 = 0x80;
+/// Shortest run of equal bytes that is sent as a repeat, shorter ones are no shorter that way
+const MIN_RUN: usize = 3;
+/// Repeat headers start after the literal ones, `0x81` repeats the next byte [`MIN_RUN`] times
+const REPEAT_OFFSET: u8 = MAX_LITERAL + 1 - MIN_RUN as u8;
+
+/// # Compressed
+///
+/// Run-length encodes the bytes of the source, once compression is enabled.
+///
+/// A header byte says what follows:
+/// - `0x00` nothing, it is padding, like the zeros a frame is filled up with
+/// - `0x01..=0x80` that many bytes, unchanged
+/// - `0x81..=0xff` a single byte, which is repeated `header - 0x7e` times
+///
+/// The bytes are compressed in blocks of up to [`BLOCK_LEN`] bytes, which are always
+/// returned completely, so padding can only ever take the place of a header.
+/// Compression has to be enabled before the first byte is read.
+pub struct Compressed<I> {
+    bytes: I,
+    enabled: bool,
+    /// Compressed bytes of the current block
+    encoded: VecDeque<u8>,
+    /// Error of the source, returned after the bytes that have been read before it
+    error: Option<io::Error>,
+}
+
+impl<I: Iterator<Item = io::Result<u8>>> Compressed<I> {
+    /// Passes the bytes on unchanged until [`Compressed::enable`] is called
+    pub fn new(bytes: I) -> Self {
+        Self {
+            bytes,
+            enabled: false,
+            encoded: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Reads and compresses the next block of the source
+    fn read_block(&mut self) {
+        let mut block = Vec::with_capacity(BLOCK_LEN);
+        while block.len() < BLOCK_LEN {
+            match self.bytes.next() {
+                Some(Ok(byte)) => block.push(byte),
+                Some(Err(err)) => {
+                    self.error = Some(err);
+                    break;
+                }
+                None => break,
+            }
+        }
+        self.encoded.extend(compress(&block));
+    }
+}
+
+impl<I: Iterator<Item = io::Result<u8>>> Iterator for Compressed<I> {
+    type Item = io::Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.enabled {
+            return self.bytes.next();
+        }
+        if self.encoded.is_empty() && self.error.is_none() {
+            self.read_block();
+        }
+        match self.encoded.pop_front() {
+            Some(byte) => Some(Ok(byte)),
+            None => self.error.take().map(Err),
+        }
+    }
+}
+
+/// Number of equal bytes at the start of the data, at most as many as a header can repeat
+fn run_len(data: &[u8]) -> usize {
+    let max = (u8::MAX - REPEAT_OFFSET) as usize;
+    data.iter()
+        .take(max)
+        .take_while(|byte| **byte == data[0])
+        .count()
+}
+
+/// Run-length encodes the data, see [`Compressed`]
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + data.len().div_ceil(MAX_LITERAL as usize));
+    let mut index = 0;
+    while index < data.len() {
+        let run = run_len(&data[index..]);
+        if run >= MIN_RUN {
+            encoded.extend([REPEAT_OFFSET + run as u8, data[index]]);
+            index += run;
+            continue;
+        }
+
+        // bytes up to the next run that is worth repeating
+        let start = index;
+        while index < data.len()
+            && index - start < MAX_LITERAL as usize
+            && run_len(&data[index..]) < MIN_RUN
+        {
+            index += 1;
+        }
+        encoded.push((index - start) as u8);
+        encoded.extend(&data[start..index]);
+    }
+    encoded
+}
+
+/// What the [`Decompressor`] expects next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expected {
+    Header,
+    /// This many bytes that are copied
+    Literal(u8),
+    /// The byte that is repeated this many times
+    Repeat(u8),
+}
+
+/// # Decompressor
+///
+/// Undoes [`Compressed`] frame by frame, values that are split between two frames
+/// are completed with the next one.
+#[derive(Debug)]
+pub struct Decompressor {
+    expected: Expected,
+}
+
+impl Decompressor {
+    pub fn new() -> Self {
+        Self {
+            expected: Expected::Header,
+        }
+    }
+
+    /// Decompresses the payload of a received frame
+    pub fn decompress(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(2 * payload.len());
+        for &byte in payload {
+            self.expected = match self.expected {
+                Expected::Header if byte == 0 => Expected::Header,
+                Expected::Header if byte <= MAX_LITERAL => Expected::Literal(byte),
+                Expected::Header => Expected::Repeat(byte - REPEAT_OFFSET),
+                Expected::Literal(remaining) => {
+                    data.push(byte);
+                    match remaining - 1 {
+                        0 => Expected::Header,
+                        remaining => Expected::Literal(remaining),
+                    }
+                }
+                Expected::Repeat(count) => {
+                    data.extend(std::iter::repeat_n(byte, count as usize));
+                    Expected::Header
+                }
+            };
+        }
+        data
+    }
+}
+
+#[test]
+fn compress_runs_and_literals() {
+    let mut data = vec![0xc0, 0xc1, 0xc1];
+    data.extend([0x00; 200]);
+    data.extend([0x42, 0x42, 0x42, 0x17]);
+    let encoded = compress(&data);
+    assert_eq!(
+        encoded,
+        [0x03, 0xc0, 0xc1, 0xc1, 0xff, 0x00, 0xc5, 0x00, 0x81, 0x42, 0x01, 0x17]
+    );
+
+    // split across frames, which are filled up with zeros
+    let mut decompressor = Decompressor::new();
+    let mut decompressed = decompressor.decompress(&encoded[..5]);
+    decompressed.extend(decompressor.decompress(&encoded[5..]));
+    decompressed.extend(decompressor.decompress(&[0x00; 8]));
+    assert_eq!(decompressed, data);
+
+    // the source is compressed in blocks, once enabled
+    let mut source = Compressed::new(data.iter().copied().map(Ok));
+    source.enable();
+    let compressed: Vec<u8> = source.map(Result::unwrap).collect();
+    assert!(compressed.len() < data.len());
+    assert_eq!(Decompressor::new().decompress(&compressed), data);
+}
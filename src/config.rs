@@ -0,0 +1,295 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::bits::{NibbleOrder, SYMBOL_BITS};
+use crate::framing::FramingKind;
+use crate::layout::{self, LayoutDescription};
+use crate::stream::{IdlePattern, FRAME_SIZE_LEN};
+use crate::{CHECKSUM_LEN, FRAME_DATA_LEN};
+
+/// Bundled settings for common setups, so that both sides can easily agree on them.
+///
+/// The profile used by [`ProtocolConfig::default`] is selected with the
+/// `lab-b15f` (default), `fast-serial` or `paranoid` cargo features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Two B15f boards connected with the patch cable in the lab
+    LabB15f,
+    /// No pacing for fast and reliable serial links
+    FastSerial,
+    /// Slow pacing for noisy links
+    Paranoid,
+}
+
+impl Profile {
+    /// The profile selected by the enabled cargo features
+    pub const fn selected() -> Self {
+        if cfg!(feature = "paranoid") {
+            Self::Paranoid
+        } else if cfg!(feature = "fast-serial") {
+            Self::FastSerial
+        } else {
+            Self::LabB15f
+        }
+    }
+}
+
+/// Settings both sides of a connection have to agree on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolConfig {
+    /// How values equal to an escape code are sent
+    pub framing: FramingKind,
+    /// Time to wait between two polls of the connection
+    pub pacing: Duration,
+    /// Order in which the nibbles of a byte are sent,
+    /// the other side switches to it after the first escape code
+    pub nibble_order: NibbleOrder,
+    /// What is sent while there is no frame to send
+    pub idle_pattern: IdlePattern,
+    /// Data bytes in a frame, smaller frames lose less data when they break
+    pub frame_size: usize,
+}
+
+impl ProtocolConfig {
+    pub fn profile(profile: Profile) -> Self {
+        match profile {
+            Profile::LabB15f => Self {
+                framing: FramingKind::Escape,
+                pacing: Duration::from_millis(1),
+                nibble_order: NibbleOrder::HighFirst,
+                idle_pattern: IdlePattern::Alternating,
+                frame_size: FRAME_DATA_LEN,
+            },
+            Profile::FastSerial => Self {
+                framing: FramingKind::Escape,
+                pacing: Duration::ZERO,
+                nibble_order: NibbleOrder::HighFirst,
+                idle_pattern: IdlePattern::Alternating,
+                frame_size: FRAME_DATA_LEN,
+            },
+            Profile::Paranoid => Self {
+                framing: FramingKind::Escape,
+                pacing: Duration::from_millis(5),
+                nibble_order: NibbleOrder::HighFirst,
+                idle_pattern: IdlePattern::Alternating,
+                frame_size: 16,
+            },
+        }
+    }
+}
+
+impl ProtocolConfig {
+    /// Frame layout and escape table, like they are sent, e.g. to generate the counterpart from
+    pub fn describe(&self) -> LayoutDescription {
+        let frame = layout::frame_fields(self.frame_size, CHECKSUM_LEN);
+        LayoutDescription {
+            symbol_bits: SYMBOL_BITS,
+            nibble_order: self.nibble_order,
+            max_frame_len: frame
+                .iter()
+                .map(|field| {
+                    if field.escaped {
+                        2 * field.len
+                    } else {
+                        field.len
+                    }
+                })
+                .sum(),
+            frame,
+            escape_codes: layout::escape_codes(),
+            framing: self.framing,
+            frame_size_payload_len: FRAME_SIZE_LEN,
+            pacing_us: self.pacing.as_micros(),
+            idle_pattern: self.idle_pattern.clone(),
+        }
+    }
+}
+
+impl Default for ProtocolConfig {
+    fn default() -> Self {
+        Self::profile(Profile::selected())
+    }
+}
+
+/// Name of the settings file, in the working directory or in `$XDG_CONFIG_HOME/protocol/`
+pub const SETTINGS_FILE: &str = "protocol.toml";
+
+/// # Settings
+///
+/// Defaults for the command line, so that lab PCs can be set up once,
+/// flags on the command line take precedence.
+///
+/// Read from flat `key = value` lines of TOML, every key is optional:
+///
+/// ```toml
+/// device = "192.168.0.2:4000"
+/// frame_size = 32
+/// pacing_us = 1000
+/// nibble_order = "high-first"
+/// framing = "escape"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub device: Option<String>,
+    /// Number of data bytes in the frames that are requested from the other side
+    pub frame_size: Option<usize>,
+    pub pacing: Option<Duration>,
+    pub nibble_order: Option<NibbleOrder>,
+    pub framing: Option<FramingKind>,
+}
+
+/// A line of the settings file that could not be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingsError {
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl Settings {
+    pub fn parse(text: &str) -> Result<Self, SettingsError> {
+        let mut settings = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message| SettingsError {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or(error("expected key = value"))?;
+            let value = value.trim();
+            // a string ends at its closing quote, anything else at a comment
+            let value = match value.strip_prefix('"') {
+                Some(string) => string.split_once('"').ok_or(error("unterminated string"))?.0,
+                None => value.split('#').next().unwrap_or_default().trim(),
+            };
+
+            match key.trim() {
+                "device" => settings.device = Some(value.into()),
+                "frame_size" => {
+                    let len = value.parse().map_err(|_| error("invalid frame size"))?;
+                    settings.frame_size = Some(len);
+                }
+                "pacing_us" => {
+                    let us = value.parse().map_err(|_| error("invalid pacing"))?;
+                    settings.pacing = Some(Duration::from_micros(us));
+                }
+                "nibble_order" => {
+                    let order = NibbleOrder::from_name(value).ok_or(error("invalid nibble order"))?;
+                    settings.nibble_order = Some(order);
+                }
+                "framing" => {
+                    let framing = FramingKind::from_name(value).ok_or(error("invalid framing"))?;
+                    settings.framing = Some(framing);
+                }
+                _ => return Err(error("unknown key")),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// The settings file in the working directory, or else the one in the XDG config directory
+    pub fn path() -> Option<PathBuf> {
+        let local = PathBuf::from(SETTINGS_FILE);
+        if local.is_file() {
+            return Some(local);
+        }
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        let global = config_dir.join("protocol").join(SETTINGS_FILE);
+        global.is_file().then_some(global)
+    }
+
+    /// Empty settings if there is no settings file
+    pub fn load() -> io::Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let text = std::fs::read_to_string(&path)?;
+        Self::parse(&text).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), err.line, err.message),
+            )
+        })
+    }
+
+    /// The config with the pacing, nibble order, framing and frame size of the settings,
+    /// where they are set
+    pub fn apply(&self, mut config: ProtocolConfig) -> ProtocolConfig {
+        if let Some(pacing) = self.pacing {
+            config.pacing = pacing;
+        }
+        if let Some(order) = self.nibble_order {
+            config.nibble_order = order;
+        }
+        if let Some(framing) = self.framing {
+            config.framing = framing;
+        }
+        if let Some(len) = self.frame_size {
+            config.frame_size = len;
+        }
+        config
+    }
+}
+
+#[test]
+fn parse_settings() {
+    let settings = Settings::parse(
+        "# lab PC 3\n\
+         device = \"192.168.0.2:4000\" # the other bench\n\
+         frame_size = 32\n\
+         \n\
+         pacing_us = 500\n\
+         nibble_order = \"low-first\"\n\
+         framing = \"escape\"\n",
+    )
+    .unwrap();
+    assert_eq!(settings.device.as_deref(), Some("192.168.0.2:4000"));
+    assert_eq!(settings.frame_size, Some(32));
+    assert_eq!(settings.framing, Some(FramingKind::Escape));
+
+    let config = settings.apply(ProtocolConfig::profile(Profile::LabB15f));
+    assert_eq!(config.pacing, Duration::from_micros(500));
+    assert_eq!(config.nibble_order, NibbleOrder::LowFirst);
+    assert_eq!(config.framing, FramingKind::Escape);
+    assert_eq!(config.frame_size, 32);
+
+    assert_eq!(
+        Settings::parse("frame_size = 32\nframesize = 16\n"),
+        Err(SettingsError {
+            line: 2,
+            message: "unknown key",
+        })
+    );
+    assert_eq!(
+        Settings::parse("device = \"tcp").unwrap_err().message,
+        "unterminated string"
+    );
+}
+
+#[test]
+fn profiles() {
+    assert_eq!(
+        ProtocolConfig::default(),
+        ProtocolConfig::profile(Profile::selected())
+    );
+    if !cfg!(any(feature = "fast-serial", feature = "paranoid")) {
+        // the lab boards are the default
+        assert_eq!(Profile::selected(), Profile::LabB15f);
+    }
+
+    let lab = ProtocolConfig::profile(Profile::LabB15f);
+    let fast = ProtocolConfig::profile(Profile::FastSerial);
+    let paranoid = ProtocolConfig::profile(Profile::Paranoid);
+    assert_eq!(lab.frame_size, FRAME_DATA_LEN);
+    assert_eq!(fast.pacing, Duration::ZERO);
+    // noisy links lose less data to a broken frame, and give the line more time to settle
+    assert!(paranoid.frame_size < lab.frame_size);
+    assert!(paranoid.pacing > lab.pacing);
+    assert_eq!(paranoid.describe().frame[1].len, paranoid.frame_size);
+}
@@ -0,0 +1,258 @@
+use crate::bits::{self, NibbleOrder};
+use crate::device::Device;
+use crate::escape::{EscapeCode, Escaped};
+use crate::stream::{boundary_buffer, separator, InputEvent, InputStream};
+use crate::{encode_frame, FRAME_DATA_LEN};
+
+/// How many nibbles are exchanged before a case gives up waiting
+const MAX_TICKS: usize = 20_000;
+
+/// A scripted scenario that is run against a foreign implementation.
+pub struct Case<D: Device> {
+    pub name: &'static str,
+    pub run: fn(&mut Tester<D>) -> Result<(), String>,
+}
+
+pub fn cases<D: Device>() -> [Case<D>; 4] {
+    [
+        Case {
+            name: "correct transfer",
+            run: correct_transfer,
+        },
+        Case {
+            name: "corrupted frame",
+            run: corrupted_frame,
+        },
+        Case {
+            name: "lost ack",
+            run: lost_ack,
+        },
+        Case {
+            name: "oversize frame",
+            run: oversize_frame,
+        },
+    ]
+}
+
+/// Runs every case and returns the name and outcome of each of them.
+pub fn run<D: Device>(device: &mut D) -> Vec<(&'static str, Result<(), String>)> {
+    cases()
+        .into_iter()
+        .map(|case| {
+            let mut tester = Tester::new(device);
+            (case.name, (case.run)(&mut tester))
+        })
+        .collect()
+}
+
+/// Drives the device nibble by nibble, without any of the
+/// automatic replies a normal [`crate::Connection`] would send.
+pub struct Tester<'a, D: Device> {
+    device: &'a mut D,
+    i_stream: InputStream,
+    /// Used to alternate the idle pattern
+    tick: usize,
+}
+
+impl<'a, D: Device> Tester<'a, D> {
+    pub fn new(device: &'a mut D) -> Self {
+        Self {
+            device,
+            i_stream: InputStream::new(),
+            tick: 0,
+        }
+    }
+
+    /// Sends the bytes as they are, only inserting buffer codes between equal nibbles.
+    pub fn transmit(&mut self, bytes: &[u8]) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        for nibble in wire_nibbles(bytes) {
+            events.push(self.exchange(nibble));
+        }
+        events
+    }
+
+    /// Idles until the predicate matches a received event or the tick limit is reached.
+    pub fn wait_for(&mut self, predicate: impl Fn(&InputEvent) -> bool) -> Option<InputEvent> {
+        for _ in 0..MAX_TICKS {
+            let nibble = if self.tick.is_multiple_of(2) {
+                0x0f
+            } else {
+                0x00
+            };
+            let event = self.exchange(nibble);
+            if predicate(&event) {
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    fn exchange(&mut self, nibble: u8) -> InputEvent {
+        self.tick += 1;
+        self.device.send(nibble);
+        self.device.debug_poll();
+        self.i_stream.push(self.device.read())
+    }
+}
+
+/// Splits bytes into nibbles, separating equal consecutive nibbles like the [`crate::stream::OutputStream`].
+pub fn wire_nibbles(bytes: &[u8]) -> Vec<u8> {
+    let mut nibbles: Vec<u8> = Vec::with_capacity(bits::symbols(bytes.len()));
+    for (index, byte) in bytes.iter().enumerate() {
+        if let Some(buffer) = index
+            .checked_sub(1)
+            .and_then(|previous| boundary_buffer(bytes[previous], *byte, NibbleOrder::HighFirst))
+        {
+            nibbles.extend(buffer);
+        }
+        for nibble in bits::split(*byte) {
+            if nibbles.last() == Some(&nibble) {
+                nibbles.extend(separator(nibble, NibbleOrder::HighFirst));
+            }
+            nibbles.push(nibble);
+        }
+    }
+    nibbles
+}
+
+/// Payload that does not contain any escape codes
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|index| 0x80 | index as u8).collect()
+}
+
+fn frame_with_payload(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![EscapeCode::StartOfFrame as u8];
+    frame.extend(Escaped::new(payload.iter().map(|byte| Ok(*byte))).map(Result::unwrap));
+    frame.push(EscapeCode::EndOfFrame as u8);
+    frame
+}
+
+fn expect_reply<D: Device>(
+    tester: &mut Tester<D>,
+    expected: impl Fn(&InputEvent) -> bool,
+    expected_name: &str,
+) -> Result<(), String> {
+    match tester.wait_for(|event| *event != InputEvent::LinkIdle) {
+        Some(event) if expected(&event) => Ok(()),
+        Some(event) => Err(format!("expected {expected_name}, got {event:?}")),
+        None => Err(format!("expected {expected_name}, got nothing")),
+    }
+}
+
+fn is_ack(event: &InputEvent) -> bool {
+    matches!(event, InputEvent::Ack { .. })
+}
+
+fn is_nak(event: &InputEvent) -> bool {
+    matches!(event, InputEvent::Nak { .. })
+}
+
+fn correct_transfer<D: Device>(tester: &mut Tester<D>) -> Result<(), String> {
+    let (frame, len) = encode_frame(&mut Escaped::new(
+        payload(FRAME_DATA_LEN).into_iter().map(Ok),
+    ))
+    .expect("payload is in memory");
+    tester.transmit(&frame[..len]);
+    expect_reply(tester, is_ack, "CFD")
+}
+
+fn corrupted_frame<D: Device>(tester: &mut Tester<D>) -> Result<(), String> {
+    // one byte of data goes missing on the way
+    tester.transmit(&frame_with_payload(&payload(FRAME_DATA_LEN - 1)));
+    expect_reply(tester, is_nak, "IFD")
+}
+
+fn lost_ack<D: Device>(tester: &mut Tester<D>) -> Result<(), String> {
+    let payload = |event: &InputEvent| match event {
+        InputEvent::DataFrame { payload, .. } => Some(*payload),
+        _ => None,
+    };
+    let is_frame = |event: &InputEvent| payload(event).is_some();
+    // the first ack asks for the first frame
+    tester.transmit(&[EscapeCode::CorrectFrameData as u8]);
+    let Some(first) = tester.wait_for(is_frame) else {
+        return Err("no frame was sent".into());
+    };
+    // not acknowledging the frame should make the other side send it again
+    match tester.wait_for(is_frame) {
+        // the retransmit is numbered as a new frame, only the data has to match
+        Some(second) if payload(&second) == payload(&first) => Ok(()),
+        Some(second) => Err(format!("expected retransmit of {first:?}, got {second:?}")),
+        None => Err("frame was not retransmitted".into()),
+    }
+}
+
+fn oversize_frame<D: Device>(tester: &mut Tester<D>) -> Result<(), String> {
+    tester.transmit(&frame_with_payload(&payload(FRAME_DATA_LEN + 1)));
+    expect_reply(tester, is_nak, "IFD")
+}
+
+/// Our own implementation at the other end of a simulated cable,
+/// which is polled after every nibble the [`Tester`] sends
+#[cfg(test)]
+struct Peer {
+    port: crate::sim::SimPort,
+    connection: crate::Connection<crate::sim::SimPort>,
+}
+
+#[cfg(test)]
+impl Peer {
+    fn new(data: Vec<u8>) -> Self {
+        let (port, other_port) = crate::sim::SimPort::pair(false);
+        let mut connection = crate::Connection::boxed(other_port, data.into_iter().map(Ok));
+        // the tester never waits, so the frame that is not acked is resent right away
+        connection.set_ack_timeout(crate::rtt::AckTimeout::Fixed(std::time::Duration::ZERO));
+        Self { port, connection }
+    }
+}
+
+#[cfg(test)]
+impl crate::device::DeviceName for Peer {
+    const NAME: &'static str = "Peer";
+}
+
+#[cfg(test)]
+impl crate::device::DeviceTx for Peer {
+    fn send(&mut self, data: u8) {
+        self.port.send(data);
+    }
+
+    fn debug_poll(&mut self) {
+        self.connection.poll();
+    }
+}
+
+#[cfg(test)]
+impl crate::device::DeviceRx for Peer {
+    fn read(&self) -> u8 {
+        self.port.read()
+    }
+}
+
+#[test]
+fn own_implementation_conforms() {
+    for case in cases::<Peer>() {
+        // only the lost ack case needs the other side to send something
+        let data = match case.name {
+            "lost ack" => payload(FRAME_DATA_LEN),
+            _ => Vec::new(),
+        };
+        let mut peer = Peer::new(data);
+        assert_eq!(
+            (case.run)(&mut Tester::new(&mut peer)),
+            Ok(()),
+            "{}",
+            case.name
+        );
+    }
+}
+
+#[test]
+fn wire_nibbles_are_separated() {
+    // equal nibbles within and across bytes
+    let nibbles = wire_nibbles(&[0x11, 0x12]);
+    assert!(nibbles.len() > bits::symbols(2));
+    assert!(nibbles.windows(2).all(|pair| pair[0] != pair[1]));
+    assert_eq!(wire_nibbles(&[0x12, 0x34]), [0x1, 0x2, 0x3, 0x4]);
+}
@@ -0,0 +1,173 @@
+use crate::bits;
+use crate::checksum::ChecksumAlgorithm;
+use crate::escape::EscapeCode;
+use crate::{CHECKSUM_LEN, ESCAPE_CODE_LEN};
+
+/// What it costs to send a payload over the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WireCost {
+    /// Nibbles written to the device, including inserted buffer codes
+    pub nibbles: usize,
+    pub frames: usize,
+    /// Payload bytes that had to be escaped
+    pub escapes: usize,
+}
+
+/// Calculates the wire cost of a payload the same way the [`crate::stream::OutputStream`] sends it.
+///
+/// Frames always have `frame_data_len` bytes, unused space is filled with zeros.
+pub fn wire_cost(payload: &[u8], frame_data_len: usize) -> WireCost {
+    let escapes = payload
+        .iter()
+        .filter(|byte| EscapeCode::from_byte(**byte).is_some())
+        .count();
+    let escaped_len = payload.len() + escapes;
+    let frames = escaped_len.div_ceil(frame_data_len).max(1);
+
+    let escaped = payload.iter().flat_map(|&byte| {
+        let repeat = if EscapeCode::from_byte(byte).is_some() { 2 } else { 1 };
+        std::iter::repeat_n(byte, repeat)
+    });
+    let mut escaped = escaped.chain(std::iter::repeat(0x00));
+
+    let mut nibbles = 0;
+    for _ in 0..frames {
+        let mut frame = vec![EscapeCode::StartOfFrame as u8];
+        frame.extend(escaped.by_ref().take(frame_data_len));
+        // TODO Use the actual checksum, once it is implemented
+        let checksum_region = 2 * CHECKSUM_LEN;
+        frame.extend(
+            [EscapeCode::Buffer1 as u8, EscapeCode::Buffer2 as u8]
+                .into_iter()
+                .cycle()
+                .take(checksum_region),
+        );
+        frame.push(EscapeCode::EndOfFrame as u8);
+        debug_assert_eq!(
+            frame.len(),
+            2 * ESCAPE_CODE_LEN + frame_data_len + checksum_region
+        );
+        nibbles += frame_nibbles(&frame);
+    }
+
+    WireCost {
+        nibbles,
+        frames,
+        escapes,
+    }
+}
+
+/// How often the checksums of a corpus collide with escape codes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChecksumAudit {
+    pub frames: usize,
+    pub checksum_bytes: usize,
+    /// Checksum bytes that are equal to an escape code and have to be escaped
+    pub escaped_bytes: usize,
+}
+
+impl ChecksumAudit {
+    /// Probability of a uniformly distributed byte being an escape code
+    pub fn random_rate() -> f64 {
+        (0..=u8::MAX)
+            .filter(|byte| EscapeCode::from_byte(*byte).is_some())
+            .count() as f64
+            / 256.0
+    }
+
+    /// Share of checksum bytes that had to be escaped
+    pub fn escape_rate(&self) -> f64 {
+        if self.checksum_bytes == 0 {
+            0.0
+        } else {
+            self.escaped_bytes as f64 / self.checksum_bytes as f64
+        }
+    }
+
+    /// Expected number of additional bytes per frame, caused by escaping the checksum
+    pub fn overhead_per_frame(&self) -> f64 {
+        if self.frames == 0 {
+            0.0
+        } else {
+            self.escaped_bytes as f64 / self.frames as f64
+        }
+    }
+
+    /// Whether the checksums collide with escape codes far more often than random bytes would
+    pub fn is_pathological(&self) -> bool {
+        self.escape_rate() > 2.0 * Self::random_rate()
+    }
+}
+
+/// Calculates the checksum of every frame of the corpus
+/// and counts how many of the checksum bytes have to be escaped.
+pub fn audit_checksum(
+    algorithm: ChecksumAlgorithm,
+    checksum_len: usize,
+    frame_data_len: usize,
+    corpus: &[u8],
+) -> ChecksumAudit {
+    let mut audit = ChecksumAudit {
+        frames: 0,
+        checksum_bytes: 0,
+        escaped_bytes: 0,
+    };
+    for frame in corpus.chunks(frame_data_len.max(1)) {
+        let checksum = algorithm.checksum(frame, checksum_len);
+        audit.frames += 1;
+        audit.checksum_bytes += checksum.len();
+        audit.escaped_bytes += checksum
+            .iter()
+            .filter(|byte| EscapeCode::from_byte(**byte).is_some())
+            .count();
+    }
+    audit
+}
+
+/// Data with typical patterns, like constant bytes, counters and text,
+/// that is used to find checksums colliding with escape codes.
+pub fn patterned_corpus(frame_data_len: usize) -> Vec<u8> {
+    let mut corpus = Vec::new();
+    for byte in u8::MIN..=u8::MAX {
+        corpus.extend(std::iter::repeat_n(byte, frame_data_len));
+    }
+    for start in u8::MIN..=u8::MAX {
+        corpus.extend((0..frame_data_len).map(|index| start.wrapping_add(index as u8)));
+    }
+    let text = b"The quick brown fox jumps over the lazy dog. ";
+    corpus.extend(text.iter().cycle().take(64 * frame_data_len));
+    corpus
+}
+
+/// Every byte is two nibbles, equal neighbouring nibbles are separated by a buffer code.
+pub fn frame_nibbles(frame: &[u8]) -> usize {
+    let nibbles: Vec<u8> = frame.iter().flat_map(|byte| bits::split(*byte)).collect();
+    let buffers = nibbles.windows(2).filter(|pair| pair[0] == pair[1]).count();
+    nibbles.len() + 2 * buffers
+}
+
+#[test]
+fn xor_of_constant_frames_is_pathological() {
+    // the xor of an odd number of equal bytes is the byte itself,
+    // so frames filled with an escape code always have to escape their checksum
+    let corpus: Vec<u8> = [EscapeCode::StartOfFrame as u8, EscapeCode::Buffer1 as u8]
+        .into_iter()
+        .flat_map(|byte| std::iter::repeat_n(byte, 15))
+        .collect();
+    let audit = audit_checksum(ChecksumAlgorithm::Xor, 1, 15, &corpus);
+    assert_eq!(audit.frames, 2);
+    assert_eq!(audit.escaped_bytes, 2);
+    assert!(audit.is_pathological());
+
+    let audit = audit_checksum(ChecksumAlgorithm::Crc32, 4, 16, &patterned_corpus(16));
+    assert!(!audit.is_pathological());
+}
+
+#[test]
+fn wire_cost_counts_frames_and_escapes() {
+    let cost = wire_cost(&[0x01, 0x12, 0xbc, 0xab], 4);
+    assert_eq!(cost.frames, 2);
+    assert_eq!(cost.escapes, 1);
+    // [12 01 12 12 bc 23] [12 ab 00 00 00 23]: 24 nibbles, 1 + 5 equal pairs
+    assert_eq!(cost.nibbles, 24 + 2 * 6);
+}
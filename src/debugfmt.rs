@@ -0,0 +1,105 @@
+use std::fmt::{Display, Write};
+
+use crate::escape::EscapeCode;
+
+/// Number of bytes in a line of [`hex_dump`]
+const BYTES_PER_LINE: usize = 16;
+
+const HIGHLIGHT: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// Formats the bytes like `[12, ab, 23]`
+pub fn hex_list(bytes: &[u8]) -> String {
+    let hex: Vec<_> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("[{}]", hex.join(", "))
+}
+
+/// Formats the bytes as lines of 16 bytes, each starting with its offset.
+///
+/// Escape codes are highlighted with ANSI colors or put in brackets,
+/// and named at the end of the line.
+pub fn hex_dump(bytes: &[u8], color: bool) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let _ = write!(dump, "{:04x} ", line * BYTES_PER_LINE);
+        let mut names = Vec::new();
+        for byte in chunk {
+            match EscapeCode::from_byte(*byte) {
+                Some(code) if color => {
+                    names.push(code.abbreviation());
+                    let _ = write!(dump, " {HIGHLIGHT}{byte:02x}{RESET} ");
+                }
+                Some(code) => {
+                    names.push(code.abbreviation());
+                    let _ = write!(dump, "[{byte:02x}]");
+                }
+                None => {
+                    let _ = write!(dump, " {byte:02x} ");
+                }
+            }
+        }
+        // keeps the names aligned on the last line
+        dump.push_str(&" ".repeat(4 * (BYTES_PER_LINE - chunk.len())));
+        if !names.is_empty() {
+            let _ = write!(dump, "  {}", names.join(" "));
+        }
+        dump.push('\n');
+    }
+    dump
+}
+
+/// Bytes that differ between two transmissions of the same frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameDiff {
+    /// Offset, byte of the previous and byte of the current transmission,
+    /// `None` if the transmission is shorter
+    pub differences: Vec<(usize, Option<u8>, Option<u8>)>,
+}
+
+impl FrameDiff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+pub fn frame_diff(previous: &[u8], current: &[u8]) -> FrameDiff {
+    let differences = (0..previous.len().max(current.len()))
+        .map(|offset| (offset, previous.get(offset), current.get(offset)))
+        .filter(|(_, previous, current)| previous != current)
+        .map(|(offset, previous, current)| (offset, previous.copied(), current.copied()))
+        .collect();
+    FrameDiff { differences }
+}
+
+impl Display for FrameDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let byte = |byte: Option<u8>| byte.map_or("--".to_string(), |byte| format!("{byte:02x}"));
+        for (offset, previous, current) in &self.differences {
+            writeln!(f, "{offset:04x}: {} -> {}", byte(*previous), byte(*current))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn hex_dump_and_diff() {
+    assert_eq!(hex_list(&[0x12, 0xab]), "[12, ab]");
+    let mut frame = vec![EscapeCode::StartOfFrame as u8];
+    frame.extend(0xc0..0xd0);
+    frame.push(EscapeCode::EndOfFrame as u8);
+    assert_eq!(
+        hex_dump(&frame, false),
+        "0000 [12] c0  c1  c2  c3  c4  c5  c6  c7  c8  c9  ca  cb  cc  cd  ce   SOF\n\
+         0010  cf [23]                                                          EOF\n"
+    );
+
+    let mut resent = frame.clone();
+    resent[3] = 0x00;
+    resent.pop();
+    let diff = frame_diff(&frame, &resent);
+    assert_eq!(
+        diff.differences,
+        [(3, Some(0xc2), Some(0x00)), (17, Some(0x23), None)]
+    );
+    assert_eq!(diff.to_string(), "0003: c2 -> 00\n0011: 23 -> --\n");
+}
@@ -1,104 +1,548 @@
+use std::cell::Cell;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
 use std::{io, iter};
 
 use b15f::B15fDriver;
 
+use crate::sim::SimPort;
+use crate::stream::IdlePattern;
 use crate::Connection;
 
-pub trait Device {
+#[cfg(feature = "embedded-hal")]
+mod hal;
+#[cfg(feature = "embedded-hal")]
+pub use hal::{B15fPin, HalDevice};
+#[cfg(unix)]
+mod pipe;
+#[cfg(unix)]
+pub use pipe::PipeDevice;
+mod quirks;
+pub use quirks::{Quirks, QuirksDevice};
+
+pub trait DeviceName {
     const NAME: &'static str;
 
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+}
+
+/// The sending half of a device
+pub trait DeviceTx: DeviceName {
     /// Only sends lower nibble of byte.
     fn send(&mut self, data: u8);
 
-    /// Only reads lower nibble of byte.
-    fn read(&self) -> u8;
+    /// Sends the nibbles in order, in one go if the device can, see [`DeviceTx::max_batch`]
+    fn send_many(&mut self, data: &[u8]) {
+        for nibble in data {
+            self.send(*nibble);
+        }
+    }
+
+    /// Number of nibbles [`DeviceTx::send_many`] and [`DeviceRx::read_many`] can handle
+    /// in one call, 1 if every nibble is sent and read on its own like on a cable
+    fn max_batch(&self) -> usize {
+        1
+    }
+
+    /// Sets the clock line, if the device has one.
+    fn send_clock(&mut self, _level: bool) {}
+
+    /// Maximum number of nibbles per second, `None` if only limited by the polling
+    fn max_rate_hz(&self) -> Option<u32> {
+        None
+    }
+
+    /// Sends the nibbles the device has buffered, called at the end of every frame.
+    ///
+    /// Devices that send every nibble right away do nothing.
+    fn flush(&mut self) {}
+
+    /// Stops driving the data lines until the next [`DeviceTx::send`],
+    /// devices that can not do that keep the last value.
+    fn release(&mut self) {}
 
     /// TODO Remove, only used for debugging
     fn debug_poll(&mut self) {}
+}
 
-    fn name(&self) -> &'static str {
-        Self::NAME
+/// The receiving half of a device
+pub trait DeviceRx: DeviceName {
+    /// Only reads lower nibble of byte.
+    fn read(&self) -> u8;
+
+    /// Reads every nibble that has arrived since the last call, as many as fit into the buffer,
+    /// returns how many have been read.
+    ///
+    /// Devices that can not queue nibbles read the current value, like [`DeviceRx::read`].
+    fn read_many(&self, buffer: &mut [u8]) -> usize {
+        let Some(first) = buffer.first_mut() else {
+            return 0;
+        };
+        *first = self.read();
+        1
+    }
+
+    /// Reads the clock line, returns `None` if the device has none.
+    ///
+    /// Devices with a clock line are used in lockstep,
+    /// a nibble is only read and sent after the clock of the other side has changed.
+    fn read_clock(&self) -> Option<bool> {
+        None
+    }
+
+    /// Whether nibbles are only told apart by a change of value, like on a plain cable,
+    /// so that reading the same value twice is not a new nibble.
+    ///
+    /// Devices with a clock line or that deliver every nibble separately return false.
+    fn detects_edges(&self) -> bool {
+        self.read_clock().is_none()
+    }
+}
+
+/// A device that can both send and receive, as needed by a [`Connection`].
+pub trait Device: DeviceTx + DeviceRx {
+    fn capabilities(&self) -> DeviceCapabilities {
+        DeviceCapabilities {
+            symbol_width: 4,
+            has_clock: self.read_clock().is_some(),
+            edge_detected: self.detects_edges(),
+            max_rate_hz: self.max_rate_hz(),
+            max_batch: self.max_batch().max(1),
+            // every device sends and receives on separate lines
+            duplex: true,
+        }
+    }
+}
+
+impl<D: DeviceTx + DeviceRx> Device for D {}
+
+/// What a device can do, so that a [`Connection`] can configure itself for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    /// Number of data lines, nibbles are the only supported symbols for now
+    pub symbol_width: u8,
+    pub has_clock: bool,
+    /// See [`DeviceRx::detects_edges`]
+    pub edge_detected: bool,
+    /// Maximum number of symbols per second, `None` if only limited by the polling
+    pub max_rate_hz: Option<u32>,
+    /// Nibbles that can be sent or read in one call, see [`DeviceTx::max_batch`]
+    pub max_batch: usize,
+    /// Whether both sides can send at the same time
+    pub duplex: bool,
+}
+
+impl DeviceCapabilities {
+    /// Time between two polls, so that the device is not polled faster than it can go
+    pub fn pacing(&self) -> Option<Duration> {
+        self.max_rate_hz
+            .map(|rate| Duration::from_secs(1) / rate.max(1))
+    }
+
+    /// With a clock line the data lines do not have to toggle while idle
+    pub fn idle_pattern(&self) -> IdlePattern {
+        if self.has_clock {
+            IdlePattern::HoldLast
+        } else {
+            IdlePattern::default()
+        }
+    }
+}
+
+/// # Split
+///
+/// Combines separate hardware for sending and receiving into a single device,
+/// e.g. a signal generator and a logic analyzer.
+pub struct Split<T: DeviceTx, R: DeviceRx> {
+    pub tx: T,
+    pub rx: R,
+}
+
+impl<T: DeviceTx, R: DeviceRx> Split<T, R> {
+    pub fn new(tx: T, rx: R) -> Self {
+        Self { tx, rx }
+    }
+}
+
+impl<T: DeviceTx, R: DeviceRx> DeviceName for Split<T, R> {
+    const NAME: &'static str = "Split";
+}
+
+impl<T: DeviceTx, R: DeviceRx> DeviceTx for Split<T, R> {
+    fn send(&mut self, data: u8) {
+        self.tx.send(data);
+    }
+
+    fn send_clock(&mut self, level: bool) {
+        self.tx.send_clock(level);
+    }
+
+    fn max_rate_hz(&self) -> Option<u32> {
+        self.tx.max_rate_hz()
+    }
+
+    fn flush(&mut self) {
+        self.tx.flush();
+    }
+
+    fn release(&mut self) {
+        self.tx.release();
+    }
+
+    fn debug_poll(&mut self) {
+        self.tx.debug_poll();
+    }
+}
+
+impl<T: DeviceTx, R: DeviceRx> DeviceRx for Split<T, R> {
+    fn read(&self) -> u8 {
+        self.rx.read()
+    }
+
+    fn read_clock(&self) -> Option<bool> {
+        self.rx.read_clock()
+    }
+
+    fn detects_edges(&self) -> bool {
+        self.rx.detects_edges()
     }
 }
 
 pub struct B15fDevice {
     driver: B15fDriver,
+    /// Whether the data pins are configured as inputs
+    released: bool,
 }
 
 impl B15fDevice {
     pub fn new() -> Result<Self, &'static str> {
         let mut driver = B15fDriver::new()?;
         driver.set_register_ddra(0x0f);
-        Ok(Self { driver })
+        Ok(Self {
+            driver,
+            released: false,
+        })
     }
 }
 
-impl Device for B15fDevice {
+impl DeviceName for B15fDevice {
     const NAME: &'static str = "B15f";
+}
 
+impl DeviceTx for B15fDevice {
     fn send(&mut self, data: u8) {
+        if self.released {
+            self.driver.set_register_ddra(0x0f);
+            self.released = false;
+        }
         self.driver.set_register_porta(data);
     }
 
+    /// Every register access is a round trip over the serial connection to the board
+    fn max_rate_hz(&self) -> Option<u32> {
+        Some(1000)
+    }
+
+    fn release(&mut self) {
+        if !self.released {
+            self.driver.set_register_ddra(0x00);
+            self.released = true;
+        }
+    }
+}
+
+impl DeviceRx for B15fDevice {
     fn read(&self) -> u8 {
         self.driver.get_register_pina()
     }
 }
 
-pub struct Arduino;
+/// # B15fListener
+///
+/// Configures every pin as input, so that the board can listen to a bus
+/// without interfering with the traffic on it.
+pub struct B15fListener {
+    driver: B15fDriver,
+}
 
-pub struct DebugDevice {
-    other_side: Connection<MirrorDevice, iter::Empty<io::Result<u8>>>,
+impl B15fListener {
+    pub fn new() -> Result<Self, &'static str> {
+        let mut driver = B15fDriver::new()?;
+        driver.set_register_ddra(0x00);
+        Ok(Self { driver })
+    }
 }
 
-impl DebugDevice {
-    pub fn new() -> Self {
-        Self {
-            other_side: Connection::new(MirrorDevice::new(), iter::empty()),
+impl DeviceName for B15fListener {
+    const NAME: &'static str = "B15f";
+}
+
+impl DeviceRx for B15fListener {
+    fn read(&self) -> u8 {
+        self.driver.get_register_pina()
+    }
+}
+
+/// # B15fLoopback
+///
+/// Sends on the upper nibble of PORTA and reads the lower one, so that a single board
+/// runs the protocol against itself once PA4-PA7 are jumpered to PA0-PA3.
+pub struct B15fLoopback {
+    driver: B15fDriver,
+}
+
+impl B15fLoopback {
+    pub fn new() -> Result<Self, &'static str> {
+        let mut driver = B15fDriver::new()?;
+        driver.set_register_ddra(0xf0);
+        Ok(Self { driver })
+    }
+}
+
+impl DeviceName for B15fLoopback {
+    const NAME: &'static str = "B15f loopback";
+}
+
+impl DeviceTx for B15fLoopback {
+    fn send(&mut self, data: u8) {
+        self.driver.set_register_porta((data & 0x0f) << 4);
+    }
+
+    fn max_rate_hz(&self) -> Option<u32> {
+        Some(1000)
+    }
+}
+
+impl DeviceRx for B15fLoopback {
+    fn read(&self) -> u8 {
+        self.driver.get_register_pina() & 0x0f
+    }
+
+    /// Every poll reads back the nibble it has just sent, so every read is a new nibble
+    fn detects_edges(&self) -> bool {
+        false
+    }
+}
+
+/// Nibbles a [`TcpDevice`] without a clock sends and reads in one call
+const TCP_BATCH: usize = 1024;
+
+/// # TcpDevice
+///
+/// Emulates the patch cable over a tcp connection, every sent nibble is a single byte.
+/// Like on the real cable, reading returns the last value the other side has sent.
+///
+/// With a clock, bit 4 of every byte is used as clock line.
+/// Without one, the nibbles are buffered until [`DeviceTx::flush`],
+/// so that a frame is not sent as one tcp segment per nibble.
+pub struct TcpDevice {
+    stream: TcpStream,
+    last_read: Cell<u8>,
+    /// Nibbles that have not been written to the stream yet
+    tx: Vec<u8>,
+    /// Last nibble and clock that have been sent, `None` without a clock line
+    clock: Option<(u8, bool)>,
+}
+
+impl TcpDevice {
+    /// Connects to `ADDR`, or waits for the other side to connect when given `listen:ADDR`.
+    pub fn open(spec: &str) -> io::Result<Self> {
+        let stream = match spec.strip_prefix("listen:") {
+            Some(addr) => TcpListener::bind(addr)?.accept()?.0,
+            None => Self::connect(spec)?,
+        };
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            last_read: Cell::new(0),
+            tx: Vec::with_capacity(TCP_BATCH),
+            clock: None,
+        })
+    }
+
+    /// Emulates a clock line next to the data lines
+    pub fn with_clock(mut self) -> Self {
+        self.clock = Some((0, false));
+        self
+    }
+
+    /// The last byte the other side has sent
+    fn last_byte(&self) -> u8 {
+        let mut buffer = [0; 64];
+        while let Ok(len @ 1..) = (&self.stream).read(&mut buffer) {
+            self.last_read.set(buffer[len - 1]);
+        }
+        self.last_read.get()
+    }
+
+    fn write(&mut self, byte: u8) {
+        // the other side has gone away, which is noticed by the protocol
+        let _ = self.stream.write_all(&[byte]);
+    }
+
+    /// Queues the nibbles, they are written once a whole batch has been queued at the latest
+    fn buffer(&mut self, nibbles: impl IntoIterator<Item = u8>) {
+        for nibble in nibbles {
+            self.tx.push(nibble & 0x0f);
+            if self.tx.len() >= TCP_BATCH {
+                self.flush();
+            }
+        }
+    }
+
+    /// The other side might not be listening yet
+    fn connect(addr: &str) -> io::Result<TcpStream> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            match TcpStream::connect(addr) {
+                Ok(stream) => return Ok(stream),
+                Err(_) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(50))
+                }
+                Err(err) => return Err(err),
+            }
         }
     }
 }
 
-impl Device for DebugDevice {
-    const NAME: &'static str = "Debug";
+impl DeviceName for TcpDevice {
+    const NAME: &'static str = "Tcp";
+}
 
+impl DeviceTx for TcpDevice {
     fn send(&mut self, data: u8) {
-        eprintln!("{} {:04b}", self.name(), data);
-        self.other_side.device.incoming = data;
+        let data = data & 0x0f;
+        match &mut self.clock {
+            Some((nibble, level)) => {
+                *nibble = data;
+                let byte = data | (*level as u8) << 4;
+                self.write(byte);
+            }
+            None => self.buffer([data]),
+        }
+    }
+
+    /// Every nibble is a byte of the stream, so they can be written at once without a clock
+    fn send_many(&mut self, data: &[u8]) {
+        if self.clock.is_some() {
+            data.iter().for_each(|nibble| self.send(*nibble));
+            return;
+        }
+        self.buffer(data.iter().copied());
+    }
+
+    fn flush(&mut self) {
+        if !self.tx.is_empty() {
+            let _ = self.stream.write_all(&self.tx);
+            self.tx.clear();
+        }
     }
 
+    fn max_batch(&self) -> usize {
+        if self.clock.is_some() {
+            1
+        } else {
+            TCP_BATCH
+        }
+    }
+
+    fn send_clock(&mut self, level: bool) {
+        if let Some((nibble, clock)) = &mut self.clock {
+            *clock = level;
+            let byte = *nibble | (level as u8) << 4;
+            self.write(byte);
+        }
+    }
+}
+
+impl DeviceRx for TcpDevice {
     fn read(&self) -> u8 {
-        self.other_side.device.outgoing
+        self.last_byte() & 0x0f
     }
 
-    fn debug_poll(&mut self) {
-        self.other_side.poll();
+    fn read_clock(&self) -> Option<bool> {
+        self.clock.map(|_| self.last_byte() >> 4 & 1 == 1)
+    }
+
+    fn read_many(&self, buffer: &mut [u8]) -> usize {
+        let len = match (&self.stream).read(buffer) {
+            Ok(len @ 1..) => len,
+            // nothing new has arrived, the lines keep their value
+            _ => return 0,
+        };
+        self.last_read.set(buffer[len - 1]);
+        buffer[..len].iter_mut().for_each(|byte| *byte &= 0x0f);
+        len
     }
 }
 
-pub struct MirrorDevice {
-    incoming: u8,
-    outgoing: u8,
+/// Loops back to a second connection in the same process, which is polled after every poll
+pub struct DebugDevice {
+    port: SimPort,
+    other_side: Connection<SimPort>,
 }
 
-impl MirrorDevice {
-    fn new() -> Self {
+impl DebugDevice {
+    pub fn new() -> Self {
+        let (port, other_port) = SimPort::pair(true);
         Self {
-            incoming: 0,
-            outgoing: 0,
+            port,
+            other_side: Connection::boxed(other_port, iter::empty()),
         }
     }
 }
 
-impl Device for MirrorDevice {
-    const NAME: &'static str = "Mirror";
+impl DeviceName for DebugDevice {
+    const NAME: &'static str = "Debug";
+}
 
+impl DeviceTx for DebugDevice {
     fn send(&mut self, data: u8) {
         eprintln!("{} {:04b}", self.name(), data);
-        self.outgoing = data;
+        self.port.send(data);
     }
 
+    fn debug_poll(&mut self) {
+        self.other_side.poll();
+    }
+}
+
+impl DeviceRx for DebugDevice {
     fn read(&self) -> u8 {
-        self.incoming
+        self.port.read()
+    }
+
+    /// Both sides are polled in turn, so every read is a new nibble
+    fn detects_edges(&self) -> bool {
+        self.port.detects_edges()
     }
 }
+
+#[test]
+fn tcp_nibbles_buffered_until_flush() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let mut sender = TcpDevice::open(&addr).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    stream.set_nonblocking(true).unwrap();
+    let receiver = TcpDevice {
+        stream,
+        last_read: Cell::new(0),
+        tx: Vec::new(),
+        clock: None,
+    };
+
+    sender.send_many(&[0x1, 0x2, 0x13]);
+    std::thread::sleep(Duration::from_millis(50));
+    let mut buffer = [0; 8];
+    assert_eq!(receiver.read_many(&mut buffer), 0);
+
+    sender.flush();
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(receiver.read_many(&mut buffer), 3);
+    assert_eq!(buffer[..3], [0x1, 0x2, 0x3]);
+}
@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use b15f::B15fDriver;
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin, PinState};
+
+use super::{DeviceName, DeviceRx, DeviceTx};
+
+/// # HalDevice
+///
+/// Sends and reads nibbles using four embedded-hal pins in each direction.
+/// The pin at index 0 carries the least significant bit.
+///
+/// Pin errors can not be reported through [`DeviceTx`] and [`DeviceRx`],
+/// so a pin that fails to be read is treated as low.
+pub struct HalDevice<O: OutputPin, I: InputPin> {
+    outputs: [O; 4],
+    // reading a pin requires mutable access
+    inputs: RefCell<[I; 4]>,
+}
+
+impl<O: OutputPin, I: InputPin> HalDevice<O, I> {
+    pub fn new(outputs: [O; 4], inputs: [I; 4]) -> Self {
+        Self {
+            outputs,
+            inputs: RefCell::new(inputs),
+        }
+    }
+}
+
+impl<O: OutputPin, I: InputPin> DeviceName for HalDevice<O, I> {
+    const NAME: &'static str = "Hal";
+}
+
+impl<O: OutputPin, I: InputPin> DeviceTx for HalDevice<O, I> {
+    fn send(&mut self, data: u8) {
+        for (bit, pin) in self.outputs.iter_mut().enumerate() {
+            let _ = pin.set_state(PinState::from(data >> bit & 1 == 1));
+        }
+    }
+}
+
+impl<O: OutputPin, I: InputPin> DeviceRx for HalDevice<O, I> {
+    fn read(&self) -> u8 {
+        self.inputs
+            .borrow_mut()
+            .iter_mut()
+            .enumerate()
+            .fold(0, |nibble, (bit, pin)| {
+                nibble | (pin.is_high().unwrap_or(false) as u8) << bit
+            })
+    }
+}
+
+/// Port A of a B15f, shared by its pins
+struct B15fPort {
+    driver: B15fDriver,
+    /// Value of PORTA, every pin that changes writes the whole register
+    output: u8,
+}
+
+/// # B15fPin
+///
+/// A pin of port A of the B15f as an embedded-hal pin, so that a [`HalDevice`]
+/// can be run on the lab boards with `--device b15f-hal`.
+///
+/// Like with [`super::B15fDevice`] the lower four pins are outputs,
+/// and the lower four bits of PINA are read. Every pin is a round trip to the board,
+/// so this is much slower than the [`super::B15fDevice`].
+pub struct B15fPin {
+    port: Rc<RefCell<B15fPort>>,
+    bit: u8,
+}
+
+impl B15fPin {
+    /// The four output pins and the four input pins of the board
+    pub fn open() -> Result<([Self; 4], [Self; 4]), &'static str> {
+        let mut driver = B15fDriver::new()?;
+        driver.set_register_ddra(0x0f);
+        driver.set_register_porta(0x00);
+        let port = Rc::new(RefCell::new(B15fPort { driver, output: 0 }));
+        let pins = || {
+            std::array::from_fn(|bit| Self {
+                port: Rc::clone(&port),
+                bit: bit as u8,
+            })
+        };
+        Ok((pins(), pins()))
+    }
+
+    fn set(&mut self, high: bool) {
+        let mut port = self.port.borrow_mut();
+        let output = port.output & !(1 << self.bit) | (high as u8) << self.bit;
+        if output != port.output {
+            port.output = output;
+            port.driver.set_register_porta(output);
+        }
+    }
+}
+
+impl ErrorType for B15fPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for B15fPin {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.set(true);
+        Ok(())
+    }
+}
+
+impl InputPin for B15fPin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.port.borrow().driver.get_register_pina() >> self.bit & 1 == 1)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+#[test]
+fn nibbles_over_pins() {
+    use embedded_hal::digital::ErrorKind;
+    use std::cell::Cell;
+
+    /// A line between pins, `None` once it is broken and can not be read anymore
+    #[derive(Clone, Default)]
+    struct Wire(Rc<Cell<Option<bool>>>);
+    impl ErrorType for Wire {
+        type Error = ErrorKind;
+    }
+    impl OutputPin for Wire {
+        fn set_low(&mut self) -> Result<(), ErrorKind> {
+            self.0.set(Some(false));
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), ErrorKind> {
+            self.0.set(Some(true));
+            Ok(())
+        }
+    }
+    impl InputPin for Wire {
+        fn is_high(&mut self) -> Result<bool, ErrorKind> {
+            self.0.get().ok_or(ErrorKind::Other)
+        }
+        fn is_low(&mut self) -> Result<bool, ErrorKind> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    // the outputs are wired to the inputs of the same device
+    let wires: [Wire; 4] = Default::default();
+    let mut device = HalDevice::new(wires.clone(), wires.clone());
+    for nibble in 0..16 {
+        device.send(nibble);
+        assert_eq!(device.read(), nibble);
+    }
+    device.send(0b0001);
+    let levels: Vec<_> = wires.iter().map(|wire| wire.0.get()).collect();
+    assert_eq!(levels, [Some(true), Some(false), Some(false), Some(false)]);
+
+    // a pin that can not be read counts as low
+    device.send(0xf);
+    wires[1].0.set(None);
+    assert_eq!(device.read(), 0b1101);
+}
@@ -0,0 +1,127 @@
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use super::{DeviceName, DeviceRx, DeviceTx};
+
+#[cfg(target_os = "linux")]
+const O_NONBLOCK: i32 = 0o4000;
+#[cfg(not(target_os = "linux"))]
+const O_NONBLOCK: i32 = 0x0004;
+
+enum Pipe {
+    Fifo { rx: File, tx: File },
+    Socket(UnixStream),
+}
+
+/// # PipeDevice
+///
+/// Emulates the patch cable between two local processes, over a pair of named pipes
+/// or a unix domain socket. Like [`super::TcpDevice`], every nibble is a single byte
+/// and reading returns the last value the other side has sent.
+pub struct PipeDevice {
+    pipe: Pipe,
+    last_read: Cell<u8>,
+}
+
+impl PipeDevice {
+    /// Opens `fifo:RX,TX`, `unix:PATH` or `unix-listen:PATH`,
+    /// returns `None` if the spec does not name a pipe.
+    ///
+    /// The FIFOs have to exist already, e.g. created with `mkfifo`,
+    /// the other side uses them the other way around.
+    pub fn open(spec: &str) -> Option<io::Result<Self>> {
+        let pipe = if let Some(paths) = spec.strip_prefix("fifo:") {
+            let (rx, tx) = paths.split_once(',')?;
+            Self::open_fifos(rx, tx)
+        } else if let Some(path) = spec.strip_prefix("unix-listen:") {
+            Self::listen(path)
+        } else if let Some(path) = spec.strip_prefix("unix:") {
+            UnixStream::connect(path).and_then(|stream| {
+                stream.set_nonblocking(true)?;
+                Ok(Pipe::Socket(stream))
+            })
+        } else {
+            return None;
+        };
+        Some(pipe.map(|pipe| Self {
+            pipe,
+            last_read: Cell::new(0),
+        }))
+    }
+
+    fn open_fifos(rx: &str, tx: &str) -> io::Result<Pipe> {
+        // opening a FIFO blocks until the other end is opened too, unless it is
+        // non-blocking, so both sides open their reading end first to not deadlock
+        let rx = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_NONBLOCK)
+            .open(rx)?;
+        let tx = OpenOptions::new().write(true).open(tx)?;
+        Ok(Pipe::Fifo { rx, tx })
+    }
+
+    fn listen(path: &str) -> io::Result<Pipe> {
+        // left behind by an earlier run
+        let _ = std::fs::remove_file(path);
+        let stream = UnixListener::bind(path)?.accept()?.0;
+        stream.set_nonblocking(true)?;
+        Ok(Pipe::Socket(stream))
+    }
+
+    /// The last byte the other side has sent
+    fn last_byte(&self) -> u8 {
+        let mut buffer = [0; 64];
+        loop {
+            let read = match &self.pipe {
+                Pipe::Fifo { rx, .. } => (&*rx).read(&mut buffer),
+                Pipe::Socket(stream) => (&*stream).read(&mut buffer),
+            };
+            match read {
+                Ok(len @ 1..) => self.last_read.set(buffer[len - 1]),
+                _ => return self.last_read.get(),
+            }
+        }
+    }
+}
+
+impl DeviceName for PipeDevice {
+    const NAME: &'static str = "Pipe";
+}
+
+impl DeviceTx for PipeDevice {
+    fn send(&mut self, data: u8) {
+        let byte = [data & 0x0f];
+        // the other side has gone away, which is noticed by the protocol
+        let _ = match &mut self.pipe {
+            Pipe::Fifo { tx, .. } => tx.write_all(&byte),
+            Pipe::Socket(stream) => stream.write_all(&byte),
+        };
+    }
+}
+
+impl DeviceRx for PipeDevice {
+    fn read(&self) -> u8 {
+        self.last_byte() & 0x0f
+    }
+}
+
+#[test]
+fn socket_pair() {
+    let (first, second) = UnixStream::pair().unwrap();
+    let mut devices = [first, second].map(|stream| {
+        stream.set_nonblocking(true).unwrap();
+        PipeDevice {
+            pipe: Pipe::Socket(stream),
+            last_read: Cell::new(0),
+        }
+    });
+    devices[0].send(0x3);
+    devices[0].send(0xa);
+    assert_eq!(devices[1].read(), 0xa);
+    // keeps the value, like the cable
+    assert_eq!(devices[1].read(), 0xa);
+    assert_eq!(devices[0].read(), 0x0);
+}
@@ -0,0 +1,207 @@
+use crate::stream::{InputState, InputStream};
+
+use super::{DeviceName, DeviceRx, DeviceTx};
+
+/// Behavior of foreign implementations, that the protocol does not ask for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// How often every nibble is written again, invisible on a cable where only changes count
+    pub repeat_nibbles: u8,
+    /// Pairs of idle symbols that are added to every idle pair, which lengthens idle periods
+    pub extra_idle: u8,
+}
+
+impl Quirks {
+    /// The Arduino firmware of our partner group
+    pub const PARTNER: Self = Self {
+        repeat_nibbles: 1,
+        extra_idle: 1,
+    };
+
+    /// Parses "partner", "none" or flags like "repeat=1,idle=2"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "partner" => Some(Self::PARTNER),
+            "none" => Some(Self::default()),
+            _ => {
+                let mut quirks = Self::default();
+                for flag in name.split(',') {
+                    let (flag, value) = flag.split_once('=')?;
+                    let value = value.parse().ok()?;
+                    match flag {
+                        "repeat" => quirks.repeat_nibbles = value,
+                        "idle" => quirks.extra_idle = value,
+                        _ => return None,
+                    }
+                }
+                Some(quirks)
+            }
+        }
+    }
+}
+
+/// # QuirksDevice
+///
+/// Sends like a foreign implementation would, so that interop can be tested
+/// without its hardware, see [`Quirks`].
+///
+/// The sent nibbles are decoded again, so that idle symbols are only added
+/// outside of frames. Only the alternating idle pattern is recognized.
+pub struct QuirksDevice<D: DeviceTx + DeviceRx> {
+    device: D,
+    quirks: Quirks,
+    /// Decodes what has been sent, to tell idle symbols from data
+    sent: InputStream,
+    last: Option<u8>,
+}
+
+impl<D: DeviceTx + DeviceRx> QuirksDevice<D> {
+    pub fn new(device: D, quirks: Quirks) -> Self {
+        let mut sent = InputStream::new();
+        sent.set_edge_detection(device.detects_edges());
+        Self {
+            device,
+            quirks,
+            sent,
+            last: None,
+        }
+    }
+}
+
+impl<D: DeviceTx + DeviceRx> DeviceName for QuirksDevice<D> {
+    const NAME: &'static str = "Quirks";
+}
+
+impl<D: DeviceTx + DeviceRx> DeviceTx for QuirksDevice<D> {
+    fn send(&mut self, data: u8) {
+        let data = data & 0x0f;
+        for _ in 0..=self.quirks.repeat_nibbles {
+            self.device.send(data);
+        }
+        self.sent.push(data);
+
+        let idle_pair = self.last == Some(0xf) && data == 0x0;
+        self.last = Some(data);
+        if !idle_pair || !matches!(self.sent.state(), InputState::WaitingForFrame) {
+            return;
+        }
+        for _ in 0..self.quirks.extra_idle {
+            for nibble in [0xf, 0x0] {
+                self.device.send(nibble);
+                self.sent.push(nibble);
+            }
+        }
+    }
+
+    fn send_clock(&mut self, level: bool) {
+        self.device.send_clock(level);
+    }
+
+    fn max_rate_hz(&self) -> Option<u32> {
+        self.device.max_rate_hz()
+    }
+
+    fn flush(&mut self) {
+        self.device.flush();
+    }
+
+    fn release(&mut self) {
+        self.device.release();
+    }
+
+    fn debug_poll(&mut self) {
+        self.device.debug_poll();
+    }
+}
+
+impl<D: DeviceTx + DeviceRx> DeviceRx for QuirksDevice<D> {
+    fn read(&self) -> u8 {
+        self.device.read()
+    }
+
+    fn read_clock(&self) -> Option<bool> {
+        self.device.read_clock()
+    }
+
+    fn detects_edges(&self) -> bool {
+        self.device.detects_edges()
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Recorder {
+    sent: Vec<u8>,
+}
+
+#[cfg(test)]
+impl DeviceName for Recorder {
+    const NAME: &'static str = "Recorder";
+}
+
+#[cfg(test)]
+impl DeviceTx for Recorder {
+    fn send(&mut self, data: u8) {
+        self.sent.push(data);
+    }
+}
+
+#[cfg(test)]
+impl DeviceRx for Recorder {
+    fn read(&self) -> u8 {
+        0
+    }
+}
+
+#[test]
+fn partner_quirks() {
+    use crate::conformance::wire_nibbles;
+    use crate::escape::EscapeCode;
+    use crate::stream::{FrameKind, InputEvent};
+
+    let mut device = QuirksDevice::new(Recorder::default(), Quirks::PARTNER);
+    for nibble in [0xf, 0x0, 0x3, 0x4, 0xf, 0x0] {
+        device.send(nibble);
+    }
+    assert_eq!(
+        device.device.sent,
+        [0xf, 0xf, 0x0, 0x0, 0xf, 0x0, 0x3, 0x3, 0x4, 0x4, 0xf, 0xf, 0x0, 0x0, 0xf, 0x0]
+    );
+
+    // idle symbols are not added inside of a frame, even to data that looks like them
+    let mut bytes = vec![EscapeCode::StartOfMiniFrame as u8];
+    bytes.extend((0..crate::MINI_FRAME_DATA_LEN as u8).map(|index| 0xf0 | index));
+    bytes.extend([EscapeCode::EndOfFrame as u8, 0xf0, 0xf0]);
+    let mut device = QuirksDevice::new(Recorder::default(), Quirks::PARTNER);
+    for nibble in wire_nibbles(&bytes) {
+        device.send(nibble);
+    }
+
+    // a cable only shows changes, so the other side still receives the frame
+    let mut input_stream = InputStream::new();
+    let events: Vec<InputEvent> = device
+        .device
+        .sent
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .filter(|event| *event != InputEvent::LinkIdle)
+        .collect();
+    let [InputEvent::DataFrame {
+        kind: FrameKind::Mini,
+        payload: frame,
+        ..
+    }] = events.as_slice()
+    else {
+        panic!("{events:?}");
+    };
+    assert_eq!(frame[..crate::MINI_FRAME_DATA_LEN], bytes[1..=crate::MINI_FRAME_DATA_LEN]);
+
+    assert_eq!(
+        Quirks::from_name("repeat=2,idle=0"),
+        Some(Quirks {
+            repeat_nibbles: 2,
+            extra_idle: 0,
+        })
+    );
+    assert_eq!(Quirks::from_name("repeat=2,flip=1"), None);
+}
@@ -0,0 +1,217 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+/// Lines that can be queued for stderr before new lines are dropped
+const BACKGROUND_LINES: usize = 4096;
+
+/// # BackgroundWriter
+///
+/// Writes complete lines from a background thread, so that a slow terminal
+/// never holds up the poll loop and logging does not change the wire timing.
+///
+/// The lines are queued in a bounded channel, which is a lock-free ring buffer,
+/// if it is full lines are dropped and counted instead of waiting.
+/// Dropping the writer waits until every queued line has been written.
+pub struct BackgroundWriter {
+    sender: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    /// The line that has not been completed yet
+    line: Vec<u8>,
+    /// Number of lines that have been dropped since the last one that could be queued
+    dropped: u64,
+}
+
+impl BackgroundWriter {
+    pub fn stderr() -> Self {
+        Self::spawn(io::stderr(), BACKGROUND_LINES)
+    }
+
+    pub fn spawn(mut output: impl Write + Send + 'static, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(capacity);
+        let thread = thread::spawn(move || {
+            for line in receiver {
+                let _ = output.write_all(&line);
+            }
+            let _ = output.flush();
+        });
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            line: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Queues the current line, without waiting for room
+    fn queue_line(&mut self) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let mut line = std::mem::take(&mut self.line);
+        if self.dropped > 0 {
+            let mut note = format!("({} log lines dropped)\n", self.dropped).into_bytes();
+            note.append(&mut line);
+            line = note;
+        }
+        match sender.try_send(line) {
+            Ok(()) => self.dropped = 0,
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            Err(TrySendError::Disconnected(_)) => self.sender = None,
+        }
+    }
+}
+
+impl Write for BackgroundWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|&byte| byte == b'\n') {
+            self.line.extend_from_slice(line);
+            if line.ends_with(b"\n") {
+                self.queue_line();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.queue_line();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        if let Some(sender) = self.sender.take().filter(|_| self.dropped > 0) {
+            // nothing is timing critical anymore, so this may wait
+            let _ = sender.send(format!("({} log lines dropped)\n", self.dropped).into_bytes());
+        }
+        // ends the thread once it has written everything
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// # Log
+///
+/// Structured log of what happens on the line.
+///
+/// Consecutive idle ticks (keep-alive and buffer symbols) are not logged one by one,
+/// but coalesced into a single "idle for N ticks" entry,
+/// which is written once the next event happens.
+pub struct Log<W: Write = BackgroundWriter> {
+    output: W,
+    tick: u64,
+    /// Number of idle ticks that have not been written yet
+    idle_ticks: u64,
+}
+
+impl Log {
+    pub fn new() -> Self {
+        Self::with_output(BackgroundWriter::stderr())
+    }
+}
+
+impl<W: Write> Log<W> {
+    pub fn with_output(output: W) -> Self {
+        Self {
+            output,
+            tick: 0,
+            idle_ticks: 0,
+        }
+    }
+
+    pub fn idle(&mut self) {
+        self.tick += 1;
+        self.idle_ticks += 1;
+    }
+
+    /// A tick in which something happened, that is not worth logging
+    pub fn busy(&mut self) {
+        self.flush_idle();
+        self.tick += 1;
+    }
+
+    pub fn event(&mut self, message: impl Display) {
+        self.flush_idle();
+        let _ = writeln!(self.output, "[{:>8}] {message}", self.tick);
+        self.tick += 1;
+    }
+
+    /// Writes text that spans whole lines as it is, like a rendered timeline
+    pub fn text(&mut self, text: impl Display) {
+        self.flush_idle();
+        let _ = write!(self.output, "{text}");
+    }
+
+    /// Writes the pending idle entry, if there is one
+    pub fn flush_idle(&mut self) {
+        if self.idle_ticks == 0 {
+            return;
+        }
+        let start = self.tick - self.idle_ticks;
+        let _ = writeln!(
+            self.output,
+            "[{start:>8}] idle for {} ticks",
+            self.idle_ticks
+        );
+        self.idle_ticks = 0;
+    }
+}
+
+#[test]
+fn coalesce_idle_ticks() {
+    let mut log = Log::with_output(Vec::new());
+    log.event("start");
+    for _ in 0..5 {
+        log.idle();
+    }
+    log.event("frame");
+    log.idle();
+    log.flush_idle();
+
+    assert_eq!(
+        String::from_utf8(log.output).unwrap(),
+        "[       0] start\n[       1] idle for 5 ticks\n[       6] frame\n[       7] idle for 1 ticks\n"
+    );
+}
+
+#[test]
+fn background_writer_drops_instead_of_blocking() {
+    use std::sync::{Arc, Mutex};
+
+    /// Blocks every write until the test lets it go
+    struct Gate(Arc<Mutex<Vec<u8>>>, Arc<Mutex<()>>);
+    impl Write for Gate {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _open = self.1.lock().unwrap();
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let gate = Arc::new(Mutex::new(()));
+    let closed = gate.lock().unwrap();
+    let mut writer = BackgroundWriter::spawn(Gate(written.clone(), gate.clone()), 2);
+    // the thread takes at most one line out of the queue while the gate is closed
+    for line in 0..10 {
+        write!(writer, "line ").unwrap();
+        writeln!(writer, "{line}").unwrap();
+    }
+    assert!(writer.dropped > 0);
+    drop(closed);
+    drop(writer);
+
+    let written = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+    assert!(written.starts_with("line 0\n"));
+    assert!(written.ends_with("log lines dropped)\n"));
+}
@@ -0,0 +1,253 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use crate::event::{Event, EventError};
+use crate::session::{self, Decision, Entry};
+
+/// Vertical space for every message
+const SVG_ROW: usize = 24;
+/// Space above the first message for the names of the lifelines
+const SVG_HEADER: usize = 40;
+const SVG_WIDTH: usize = 520;
+/// Horizontal positions of our lifeline and the one of the other side
+const SVG_LOCAL: usize = 140;
+const SVG_PEER: usize = 460;
+
+/// An arrow between both lifelines or a note next to them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// From us to the other side, `retransmit` if the frame has been sent before
+    Sent { label: String, retransmit: bool },
+    /// From the other side to us, `broken` if it could not be decoded
+    Received { label: String, broken: bool },
+    Note(String),
+}
+
+impl Message {
+    fn sent(label: impl Into<String>) -> Self {
+        Self::Sent {
+            label: label.into(),
+            retransmit: false,
+        }
+    }
+
+    fn received(label: impl Into<String>) -> Self {
+        Self::Received {
+            label: label.into(),
+            broken: false,
+        }
+    }
+
+    fn broken(label: impl Into<String>) -> Self {
+        Self::Received {
+            label: label.into(),
+            broken: true,
+        }
+    }
+}
+
+/// The arrows a decision stands for, the session log only records the side that wrote it,
+/// so what the other side sent is inferred from how the connection reacted to it
+pub fn messages(decision: &Decision) -> Vec<Message> {
+    match *decision {
+        Decision::Event(Event::FrameSent { seq }) => vec![Message::sent(format!("frame {seq}"))],
+        Decision::Event(Event::Resend { seq, retries }) => vec![
+            Message::received("IFD"),
+            Message::Sent {
+                label: format!("frame {seq} (retry {retries})"),
+                retransmit: true,
+            },
+        ],
+        Decision::Event(Event::Acked { seq }) => vec![Message::received(format!("CFD {seq}"))],
+        Decision::Event(Event::Received { seq, len }) => vec![
+            Message::received(format!("frame {seq} ({len} bytes)")),
+            Message::sent("CFD"),
+        ],
+        Decision::Event(Event::Error(EventError::ChecksumMismatch { seq })) => {
+            vec![Message::broken(format!("frame {seq}")), Message::sent("IFD")]
+        }
+        Decision::Event(Event::Error(EventError::FrameOverrun)) => {
+            vec![Message::broken("overlong frame"), Message::sent("IFD")]
+        }
+        Decision::Event(Event::Error(EventError::MalformedFrame)) => {
+            vec![Message::broken("incomplete frame"), Message::sent("IFD")]
+        }
+        Decision::Event(Event::Error(EventError::InvalidEcho)) => vec![Message::broken("echo")],
+        Decision::Event(Event::Error(EventError::UnknownSession)) => vec![
+            Message::Note("unknown session".into()),
+            Message::sent("ABT"),
+        ],
+        Decision::Event(Event::Error(EventError::FeatureMismatch)) => vec![
+            Message::Note("different features".into()),
+            Message::sent("ABT"),
+        ],
+        Decision::Event(Event::Error(EventError::SinkFailed)) => vec![
+            Message::Note("could not write data".into()),
+            Message::sent("ABT"),
+        ],
+        Decision::Event(Event::EchoRequest { seq }) => vec![
+            Message::received(format!("echo {seq}")),
+            Message::sent(format!("echo reply {seq}")),
+        ],
+        Decision::Event(Event::EchoReply { seq }) => {
+            vec![Message::received(format!("echo reply {seq}"))]
+        }
+        Decision::Event(Event::PriorityReceived { len }) => {
+            vec![Message::received(format!("priority ({len} bytes)"))]
+        }
+        Decision::Event(Event::PeerFinished) => vec![Message::received("FS")],
+        Decision::Event(Event::Aborted) => vec![Message::received("ABT")],
+        Decision::Event(Event::Cancelled) => vec![Message::sent("ABT")],
+        Decision::Event(Event::FrameSizeChanged { len }) => {
+            vec![Message::received(format!("SFS {len}"))]
+        }
+        Decision::RequestFrameSize { len, errors } => vec![
+            Message::Note(format!("{errors} broken frames in a row")),
+            Message::sent(format!("SFS {len}")),
+        ],
+        Decision::Stalled { polls } => vec![Message::Note(format!("stalled for {polls} polls"))],
+    }
+}
+
+/// # SequenceChart
+///
+/// Message sequence chart of a session log, with our lifeline on the left
+/// and the one of the other side on the right, for lab reports.
+///
+/// Retransmitted frames are dashed, frames that arrived broken are red.
+#[derive(Debug, Default)]
+pub struct SequenceChart {
+    /// Every message with the time since the session started
+    rows: Vec<(Duration, Message)>,
+    /// Numbers of the lines that could not be read
+    skipped: Vec<usize>,
+}
+
+impl SequenceChart {
+    pub fn from_log(log: &str) -> Self {
+        let mut chart = Self::default();
+        for (number, line) in log.lines().enumerate() {
+            let Some(Entry {
+                elapsed, decision, ..
+            }) = session::parse_entry(line)
+            else {
+                chart.skipped.push(number + 1);
+                continue;
+            };
+            for message in messages(&decision) {
+                chart.rows.push((elapsed, message));
+            }
+        }
+        chart
+    }
+
+    #[cfg(test)]
+    pub fn messages(&self) -> impl Iterator<Item = &Message> {
+        self.rows.iter().map(|(_, message)| message)
+    }
+
+    /// Line numbers of the session log that have been skipped
+    pub fn skipped(&self) -> &[usize] {
+        &self.skipped
+    }
+
+    pub fn render_svg(&self) -> String {
+        let height = SVG_HEADER + (self.rows.len() + 1) * SVG_ROW;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{SVG_WIDTH}\" height=\"{height}\" font-size=\"12\">\n"
+        );
+        let _ = writeln!(
+            svg,
+            "<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\"><path d=\"M0,0 L10,5 L0,10 z\"/></marker></defs>"
+        );
+
+        for (x, name) in [(SVG_LOCAL, "local"), (SVG_PEER, "peer")] {
+            let _ = writeln!(
+                svg,
+                "<text x=\"{x}\" y=\"20\" text-anchor=\"middle\">{name}</text>"
+            );
+            let _ = writeln!(
+                svg,
+                "<line x1=\"{x}\" y1=\"28\" x2=\"{x}\" y2=\"{height}\" stroke=\"gray\"/>"
+            );
+        }
+
+        for (row, (elapsed, message)) in self.rows.iter().enumerate() {
+            let y = SVG_HEADER + (row + 1) * SVG_ROW;
+            let _ = writeln!(
+                svg,
+                "<text x=\"4\" y=\"{y}\" fill=\"gray\">{elapsed:.3?}</text>"
+            );
+            let (from, to, label, style) = match message {
+                Message::Sent { label, retransmit } => {
+                    let style = if *retransmit {
+                        "stroke=\"black\" stroke-dasharray=\"6,3\""
+                    } else {
+                        "stroke=\"black\""
+                    };
+                    (SVG_LOCAL, SVG_PEER, label, style)
+                }
+                Message::Received { label, broken } => {
+                    let style = if *broken {
+                        "stroke=\"red\""
+                    } else {
+                        "stroke=\"black\""
+                    };
+                    (SVG_PEER, SVG_LOCAL, label, style)
+                }
+                Message::Note(note) => {
+                    let _ = writeln!(
+                        svg,
+                        "<text x=\"{}\" y=\"{y}\" text-anchor=\"middle\" font-style=\"italic\">{note}</text>",
+                        (SVG_LOCAL + SVG_PEER) / 2
+                    );
+                    continue;
+                }
+            };
+            let _ = writeln!(
+                svg,
+                "<line x1=\"{from}\" y1=\"{y}\" x2=\"{to}\" y2=\"{y}\" {style} marker-end=\"url(#arrow)\"/>"
+            );
+            let _ = writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{label}</text>",
+                (SVG_LOCAL + SVG_PEER) / 2,
+                y - 4
+            );
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+#[test]
+fn sequence_chart_of_session_log() {
+    let log = "\
+        100 tx=1 rx=0 sent seq=1\n\
+        900 tx=1 rx=0 resend seq=1 retries=1\n\
+        1700 tx=1 rx=0 acked seq=1\n\
+        2000 tx=1 rx=1 checksum-mismatch seq=1\n\
+        not a session log line\n";
+    let chart = SequenceChart::from_log(log);
+    assert_eq!(chart.skipped(), [5]);
+    assert_eq!(
+        chart.messages().cloned().collect::<Vec<_>>(),
+        [
+            Message::sent("frame 1"),
+            Message::received("IFD"),
+            Message::Sent {
+                label: "frame 1 (retry 1)".into(),
+                retransmit: true,
+            },
+            Message::received("CFD 1"),
+            Message::broken("frame 1"),
+            Message::sent("IFD"),
+        ]
+    );
+
+    let svg = chart.render_svg();
+    assert_eq!(svg.matches("<line").count(), 2 + 6);
+    assert_eq!(svg.matches("stroke-dasharray").count(), 1);
+    assert_eq!(svg.matches("stroke=\"red\"").count(), 1);
+}
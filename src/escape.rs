@@ -1,6 +1,9 @@
+use std::fmt::Display;
 use std::io;
 
-#[derive(Debug, PartialEq, Eq)]
+use crate::source::DataSource;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum EscapeCode {
     /// SOF
@@ -17,10 +20,18 @@ pub enum EscapeCode {
     Buffer2 = 0x65,
     // FS
     FinishedSending = 0x67,
+    /// SOE, starts a frame that is echoed back by the other side
+    StartOfEcho = 0x78,
+    /// ABT, discard everything and start over
+    Abort = 0x89,
+    /// SFS, starts a frame containing the frame size the receiver wants to get
+    SetFrameSize = 0x9a,
+    /// SOM, starts a data frame with only [`crate::MINI_FRAME_DATA_LEN`] data bytes
+    StartOfMiniFrame = 0xde,
 }
 
 impl EscapeCode {
-    const VALUES: [u8; 7] = [
+    const VALUES: [u8; 11] = [
         Self::StartOfFrame as u8,
         Self::EndOfFrame as u8,
         Self::CorrectFrameData as u8,
@@ -28,13 +39,92 @@ impl EscapeCode {
         Self::Buffer1 as u8,
         Self::Buffer2 as u8,
         Self::FinishedSending as u8,
+        Self::StartOfEcho as u8,
+        Self::Abort as u8,
+        Self::SetFrameSize as u8,
+        Self::StartOfMiniFrame as u8,
     ];
 
     pub fn from_byte(byte: u8) -> Option<Self> {
-        Self::VALUES.contains(&byte).then_some(
+        Self::VALUES.contains(&byte).then(|| {
             /* SAFETY: byte is a valid escape code */
-            unsafe { std::mem::transmute(byte) },
-        )
+            unsafe { std::mem::transmute::<u8, Self>(byte) }
+        })
+    }
+
+    /// Short name like SOF, as used in the protocol description
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            Self::StartOfFrame => "SOF",
+            Self::EndOfFrame => "EOF",
+            Self::CorrectFrameData => "CFD",
+            Self::IncorrectFrameData => "IFD",
+            Self::Buffer1 => "BU1",
+            Self::Buffer2 => "BU2",
+            Self::FinishedSending => "FS",
+            Self::StartOfEcho => "SOE",
+            Self::Abort => "ABT",
+            Self::SetFrameSize => "SFS",
+            Self::StartOfMiniFrame => "SOM",
+        }
+    }
+}
+
+/// # EscapeStats
+///
+/// How often every escape code has been received, to notice an encoder that never
+/// sends a code, or data that keeps looking like one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EscapeStats {
+    /// Decoded as the escape code, also where it was not expected
+    seen: [u32; EscapeCode::VALUES.len()],
+    /// Decoded as a data byte with the value of the escape code, which has been repeated
+    escaped: [u32; EscapeCode::VALUES.len()],
+    /// Taken for data because it started in the middle of a byte,
+    /// or dropped because it arrived outside of a frame
+    misparsed: [u32; EscapeCode::VALUES.len()],
+}
+
+impl EscapeStats {
+    fn index(byte: u8) -> Option<usize> {
+        EscapeCode::VALUES.iter().position(|value| *value == byte)
+    }
+
+    pub fn record_seen(&mut self, escape_code: EscapeCode) {
+        if let Some(index) = Self::index(escape_code as u8) {
+            self.seen[index] += 1;
+        }
+    }
+
+    pub fn record_escaped(&mut self, byte: u8) {
+        if let Some(index) = Self::index(byte) {
+            self.escaped[index] += 1;
+        }
+    }
+
+    pub fn record_misparsed(&mut self, escape_code: EscapeCode) {
+        if let Some(index) = Self::index(escape_code as u8) {
+            self.misparsed[index] += 1;
+        }
+    }
+
+    /// How often the escape code has been seen, escaped and misparsed
+    pub fn counts(&self, escape_code: EscapeCode) -> [u32; 3] {
+        let index = Self::index(escape_code as u8).expect("every escape code has a value");
+        [self.seen[index], self.escaped[index], self.misparsed[index]]
+    }
+}
+
+impl Display for EscapeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "code {:>8} {:>8} {:>9}", "seen", "escaped", "misparsed")?;
+        for value in EscapeCode::VALUES {
+            let escape_code = EscapeCode::from_byte(value).expect("escape code value");
+            let [seen, escaped, misparsed] = self.counts(escape_code);
+            let name = escape_code.abbreviation();
+            writeln!(f, "{name:<4} {seen:>8} {escaped:>8} {misparsed:>9}")?;
+        }
+        Ok(())
     }
 }
 
@@ -42,6 +132,8 @@ pub struct Escaped<I: Iterator<Item = io::Result<u8>>> {
     bytes: I,
     /// Second half of an escaped value
     escape: Option<u8>,
+    /// Second half of an escaped value at the last checkpoint, see [`DataSource`]
+    checkpoint_escape: Option<u8>,
     done: bool,
 }
 
@@ -50,6 +142,7 @@ impl<I: Iterator<Item = io::Result<u8>>> Escaped<I> {
         Self {
             bytes,
             escape: None,
+            checkpoint_escape: None,
             done: false,
         }
     }
@@ -57,6 +150,20 @@ impl<I: Iterator<Item = io::Result<u8>>> Escaped<I> {
     pub fn is_done(&self) -> bool {
         self.done
     }
+
+    /// The source of the unescaped bytes
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.bytes
+    }
+
+    /// The next byte without escaping it, must not be mixed with [`Iterator::next`],
+    /// which might still have the second half of an escaped value to return
+    pub fn next_raw(&mut self) -> Option<io::Result<u8>> {
+        debug_assert!(self.escape.is_none());
+        let result = self.bytes.next();
+        self.done = result.is_some();
+        result
+    }
 }
 
 impl<I: Iterator<Item = io::Result<u8>>> Iterator for Escaped<I> {
@@ -70,7 +177,7 @@ impl<I: Iterator<Item = io::Result<u8>>> Iterator for Escaped<I> {
         let result = self.bytes.next().inspect(|maybe_byte| {
             if let Ok(byte) = maybe_byte {
                 // Repeat value of escape code to escape it
-                if EscapeCode::VALUES.contains(&byte) {
+                if EscapeCode::VALUES.contains(byte) {
                     self.escape = Some(*byte);
                 }
             }
@@ -79,3 +186,15 @@ impl<I: Iterator<Item = io::Result<u8>>> Iterator for Escaped<I> {
         result
     }
 }
+
+impl<I: DataSource> DataSource for Escaped<I> {
+    fn checkpoint(&mut self) {
+        self.bytes.checkpoint();
+        self.checkpoint_escape = self.escape;
+    }
+
+    fn rollback(&mut self) {
+        self.bytes.rollback();
+        self.escape = self.checkpoint_escape;
+    }
+}
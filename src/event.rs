@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+/// What happened on a [`crate::Connection`] during a poll.
+///
+/// Translated from the low level [`crate::stream::InputEvent`]s of the input stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A frame has been received and its data has been written to the sink
+    Received {
+        seq: u32,
+        len: usize,
+    },
+    /// The other side has acknowledged the frame
+    Acked {
+        seq: u32,
+    },
+    /// A new frame is being sent
+    FrameSent {
+        seq: u32,
+    },
+    /// The other side has requested the frame again
+    Resend {
+        seq: u32,
+        retries: u32,
+    },
+    EchoRequest {
+        seq: u32,
+    },
+    EchoReply {
+        seq: u32,
+    },
+    /// A priority message has been handed to the priority handler
+    PriorityReceived {
+        len: usize,
+    },
+    /// The other side will not send any more data
+    PeerFinished,
+    /// The other side has discarded everything and starts over
+    Aborted,
+    /// The connection has been cancelled and sends ABT to the other side
+    Cancelled,
+    /// The other side wants to receive frames with this many data bytes
+    FrameSizeChanged {
+        len: usize,
+    },
+    Error(EventError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventError {
+    /// The received frame was longer than allowed and has been dropped
+    FrameOverrun,
+    /// The received frame was incomplete or interrupted and has been dropped
+    MalformedFrame,
+    /// The checksum of the received frame did not match its data
+    ChecksumMismatch { seq: u32 },
+    /// An echo frame did not contain a valid echo
+    InvalidEcho,
+    /// The other side announced a different session than the one that has been resumed
+    UnknownSession,
+    /// The other side announced different [`crate::scramble::Features`] than ours
+    FeatureMismatch,
+    /// The received data could not be written, see [`crate::Connection::sink_error`]
+    SinkFailed,
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Received { seq, len } => write!(f, "received frame {seq} ({len} bytes)"),
+            Self::Acked { seq } => write!(f, "frame {seq} acknowledged"),
+            Self::FrameSent { seq } => write!(f, "sending frame {seq}"),
+            Self::Resend { seq, retries } => write!(f, "resending frame {seq} (retry {retries})"),
+            Self::EchoRequest { seq } => write!(f, "echo request {seq}"),
+            Self::EchoReply { seq } => write!(f, "echo reply {seq}"),
+            Self::PriorityReceived { len } => write!(f, "received priority message ({len} bytes)"),
+            Self::PeerFinished => write!(f, "other side finished sending"),
+            Self::Aborted => write!(f, "aborted by other side"),
+            Self::Cancelled => write!(f, "cancelled, aborting"),
+            Self::FrameSizeChanged { len } => write!(f, "frame size changed to {len} bytes"),
+            Self::Error(error) => write!(f, "error: {error}"),
+        }
+    }
+}
+
+impl Display for EventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FrameOverrun => write!(f, "frame overrun"),
+            Self::MalformedFrame => write!(f, "malformed frame"),
+            Self::ChecksumMismatch { seq } => write!(f, "checksum mismatch in frame {seq}"),
+            Self::InvalidEcho => write!(f, "invalid echo frame"),
+            Self::UnknownSession => write!(f, "other side belongs to another session"),
+            Self::FeatureMismatch => write!(f, "other side uses different features"),
+            Self::SinkFailed => write!(f, "could not write the received data"),
+        }
+    }
+}
+
+/// Which events are lost, when the [`EventQueue`] is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Keeps the latest events, so the application always sees the current state
+    #[default]
+    DropOldest,
+    /// Keeps the events the application has not seen yet, and loses the new ones
+    DropNewest,
+}
+
+impl Overflow {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "drop-oldest" => Some(Self::DropOldest),
+            "drop-newest" => Some(Self::DropNewest),
+            _ => None,
+        }
+    }
+}
+
+/// # EventQueue
+///
+/// Events of a [`crate::Connection`] that the application has not taken yet.
+///
+/// The poll loop never waits for the application, once the queue is full
+/// events are dropped according to the [`Overflow`] policy and counted.
+#[derive(Debug)]
+pub struct EventQueue {
+    events: VecDeque<Event>,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: u64,
+}
+
+impl Default for EventQueue {
+    fn default() -> Self {
+        Self::new(1024, Overflow::default())
+    }
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize, overflow: Overflow) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            overflow,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        if self.events.len() < self.capacity {
+            self.events.push_back(event);
+            return;
+        }
+        self.dropped += 1;
+        if self.overflow == Overflow::DropOldest {
+            self.events.pop_front();
+            self.events.push_back(event);
+        }
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = Event> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Events that have been lost, because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[test]
+fn event_queue_overflow() {
+    let acked = |seq| Event::Acked { seq };
+
+    let mut queue = EventQueue::new(2, Overflow::DropOldest);
+    (1..=3).for_each(|seq| queue.push(acked(seq)));
+    assert_eq!(queue.drain().collect::<Vec<_>>(), [acked(2), acked(3)]);
+    assert_eq!(queue.dropped(), 1);
+
+    let mut queue = EventQueue::new(2, Overflow::DropNewest);
+    (1..=3).for_each(|seq| queue.push(acked(seq)));
+    assert_eq!(queue.drain().collect::<Vec<_>>(), [acked(1), acked(2)]);
+    queue.push(acked(4));
+    assert_eq!(queue.drain().collect::<Vec<_>>(), [acked(4)]);
+    assert_eq!(queue.dropped(), 1);
+    assert_eq!(queue.drain().next(), None);
+
+    assert_eq!(Overflow::from_name("drop-newest"), Some(Overflow::DropNewest));
+    assert_eq!(Overflow::from_name("block"), None);
+}
@@ -0,0 +1,416 @@
+use crate::Frame;
+
+/// A fault that is injected into the next matching action of a [`crate::Connection`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Ignores the next CFD, as if it had been lost on the wire
+    DropAck,
+    /// Only sends the first `len` bytes of the next frame, without its EOF,
+    /// the whole frame is sent once it is resent
+    TruncateFrame { len: usize },
+    /// Waits this many polls before the next frame is resent
+    DelayRetransmission { polls: u32 },
+}
+
+/// # FaultInjector
+///
+/// Deliberately breaks the protocol, so that the recovery of the other side can be tested.
+/// Every fault only applies once, in the order they have been injected.
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    pending: Vec<Fault>,
+    injected: Vec<Fault>,
+    /// Polls until a delayed retransmission is sent
+    delay: Option<u32>,
+    /// The whole frame and its length, after it has been truncated
+    truncated: Option<(Frame, usize)>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inject(&mut self, fault: Fault) {
+        self.pending.push(fault);
+    }
+
+    /// Faults that have already been applied
+    pub fn injected(&self) -> &[Fault] {
+        &self.injected
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty() && self.delay.is_none()
+    }
+
+    fn take(&mut self, matches: impl Fn(&Fault) -> bool) -> Option<Fault> {
+        let index = self.pending.iter().position(matches)?;
+        let fault = self.pending.remove(index);
+        self.injected.push(fault);
+        Some(fault)
+    }
+
+    /// Applies pending frame faults to the encoded frame of `len` bytes,
+    /// returns the number of bytes that have to be sent.
+    pub fn frame(&mut self, frame: &mut Frame, len: usize) -> usize {
+        match self.take(|fault| matches!(fault, Fault::TruncateFrame { .. })) {
+            Some(Fault::TruncateFrame { len: truncated }) => {
+                self.truncated = Some((*frame, len));
+                truncated.min(len)
+            }
+            _ => len,
+        }
+    }
+
+    /// The whole frame, if the last one has been truncated
+    pub fn take_truncated(&mut self) -> Option<(Frame, usize)> {
+        self.truncated.take()
+    }
+
+    /// Whether the received CFD has to be ignored
+    pub fn drop_ack(&mut self) -> bool {
+        self.take(|fault| *fault == Fault::DropAck).is_some()
+    }
+
+    /// Whether the retransmission has to wait, it is released by [`FaultInjector::poll`]
+    pub fn delay_retransmission(&mut self) -> bool {
+        match self.take(|fault| matches!(fault, Fault::DelayRetransmission { .. })) {
+            Some(Fault::DelayRetransmission { polls }) => {
+                self.delay = Some(polls);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Counts down a delayed retransmission, returns true once it has to be sent
+    pub fn poll(&mut self) -> bool {
+        let Some(polls) = &mut self.delay else {
+            return false;
+        };
+        *polls = polls.saturating_sub(1);
+        let released = *polls == 0;
+        if released {
+            self.delay = None;
+        }
+        released
+    }
+}
+
+/// Replays a script of nibbles, one for every nibble that is sent
+struct ScriptedDevice {
+    script: std::vec::IntoIter<u8>,
+    current: u8,
+    sent: Vec<u8>,
+}
+
+impl crate::device::DeviceName for ScriptedDevice {
+    const NAME: &'static str = "Scripted";
+}
+
+impl crate::device::DeviceTx for ScriptedDevice {
+    fn send(&mut self, data: u8) {
+        self.sent.push(data);
+        if let Some(nibble) = self.script.next() {
+            self.current = nibble;
+        }
+    }
+}
+
+impl crate::device::DeviceRx for ScriptedDevice {
+    fn read(&self) -> u8 {
+        self.current
+    }
+}
+
+/// Runs a connection with one frame of data against the replies,
+/// each of them is followed by enough idle values to send a whole frame.
+///
+/// Returns the events with the index of the poll they happened in and the sent nibbles.
+fn run_faults(faults: &[Fault], replies: &[u8]) -> (Vec<(usize, crate::event::Event)>, Vec<u8>) {
+    const IDLE: [u8; 100] = [0xf0; 100];
+    let mut script = Vec::new();
+    for reply in replies {
+        script.push(*reply);
+        script.extend(IDLE);
+    }
+    let device = ScriptedDevice {
+        script: crate::conformance::wire_nibbles(&script).into_iter(),
+        current: 0x0,
+        sent: Vec::new(),
+    };
+    let data = (0..crate::FRAME_DATA_LEN).map(|index| Ok(0xc0 | (index % 16) as u8));
+    let mut connection = crate::Connection::new(device, data);
+    let mut injector = FaultInjector::new();
+    for fault in faults {
+        injector.inject(*fault);
+    }
+    connection.set_fault_injector(injector);
+
+    let mut events = Vec::new();
+    for poll in 0..(2 * IDLE.len() * (replies.len() + 1)) {
+        connection.poll();
+        events.extend(connection.events().iter().map(|event| (poll, *event)));
+    }
+    assert!(connection
+        .fault_injector()
+        .is_some_and(FaultInjector::is_done));
+    (events, connection.device.sent)
+}
+
+#[test]
+fn dropped_ack_and_delayed_retransmission() {
+    use crate::event::Event;
+    const CFD: u8 = crate::escape::EscapeCode::CorrectFrameData as u8;
+    const IFD: u8 = crate::escape::EscapeCode::IncorrectFrameData as u8;
+
+    // the first CFD is lost, so the frame is only sent after the second one
+    let (events, _) = run_faults(&[Fault::DropAck], &[CFD, CFD]);
+    let sent: Vec<_> = events
+        .iter()
+        .filter(|(_, event)| matches!(event, Event::FrameSent { .. }))
+        .collect();
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].0 > 2 * 100);
+
+    let resend_poll = |events: &[(usize, Event)]| {
+        events
+            .iter()
+            .find(|(_, event)| matches!(event, Event::Resend { .. }))
+            .map(|(poll, _)| *poll)
+            .expect("frame is resent")
+    };
+    let replies = [CFD, IFD];
+    let (events, _) = run_faults(&[], &replies);
+    let (delayed, _) = run_faults(&[Fault::DelayRetransmission { polls: 5 }], &replies);
+    assert_eq!(resend_poll(&delayed), resend_poll(&events) + 5);
+}
+
+#[test]
+fn truncated_frame_is_resent_whole() {
+    let data = (0..crate::FRAME_DATA_LEN).map(|index| Ok(0xc0 | (index % 16) as u8));
+    let (mut frame, len) = crate::encode_partial_frame(&mut crate::Escaped::new(data), 16).unwrap();
+    let whole = frame;
+
+    let mut injector = FaultInjector::new();
+    injector.inject(Fault::TruncateFrame { len: 10 });
+    assert_eq!(injector.frame(&mut frame, len), 10);
+    assert_eq!(injector.take_truncated(), Some((whole, len)));
+    // only applies once
+    assert_eq!(injector.frame(&mut frame, len), len);
+    assert_eq!(injector.injected(), [Fault::TruncateFrame { len: 10 }]);
+}
+
+#[test]
+fn nak_before_first_frame_resends_nothing() {
+    use crate::event::Event;
+    use crate::ping::Echo;
+    use crate::stream::{ControlMsg, InputEvent, InputStream};
+    const IFD: u8 = crate::escape::EscapeCode::IncorrectFrameData as u8;
+
+    // the NAK arrives after the echo has been sent, while no data frame has been sent yet
+    let mut script = vec![0xf0; 200];
+    script.push(IFD);
+    script.extend([0xf0; 200]);
+    let script = crate::conformance::wire_nibbles(&script);
+    let polls = script.len();
+    let device = ScriptedDevice {
+        script: script.into_iter(),
+        current: 0x0,
+        sent: Vec::new(),
+    };
+    let mut connection = crate::Connection::new(device, std::iter::empty());
+    let echo = Echo {
+        reply: false,
+        seq: 7,
+        timestamp: 0,
+    };
+    connection.send_echo(echo);
+
+    let mut resends = 0;
+    for _ in 0..polls {
+        connection.poll();
+        resends += connection
+            .events()
+            .iter()
+            .filter(|event| matches!(event, Event::Resend { .. }))
+            .count();
+    }
+    assert_eq!(resends, 0);
+
+    let mut i_stream = InputStream::new();
+    let echoes = connection
+        .device
+        .sent
+        .iter()
+        .filter(|nibble| match i_stream.push(**nibble) {
+            InputEvent::Control(ControlMsg::Echo(data)) => Echo::from_bytes(&data) == Some(echo),
+            _ => false,
+        })
+        .count();
+    assert_eq!(echoes, 1);
+}
+
+#[test]
+fn idle_peer_stalls_the_connection() {
+    // the idle pattern keeps being decoded, but the first frame is never requested
+    let script = crate::conformance::wire_nibbles(&[0xf0; crate::WATCHDOG_POLLS as usize]);
+    let device = ScriptedDevice {
+        script: script.into_iter(),
+        current: 0x0,
+        sent: Vec::new(),
+    };
+    let data = (0..crate::FRAME_DATA_LEN).map(|index| Ok(index as u8));
+    let mut connection = crate::Connection::new(device, data);
+
+    let mut polls = 0;
+    while connection.poll() {
+        polls += 1;
+        assert!(polls <= crate::WATCHDOG_POLLS, "the watchdog did not fire");
+    }
+    assert!(connection.is_stalled());
+}
+
+#[test]
+fn unreadable_source_aborts_the_transfer() {
+    const CFD: u8 = crate::escape::EscapeCode::CorrectFrameData as u8;
+
+    // the first frame is requested, but the source fails while it is encoded
+    let mut script = vec![0xf0; 4];
+    script.push(CFD);
+    script.extend([0xf0; 200]);
+    let script = crate::conformance::wire_nibbles(&script);
+    let polls = script.len();
+    let device = ScriptedDevice {
+        script: script.into_iter(),
+        current: 0x0,
+        sent: Vec::new(),
+    };
+    let data = [Ok(0xc0), Err(std::io::Error::other("unplugged"))];
+    let mut connection = crate::Connection::new(device, data.into_iter());
+
+    let mut running = true;
+    for _ in 0..polls {
+        running = connection.poll();
+        if !running {
+            break;
+        }
+    }
+    assert!(!running, "the transfer was not aborted");
+    assert_eq!(
+        connection.source_error().map(ToString::to_string).as_deref(),
+        Some("unplugged")
+    );
+}
+
+/// Receives the frames of a connection like the other side would,
+/// but answers a random share of the intact ones with IFD,
+/// so that they have to be resent.
+struct StormPeer {
+    i_stream: crate::stream::InputStream,
+    prbs: crate::soak::Prbs,
+    /// Chance out of 256 that a received frame is NAKed
+    nak_rate: u8,
+    /// Nibbles that are sent next, the last one is held afterwards
+    replies: std::collections::VecDeque<u8>,
+    current: u8,
+    /// Data of the accepted frames, in the order it would be written to the sink
+    accepted: Vec<u8>,
+    naks: u32,
+}
+
+impl StormPeer {
+    fn new(seed: u64, nak_rate: u8) -> Self {
+        const CFD: u8 = crate::escape::EscapeCode::CorrectFrameData as u8;
+        Self {
+            i_stream: crate::stream::InputStream::new(),
+            prbs: crate::soak::Prbs::new(seed),
+            nak_rate,
+            // requests the first frame
+            replies: crate::conformance::wire_nibbles(&[0xf0, CFD, 0xf0]).into(),
+            current: 0x0,
+            accepted: Vec::new(),
+            naks: 0,
+        }
+    }
+}
+
+impl crate::device::DeviceName for StormPeer {
+    const NAME: &'static str = "Storm";
+}
+
+impl crate::device::DeviceTx for StormPeer {
+    fn send(&mut self, data: u8) {
+        use crate::escape::EscapeCode;
+        use crate::stream::InputEvent;
+
+        match self.i_stream.push(data) {
+            InputEvent::DataFrame { payload, .. } => {
+                let ack = if self.prbs.next_byte() < self.nak_rate {
+                    self.naks += 1;
+                    EscapeCode::IncorrectFrameData
+                } else {
+                    self.accepted.extend_from_slice(&payload);
+                    EscapeCode::CorrectFrameData
+                };
+                // the reply is delayed by a random number of idle bytes
+                let mut reply = vec![0xf0; (self.prbs.next_byte() % 8) as usize];
+                reply.extend([ack as u8, 0xf0]);
+                self.replies.extend(crate::conformance::wire_nibbles(&reply));
+            }
+            // the connection asks for a first frame as well, the peer has none to send
+            InputEvent::LinkIdle | InputEvent::Ack { seq: 0 } => (),
+            event => panic!("intact frames are never rejected: {event:?}"),
+        }
+        if let Some(nibble) = self.replies.pop_front() {
+            self.current = nibble;
+        }
+    }
+}
+
+impl crate::device::DeviceRx for StormPeer {
+    fn read(&self) -> u8 {
+        self.current
+    }
+}
+
+#[test]
+fn in_order_delivery_under_retransmission_storm() {
+    use crate::event::Event;
+    use crate::soak::Prbs;
+
+    for seed in 1..=1_000u64 {
+        let mut prbs = Prbs::new(seed);
+        let source: Vec<u8> = std::iter::repeat_with(|| prbs.next_byte())
+            .take(1 + (seed as usize * 37) % 300)
+            .collect();
+        // NAKs up to half of the frames
+        let nak_rate = (seed % 129) as u8;
+        let peer = StormPeer::new(seed, nak_rate);
+        let mut connection = crate::Connection::new(peer, source.clone().into_iter().map(Ok));
+        // only the NAKs of the peer may cause resends, however slow the test runs
+        connection.set_ack_timeout(crate::rtt::AckTimeout::Off);
+
+        let mut resends = 0;
+        let mut polls = 0;
+        while connection.device.accepted.len() < source.len() {
+            connection.poll();
+            resends += connection
+                .events()
+                .iter()
+                .filter(|event| matches!(event, Event::Resend { .. }))
+                .count() as u32;
+            polls += 1;
+            assert!(polls < 1_000_000, "seed {seed}: transfer did not finish");
+        }
+
+        let peer = &connection.device;
+        let (received, padding) = peer.accepted.split_at(source.len());
+        assert_eq!(received, source, "seed {seed}, nak rate {nak_rate}");
+        // the last frame is filled up with zeros
+        assert!(padding.iter().all(|byte| *byte == 0), "seed {seed}");
+        // every NAK has been answered by resending the same frame
+        assert_eq!(resends, peer.naks, "seed {seed}");
+    }
+}
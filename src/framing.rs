@@ -0,0 +1,102 @@
+use std::fmt::Debug;
+
+use crate::bits;
+use crate::escape::EscapeCode;
+
+/// # Framing
+///
+/// How frames are told apart from the data on the wire.
+///
+/// Every frame is wrapped in escape codes and a value equal to an escape code
+/// is followed by a second byte, that marks it as data. The streams of a
+/// [`crate::Connection`] ask the framing for that byte when sending and receiving.
+pub trait Framing: Debug {
+    /// The byte that is sent after a value equal to an escape code, to mark it as data
+    fn escaped(&self, byte: u8) -> u8;
+
+    /// Replaces the second byte of every escaped value in the body of a frame,
+    /// which is encoded by doubling them like [`crate::Escaped`] does
+    fn rewrite(&self, body: &mut [u8]) {
+        let mut index = 0;
+        while index + 1 < body.len() {
+            let byte = body[index];
+            if EscapeCode::from_byte(byte).is_some() && body[index + 1] == byte {
+                body[index + 1] = self.escaped(byte);
+                index += 2;
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+impl dyn Framing {
+    /// Nibbles that are sent for every byte value with this framing,
+    /// see [`crate::stream::encoding_table`]
+    pub fn encoding_table(&'static self) -> Vec<Vec<u8>> {
+        crate::stream::encoding_table(self)
+    }
+}
+
+/// The value is sent twice, `0x12` becomes `0x12 0x12`
+#[derive(Debug)]
+pub struct EscapeFraming;
+
+impl Framing for EscapeFraming {
+    fn escaped(&self, byte: u8) -> u8 {
+        byte
+    }
+
+    /// Frames are already encoded like this
+    fn rewrite(&self, _body: &mut [u8]) {}
+}
+
+/// Escaping as described by older versions of the protocol documentation,
+/// the value is followed by its swapped nibbles, `0x12` becomes `0x12 0x21`
+#[derive(Debug)]
+pub struct LegacyFraming;
+
+impl Framing for LegacyFraming {
+    fn escaped(&self, byte: u8) -> u8 {
+        bits::swap_nibbles(byte)
+    }
+}
+
+/// The framings that can be chosen by name, e.g. with `--framing`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramingKind {
+    Escape,
+    Legacy,
+}
+
+impl FramingKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "escape" => Some(Self::Escape),
+            "legacy" => Some(Self::Legacy),
+            _ => None,
+        }
+    }
+
+    pub fn framing(self) -> &'static dyn Framing {
+        match self {
+            Self::Escape => &EscapeFraming,
+            Self::Legacy => &LegacyFraming,
+        }
+    }
+}
+
+#[test]
+fn framing_names() {
+    assert_eq!(FramingKind::from_name("escape"), Some(FramingKind::Escape));
+    assert_eq!(FramingKind::from_name("legacy"), Some(FramingKind::Legacy));
+    assert_eq!(FramingKind::Legacy.framing().escaped(0x12), 0x21);
+    assert_eq!(FramingKind::Escape.framing().escaped(0x12), 0x12);
+
+    // an escaped SOF, two escaped BU1 and the buffer codes of the padding
+    let mut body = [0x12, 0x12, 0xab, 0x56, 0x56, 0x56, 0x56, 0x56, 0x65];
+    FramingKind::Escape.framing().rewrite(&mut body);
+    assert_eq!(body, [0x12, 0x12, 0xab, 0x56, 0x56, 0x56, 0x56, 0x56, 0x65]);
+    FramingKind::Legacy.framing().rewrite(&mut body);
+    assert_eq!(body, [0x12, 0x21, 0xab, 0x56, 0x65, 0x56, 0x65, 0x56, 0x65]);
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::checksum::crc32_update;
+
+/// # FileHasher
+///
+/// Whole-file hash, a CRC-32 over the content, as it is sent in the metadata
+/// of every file in watch mode and checked by `protocol verify`.
+///
+/// Fed in pieces, so large files do not have to be read at once.
+#[derive(Debug, Clone)]
+pub struct FileHasher {
+    crc: u32,
+}
+
+impl Default for FileHasher {
+    fn default() -> Self {
+        Self { crc: !0 }
+    }
+}
+
+impl FileHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc = crc32_update(self.crc, data);
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+
+pub fn hash(data: &[u8]) -> u32 {
+    let mut hasher = FileHasher::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+pub fn hash_file(path: impl AsRef<Path>) -> io::Result<u32> {
+    let mut file = File::open(path)?;
+    let mut hasher = FileHasher::new();
+    let mut buffer = [0; 8192];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(hasher.finish()),
+            len => hasher.update(&buffer[..len]),
+        }
+    }
+}
+
+/// Eight lowercase hex digits
+pub fn to_hex(hash: u32) -> String {
+    format!("{hash:08x}")
+}
+
+/// Accepts upper and lower case, with or without a leading `0x`
+pub fn from_hex(hex: &str) -> Option<u32> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    // `from_str_radix` would accept a leading sign as well
+    if hex.is_empty() || hex.len() > 8 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[test]
+fn whole_file_hash() {
+    let data = b"123456789";
+    let mut hasher = FileHasher::new();
+    for chunk in data.chunks(4) {
+        hasher.update(chunk);
+    }
+    assert_eq!(hasher.finish(), 0xcbf4_3926);
+    assert_eq!(hash(data), crate::checksum::crc32(data));
+
+    let path = std::env::temp_dir().join(format!("protocol-hash-{}", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    assert_eq!(hash_file(&path).unwrap(), 0xcbf4_3926);
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(to_hex(0x0012_abcd), "0012abcd");
+    assert_eq!(from_hex("0x0012ABCD"), Some(0x0012_abcd));
+    assert_eq!(from_hex("cbf43926"), Some(0xcbf4_3926));
+    assert_eq!(from_hex("1cbf43926"), None);
+    assert_eq!(from_hex("+1"), None);
+    assert_eq!(from_hex(""), None);
+}
@@ -0,0 +1,195 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// When a data frame went through each step of being sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTiming {
+    pub seq: u32,
+    pub encoded: Instant,
+    pub first_nibble: Option<Instant>,
+    /// Of the last transmission, if the frame has been resent
+    pub last_nibble: Option<Instant>,
+    pub acked: Option<Instant>,
+    pub retransmissions: u32,
+}
+
+/// Part of the time it takes to get a frame across
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// From encoding to the first nibble, waiting for pacing or an echo frame
+    Queued,
+    /// From the first to the last nibble, including retransmissions
+    Wire,
+    /// From the last nibble to the ack, waiting for the other side to poll
+    Ack,
+    /// From encoding to the ack
+    Total,
+}
+
+impl Stage {
+    pub const ALL: [Self; 4] = [Self::Queued, Self::Wire, Self::Ack, Self::Total];
+
+    fn duration(self, timing: &FrameTiming) -> Option<Duration> {
+        let (start, end) = match self {
+            Self::Queued => (Some(timing.encoded), timing.first_nibble),
+            Self::Wire => (timing.first_nibble, timing.last_nibble),
+            Self::Ack => (timing.last_nibble, timing.acked),
+            Self::Total => (Some(timing.encoded), timing.acked),
+        };
+        Some(end?.saturating_duration_since(start?))
+    }
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Queued => write!(f, "queued"),
+            Self::Wire => write!(f, "wire"),
+            Self::Ack => write!(f, "ack"),
+            Self::Total => write!(f, "total"),
+        }
+    }
+}
+
+/// # LatencyTracker
+///
+/// Timestamps every data frame of a [`crate::Connection`], to tell whether the time is spent
+/// waiting for pacing, on the wire, or waiting for the other side.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    frames: Vec<FrameTiming>,
+    /// Index of the frame that is currently being written
+    writing: Option<usize>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frames(&self) -> &[FrameTiming] {
+        &self.frames
+    }
+
+    pub fn encoded(&mut self, seq: u32) {
+        self.frames.push(FrameTiming {
+            seq,
+            encoded: Instant::now(),
+            first_nibble: None,
+            last_nibble: None,
+            acked: None,
+            retransmissions: 0,
+        });
+        self.writing = Some(self.frames.len() - 1);
+    }
+
+    pub fn resent(&mut self, seq: u32) {
+        if let Some(index) = self.frames.iter().rposition(|timing| timing.seq == seq) {
+            self.frames[index].retransmissions += 1;
+            self.writing = Some(index);
+        }
+    }
+
+    /// Called after a nibble has been sent, with whether the frame is still being written
+    pub fn nibble_sent(&mut self, writing: bool) {
+        let Some(index) = self.writing else {
+            return;
+        };
+        let now = Instant::now();
+        let timing = &mut self.frames[index];
+        timing.first_nibble.get_or_insert(now);
+        if !writing {
+            timing.last_nibble = Some(now);
+            self.writing = None;
+        }
+    }
+
+    pub fn acked(&mut self, seq: u32) {
+        if let Some(timing) = self
+            .frames
+            .iter_mut()
+            .rev()
+            .find(|timing| timing.seq == seq)
+        {
+            timing.acked.get_or_insert(Instant::now());
+        }
+    }
+
+    /// The duration of the stage that `percent` of the frames stay below,
+    /// only frames that have completed the stage are counted.
+    pub fn percentile(&self, stage: Stage, percent: f64) -> Option<Duration> {
+        let mut durations: Vec<_> = self
+            .frames
+            .iter()
+            .filter_map(|timing| stage.duration(timing))
+            .collect();
+        durations.sort_unstable();
+        // nearest rank
+        let rank = (percent / 100.0 * durations.len() as f64).ceil() as usize;
+        durations
+            .get(rank.clamp(1, durations.len().max(1)) - 1)
+            .copied()
+    }
+}
+
+impl Display for LatencyTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let retransmissions: u32 = self
+            .frames
+            .iter()
+            .map(|timing| timing.retransmissions)
+            .sum();
+        writeln!(
+            f,
+            "{} frames, {retransmissions} retransmissions",
+            self.frames.len()
+        )?;
+        for stage in Stage::ALL {
+            let [p50, p90, p99] = [50.0, 90.0, 99.0].map(|percent| {
+                self.percentile(stage, percent)
+                    .map(|duration| format!("{duration:.2?}"))
+                    .unwrap_or_else(|| "-".to_string())
+            });
+            writeln!(f, "{stage:>6}: p50 {p50}, p90 {p90}, p99 {p99}")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn latency_percentiles() {
+    let mut tracker = LatencyTracker::new();
+    assert_eq!(tracker.percentile(Stage::Total, 50.0), None);
+
+    let start = Instant::now();
+    for seq in 1..=10 {
+        tracker.frames.push(FrameTiming {
+            seq,
+            encoded: start,
+            first_nibble: Some(start),
+            last_nibble: Some(start),
+            acked: Some(start + Duration::from_millis(seq as u64)),
+            retransmissions: 0,
+        });
+    }
+    assert_eq!(
+        tracker.percentile(Stage::Total, 50.0),
+        Some(Duration::from_millis(5))
+    );
+    assert_eq!(
+        tracker.percentile(Stage::Ack, 90.0),
+        Some(Duration::from_millis(9))
+    );
+    assert_eq!(
+        tracker.percentile(Stage::Ack, 100.0),
+        Some(Duration::from_millis(10))
+    );
+    assert_eq!(tracker.percentile(Stage::Wire, 99.0), Some(Duration::ZERO));
+
+    tracker.encoded(11);
+    tracker.nibble_sent(true);
+    tracker.nibble_sent(false);
+    tracker.resent(11);
+    assert_eq!(tracker.frames()[10].retransmissions, 1);
+    assert!(tracker.frames()[10].first_nibble.is_some());
+}
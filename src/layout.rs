@@ -0,0 +1,137 @@
+use std::fmt::Write;
+
+use crate::bits::NibbleOrder;
+use crate::escape::EscapeCode;
+use crate::framing::FramingKind;
+use crate::stream::IdlePattern;
+use crate::ESCAPE_CODE_LEN;
+
+/// A part of a frame on the wire, before buffer codes are inserted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    /// Number of values
+    pub len: usize,
+    /// Whether values equal to an escape code take two bytes,
+    /// so that the fields after it start at different offsets
+    pub escaped: bool,
+}
+
+/// # LayoutDescription
+///
+/// Frame layout, escape table and checksum of a [`crate::config::ProtocolConfig`],
+/// as returned by [`crate::config::ProtocolConfig::describe`],
+/// so that the counterpart on the other side and the documentation can be generated from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutDescription {
+    pub symbol_bits: u32,
+    pub nibble_order: NibbleOrder,
+    pub frame: Vec<Field>,
+    /// Number of bytes of a frame, in which every value is escaped
+    pub max_frame_len: usize,
+    /// Value and abbreviation of every escape code
+    pub escape_codes: Vec<(u8, &'static str)>,
+    /// How a value equal to an escape code is sent, see [`crate::framing::Framing`]
+    pub framing: FramingKind,
+    pub frame_size_payload_len: usize,
+    pub pacing_us: u128,
+    pub idle_pattern: IdlePattern,
+}
+
+impl LayoutDescription {
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"symbol_bits\": {},", self.symbol_bits);
+        let order = match self.nibble_order {
+            NibbleOrder::HighFirst => "high-first",
+            NibbleOrder::LowFirst => "low-first",
+        };
+        let _ = writeln!(json, "  \"nibble_order\": \"{order}\",");
+
+        let fields: Vec<_> = self
+            .frame
+            .iter()
+            .map(|field| {
+                format!(
+                    "    {{ \"name\": \"{}\", \"len\": {}, \"escaped\": {} }}",
+                    field.name, field.len, field.escaped
+                )
+            })
+            .collect();
+        let _ = writeln!(json, "  \"frame\": [\n{}\n  ],", fields.join(",\n"));
+        let _ = writeln!(json, "  \"max_frame_len\": {},", self.max_frame_len);
+
+        let codes: Vec<_> = self
+            .escape_codes
+            .iter()
+            .map(|(value, name)| format!("    {{ \"name\": \"{name}\", \"value\": {value} }}"))
+            .collect();
+        let _ = writeln!(json, "  \"escape_codes\": [\n{}\n  ],", codes.join(",\n"));
+
+        let framing = match self.framing {
+            FramingKind::Escape => "escape",
+            FramingKind::Legacy => "legacy",
+        };
+        let _ = writeln!(json, "  \"framing\": \"{framing}\",");
+        let _ = writeln!(
+            json,
+            "  \"frame_size_payload_len\": {},",
+            self.frame_size_payload_len
+        );
+        let _ = writeln!(json, "  \"pacing_us\": {},", self.pacing_us);
+        let idle = match &self.idle_pattern {
+            IdlePattern::Alternating => "alternating".to_string(),
+            IdlePattern::Sequence(nibbles) => {
+                nibbles.iter().map(|nibble| format!("{nibble:x}")).collect()
+            }
+            IdlePattern::HoldLast => "hold".to_string(),
+            IdlePattern::TriState => "tristate".to_string(),
+        };
+        let _ = writeln!(json, "  \"idle_pattern\": \"{idle}\"");
+        json.push_str("}\n");
+        json
+    }
+}
+
+/// SOF, data, checksum, EOF
+pub fn frame_fields(frame_data_len: usize, checksum_len: usize) -> Vec<Field> {
+    [
+        ("start_of_frame", ESCAPE_CODE_LEN, false),
+        ("data", frame_data_len, true),
+        ("checksum", checksum_len, true),
+        ("end_of_frame", ESCAPE_CODE_LEN, false),
+    ]
+    .into_iter()
+    .map(|(name, len, escaped)| Field { name, len, escaped })
+    .collect()
+}
+
+pub fn escape_codes() -> Vec<(u8, &'static str)> {
+    (u8::MIN..=u8::MAX)
+        .filter_map(|byte| Some((byte, EscapeCode::from_byte(byte)?.abbreviation())))
+        .collect()
+}
+
+#[test]
+fn describe_lab_profile() {
+    let config = crate::config::ProtocolConfig::profile(crate::config::Profile::LabB15f);
+    let layout = config.describe();
+    assert_eq!(
+        layout.frame[1],
+        Field {
+            name: "data",
+            len: crate::FRAME_DATA_LEN,
+            escaped: true,
+        }
+    );
+    // as much as the encoder reserves for a frame
+    assert_eq!(layout.max_frame_len, crate::FRAME_LEN);
+    assert_eq!(layout.frame[2].len, crate::CHECKSUM_LEN);
+    assert_eq!(layout.escape_codes.len(), 11);
+    assert_eq!(layout.escape_codes[0], (0x12, "SOF"));
+
+    let json = layout.to_json();
+    assert!(json.contains("{ \"name\": \"SOF\", \"value\": 18 }"));
+    assert!(json.contains("\"nibble_order\": \"high-first\""));
+    assert!(json.contains("\"framing\": \"escape\""));
+}
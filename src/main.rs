@@ -1,145 +1,2740 @@
-use std::io::{stdin, stdout, Read, Write};
-use std::{thread, time::Duration};
+use std::collections::VecDeque;
+use std::io::{stdin, stdout, BufReader, Bytes, IsTerminal, Read, Stdout};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use std::{iter, thread};
+
+mod align;
+use align::WordAlignment;
+
+mod analyze;
+use analyze::Pins;
+
+mod batch;
+use batch::Batch;
+
+mod bench;
+
+mod bits;
+use bits::NibbleOrder;
+
+mod budget;
+use budget::MemoryBudget;
+
+mod calibrate;
+use calibrate::{
+    calibration_echo, calibration_rate, Calibration, CALIBRATION_ECHO_SEQ, CALIBRATION_RATES,
+};
+
+mod cancel;
+use cancel::CancellationToken;
+
+mod checksum;
+use checksum::ChecksumAlgorithm;
+
+mod compress;
+use compress::{Compressed, Decompressor};
+
+mod conformance;
+
+mod config;
+use config::{ProtocolConfig, Settings};
+
+mod cost;
+
+mod diagnostics;
+use diagnostics::Log;
+
+mod debugfmt;
+
+mod diagram;
+use diagram::SequenceChart;
 
 mod device;
-use device::{DebugDevice, Device};
+use device::{
+    B15fDevice, B15fListener, B15fLoopback, DebugDevice, Device, Quirks, QuirksDevice, Split,
+    TcpDevice,
+};
 use escape::{EscapeCode, Escaped};
 
 mod escape;
 
+mod event;
+use event::{Event, EventError, EventQueue, Overflow};
+
+#[cfg(test)]
+mod fault;
+#[cfg(test)]
+use fault::FaultInjector;
+
+mod framing;
+
+mod hash;
+
+mod latency;
+use latency::LatencyTracker;
+
+mod layout;
+
+mod manifest;
+use manifest::Manifest;
+
+mod message;
+use message::{MessageSender, MessageSink};
+
+mod metrics;
+use metrics::Metrics;
+
+mod middleware;
+use middleware::FrameMiddleware;
+
+mod nibble;
+
+mod ping;
+use ping::Echo;
+
+mod priority;
+use priority::{MessageTooLong, PRIORITY_ECHO_SEQ};
+
+mod ratelimit;
+use ratelimit::RateLimiter;
+
+mod resume;
+use resume::{ResumeToken, OFFSET_ECHO_SEQ, SESSION_ECHO_SEQ};
+
+mod retransmit;
+use retransmit::{FixedRetransmitCache, FrameCache, RetransmitCache};
+
+mod schedule;
+use schedule::{SchedulePolicy, Slot, TxScheduler};
+
+mod scramble;
+use scramble::{Features, Scrambler, FEATURES_ECHO_SEQ};
+
+mod rtt;
+use rtt::{AckTimeout, RttEstimator};
+
+mod session;
+use session::{Decision, SessionLog};
+
+mod signal;
+
+mod sim;
+
+mod sink;
+use sink::{Rotate, RotatingSink, Sink};
+
+mod snapshot;
+use snapshot::Snapshot;
+
+mod sniff;
+
+mod soak;
+
+#[cfg(test)]
+mod spec;
+
+mod source;
+use source::{BoxedSource, ChannelSource, DataSource, ReplaySource};
+
+mod stdio;
+use stdio::StdioDevice;
+
 mod stream;
-use stream::{Command, InputStream, OutputStream};
+use stream::{
+    frame_size_payload, ControlMsg, FrameKind, IdlePattern, InputEvent, InputState, InputStream,
+    OutputState, OutputStream, Squelch, Strictness, FRAME_SIZE_LEN,
+};
 
-fn main() -> Result<(), &'static str> {
-    let stdin = stdin().lock().bytes();
-    let mut connection = Connection::new(DebugDevice::new(), stdin);
+mod tap;
+use tap::{Direction, PipelineTap, TextTap};
 
-    while connection.poll() {
-        thread::sleep(Duration::from_millis(1));
-    }
+mod theory;
 
-    // dbg!(String::from_utf8_lossy(&connection.received));
-    Ok(())
+mod viz;
+use viz::Timeline;
+
+mod watch;
+
+mod wire;
+
+/// Defaults from the settings file, loaded once at startup
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
 }
 
-const ESCAPE_CODE_LEN: usize = 1;
-const CHECKSUM_LEN: usize = 0;
-const FRAME_DATA_LEN: usize = 64;
-const FRAME_LEN: usize = ESCAPE_CODE_LEN + FRAME_DATA_LEN + CHECKSUM_LEN + ESCAPE_CODE_LEN;
-pub type Frame = [u8; FRAME_LEN];
+/// The protocol config with the defaults of the settings file
+fn protocol_config() -> ProtocolConfig {
+    settings().apply(ProtocolConfig::default())
+}
 
-/// # Steps
-///
-/// 1. calculate checksums
-/// 2. add start of frame
-/// 3. escape and add values
-/// 4. add checksums
-/// 5. add end of frame
-///
-/// ## Structure of frame
-///
-/// - SOF
-/// - data
-/// - checksums
-/// - EOF
-///
-/// ## Calculating checksums
-///
-/// TODO
-///
-/// ## Encoding values equal to escape codes
-///
-/// | Function               | Escape code | Escaped value  |
-/// | ---------------------- | ----------- | -------------- |
-/// | start of frame         | (SOF) 0x12  | 0x12 0x12      |
-/// | end of frame           | (EOF) 0x23  | 0x23 0x23      |
-/// | correct frame data     | (CDF) 0x34  | 0x34 0x34      |
-/// | incorrect frame data   | (IDF) 0x45  | 0x45 0x45      |
-/// | buffer                 | (BU)  0x56  | 0x56 0x56      |
-/// | finished sending       | (FS)  0x67  | 0x67 0x67      |
-///
-/// 0x56 0x65 0x9a 0x56
-/// 0x56      0x9a 0x56
-/// 0x56      0x65
-///
-fn encode_frame(data: &mut impl Iterator<Item = std::io::Result<u8>>) -> Frame {
-    let mut frame = [0; FRAME_LEN];
-    frame[0] = EscapeCode::StartOfFrame as u8;
+/// The `--framing` argument, or else the framing of the settings file
+fn framing_kind() -> Result<framing::FramingKind, &'static str> {
+    match arg_value("--framing") {
+        Some(name) => framing::FramingKind::from_name(&name).ok_or("invalid framing"),
+        None => Ok(protocol_config().framing),
+    }
+}
 
-    for cell in &mut frame[1..(1 + FRAME_DATA_LEN)] {
-        *cell = match data.next() {
-            Some(Ok(byte)) => byte,
-            Some(Err(err)) => todo!("{}", err),
-            // TODO Send finished escape code
-            None => break,
+/// The `--device` argument, or else the device of the settings file
+fn device_spec() -> Option<String> {
+    arg_value("--device").or_else(|| settings().device.clone())
+}
+
+fn main() -> Result<(), &'static str> {
+    match Settings::load() {
+        Ok(settings) => {
+            let _ = SETTINGS.set(settings);
+        }
+        Err(err) => {
+            eprintln!("Could not read settings: {err}");
+            return Err("invalid settings file");
         }
     }
 
-    // TODO Encode chucksums
+    match std::env::args().nth(1).as_deref() {
+        Some("conformance") => return run_conformance(),
+        Some("soak") => return run_soak(),
+        Some("loopback") => return run_loopback(),
+        Some("ping") => return run_ping(),
+        Some("sniff") => return run_sniff(),
+        Some("explain") => return run_explain(),
+        Some("diagram") => return run_diagram(),
+        Some("decode") => return run_decode(),
+        Some("analyze") => return run_analyze(),
+        Some("bench") => return run_bench(),
+        Some("cost") => return run_cost(),
+        Some("theory") => return run_theory(),
+        Some("simulate") => return run_simulate(),
+        Some("watch") => return run_watch(),
+        Some("verify") => return run_verify(),
+        Some("budget") => {
+            print!("{}", MemoryBudget::embedded());
+            return Ok(());
+        }
+        Some("encoding-table") => {
+            print!("{}", stream::encoding_table_text(framing_kind()?.framing()));
+            return Ok(());
+        }
+        Some("describe") => {
+            print!("{}", protocol_config().describe().to_json());
+            return Ok(());
+        }
+        _ => (),
+    }
 
-    frame[FRAME_LEN - 1] = EscapeCode::EndOfFrame as u8;
+    if stdio_frames() {
+        return transfer_with_quirks(StdioDevice::new(stdin(), stdout()));
+    }
 
-    frame
+    match device_spec() {
+        Some(spec) => {
+            // the embedded build's device, on the pins of the lab board
+            #[cfg(feature = "embedded-hal")]
+            if spec == "b15f-hal" {
+                let (outputs, inputs) = device::B15fPin::open()?;
+                return transfer_with_quirks(device::HalDevice::new(outputs, inputs));
+            }
+            #[cfg(unix)]
+            if let Some(device) = device::PipeDevice::open(&spec) {
+                return transfer_with_quirks(device.map_err(|_| "could not open pipe device")?);
+            }
+            let mut device = TcpDevice::open(&spec).map_err(|_| "could not open tcp device")?;
+            if std::env::args().any(|arg| arg == "--clock") {
+                device = device.with_clock();
+            }
+            // the lines of the other side are read by separate hardware
+            if let Some(rx_spec) = arg_value("--rx-device") {
+                let rx = TcpDevice::open(&rx_spec).map_err(|_| "could not open rx device")?;
+                return transfer_with_quirks(Split::new(device, rx));
+            }
+            transfer_with_quirks(device)
+        }
+        None => transfer_with_quirks(DebugDevice::new()),
+    }
 }
 
-/// 1. calculate checksums for received data
-/// 2. compare checksums
-///
-fn decode_frame(frame: &[u8; FRAME_DATA_LEN + CHECKSUM_LEN]) -> &[u8] {
-    frame
+/// Sends like a foreign implementation, if asked to with `--quirks`
+fn transfer_with_quirks(device: impl Device) -> Result<(), &'static str> {
+    match arg_value("--quirks") {
+        Some(name) => {
+            let quirks = Quirks::from_name(&name).ok_or("invalid quirks")?;
+            transfer_to_output(QuirksDevice::new(device, quirks))
+        }
+        None => transfer_to_output(device),
+    }
 }
 
-struct Connection<D: Device, I: Iterator<Item = std::io::Result<u8>>> {
-    device: D,
-    i_stream: InputStream,
-    o_stream: OutputStream,
-    data: Escaped<I>,
-    done_receiving: bool,
-    debug_lines: [String; 4],
+fn transfer_to_output(device: impl Device) -> Result<(), &'static str> {
+    match arg_value("--output") {
+        Some(pattern) => {
+            let rotate = match arg_value("--rotate") {
+                Some(rotate) => Rotate::parse(&rotate).ok_or("invalid rotation")?,
+                None => Rotate::Size(u64::MAX),
+            };
+            // written as .part files, which get their names once everything has been received
+            let atomic = !std::env::args().any(|arg| arg == "--no-atomic");
+            // the files of an interrupted transfer are continued
+            let resuming = [arg_value("--resume"), arg_value("--snapshot")]
+                .into_iter()
+                .flatten()
+                .any(|path| std::path::Path::new(&path).exists());
+            let sink = if resuming {
+                RotatingSink::resume(&pattern, rotate, atomic)
+            } else {
+                RotatingSink::new(&pattern, rotate, atomic)
+            }
+            .map_err(|_| "could not create output")?;
+            transfer(device, sink)
+        }
+        // stdout carries the frames
+        None if stdio_frames() => transfer(device, std::io::sink()),
+        None => transfer(device, stdout()),
+    }
 }
 
-impl<D: Device, I: Iterator<Item = std::io::Result<u8>>> Connection<D, I> {
-    fn new(device: D, bytes: I) -> Self {
-        Self {
-            device,
-            o_stream: OutputStream::new(),
-            i_stream: InputStream::new(),
-            data: Escaped::new(bytes),
-            done_receiving: false,
-            debug_lines: [const { String::new() }; 4],
+fn transfer(device: impl Device, sink: impl Sink) -> Result<(), &'static str> {
+    let resume_path = arg_value("--resume");
+    let resumed = match &resume_path {
+        Some(path) if std::path::Path::new(path).exists() => {
+            Some(ResumeToken::load(path).map_err(|_| "could not read session token")?)
+        }
+        _ => None,
+    };
+    let snapshot_path = arg_value("--snapshot");
+    let snapshot = match &snapshot_path {
+        Some(path) if std::path::Path::new(path).exists() => {
+            Some(Snapshot::load(path).map_err(|_| "could not read snapshot")?)
+        }
+        _ => None,
+    };
+    // the acknowledged data is not sent again
+    let skip = match snapshot {
+        Some(snapshot) => snapshot.source_offset(),
+        None => resumed.map_or(0, |token| token.sent_bytes),
+    };
+    // `protocol send file` sends the file, everything else sends stdin,
+    // unless it carries the frames
+    let mut input: Box<dyn Read> = match std::env::args().nth(1).as_deref() {
+        Some("send") => {
+            let path = std::env::args().nth(2).ok_or("missing file")?;
+            Box::new(std::fs::File::open(path).map_err(|_| "could not open input")?)
+        }
+        _ if stdio_frames() => Box::new(std::io::empty()),
+        _ => Box::new(stdin().lock()),
+    };
+    std::io::copy(&mut (&mut input).take(skip), &mut std::io::sink())
+        .map_err(|_| "could not read input")?;
+    let mut connection = Connection::send_reader(device, input, sink);
+    // the other side may still be in the middle of an interrupted transfer
+    if std::env::args().any(|arg| arg == "--reset") {
+        connection.reset();
+    }
+    let frame_size = match arg_value("--frame-size") {
+        Some(len) => len.parse().map_err(|_| "invalid frame size")?,
+        None => protocol_config().frame_size,
+    };
+    // both sides start out with full frames
+    if frame_size != FRAME_DATA_LEN {
+        connection.request_frame_size(frame_size);
+    }
+    connection.set_nibble_order(match arg_value("--nibble-order") {
+        Some(order) => NibbleOrder::from_name(&order).ok_or("invalid nibble order")?,
+        None => protocol_config().nibble_order,
+    });
+    connection.set_framing(framing_kind()?.framing());
+    match arg_value("--edge-detection").as_deref() {
+        Some("on") => connection.set_edge_detection(true),
+        Some("off") => connection.set_edge_detection(false),
+        Some(_) => return Err("invalid edge detection"),
+        None => (),
+    }
+    if std::env::args().any(|arg| arg == "--align-words") {
+        connection.add_middleware(WordAlignment);
+    }
+    if std::env::args().any(|arg| arg == "--calibrate") {
+        connection.start_calibration();
+    }
+    if let Some(rate) = arg_value("--max-rate") {
+        connection.set_rate_limit(RateLimiter::parse(&rate).ok_or("invalid rate")?);
+    }
+    if let Some(idle) = arg_value("--idle") {
+        connection.set_idle_pattern(IdlePattern::from_name(&idle).ok_or("invalid idle pattern")?);
+    }
+    if let Some(polls) = arg_value("--squelch") {
+        let polls = polls.parse().map_err(|_| "invalid squelch")?;
+        let peer_idle = match arg_value("--peer-idle") {
+            Some(idle) => IdlePattern::from_name(&idle).ok_or("invalid idle pattern")?,
+            None => protocol_config().idle_pattern,
+        };
+        connection.set_squelch(Squelch::Polls(polls), peer_idle);
+    }
+    if let Some(strictness) = arg_value("--strictness") {
+        connection.set_strictness(Strictness::from_name(&strictness).ok_or("invalid strictness")?);
+    }
+    if let Some(timeout) = arg_value("--ack-timeout") {
+        connection.set_ack_timeout(match timeout.as_str() {
+            "off" => AckTimeout::Off,
+            ms => AckTimeout::Fixed(Duration::from_millis(
+                ms.parse().map_err(|_| "invalid ack timeout")?,
+            )),
+        });
+    }
+    if let Some(capacity) = arg_value("--event-queue") {
+        let capacity = capacity.parse().map_err(|_| "invalid event queue capacity")?;
+        let overflow = match arg_value("--event-overflow") {
+            Some(overflow) => Overflow::from_name(&overflow).ok_or("invalid event overflow")?,
+            None => Overflow::default(),
+        };
+        connection.set_event_queue(EventQueue::new(capacity, overflow));
+    }
+    if let Some(frames) = arg_value("--retransmit-frames") {
+        connection.set_retransmit_cache(RetransmitCache::new(
+            frames
+                .parse()
+                .map_err(|_| "invalid number of retransmit frames")?,
+        ));
+    }
+    // the buffers of the embedded build, see `protocol budget`
+    if std::env::args().any(|arg| arg == "--bounded-memory") {
+        connection.set_retransmit_cache(
+            FixedRetransmitCache::<{ budget::EMBEDDED_RETRANSMIT_FRAMES }>::new(),
+        );
+    }
+    if let Some(policy) = arg_value("--schedule") {
+        connection.set_schedule_policy(
+            SchedulePolicy::from_name(&policy).ok_or("invalid schedule policy")?,
+        );
+    }
+    if let Some(path) = arg_value("--session") {
+        connection
+            .set_session_log(SessionLog::append(&path).map_err(|_| "could not open session log")?);
+    }
+    if std::env::args().any(|arg| arg == "--scramble") {
+        connection.enable_scrambling();
+    }
+    if std::env::args().any(|arg| arg == "--compress") {
+        // the offsets of a resumed session count compressed bytes
+        if skip > 0 {
+            return Err("compressed transfers can not be resumed");
         }
+        connection.enable_compression();
+    }
+    if std::env::args().any(|arg| arg == "--mini-frames") {
+        connection.set_mini_frames(true);
+    }
+    if std::env::args().any(|arg| arg == "--no-pre-encode") {
+        connection.set_pre_encode(false);
+    }
+    if std::env::args().any(|arg| arg == "--tap") {
+        connection.set_tap(TextTap::new());
+    }
+    if std::env::args().any(|arg| arg == "--viz-live") {
+        connection.set_live_timeline(true);
+    }
+    // shown right away, instead of ending up in the sink with the data
+    connection.set_priority_handler(|message| {
+        eprintln!("Priority message: {}", String::from_utf8_lossy(message));
+    });
+    if let Some(message) = arg_value("--priority") {
+        connection
+            .send_priority(message.as_bytes())
+            .map_err(|_| "priority message does not fit into a frame")?;
+    }
+    match resumed {
+        Some(token) => connection.resume(token),
+        None if resume_path.is_some() => connection.announce_session(),
+        None => (),
+    }
+    if let Some(snapshot) = snapshot {
+        connection.restore(snapshot);
+    }
+    let cancel = CancellationToken::new();
+    if !signal::cancel_on_ctrl_c(cancel.clone()) {
+        eprintln!("Could not install Ctrl-C handler");
+    }
+    connection.set_cancellation(cancel.clone());
+    if arg_value("--manifest").is_some() || arg_value("--verify-manifest").is_some() {
+        connection.record_manifests();
     }
 
-    // Returns false when all data has been sent and received
-    fn poll(&mut self) -> bool {
-        let nibble_out = self.o_stream.next();
-        for i in 0..4 {
-            self.debug_lines[i].push_str(if (nibble_out << i) & 0b1000 == 0b1000 {
-                "◻️"
-            } else {
-                "◼"
-            });
+    // the device knows best how fast it can go
+    let pacing = connection
+        .device
+        .capabilities()
+        .pacing()
+        .unwrap_or(protocol_config().pacing);
+    // a batch of nibbles is only paced once
+    let max_batch = connection.device.capabilities().max_batch;
+    let mut polls = 0;
+    loop {
+        let running = connection.poll();
+        polls += 1;
+        let batch_done = polls % max_batch == 0;
+        // the state is saved between batches, so writing files never delays a nibble
+        if batch_done || !running {
+            let mut progressed = false;
+            let mut sent = false;
+            for event in connection.drain_events() {
+                progressed |= matches!(event, Event::Acked { .. } | Event::Received { .. });
+                sent |= matches!(event, Event::FrameSent { .. });
+            }
+            if let Some(path) = resume_path.as_deref().filter(|_| progressed) {
+                connection
+                    .resume_token()
+                    .save(path)
+                    .map_err(|_| "could not write session token")?;
+            }
+            if let Some(path) = snapshot_path.as_deref().filter(|_| progressed || sent) {
+                connection
+                    .snapshot()
+                    .save(path)
+                    .map_err(|_| "could not write snapshot")?;
+            }
+        }
+        if !running {
+            connection.flush_batch();
+            break;
         }
+        if batch_done {
+            thread::sleep(pacing);
+        }
+    }
+    if connection.dropped_events() > 0 {
+        eprintln!(
+            "{} events were dropped, the event queue was full",
+            connection.dropped_events()
+        );
+    }
+    if connection.resume_rejected() {
+        return Err("the other side does not continue the resumed session");
+    }
+    if connection.features_rejected() {
+        return Err("the other side does not use the same features");
+    }
+    if let Some(err) = connection.source_error() {
+        eprintln!("Could not read the data: {err}");
+        return Err("could not read input");
+    }
+    if let Some(err) = connection.sink_error() {
+        eprintln!("Could not write the data: {err}");
+        return Err("could not write output");
+    }
+    if connection.is_stalled() {
+        return Err("connection stalled");
+    }
+    connection
+        .output
+        .finish()
+        .map_err(|_| "could not write output")?;
 
-        self.device.send(nibble_out);
-        let nibble_in = self.device.read();
+    if let Some(path) = arg_value("--viz") {
+        std::fs::write(path, connection.timeline.render_svg())
+            .map_err(|_| "could not write visualization")?;
+    }
+    if let Some(path) = arg_value("--vcd") {
+        std::fs::write(path, connection.timeline.render_vcd())
+            .map_err(|_| "could not write value change dump")?;
+    }
+
+    if std::env::args().any(|arg| arg == "--viz-stats") {
+        eprint!("{}", connection.timeline.statistics());
+    }
+    if std::env::args().any(|arg| arg == "--escape-stats") {
+        eprint!("{}", connection.i_stream.escape_stats());
+    }
+    if std::env::args().any(|arg| arg == "--latency") {
+        eprint!("{}", connection.latency());
+        if let Some(rtt) = connection.rtt() {
+            eprint!("{rtt}");
+        }
+    }
+    if let Some(path) = arg_value("--metrics") {
+        std::fs::write(path, connection.metrics().to_json())
+            .map_err(|_| "could not write metrics")?;
+    }
+    if let Some(path) = arg_value("--manifest") {
+        if let Some(manifest) = &connection.sent_manifest {
+            manifest
+                .save(&path)
+                .map_err(|_| "could not write manifest")?;
+        }
+    }
+    if let Some(path) = arg_value("--verify-manifest") {
+        let sent = Manifest::load(&path).map_err(|_| "could not read manifest")?;
+        let received = connection
+            .received_manifest
+            .as_ref()
+            .expect("manifests recorded");
+        let mismatches = sent.verify(received);
+        for mismatch in &mismatches {
+            eprintln!("Manifest mismatch: {mismatch}");
+        }
+        if !mismatches.is_empty() {
+            return Err("received data does not match the manifest");
+        }
+    }
 
-        match self.i_stream.push(nibble_in) {
-            Command::Received(frame) => stdout().lock().write_all(decode_frame(&frame)).unwrap(),
-            Command::SendNextFrame => {
-                for line in &mut self.debug_lines {
-                    eprintln!("{} {}", self.device.name(), line);
-                    line.clear();
+    if cancel.is_cancelled() {
+        eprintln!(
+            "Cancelled while {}: {} frames sent, {} frames received",
+            connection.state(),
+            connection.seq,
+            connection.i_stream.received_frames()
+        );
+        // everything had already been sent and received, nothing got lost
+        if !connection.is_closed() {
+            return Err("transfer cancelled before all data was transferred");
+        }
+    }
+
+    if connection.is_closed() {
+        connection
+            .output
+            .commit()
+            .map_err(|_| "could not rename output")?;
+    }
+
+    // a finished transfer is not resumed
+    if let Some(path) = resume_path.filter(|_| connection.is_closed()) {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Some(path) = snapshot_path.filter(|_| connection.is_closed()) {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // dbg!(String::from_utf8_lossy(&connection.received));
+    Ok(())
+}
+
+/// Returns the argument following the flag
+fn arg_value(flag: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != flag).nth(1)
+}
+
+fn run_ping() -> Result<(), &'static str> {
+    let count = match arg_value("--count") {
+        Some(count) => count.parse().map_err(|_| "invalid count")?,
+        None => 10,
+    };
+    let timeout = Duration::from_secs(1);
+
+    let mut connection = Connection::new(B15fDevice::new()?, std::iter::empty());
+    let mut stats = ping::RttStats::new();
+    let start = Instant::now();
+
+    for seq in 0..count {
+        connection.send_echo(Echo {
+            reply: false,
+            seq,
+            timestamp: start.elapsed().as_micros() as u64,
+        });
+        stats.sent();
+
+        let sent_at = Instant::now();
+        'waiting: while sent_at.elapsed() < timeout {
+            connection.poll();
+            for echo in connection.echo_replies.drain(..) {
+                if echo.seq == seq {
+                    let now = start.elapsed().as_micros() as u64;
+                    let rtt = Duration::from_micros(now.saturating_sub(echo.timestamp));
+                    println!("echo {seq}: {rtt:?}");
+                    stats.received(rtt);
+                    break 'waiting;
                 }
-                self.o_stream.send_frame(encode_frame(&mut self.data));
             }
-            Command::ResendLastFrame => self.o_stream.resend_frame(),
-            Command::StopReceivingData => self.done_receiving = true,
-            Command::None => (),
+        }
+    }
+
+    println!("{stats}");
+    Ok(())
+}
+
+/// Recomputes the whole-file hash, that watch mode sends with every file,
+/// and compares it with the one given by `--hash`
+fn run_verify() -> Result<(), &'static str> {
+    let path = std::env::args().nth(2).ok_or("missing file")?;
+    let actual = hash::hash_file(&path).map_err(|_| "could not read file")?;
+    let Some(expected) = arg_value("--hash") else {
+        println!("{} {path}", hash::to_hex(actual));
+        return Ok(());
+    };
+    let expected = hash::from_hex(&expected).ok_or("invalid hash")?;
+    if actual != expected {
+        println!(
+            "{path}: mismatch, expected {} but got {}",
+            hash::to_hex(expected),
+            hash::to_hex(actual)
+        );
+        return Err("hash mismatch");
+    }
+    println!("{path}: match");
+    Ok(())
+}
+
+fn run_watch() -> Result<(), &'static str> {
+    let dir = std::env::args().nth(2).ok_or("missing directory")?;
+    match device_spec() {
+        Some(spec) => watch_over(
+            TcpDevice::open(&spec).map_err(|_| "could not open tcp device")?,
+            dir.into(),
+        ),
+        None => watch_over(B15fDevice::new()?, dir.into()),
+    }
+}
+
+/// Sends every file of the directory and then, with the watch feature, each file again
+/// once it changes, or with `--receive` writes the files sent by the other side into the directory
+fn watch_over(device: impl Device, dir: std::path::PathBuf) -> Result<(), &'static str> {
+    let (mut connection, sender) = Connection::with_messages(device, 4);
+    let mut receiver = watch::FileReceiver::new(&dir);
+    if std::env::args().any(|arg| arg == "--receive") {
+        // nothing to send, the connection finishes once the other side does
+        drop(sender);
+    } else {
+        spawn_watcher(dir, sender)?;
+    }
+
+    let pacing = connection
+        .device
+        .capabilities()
+        .pacing()
+        .unwrap_or(protocol_config().pacing);
+    let max_batch = connection.device.capabilities().max_batch;
+    let mut polls = 0;
+    while connection.poll() {
+        polls += 1;
+        while let Some(message) = connection.recv_message() {
+            match receiver.receive(&message) {
+                Ok(Some(name)) => eprintln!("Received {name}"),
+                Ok(None) => (),
+                Err(err) => eprintln!("Could not receive file: {err}"),
+            }
+        }
+        if polls % max_batch == 0 {
+            thread::sleep(pacing);
+        }
+    }
+    if connection.is_stalled() {
+        return Err("connection stalled");
+    }
+    Ok(())
+}
+
+/// Sends the files as messages, `false` once the connection has been dropped
+fn send_files(paths: Vec<std::path::PathBuf>, sender: &MessageSender) -> bool {
+    for path in paths {
+        let Ok(messages) = watch::file_messages(&path) else {
+            eprintln!("Could not read {}", path.display());
+            continue;
         };
+        for message in messages {
+            if sender.send_message(&message).is_err() {
+                return false;
+            }
+        }
+        eprintln!("Sent {}", path.display());
+    }
+    true
+}
+
+/// The files of the directory, without its subdirectories
+fn dir_files(dir: &std::path::Path) -> Result<Vec<std::path::PathBuf>, &'static str> {
+    Ok(std::fs::read_dir(dir)
+        .map_err(|_| "could not read directory")?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file())
+        .collect())
+}
+
+#[cfg(feature = "watch")]
+fn spawn_watcher(dir: std::path::PathBuf, sender: MessageSender) -> Result<(), &'static str> {
+    let watcher = watch::DirWatcher::new(&dir).map_err(|_| "could not watch directory")?;
+    let mut paths = dir_files(&dir)?;
+    thread::spawn(move || {
+        while send_files(paths, &sender) {
+            match watcher.changed() {
+                Some(changed) => paths = changed,
+                None => return,
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Without the watch feature the files are only sent once
+#[cfg(not(feature = "watch"))]
+fn spawn_watcher(dir: std::path::PathBuf, sender: MessageSender) -> Result<(), &'static str> {
+    let paths = dir_files(&dir)?;
+    thread::spawn(move || send_files(paths, &sender));
+    Ok(())
+}
+
+/// Whether stdin and stdout carry the frames, instead of a device
+fn stdio_frames() -> bool {
+    std::env::args().any(|arg| arg == "--stdio-frames")
+}
+
+fn run_explain() -> Result<(), &'static str> {
+    let path = std::env::args().nth(2).ok_or("missing session log")?;
+    let log = std::fs::read_to_string(path).map_err(|_| "could not read session log")?;
+    print!("{}", session::explain(&log));
+    Ok(())
+}
+
+/// Decodes a capture with one nibble per byte, like a [`TcpDevice`] sends them
+fn run_decode() -> Result<(), &'static str> {
+    let path = std::env::args().nth(2).ok_or("missing capture")?;
+    let capture = std::fs::read(path).map_err(|_| "could not read capture")?;
+    let mut decoder = wire::WireDecoder::new();
+    // every nibble has been captured once, repeated values are separate nibbles
+    if std::env::args().any(|arg| arg == "--every-nibble") {
+        decoder.set_edge_detection(false);
+    }
+    if let Some(order) = arg_value("--nibble-order") {
+        decoder.set_nibble_order(NibbleOrder::from_name(&order).ok_or("invalid nibble order")?);
+    }
+    if let Some(len) = arg_value("--frame-size") {
+        let len = len.parse().map_err(|_| "invalid frame size")?;
+        decoder.set_frame_data_len(len);
+    }
+    for event in decoder.feed(&capture) {
+        if let Some(sniffed) = sniff::describe(event) {
+            println!("{sniffed}");
+        }
+    }
+    Ok(())
+}
+
+fn run_diagram() -> Result<(), &'static str> {
+    let path = std::env::args().nth(2).ok_or("missing session log")?;
+    let log = std::fs::read_to_string(path).map_err(|_| "could not read session log")?;
+    let chart = SequenceChart::from_log(&log);
+    for line in chart.skipped() {
+        eprintln!("Skipped line {line}, it is not a session log entry");
+    }
+    match arg_value("--out") {
+        Some(path) => {
+            std::fs::write(path, chart.render_svg()).map_err(|_| "could not write diagram")?
+        }
+        None => print!("{}", chart.render_svg()),
+    }
+    Ok(())
+}
+
+fn run_analyze() -> Result<(), &'static str> {
+    let path = arg_value("--pina-dump").ok_or("missing --pina-dump")?;
+    let dump = std::fs::read_to_string(path).map_err(|_| "could not read PINA dump")?;
+    let pins = match arg_value("--pins") {
+        Some(pins) => Pins::from_name(&pins).ok_or("invalid pins")?,
+        None => Pins::default(),
+    };
+    let strictness = match arg_value("--strictness") {
+        Some(strictness) => Strictness::from_name(&strictness).ok_or("invalid strictness")?,
+        None => Strictness::default(),
+    };
+
+    let (samples, invalid) = analyze::parse_pina_dump(&dump);
+    for line in invalid {
+        eprintln!("Skipped line {line}, it is not a PINA sample");
+    }
+    let report = analyze::analyze(&samples, pins, strictness);
+    print!("{report}");
+    if report.failures() > 0 {
+        return Err("decoding the trace failed");
+    }
+    Ok(())
+}
+
+fn run_sniff() -> Result<(), &'static str> {
+    let pacing = protocol_config().pacing;
+    match device_spec().as_deref() {
+        None | Some("b15f") => sniff::run(&B15fListener::new()?, pacing),
+        Some(spec) => {
+            let device = TcpDevice::open(spec).map_err(|_| "could not open tcp device")?;
+            sniff::run(&device, pacing)
+        }
+    }
+    Ok(())
+}
+
+fn run_soak() -> Result<(), &'static str> {
+    let duration = match arg_value("--duration") {
+        Some(duration) => soak::parse_duration(&duration).ok_or("invalid duration")?,
+        None => Duration::from_secs(60),
+    };
+    let seed = match arg_value("--seed") {
+        Some(seed) => seed.parse().map_err(|_| "invalid seed")?,
+        None => 42,
+    };
+
+    let mut connection = Connection::with_output(
+        B15fDevice::new()?,
+        soak::Source::new(seed, duration),
+        soak::Verifier::new(seed),
+    );
+    // compared with the theory by `protocol theory --session`
+    if let Some(path) = arg_value("--session") {
+        connection
+            .set_session_log(SessionLog::append(&path).map_err(|_| "could not open session log")?);
+    }
+    while connection.poll() {}
+    if connection.is_stalled() {
+        return Err("connection stalled");
+    }
+
+    eprintln!("Soak: {}", connection.output.report());
+    Ok(())
+}
+
+/// Runs the protocol against itself on a single board, see [`B15fLoopback`],
+/// for groups without a partner board, or with `--sim` over a simulated cable without any board
+fn run_loopback() -> Result<(), &'static str> {
+    if std::env::args().any(|arg| arg == "--sim") {
+        loopback_over(sim::SimPort::loopback())
+    } else {
+        loopback_over(B15fLoopback::new()?)
+    }
+}
+
+fn loopback_over(device: impl Device) -> Result<(), &'static str> {
+    let duration = match arg_value("--duration") {
+        Some(duration) => soak::parse_duration(&duration).ok_or("invalid duration")?,
+        None => Duration::from_secs(10),
+    };
+    let seed = match arg_value("--seed") {
+        Some(seed) => seed.parse().map_err(|_| "invalid seed")?,
+        None => 42,
+    };
+
+    let mut connection = Connection::with_output(
+        device,
+        soak::Source::new(seed, duration),
+        soak::Verifier::new(seed),
+    );
+    let pacing = connection
+        .device
+        .capabilities()
+        .pacing()
+        .unwrap_or(protocol_config().pacing);
+    while connection.poll() {
+        thread::sleep(pacing);
+    }
+    if connection.is_stalled() {
+        return Err("connection stalled, are PA4-PA7 jumpered to PA0-PA3?");
+    }
+
+    eprintln!("Loopback: {}", connection.output.report());
+    if connection.output.byte_errors() > 0 {
+        return Err("data did not arrive intact");
+    }
+    Ok(())
+}
+
+/// Sends stdin from one simulated connection to another, which writes it to stdout
+fn run_simulate() -> Result<(), &'static str> {
+    let interleaving = match (arg_value("--replay"), arg_value("--seed")) {
+        (Some(path), _) => {
+            let schedule = std::fs::read_to_string(path).map_err(|_| "could not read schedule")?;
+            sim::Interleaving::replay(schedule.trim()).ok_or("invalid schedule")?
+        }
+        (None, Some(seed)) => sim::Interleaving::seeded(seed.parse().map_err(|_| "invalid seed")?),
+        (None, None) => sim::Interleaving::seeded(42),
+    };
+    let polls = match arg_value("--polls") {
+        Some(polls) => polls.parse().map_err(|_| "invalid number of polls")?,
+        None => 100_000,
+    };
+
+    let mut simulator = sim::Simulator::new(
+        (stdin().lock().bytes(), std::io::sink()),
+        (iter::empty(), stdout()),
+        interleaving,
+    );
+    simulator.run(polls);
+    if let Some(path) = arg_value("--record") {
+        std::fs::write(path, simulator.interleaving().to_string())
+            .map_err(|_| "could not write schedule")?;
+    }
+    if !(simulator.a.is_closed() && simulator.b.is_closed()) {
+        return Err("simulation did not finish");
+    }
+    Ok(())
+}
+
+fn run_bench() -> Result<(), &'static str> {
+    if arg_value("--device").is_some_and(|device| device != "sim") {
+        return Err("only the simulated device can be benchmarked");
+    }
+    let error_rate = match arg_value("--error-rate") {
+        Some(rate) => rate.parse().map_err(|_| "invalid error rate")?,
+        None => 0.001,
+    };
+    let len = match arg_value("--bytes") {
+        Some(len) => len.parse().map_err(|_| "invalid number of bytes")?,
+        None => 4096,
+    };
+    let seed = match arg_value("--seed") {
+        Some(seed) => seed.parse().map_err(|_| "invalid seed")?,
+        None => 42,
+    };
+    let checksum = match arg_value("--checksum") {
+        Some(name) => Some(ChecksumAlgorithm::from_name(&name).ok_or("invalid checksum")?),
+        None => None,
+    };
+    let configs: Vec<_> = bench::BenchConfig::matrix()
+        .into_iter()
+        .filter(|config| checksum.is_none_or(|checksum| config.checksum == checksum))
+        .collect();
+
+    // checksums that often equal an escape code make every frame longer
+    let mut audited: Vec<_> = configs
+        .iter()
+        .map(|config| (config.checksum, config.checksum_len, config.frame_data_len))
+        .collect();
+    audited.dedup();
+    for (checksum, checksum_len, frame_data_len) in audited {
+        let corpus = cost::patterned_corpus(frame_data_len);
+        let audit = cost::audit_checksum(checksum, checksum_len, frame_data_len, &corpus);
+        if audit.is_pathological() {
+            eprintln!(
+                "Warning: {}x{checksum_len} checksums of {frame_data_len} byte frames \
+                 are escaped {:.1}% of the time, {:.2} bytes more per frame",
+                checksum.name(),
+                100.0 * audit.escape_rate(),
+                audit.overhead_per_frame()
+            );
+        }
+    }
+
+    let mut prbs = soak::Prbs::new(seed);
+    let payload: Vec<u8> = (0..len).map(|_| prbs.next_byte()).collect();
+    let results: Vec<_> = configs
+        .into_iter()
+        .map(|config| {
+            // every configuration sees the same errors
+            let mut channel = bench::SimChannel::new(seed, error_rate);
+            bench::run(config, &payload, &mut channel)
+        })
+        .collect();
+    print!("{}", bench::table(&results));
+    Ok(())
+}
+
+/// What sending the file costs on the wire, with the frame size of the profile and settings
+fn run_cost() -> Result<(), &'static str> {
+    let path = std::env::args().nth(2).ok_or("missing file")?;
+    let payload = std::fs::read(path).map_err(|_| "could not read file")?;
+    let frame_data_len = match arg_value("--frame-size") {
+        Some(len) => len.parse().map_err(|_| "invalid frame size")?,
+        None => protocol_config().frame_size,
+    };
+    if !(1..=FRAME_DATA_LEN).contains(&frame_data_len) {
+        return Err("invalid frame size");
+    }
+    let cost = cost::wire_cost(&payload, frame_data_len);
+    println!(
+        "{} frames, {} escaped bytes, {} nibbles",
+        cost.frames, cost.escapes, cost.nibbles
+    );
+    // one nibble per poll, without acks and retransmissions
+    println!(
+        "at least {:?} at the configured pacing",
+        protocol_config().pacing * cost.nibbles as u32
+    );
+    Ok(())
+}
+
+/// Expected retransmissions and the best frame size for a nibble error rate,
+/// next to what a soak test written with `--session` has measured
+fn run_theory() -> Result<(), &'static str> {
+    let error_rate = arg_value("--error-rate")
+        .ok_or("missing --error-rate")?
+        .parse()
+        .map_err(|_| "invalid error rate")?;
+    let measured = match arg_value("--session") {
+        Some(path) => {
+            let log = std::fs::read_to_string(path).map_err(|_| "could not read session log")?;
+            Some(theory::Measured::from_log(&log))
+        }
+        None => None,
+    };
+    let report = theory::Report {
+        model: theory::LossModel::new(error_rate),
+        measured,
+    };
+    print!("{report}");
+    Ok(())
+}
+
+/// Runs the conformance suite on the board, or with `--device` against the other side of a tcp device
+fn run_conformance() -> Result<(), &'static str> {
+    match device_spec().as_deref() {
+        None | Some("b15f") => conformance_on(B15fDevice::new()?),
+        Some(spec) => {
+            conformance_on(TcpDevice::open(spec).map_err(|_| "could not open tcp device")?)
+        }
+    }
+}
+
+fn conformance_on(mut device: impl Device) -> Result<(), &'static str> {
+    let results = conformance::run(&mut device);
+
+    for (name, result) in &results {
+        match result {
+            Ok(()) => println!("PASS {name}"),
+            Err(reason) => println!("FAIL {name}: {reason}"),
+        }
+    }
+
+    if results.iter().all(|(_, result)| result.is_ok()) {
+        Ok(())
+    } else {
+        Err("conformance tests failed")
+    }
+}
+
+const ESCAPE_CODE_LEN: usize = 1;
+const CHECKSUM_LEN: usize = 0;
+/// Space reserved in the frame, every checksum byte might have to be escaped
+const ESCAPED_CHECKSUM_LEN: usize = 2 * CHECKSUM_LEN;
+const FRAME_DATA_LEN: usize = 64;
+/// Number of data bytes in a mini frame, see [`EscapeCode::StartOfMiniFrame`]
+const MINI_FRAME_DATA_LEN: usize = 16;
+/// Smallest frame size that is requested when frames keep breaking
+const MIN_FRAME_DATA_LEN: usize = 8;
+/// Number of broken frames in a row, after which smaller frames are requested
+const MAX_CONSECUTIVE_ERRORS: u32 = 3;
+/// Number of polls without a frame being acked or received, after which the connection is stuck
+const WATCHDOG_POLLS: u32 = 10_000;
+/// Number of polls without an answer to the features, after which they are announced again
+const FEATURES_RETRY_POLLS: u32 = 2_000;
+/// Every data byte might have to be escaped, like the checksum
+const FRAME_LEN: usize =
+    ESCAPE_CODE_LEN + 2 * FRAME_DATA_LEN + ESCAPED_CHECKSUM_LEN + ESCAPE_CODE_LEN;
+pub type Frame = [u8; FRAME_LEN];
+/// Receives the priority messages of the other side, see [`Connection::set_priority_handler`]
+type PriorityHandler = Box<dyn FnMut(&[u8])>;
+
+/// # Steps
+///
+/// 1. calculate checksums
+/// 2. add start of frame
+/// 3. escape and add values
+/// 4. add checksums
+/// 5. add end of frame
+///
+/// ## Structure of frame
+///
+/// - SOF
+/// - data
+/// - checksums
+/// - EOF
+///
+/// The grammar of every kind of frame is written out in `spec.rs`, which is tested against
+/// the encoder and the [`InputStream`].
+///
+/// ## Calculating checksums
+///
+/// TODO
+///
+/// ## Escaping checksums
+///
+/// The checksum bytes are escaped the same way as the data, using [`Escaped`].
+/// Unused space in the checksum region is padded with alternating buffer codes,
+/// which the receiver skips.
+///
+/// ## Encoding values equal to escape codes
+///
+/// | Function               | Escape code | Escaped value  |
+/// | ---------------------- | ----------- | -------------- |
+/// | start of frame         | (SOF) 0x12  | 0x12 0x12      |
+/// | end of frame           | (EOF) 0x23  | 0x23 0x23      |
+/// | correct frame data     | (CFD) 0x34  | 0x34 0x34      |
+/// | incorrect frame data   | (IFD) 0x45  | 0x45 0x45      |
+/// | buffer                 | (BU1) 0x56  | 0x56 0x56      |
+/// | buffer                 | (BU2) 0x65  | 0x65 0x65      |
+/// | finished sending       | (FS)  0x67  | 0x67 0x67      |
+/// | start of echo          | (SOE) 0x78  | 0x78 0x78      |
+/// | abort                  | (ABT) 0x89  | 0x89 0x89      |
+/// | set frame size         | (SFS) 0x9a  | 0x9a 0x9a      |
+/// | start of mini frame    | (SOM) 0xde  | 0xde 0xde      |
+///
+/// 0x56 0x65 0x9a 0x56
+/// 0x56      0x9a 0x56
+/// 0x56      0x65
+///
+fn encode_frame<I: Iterator<Item = std::io::Result<u8>>>(
+    data: &mut Escaped<I>,
+) -> std::io::Result<(Frame, usize)> {
+    encode_partial_frame(data, FRAME_DATA_LEN)
+}
+
+/// Splits the bytes into frames, the last one is filled up with zeros.
+///
+/// Returns every frame and the number of its bytes that have to be sent,
+/// or the error of the source, after which no more frames follow.
+pub fn encode_frames(
+    bytes: impl Iterator<Item = std::io::Result<u8>>,
+) -> impl Iterator<Item = std::io::Result<(Frame, usize)>> {
+    encode_partial_frames(bytes, FRAME_DATA_LEN)
+}
+
+/// Like [`encode_frames`], but with only `data_len` data bytes per frame.
+///
+/// Returns every frame and the number of its bytes that have to be sent,
+/// or the error of the source, after which no more frames follow.
+pub fn encode_partial_frames(
+    bytes: impl Iterator<Item = std::io::Result<u8>>,
+    data_len: usize,
+) -> impl Iterator<Item = std::io::Result<(Frame, usize)>> {
+    let mut data = Escaped::new(bytes);
+    let mut failed = false;
+    iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        match encode_values(&mut data, data_len) {
+            Ok((frame, len, values)) => (values > 0).then_some(Ok((frame, len))),
+            Err(err) => {
+                failed = true;
+                Some(Err(err))
+            }
+        }
+    })
+}
+
+/// Encodes a frame with only `data_len` data bytes.
+///
+/// Returns the frame and the number of its bytes that have to be sent.
+fn encode_partial_frame<I: Iterator<Item = std::io::Result<u8>>>(
+    data: &mut Escaped<I>,
+    data_len: usize,
+) -> std::io::Result<(Frame, usize)> {
+    let (frame, len, _) = encode_values(data, data_len)?;
+    Ok((frame, len))
+}
+
+/// Like [`encode_partial_frame`], but also returns how many bytes have been read
+/// from the data, the rest of the frame is filled up with zeros.
+///
+/// An escaped value takes two bytes of the frame, but counts as a single data byte,
+/// like the [`InputStream`] counts it, so that a value is never split between two frames.
+fn encode_values<I: Iterator<Item = std::io::Result<u8>>>(
+    data: &mut Escaped<I>,
+    data_len: usize,
+) -> std::io::Result<(Frame, usize, usize)> {
+    let data_len = data_len.clamp(1, FRAME_DATA_LEN);
+    let mut frame = [0; FRAME_LEN];
+    frame[0] = EscapeCode::StartOfFrame as u8;
+
+    // the checksum is calculated over the values, like the receiver sees them
+    let mut unescaped = [0; FRAME_DATA_LEN];
+    let mut len = ESCAPE_CODE_LEN;
+    let mut values = 0;
+    while values < data_len {
+        let byte = match data.next_raw() {
+            Some(Ok(byte)) => byte,
+            Some(Err(err)) => return Err(err),
+            // TODO Send finished escape code
+            None => break,
+        };
+        unescaped[values] = byte;
+        frame[len] = byte;
+        len += 1;
+        // repeat value of escape code to escape it
+        if EscapeCode::from_byte(byte).is_some() {
+            frame[len] = byte;
+            len += 1;
+        }
+        values += 1;
+    }
+    // the zeros the frame is filled up with do not have to be escaped
+    len += data_len - values;
+
+    let checksum = checksum(&unescaped[..data_len]);
+    write_checksum(&mut frame[len..(len + ESCAPED_CHECKSUM_LEN)], &checksum);
+    len += ESCAPED_CHECKSUM_LEN;
+
+    frame[len] = EscapeCode::EndOfFrame as u8;
+
+    Ok((frame, len + ESCAPE_CODE_LEN, values))
+}
+
+/// Encodes as many bytes as fit into the frame after being transformed by the stages.
+///
+/// Room for what the stages add is kept free, see [`FrameMiddleware::max_growth`].
+fn encode_transformed_frame<I: Iterator<Item = std::io::Result<u8>>>(
+    data: &mut Escaped<I>,
+    data_len: usize,
+    stages: &mut [Box<dyn FrameMiddleware>],
+) -> std::io::Result<(Frame, usize)> {
+    let data_len = data_len.clamp(1, FRAME_DATA_LEN);
+    let growth: usize = stages.iter().map(|stage| stage.max_growth()).sum();
+    let mut payload = Vec::with_capacity(data_len);
+    while growth + payload.len() < data_len {
+        match data.next_raw() {
+            Some(Ok(byte)) => payload.push(byte),
+            Some(Err(err)) => return Err(err),
+            None => break,
+        }
+    }
+
+    middleware::on_send(stages, &mut payload);
+    if payload.len() > data_len {
+        eprintln!(
+            "Middleware grew the payload to {} bytes, truncating it to {data_len}",
+            payload.len()
+        );
+    }
+    encode_partial_frame(&mut Escaped::new(payload.into_iter().map(Ok)), data_len)
+}
+
+/// Escapes the checksum into the cells and fills the rest of them with buffer codes
+fn write_checksum(cells: &mut [u8], checksum: &[u8]) {
+    let escaped_checksum = Escaped::new(checksum.iter().copied().map(Ok)).flatten();
+    let padding = [EscapeCode::Buffer1 as u8, EscapeCode::Buffer2 as u8]
+        .into_iter()
+        .cycle();
+    for (cell, byte) in cells.iter_mut().zip(escaped_checksum.chain(padding)) {
+        *cell = byte;
+    }
+}
+
+/// TODO Calculate checksums
+fn checksum(_data: &[u8]) -> [u8; CHECKSUM_LEN] {
+    [0; CHECKSUM_LEN]
+}
+
+/// 1. calculate checksums for received data
+/// 2. compare checksums
+///
+/// The checksum has already been unescaped by the [`InputStream`].
+fn decode_frame(frame: &[u8; FRAME_DATA_LEN + CHECKSUM_LEN], data_len: usize) -> Option<&[u8]> {
+    let (data, rest) = frame.split_at(data_len);
+    let received_checksum = &rest[..CHECKSUM_LEN];
+    (checksum(data) == received_checksum).then_some(data)
+}
+
+#[test]
+fn stream_large_source() {
+    // generated on the fly, nothing but the current frame is held in memory
+    const LEN: usize = 4 * 1024 * 1024;
+    let reader = std::io::repeat(0xab).take(LEN as u64);
+    let data = BufReader::with_capacity(FRAME_DATA_LEN, reader).bytes();
+
+    let mut frames = 0;
+    for (frame, _) in encode_frames(data).map(Result::unwrap) {
+        assert_eq!(frame[1..=FRAME_DATA_LEN], [0xab; FRAME_DATA_LEN]);
+        frames += 1;
+    }
+    assert_eq!(frames, LEN / FRAME_DATA_LEN);
+}
+
+#[test]
+fn encode_frames_keeps_escaped_values() {
+    let data = [0xc1, 0xc2, 0xc3, EscapeCode::StartOfFrame as u8, 0xc4];
+    let frames: Vec<_> = encode_partial_frames(data.into_iter().map(Ok), 4)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(frames.len(), 2);
+    // the escaped SOF counts as a single value, both of its halves stay in the first frame
+    assert_eq!(frames[0].0[1..6], [0xc1, 0xc2, 0xc3, 0x12, 0x12]);
+    assert_eq!(frames[1].0[1..5], [0xc4, 0x00, 0x00, 0x00]);
+}
+
+/// What the connection is currently doing, as returned by [`Connection::state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    /// No frame has been requested by the other side yet
+    Handshaking,
+    /// A frame is being written to the device
+    Transferring,
+    /// The last frame has been written, but not yet acknowledged
+    WaitingForAck { seq: u32, retries: u32 },
+    /// All data has been sent, but the other side is still sending
+    Draining,
+    /// All data has been sent and received
+    Closed,
+}
+
+impl std::fmt::Display for ConnState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Handshaking => write!(f, "handshaking"),
+            Self::Transferring => write!(f, "transferring"),
+            Self::WaitingForAck { seq, retries } => {
+                write!(f, "waiting for ack of frame {seq} (retries: {retries})")
+            }
+            Self::Draining => write!(f, "draining"),
+            Self::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+/// # Connection
+///
+/// Sends the data source and receives into the sink over a device, one nibble per poll.
+///
+/// ## Delivery order
+///
+/// Frames are sent stop-and-wait, the next frame is only encoded once the current one
+/// has been acked with CFD. A frame that is answered with IFD or rejected by the other side
+/// is resent from the [`RetransmitCache`], or encoded again from the same source bytes
+/// if it has been dropped from the cache. So the sink of the other side receives
+/// every byte of the source exactly once and in order, no matter how often frames are resent,
+/// as long as the acks themselves arrive intact.
+///
+/// ## Data source
+///
+/// The type of the data source is part of the type of the connection,
+/// [`Connection::boxed`] hides it behind a [`BoxedSource`],
+/// so that a `Connection<D>` can be stored without naming the iterator.
+struct Connection<
+    D: Device,
+    I: Iterator<Item = std::io::Result<u8>> = BoxedSource,
+    S: Sink = Stdout,
+> {
+    device: D,
+    i_stream: InputStream,
+    o_stream: OutputStream,
+    /// Replays the data of the frame that has not been acked yet, see [`DataSource`]
+    data: Escaped<ReplaySource<Compressed<I>>>,
+    /// Where received data is written to
+    output: S,
+    done_receiving: bool,
+    timeline: Timeline,
+    /// Whether the nibbles of every acked frame are printed, see [`Timeline::flush_text`]
+    live_timeline: bool,
+    log: Log,
+    /// Number of frames that have been sent so far
+    seq: u32,
+    /// How often the current frame has been resent
+    retries: u32,
+    /// Echo frame and its length, that is sent as soon as the current frame is done
+    pending_echo: Option<(Frame, usize)>,
+    /// Data frame and its length, that is sent once the [`TxScheduler`] lets it
+    pending_frame: Option<(Frame, usize)>,
+    /// The data frame after the one in flight and its length,
+    /// encoded ahead of time so that it can be sent as soon as the ack arrives
+    prepared_frame: Option<(Frame, usize)>,
+    /// Frame in flight, after which the next frame has been tried to be encoded ahead of time
+    prepared_after: u32,
+    pre_encode: bool,
+    /// CFD or IFD for the last frame that has been received,
+    /// a CFD before any frame asks the other side for its first one
+    pending_ack: Option<EscapeCode>,
+    /// Decides between the pending ack and the pending data frame
+    scheduler: TxScheduler,
+    /// Encoded priority messages, that are sent before any other frame
+    priority: VecDeque<(Frame, usize)>,
+    /// Receives the priority messages of the other side, outside of the sink
+    on_priority: Option<PriorityHandler>,
+    /// Whether little data is sent in mini frames, instead of filling up a whole frame
+    mini_frames: bool,
+    /// Replies to our echo requests, that have not been looked at yet
+    echo_replies: Vec<Echo>,
+    /// What happened during the last poll
+    events: Vec<Event>,
+    /// Events the application has not taken yet, see [`Connection::drain_events`]
+    queue: EventQueue,
+    /// Number of data bytes in the frames that are sent
+    tx_frame_data_len: usize,
+    /// Number of data bytes in the frames that are received
+    rx_frame_data_len: usize,
+    /// Frame size that has to be requested from the other side
+    pending_frame_size: Option<usize>,
+    /// Number of received frames in a row, that were broken
+    consecutive_errors: u32,
+    /// Polls since a frame has been acked or received
+    stalled_polls: u32,
+    /// Acked and received frames during the last poll,
+    /// the idle pattern is decoded as well while both sides wait for each other
+    last_progress: (u32, u32),
+    /// Shows how frames are transformed by each layer
+    tap: Option<Box<dyn PipelineTap>>,
+    /// Records every decision, so that the transfer can be explained afterwards
+    session: Option<SessionLog>,
+    /// Whether the device has a clock line and is used in lockstep
+    clocked: bool,
+    /// Whether repeated values read from the device are the same nibble
+    edge_detection: bool,
+    /// Level of our clock line
+    clock: bool,
+    /// Level of the clock line of the other side, when it was last read
+    peer_clock: bool,
+    /// Whether the first nibble has been sent in lockstep
+    clock_started: bool,
+    /// Stops the connection from another thread
+    cancel: Option<CancellationToken>,
+    /// Whether ABT is being sent, because the connection has been cancelled
+    cancelling: bool,
+    /// Breaks the protocol on purpose, for negative tests
+    #[cfg(test)]
+    faults: Option<FaultInjector>,
+    /// Hashes of the payloads of the sent frames
+    sent_manifest: Option<Manifest>,
+    /// Hashes of the payloads of the received frames
+    received_manifest: Option<Manifest>,
+    /// When each data frame has been encoded, sent and acknowledged
+    latency: LatencyTracker,
+    /// How long to wait for the ack of a data frame, before it is resent
+    ack_timeout: AckTimeout,
+    /// Whether the frame that is written right now is a data frame
+    writing_data: bool,
+    /// When the last nibble of the data frame, that waits for its ack, has been sent
+    awaiting_ack_since: Option<Instant>,
+    /// Last received frame with a wrong checksum, to compare it with its retransmission
+    broken_frame: Option<Vec<u8>>,
+    /// Sent frames that have not been acked yet
+    sent_frames: Box<dyn FrameCache>,
+    /// Slows down how fast nibbles are sent
+    rate_limit: Option<RateLimiter>,
+    /// Line speed autodetection, while it is running
+    calibration: Option<Calibration>,
+    /// Nibbles of many polls are sent and read at once, if the device can
+    batch: Option<Batch>,
+    /// Transform the payload of every data frame
+    middleware: Vec<Box<dyn FrameMiddleware>>,
+    /// Session ids and acknowledged progress, to resume the transfer later
+    progress: ResumeToken,
+    /// Payload bytes of the last frame, that has not been acked yet
+    unacked_bytes: u64,
+    /// Whether the other side announced a different session than the resumed one
+    resume_rejected: bool,
+    /// Transformations of the payload, that the other side has to use too
+    features: Features,
+    /// Whether the features have to be announced before the next frame
+    announce_features: bool,
+    /// Polls since the features have been announced without an answer,
+    /// `None` once the other side has answered, data frames wait until then
+    unanswered_features: Option<u32>,
+    /// Whether the other side announced different features
+    features_rejected: bool,
+    /// Why the data source could not be read, which aborted the transfer
+    source_error: Option<std::io::Error>,
+    /// Why the received data could not be written, which aborted the transfer
+    sink_error: Option<std::io::Error>,
+    /// Undoes the compression of the other side, once it is enabled
+    decompressor: Option<Decompressor>,
+}
+
+impl<D: Device, I: Iterator<Item = std::io::Result<u8>>> Connection<D, I> {
+    fn new(device: D, bytes: I) -> Self {
+        Self::with_output(device, bytes, stdout())
+    }
+}
+
+impl<D: Device> Connection<D> {
+    /// Creates a connection whose type does not depend on the data source
+    fn boxed(device: D, bytes: impl Iterator<Item = std::io::Result<u8>> + 'static) -> Self {
+        Self::new(device, Box::new(bytes))
+    }
+}
+
+impl<D: Device, R: Read, S: Sink> Connection<D, Bytes<BufReader<R>>, S> {
+    /// Sends everything the reader returns.
+    ///
+    /// The reader is read one frame at a time,
+    /// so arbitrarily large sources are sent with constant memory.
+    fn send_reader(device: D, reader: R, output: S) -> Self {
+        Self::with_output(
+            device,
+            BufReader::with_capacity(FRAME_DATA_LEN, reader).bytes(),
+            output,
+        )
+    }
+}
+
+impl<D: Device> Connection<D, ChannelSource, MessageSink> {
+    /// Creates a connection that sends and receives whole messages instead of a byte stream,
+    /// the messages are queued through the returned sender, which can be moved to another thread.
+    fn with_messages(device: D, bound: usize) -> (Self, MessageSender) {
+        let (sender, source) = ChannelSource::new(bound);
+        let connection = Self::with_output(device, source, MessageSink::new());
+        (connection, MessageSender::new(sender))
+    }
+
+    /// The oldest message that has been received completely
+    pub fn recv_message(&mut self) -> Option<Vec<u8>> {
+        self.output.recv_message()
+    }
+
+    #[cfg(feature = "serde")]
+    #[cfg_attr(not(test), expect(dead_code))]
+    pub fn recv_msg<T: serde::de::DeserializeOwned>(
+        &mut self,
+    ) -> Option<Result<T, message::TypedMessageError>> {
+        self.output.recv_msg()
+    }
+}
+
+impl<D: Device, I: Iterator<Item = std::io::Result<u8>>, S: Sink> Connection<D, I, S> {
+    fn with_output(device: D, bytes: I, output: S) -> Self {
+        let clocked = device.read_clock().is_some();
+        let edge_detection = device.detects_edges();
+        let mut connection = Self {
+            device,
+            o_stream: OutputStream::new(),
+            i_stream: InputStream::new(),
+            data: Escaped::new(ReplaySource::new(Compressed::new(bytes))),
+            output,
+            done_receiving: false,
+            timeline: Timeline::new(D::NAME),
+            live_timeline: false,
+            log: Log::new(),
+            seq: 0,
+            retries: 0,
+            pending_echo: None,
+            pending_frame: None,
+            prepared_frame: None,
+            prepared_after: 0,
+            pre_encode: true,
+            pending_ack: Some(EscapeCode::CorrectFrameData),
+            scheduler: TxScheduler::default(),
+            priority: VecDeque::new(),
+            on_priority: None,
+            mini_frames: false,
+            echo_replies: Vec::new(),
+            events: Vec::new(),
+            queue: EventQueue::default(),
+            tx_frame_data_len: FRAME_DATA_LEN,
+            rx_frame_data_len: FRAME_DATA_LEN,
+            pending_frame_size: None,
+            consecutive_errors: 0,
+            stalled_polls: 0,
+            last_progress: (0, 0),
+            tap: None,
+            session: None,
+            clocked,
+            edge_detection,
+            clock: false,
+            peer_clock: false,
+            clock_started: false,
+            cancel: None,
+            cancelling: false,
+            #[cfg(test)]
+            faults: None,
+            sent_manifest: None,
+            received_manifest: None,
+            latency: LatencyTracker::new(),
+            ack_timeout: AckTimeout::default(),
+            writing_data: false,
+            awaiting_ack_since: None,
+            broken_frame: None,
+            sent_frames: Box::new(RetransmitCache::default()),
+            rate_limit: None,
+            calibration: None,
+            batch: None,
+            middleware: Vec::new(),
+            progress: ResumeToken::new(FRAME_DATA_LEN),
+            unacked_bytes: 0,
+            resume_rejected: false,
+            features: Features::default(),
+            announce_features: false,
+            unanswered_features: None,
+            features_rejected: false,
+            source_error: None,
+            sink_error: None,
+            decompressor: None,
+        };
+        connection.i_stream.set_edge_detection(edge_detection);
+        connection
+            .timeline
+            .set_layout(FRAME_DATA_LEN, CHECKSUM_LEN, edge_detection);
+        let capabilities = connection.device.capabilities();
+        if capabilities.max_batch > 1 && !clocked {
+            connection.batch = Some(Batch::new(capabilities.max_batch));
+        }
+        let idle = capabilities.idle_pattern();
+        connection.o_stream.set_idle_pattern(idle);
+        // the other side reads every nibble as well, so equal ones need no buffer in between
+        connection.o_stream.set_clocked(!edge_detection);
+        connection
+    }
+
+    /// Sets how anomalies in received data are handled
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        let nibble_order = self.i_stream.nibble_order();
+        let framing = self.i_stream.framing();
+        let (squelch, peer_idle) = self.i_stream.squelch();
+        let peer_idle = peer_idle.clone();
+        self.i_stream = InputStream::with_strictness(strictness);
+        self.i_stream.set_squelch(squelch, peer_idle);
+        self.i_stream.set_edge_detection(self.edge_detection);
+        self.i_stream.set_frame_data_len(self.rx_frame_data_len);
+        self.i_stream.set_nibble_order(nibble_order);
+        self.i_stream.set_framing(framing);
+    }
+
+    /// Sets the order in which the nibbles of a byte are sent.
+    ///
+    /// If the other side turns out to use the other order, both streams switch to it.
+    pub fn set_nibble_order(&mut self, order: NibbleOrder) {
+        self.i_stream.set_nibble_order(order);
+        self.o_stream.set_nibble_order(order);
+    }
+
+    /// Sets how both streams escape values that are equal to an escape code,
+    /// see [`framing::FramingKind::framing`]
+    pub fn set_framing(&mut self, framing: &'static dyn framing::Framing) {
+        self.i_stream.set_framing(framing);
+        self.o_stream.set_framing(framing);
+    }
+
+    /// Overrides [`device::DeviceRx::detects_edges`] of the device
+    pub fn set_edge_detection(&mut self, edge_detection: bool) {
+        self.edge_detection = edge_detection;
+        self.i_stream.set_edge_detection(edge_detection);
+        self.o_stream.set_clocked(!edge_detection);
+    }
+
+    /// Ignores the line until it has been idle long enough, e.g. while nothing drives the bus,
+    /// the other side is expected to send `peer_idle` while it has no frame to send
+    pub fn set_squelch(&mut self, squelch: Squelch, peer_idle: IdlePattern) {
+        self.i_stream.set_squelch(squelch, peer_idle);
+    }
+
+    /// Sets what is sent while there is no frame to send
+    pub fn set_idle_pattern(&mut self, idle: IdlePattern) {
+        self.o_stream.set_idle_pattern(idle);
+    }
+
+    /// Injects faults into the frames that are sent and the acks that are received
+    #[cfg(test)]
+    pub fn set_fault_injector(&mut self, faults: FaultInjector) {
+        self.faults = Some(faults);
+    }
+
+    #[cfg(test)]
+    pub fn fault_injector(&self) -> Option<&FaultInjector> {
+        self.faults.as_ref()
+    }
+
+    /// Adds a stage that transforms the payload of every data frame,
+    /// after the stages that have been added before
+    pub fn add_middleware(&mut self, stage: impl FrameMiddleware + 'static) {
+        self.middleware.push(Box::new(stage));
+    }
+
+    /// Replaces the adaptive ack timeout, e.g. with a fixed one for deterministic tests
+    pub fn set_ack_timeout(&mut self, timeout: AckTimeout) {
+        self.ack_timeout = timeout;
+    }
+
+    /// Round trip time that the ack timeout is estimated from, `None` if it is not adaptive
+    pub fn rtt(&self) -> Option<&RttEstimator> {
+        self.ack_timeout.estimator()
+    }
+
+    /// Sets whether acks or data frames go first, when both are waiting to be sent
+    pub fn set_schedule_policy(&mut self, policy: SchedulePolicy) {
+        self.scheduler = TxScheduler::new(policy);
+    }
+
+    /// Sends a small message ahead of the data frames that are waiting,
+    /// the other side hands it to its [`Connection::set_priority_handler`] instead of the sink.
+    ///
+    /// The message is not resent if it gets lost, see [`priority::encode`].
+    pub fn send_priority(&mut self, message: &[u8]) -> Result<(), MessageTooLong> {
+        self.priority.push_back(priority::encode(message)?);
+        Ok(())
+    }
+
+    /// Sends data that fits into [`MINI_FRAME_DATA_LEN`] bytes in a mini frame,
+    /// which the other side has to support, so that short messages are sent sooner
+    pub fn set_mini_frames(&mut self, mini_frames: bool) {
+        self.mini_frames = mini_frames;
+    }
+
+    /// Scrambles the payload of every data frame, see [`Scrambler`],
+    /// the other side has to enable it too
+    pub fn enable_scrambling(&mut self) {
+        if !self.features.scramble {
+            self.features.scramble = true;
+            self.add_middleware(Scrambler);
+            self.announce_features = true;
+            self.unanswered_features = Some(0);
+        }
+    }
+
+    /// Compresses the data before it is split into frames, see [`Compressed`],
+    /// and decompresses received frames, the other side has to enable it too.
+    ///
+    /// Has to be called before any frame is sent.
+    pub fn enable_compression(&mut self) {
+        if !self.features.compress {
+            self.features.compress = true;
+            self.data.get_mut().get_mut().enable();
+            self.decompressor = Some(Decompressor::new());
+            self.announce_features = true;
+            self.unanswered_features = Some(0);
+        }
+    }
+
+    /// Encodes the next frame while the current one is sent, instead of once it has been acked
+    pub fn set_pre_encode(&mut self, pre_encode: bool) {
+        self.pre_encode = pre_encode;
+    }
+
+    /// Called with every priority message the other side sends
+    pub fn set_priority_handler(&mut self, handler: impl FnMut(&[u8]) + 'static) {
+        self.on_priority = Some(Box::new(handler));
+    }
+
+    /// Limits how many bits are sent per second
+    pub fn set_rate_limit(&mut self, limiter: RateLimiter) {
+        self.rate_limit = Some(limiter);
+    }
+
+    /// Tries increasing rates until the other side stops replying, see [`Calibration`]
+    pub fn start_calibration(&mut self) {
+        self.calibration = Some(Calibration::new());
+        self.try_calibration_rate();
+    }
+
+    /// Sends the calibration pattern at the next rate, or adopts the result if there is none
+    fn try_calibration_rate(&mut self) {
+        let Some(calibration) = &self.calibration else {
+            return;
+        };
+        match calibration.rate() {
+            Some(rate) => {
+                self.rate_limit = Some(RateLimiter::new(rate));
+                self.send_echo(calibration_echo(rate, false));
+            }
+            None => {
+                // the slowest rate is kept, if not even that one got through
+                let rate = calibration.result().unwrap_or(CALIBRATION_RATES[0]);
+                self.log
+                    .event(format_args!("calibrated the line speed to {rate} bps"));
+                self.rate_limit = Some(RateLimiter::new(rate));
+                self.calibration = None;
+            }
+        }
+    }
+
+    /// Replaces the cache of unacked frames that are kept to be resent, before anything is sent,
+    /// e.g. with a larger one or a [`FixedRetransmitCache`] that never allocates
+    pub fn set_retransmit_cache(&mut self, cache: impl FrameCache + 'static) {
+        self.sent_frames = Box::new(cache);
+    }
+
+    /// Timestamps of every data frame that has been sent
+    pub fn latency(&self) -> &LatencyTracker {
+        &self.latency
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        Metrics::collect(
+            &self.latency,
+            &self.ack_timeout,
+            self.scheduler.policy(),
+            self.sent_frames.as_ref(),
+        )
+    }
+
+    /// Records the hash of every sent and received payload,
+    /// so that the transfer can be audited afterwards
+    pub fn record_manifests(&mut self) {
+        self.sent_manifest = Some(Manifest::new());
+        self.received_manifest = Some(Manifest::new());
+    }
+
+    /// Once the token is cancelled, ABT is sent and [`Connection::poll`] returns false.
+    pub fn set_cancellation(&mut self, token: CancellationToken) {
+        self.cancel = Some(token);
+    }
+
+    pub fn set_session_log(&mut self, session: SessionLog) {
+        self.session = Some(session);
+    }
+
+    fn record(&mut self, decision: Decision) {
+        if let Some(session) = &mut self.session {
+            session.record(self.seq, self.i_stream.received_frames(), decision);
+        }
+    }
+
+    /// Prints the nibbles that have been sent and received up to every ack to the log
+    pub fn set_live_timeline(&mut self, live: bool) {
+        self.live_timeline = live;
+    }
+
+    pub fn set_tap(&mut self, tap: impl PipelineTap + 'static) {
+        self.tap = Some(Box::new(tap));
+    }
+
+    /// Tells the other side to discard everything and starts over with the handshake.
+    ///
+    /// Data that has already been taken from the data source is not sent again.
+    pub fn reset(&mut self) {
+        self.o_stream.send_control(EscapeCode::Abort);
+        self.discard();
+    }
+
+    /// Resets sequence numbers and drops everything that has not been delivered
+    fn discard(&mut self) {
+        let nibble_order = self.i_stream.nibble_order();
+        let framing = self.i_stream.framing();
+        let (squelch, peer_idle) = self.i_stream.squelch();
+        let peer_idle = peer_idle.clone();
+        self.i_stream = InputStream::with_strictness(self.i_stream.strictness());
+        self.i_stream.set_squelch(squelch, peer_idle);
+        self.i_stream.set_edge_detection(self.edge_detection);
+        self.i_stream.set_nibble_order(nibble_order);
+        self.i_stream.set_framing(framing);
+        self.seq = 0;
+        self.retries = 0;
+        self.writing_data = false;
+        self.awaiting_ack_since = None;
+        self.unacked_bytes = 0;
+        self.pending_echo = None;
+        self.pending_frame = None;
+        self.prepared_frame = None;
+        self.prepared_after = 0;
+        self.pending_ack = Some(EscapeCode::CorrectFrameData);
+        self.priority.clear();
+        self.echo_replies.clear();
+        self.done_receiving = false;
+        self.tx_frame_data_len = FRAME_DATA_LEN;
+        self.rx_frame_data_len = FRAME_DATA_LEN;
+        self.pending_frame_size = None;
+        self.consecutive_errors = 0;
+        self.stalled_polls = 0;
+        self.last_progress = (0, 0);
+        self.sent_frames.clear();
+        // the handshake starts over
+        self.announce_features = !self.features.is_empty();
+        self.unanswered_features = self.announce_features.then_some(0);
+        if self.decompressor.is_some() {
+            self.decompressor = Some(Decompressor::new());
+        }
+    }
+
+    /// Asks the other side to send frames with `len` data bytes from now on.
+    ///
+    /// Frames of the old size that are still on their way will be rejected and resent.
+    pub fn request_frame_size(&mut self, len: usize) {
+        let len = len.clamp(1, FRAME_DATA_LEN);
+        self.rx_frame_data_len = len;
+        self.i_stream.set_frame_data_len(len);
+        self.pending_frame_size = Some(len);
+    }
+
+    /// Halves the frame size after too many broken frames in a row
+    fn track_error_rate(&mut self, broken: bool) {
+        if !broken {
+            self.consecutive_errors = 0;
+            return;
+        }
+        self.consecutive_errors += 1;
+        if self.consecutive_errors >= MAX_CONSECUTIVE_ERRORS
+            && self.rx_frame_data_len > MIN_FRAME_DATA_LEN
+        {
+            let len = (self.rx_frame_data_len / 2).max(MIN_FRAME_DATA_LEN);
+            self.record(Decision::RequestFrameSize {
+                len,
+                errors: self.consecutive_errors,
+            });
+            self.consecutive_errors = 0;
+            self.request_frame_size(len);
+        }
+    }
+
+    pub fn send_echo(&mut self, echo: Echo) {
+        self.pending_echo = Some(echo.encode());
+    }
+
+    /// Tells the other side the session id, so that the transfer can be resumed later
+    pub fn announce_session(&mut self) {
+        self.send_echo(Echo {
+            reply: false,
+            seq: SESSION_ECHO_SEQ,
+            timestamp: self.progress.id,
+        });
+    }
+
+    /// Continues a transfer saved with [`Connection::resume_token`],
+    /// the data source has to start after the bytes that have been acknowledged.
+    pub fn resume(&mut self, token: ResumeToken) {
+        self.continue_from(token);
+        self.announce_session();
+    }
+
+    /// Takes over the acknowledged progress and the negotiated parameters
+    fn continue_from(&mut self, token: ResumeToken) {
+        self.progress = token;
+        self.seq = token.sent_frames;
+        self.tx_frame_data_len = token.tx_frame_data_len.clamp(1, FRAME_DATA_LEN);
+        self.rx_frame_data_len = token.rx_frame_data_len.clamp(1, FRAME_DATA_LEN);
+        self.i_stream.set_frame_data_len(self.rx_frame_data_len);
+        self.i_stream.set_received_frames(token.received_frames);
+        self.set_nibble_order(token.nibble_order);
+        // ahead of the data, so that the other side drops what it would write twice
+        let offset = Echo {
+            reply: false,
+            seq: OFFSET_ECHO_SEQ,
+            timestamp: token.sent_bytes,
+        };
+        self.priority.push_back(offset.encode());
+    }
+
+    /// The other side sends again from `offset`, everything after it is removed from the output
+    fn peer_resumed_at(&mut self, offset: u64) {
+        match self.output.truncate(offset) {
+            Ok(()) => self.progress.received_bytes = offset,
+            // nothing to remove from outputs that can not be truncated
+            Err(err)
+                if err.kind() == std::io::ErrorKind::Unsupported
+                    && offset == self.progress.received_bytes => {}
+            Err(err) => self.log.event(format_args!(
+                "could not continue the output at byte {offset}: {err}"
+            )),
+        }
+    }
+
+    /// Everything needed to continue after a restart, see [`Snapshot`]
+    pub fn snapshot(&self) -> Snapshot {
+        // nothing is pending once the last frame has been acked
+        let pending = (self.seq > self.progress.sent_frames)
+            .then(|| self.sent_frames.get(self.seq))
+            .flatten();
+        Snapshot {
+            token: self.resume_token(),
+            retries: self.retries,
+            done_receiving: self.done_receiving,
+            pending,
+            pending_bytes: if pending.is_some() {
+                self.unacked_bytes
+            } else {
+                0
+            },
+        }
+    }
+
+    /// Continues where the snapshot was taken, without announcing the session again.
+    ///
+    /// The pending frame is sent again right away,
+    /// the data source has to start at [`Snapshot::source_offset`].
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.continue_from(snapshot.token);
+        self.retries = snapshot.retries;
+        self.done_receiving = snapshot.done_receiving;
+        if let Some((frame, len)) = snapshot.pending {
+            self.seq += 1;
+            self.unacked_bytes = snapshot.pending_bytes;
+            let _ = self.sent_frames.insert(self.seq, frame, len);
+            self.pending_frame = Some((frame, len));
+        }
+    }
+
+    pub fn resume_token(&self) -> ResumeToken {
+        ResumeToken {
+            tx_frame_data_len: self.tx_frame_data_len,
+            rx_frame_data_len: self.rx_frame_data_len,
+            nibble_order: self.i_stream.nibble_order(),
+            ..self.progress
+        }
+    }
+
+    pub fn resume_rejected(&self) -> bool {
+        self.resume_rejected
+    }
+
+    pub fn features_rejected(&self) -> bool {
+        self.features_rejected
+    }
+
+    pub fn source_error(&self) -> Option<&std::io::Error> {
+        self.source_error.as_ref()
+    }
+
+    /// The error that aborted the transfer, because the received data could not be written
+    pub fn sink_error(&self) -> Option<&std::io::Error> {
+        self.sink_error.as_ref()
+    }
+
+    /// Aborts if the other side transforms its payloads differently, see [`Features`]
+    fn peer_features(&mut self, peer: Features) {
+        if peer == self.features {
+            return;
+        }
+        self.log.event(format_args!(
+            "other side uses {peer}, but we use {}",
+            self.features
+        ));
+        self.events.push(Event::Error(EventError::FeatureMismatch));
+        self.features_rejected = true;
+        if !self.cancelling {
+            self.o_stream.send_control(EscapeCode::Abort);
+            self.cancelling = true;
+        }
+    }
+
+    /// Remembers the session id of the other side,
+    /// aborts if it is not the one the transfer has been resumed with
+    fn peer_announced(&mut self, peer_id: u64) {
+        match self.progress.peer_id {
+            Some(known) if known != peer_id => {
+                self.events.push(Event::Error(EventError::UnknownSession));
+                self.resume_rejected = true;
+                if !self.cancelling {
+                    self.o_stream.send_control(EscapeCode::Abort);
+                    self.cancelling = true;
+                }
+            }
+            _ => self.progress.peer_id = Some(peer_id),
+        }
+    }
+
+    pub fn state(&self) -> ConnState {
+        if self.is_closed() {
+            ConnState::Closed
+        } else if self.data.is_done() {
+            ConnState::Draining
+        } else if self.seq == 0 {
+            ConnState::Handshaking
+        } else if matches!(self.o_stream.state(), OutputState::WritingFrame)
+            || self.pending_frame.is_some()
+        {
+            ConnState::Transferring
+        } else {
+            ConnState::WaitingForAck {
+                seq: self.seq,
+                retries: self.retries,
+            }
+        }
+    }
+
+    /// Whether the watchdog has detected, that neither side makes any progress
+    pub fn is_stalled(&self) -> bool {
+        self.stalled_polls >= self.watchdog_polls()
+    }
+
+    /// Polls without progress after which the connection is stuck,
+    /// a batch of nibbles is only paced once, so it takes as long as a single poll
+    fn watchdog_polls(&self) -> u32 {
+        let batch = self.batch.as_ref().map_or(1, Batch::max_batch);
+        WATCHDOG_POLLS.saturating_mul(batch as u32)
+    }
+
+    /// Counts polls without progress and logs the state machines once stuck
+    fn watchdog(&mut self) {
+        let progress = (self.progress.sent_frames, self.progress.received_frames);
+        if progress != self.last_progress {
+            self.last_progress = progress;
+            self.stalled_polls = 0;
+            return;
+        }
+
+        self.stalled_polls += 1;
+        if self.stalled_polls == self.watchdog_polls() {
+            self.record(Decision::Stalled {
+                polls: self.stalled_polls,
+            });
+            self.log.event(format_args!(
+                "stalled for {} polls: connection {}, input {}, output {}",
+                self.stalled_polls,
+                self.state(),
+                self.i_stream.state(),
+                self.o_stream.state()
+            ));
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.data.is_done() && self.done_receiving
+    }
+
+    /// Only exchanges nibbles once the clock of the other side has changed,
+    /// so that both sides advance in lockstep and no nibble is read twice or missed.
+    ///
+    /// Returns the sent and the received nibble, if the other side has sent a new one.
+    fn exchange_clocked(&mut self) -> Option<(u8, u8)> {
+        let level = self.device.read_clock().unwrap_or(self.peer_clock);
+        let edge = level != self.peer_clock;
+        // the first nibble is sent without waiting, so that both sides can get started
+        if !edge && self.clock_started {
+            return None;
+        }
+        self.peer_clock = level;
+        self.clock_started = true;
+
+        let nibble_in = self.device.read();
+        let released = self.o_stream.is_released();
+        let nibble_out = self.o_stream.next();
+        if released {
+            self.device.release();
+        } else {
+            self.device.send(nibble_out);
+        }
+        self.clock = !self.clock;
+        self.device.send_clock(self.clock);
+        edge.then_some((nibble_out, nibble_in))
+    }
+
+    /// Sends the nibbles that are still queued in the [`Batch`] and in the device
+    pub fn flush_batch(&mut self) {
+        if let Some(batch) = &mut self.batch {
+            batch.flush(&mut self.device);
+        }
+        self.device.flush();
+    }
+
+    /// Events that happened during the last poll
+    #[cfg(test)]
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Replaces the queue of events for the application, e.g. with a smaller or larger one
+    pub fn set_event_queue(&mut self, queue: EventQueue) {
+        self.queue = queue;
+    }
+
+    /// Takes every event the application has not taken yet, oldest first,
+    /// unlike [`Connection::events`] it does not have to be called after every poll
+    pub fn drain_events(&mut self) -> impl Iterator<Item = Event> + '_ {
+        self.queue.drain()
+    }
+
+    /// Events that have been lost, because the application did not take them in time
+    pub fn dropped_events(&self) -> u64 {
+        self.queue.dropped()
+    }
+
+    /// The frame that is sent instead, once the [`FaultInjector`] has broken it
+    #[cfg(test)]
+    fn inject_frame_faults(&mut self, mut frame: Frame, len: usize) -> (Frame, usize) {
+        let len = match &mut self.faults {
+            Some(faults) => faults.frame(&mut frame, len),
+            None => len,
+        };
+        (frame, len)
+    }
+
+    /// Whether the reply is dropped or delayed on purpose by the [`FaultInjector`]
+    #[cfg(test)]
+    fn inject_fault(&mut self, event: &InputEvent) -> bool {
+        let Some(faults) = &mut self.faults else {
+            return false;
+        };
+        match event {
+            InputEvent::Ack { .. } => faults.drop_ack(),
+            InputEvent::Nak { .. } => faults.delay_retransmission(),
+            _ => false,
+        }
+    }
+
+    /// Encodes the next frame from the data source,
+    /// returns the frame and the number of its bytes that have to be sent.
+    ///
+    /// With mini frames, data that fits into [`MINI_FRAME_DATA_LEN`] bytes is not filled up
+    /// to a whole frame, e.g. a short message that has just been queued.
+    fn encode_next_frame(&mut self) -> std::io::Result<(Frame, usize)> {
+        let data_len = self.tx_frame_data_len;
+        if !self.middleware.is_empty() {
+            return encode_transformed_frame(&mut self.data, data_len, &mut self.middleware);
+        }
+        if !self.mini_frames || data_len <= MINI_FRAME_DATA_LEN {
+            return encode_partial_frame(&mut self.data, data_len);
+        }
+
+        let (frame, len, values) = encode_values(&mut self.data, data_len)?;
+        if values > MINI_FRAME_DATA_LEN {
+            return Ok((frame, len));
+        }
+        // the source has been checkpointed right before, so it is read again
+        self.data.rollback();
+        let (mut frame, len) = encode_partial_frame(&mut self.data, MINI_FRAME_DATA_LEN)?;
+        frame[0] = EscapeCode::StartOfMiniFrame as u8;
+        Ok((frame, len))
+    }
+
+    /// Encodes the frame after the one in flight, so that the wire does not wait for it.
+    ///
+    /// Only full frames are kept, a source that has not produced enough data yet
+    /// is read again once the ack arrives. Frames that go through middleware are always
+    /// encoded once acked, since the stages might keep state.
+    fn prepare_next_frame(&mut self) {
+        if !self.pre_encode
+            || self.prepared_frame.is_some()
+            || self.prepared_after == self.seq
+            || !self.middleware.is_empty()
+        {
+            return;
+        }
+        // the frame in flight can not be encoded again once the source moved on
+        if self.sent_frames.get(self.seq).is_none() {
+            return;
+        }
+        self.prepared_after = self.seq;
+        self.data.checkpoint();
+
+        let data_len = self.tx_frame_data_len;
+        let (frame, len, values) = match encode_values(&mut self.data, data_len) {
+            Ok(encoded) => encoded,
+            Err(err) => return self.source_failed(err),
+        };
+        if values < data_len {
+            self.data.rollback();
+            return;
+        }
+        self.prepared_frame = Some((frame, len));
+    }
+
+    /// Queues the frame after the acked one, prepared or encoded from the source
+    fn send_next_frame(&mut self) {
+        let encoded = match self.prepared_frame.take() {
+            Some(prepared) => Ok(prepared),
+            None => {
+                self.data.checkpoint();
+                self.encode_next_frame()
+            }
+        };
+        let (frame, len) = match encoded {
+            Ok(encoded) => encoded,
+            Err(err) => return self.source_failed(err),
+        };
+        // cached before any faults are injected, so that the resent frame is intact
+        if let Err(full) = self.sent_frames.insert(self.seq + 1, frame, len) {
+            self.log.event(format_args!(
+                "retransmit cache full with {} frames ({} B), waiting for frame {} to be acked",
+                self.sent_frames.len(),
+                self.sent_frames.bytes_used(),
+                full.oldest_seq
+            ));
+        }
+        let data_end = len - ESCAPED_CHECKSUM_LEN - ESCAPE_CODE_LEN;
+        let payload = tap::unescape(&frame[ESCAPE_CODE_LEN..data_end]);
+        self.unacked_bytes = payload.len() as u64;
+        if let Some(manifest) = &mut self.sent_manifest {
+            manifest.record(self.seq + 1, &payload);
+        }
+        #[cfg(test)]
+        let (frame, len) = self.inject_frame_faults(frame, len);
+        if let Some(tap) = &mut self.tap {
+            tap::tap_sent(tap.as_mut(), &frame[..len]);
+        }
+        self.pending_frame = Some((frame, len));
+        self.seq += 1;
+        self.latency.encoded(self.seq);
+        self.retries = 0;
+        self.events.push(Event::FrameSent { seq: self.seq });
+    }
+
+    /// Aborts the transfer, since the data can not be read
+    fn source_failed(&mut self, err: std::io::Error) {
+        self.log.event(format_args!("could not read the data: {err}"));
+        self.source_error = Some(err);
+        if !self.cancelling {
+            self.o_stream.send_control(EscapeCode::Abort);
+            self.cancelling = true;
+        }
+    }
+
+    /// Aborts the transfer, since the received data can not be written
+    fn sink_failed(&mut self, err: std::io::Error) {
+        self.log.event(format_args!("could not write the data: {err}"));
+        self.sink_error = Some(err);
+        self.events.push(Event::Error(EventError::SinkFailed));
+        if !self.cancelling {
+            self.o_stream.send_control(EscapeCode::Abort);
+            self.cancelling = true;
+        }
+    }
+
+    fn resend(&mut self) {
+        #[cfg(test)]
+        let truncated = self.faults.as_mut().and_then(FaultInjector::take_truncated);
+        #[cfg(not(test))]
+        let truncated = None;
+        self.pending_frame = match truncated.or_else(|| self.sent_frames.get(self.seq)) {
+            Some(frame) => Some(frame),
+            // the frame did not fit into the cache, so it is encoded again
+            None if self.seq > 0 => {
+                self.data.rollback();
+                match self.encode_next_frame() {
+                    Ok(frame) => Some(frame),
+                    Err(err) => return self.source_failed(err),
+                }
+            }
+            // no data frame has been sent yet, the output stream only held echoes
+            // or control frames, which are never resent
+            None => return,
+        };
+        self.retries += 1;
+        self.awaiting_ack_since = None;
+        self.latency.resent(self.seq);
+        self.events.push(Event::Resend {
+            seq: self.seq,
+            retries: self.retries,
+        });
+    }
+
+    // Returns false when all data has been sent and received
+    fn poll(&mut self) -> bool {
+        self.events.clear();
+
+        // the device is left alone until the next nibble may be sent
+        if self
+            .rate_limit
+            .as_mut()
+            .is_some_and(|limit| !limit.try_send_symbol())
+        {
+            return true;
+        }
+
+        #[cfg(test)]
+        if self.faults.as_mut().is_some_and(FaultInjector::poll) {
+            self.resend();
+        }
+
+        let ack_overdue = self
+            .awaiting_ack_since
+            .zip(self.ack_timeout.timeout())
+            .is_some_and(|(sent, timeout)| sent.elapsed() >= timeout);
+        if ack_overdue && !self.cancelling {
+            self.ack_timeout.timed_out();
+            self.log.event(format_args!("frame {} has not been acked in time", self.seq));
+            self.resend();
+        }
+
+        if self.calibration.as_mut().is_some_and(Calibration::poll) {
+            self.try_calibration_rate();
+        }
+
+        let cancelled = self
+            .cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled);
+        if cancelled && !self.cancelling {
+            self.o_stream.send_control(EscapeCode::Abort);
+            self.cancelling = true;
+            self.events.push(Event::Cancelled);
+        }
+
+        // the announcement or its answer got lost
+        if let Some(polls) = &mut self.unanswered_features {
+            *polls += 1;
+            if *polls >= FEATURES_RETRY_POLLS {
+                self.announce_features = true;
+            }
+        }
+        // ahead of everything else, so that the other side knows before the first frame
+        if std::mem::take(&mut self.announce_features) {
+            self.priority.push_front(self.features.announcement());
+            if let Some(polls) = &mut self.unanswered_features {
+                *polls = 0;
+            }
+        }
+        if !self.cancelling && matches!(self.o_stream.state(), OutputState::WaitingForFrame) {
+            if let Some(len) = self.pending_frame_size.take() {
+                let payload = frame_size_payload(len);
+                let (mut frame, wire_len) = encode_partial_frame(
+                    &mut Escaped::new(payload.into_iter().map(Ok)),
+                    FRAME_SIZE_LEN,
+                )
+                .expect("frame size is in memory");
+                frame[0] = EscapeCode::SetFrameSize as u8;
+                self.o_stream.send_frame(frame, wire_len);
+            } else if let Some((frame, len)) = self.priority.pop_front() {
+                self.o_stream.send_frame(frame, len);
+            } else if let Some(slot) = self
+                .scheduler
+                .next(
+                    self.pending_ack.is_some(),
+                    self.pending_frame.is_some() && self.unanswered_features.is_none(),
+                )
+            {
+                match slot {
+                    Slot::Ack => {
+                        let ack = self.pending_ack.take().expect("ack is pending");
+                        self.o_stream.send_control(ack);
+                    }
+                    Slot::Data => {
+                        let (frame, len) = self.pending_frame.take().expect("frame is pending");
+                        self.o_stream.send_frame(frame, len);
+                        self.writing_data = true;
+                    }
+                }
+            } else if let Some((frame, len)) = self.pending_echo.take() {
+                self.o_stream.send_frame(frame, len);
+            }
+        }
+
+        let was_writing = matches!(self.o_stream.state(), OutputState::WritingFrame);
+        let exchanged = if self.clocked {
+            self.exchange_clocked()
+        } else if let Some(batch) = &mut self.batch {
+            let nibble_out = self.o_stream.next();
+            Some((nibble_out, batch.exchange(&mut self.device, nibble_out)))
+        } else {
+            let released = self.o_stream.is_released();
+            let nibble_out = self.o_stream.next();
+            if released {
+                self.device.release();
+            } else {
+                self.device.send(nibble_out);
+            }
+            Some((nibble_out, self.device.read()))
+        };
+        if exchanged.is_some() && was_writing {
+            let writing = matches!(self.o_stream.state(), OutputState::WritingFrame);
+            self.latency.nibble_sent(writing);
+            // the frame is complete, so it goes out in one piece
+            if !writing {
+                self.flush_batch();
+            }
+            if !writing && self.writing_data {
+                self.writing_data = false;
+                self.awaiting_ack_since = Some(Instant::now());
+            }
+        }
+        let event = match exchanged {
+            Some((nibble_out, nibble_in)) => {
+                self.timeline.record(nibble_out, nibble_in);
+                if let Some(tap) = &mut self.tap {
+                    tap.wire(Direction::Send, nibble_out);
+                    tap.wire(Direction::Receive, nibble_in);
+                }
+                // acks and naks refer to the frame that has been sent last
+                self.i_stream.set_in_flight(self.seq);
+                self.i_stream.push(nibble_in)
+            }
+            None => InputEvent::LinkIdle,
+        };
+        // answer in the order the other side has been detected to use
+        self.o_stream.set_nibble_order(self.i_stream.nibble_order());
+        let idle = matches!(event, InputEvent::LinkIdle)
+            && matches!(self.i_stream.state(), InputState::WaitingForFrame)
+            && matches!(self.o_stream.state(), OutputState::WaitingForFrame);
+
+        match event {
+            InputEvent::DataFrame {
+                seq,
+                kind,
+                payload: frame,
+            } => {
+                // mini frames keep their size, whatever frame size has been requested
+                let data_len = match kind {
+                    FrameKind::Mini => MINI_FRAME_DATA_LEN,
+                    FrameKind::Full => self.rx_frame_data_len,
+                };
+                match decode_frame(&frame, data_len) {
+                    Some(data) => {
+                        if let Some(tap) = &mut self.tap {
+                            tap::tap_received(tap.as_mut(), data);
+                        }
+                        if let Some(broken) = self.broken_frame.take() {
+                            let diff = debugfmt::frame_diff(&broken, &frame);
+                            if diff.is_empty() {
+                                self.log.event(format_args!(
+                                    "frame {seq} was retransmitted unchanged"
+                                ));
+                            } else {
+                                // the log ends up on stderr
+                                let color = std::io::stderr().is_terminal();
+                                self.log.event(format_args!(
+                                    "frame {seq} was retransmitted, changed:\n{diff}{}",
+                                    debugfmt::hex_dump(&frame, color)
+                                ));
+                            }
+                        }
+                        // the stream also counts frames that failed the checksum, which are sent again
+                        if let Some(manifest) = &mut self.received_manifest {
+                            manifest.record_next(data);
+                        }
+                        let mut payload = data.to_vec();
+                        middleware::on_receive(&mut self.middleware, &mut payload);
+                        if let Some(decompressor) = &mut self.decompressor {
+                            payload = decompressor.decompress(&payload);
+                        }
+                        match self.output.receive(&payload) {
+                            Ok(()) => {
+                                self.progress.received_frames = seq;
+                                self.progress.received_bytes += payload.len() as u64;
+                                self.events.push(Event::Received {
+                                    seq,
+                                    len: data.len(),
+                                });
+                                self.pending_ack = Some(EscapeCode::CorrectFrameData);
+                                self.track_error_rate(false);
+                            }
+                            // not acked, the other side must not count it as delivered
+                            Err(err) => self.sink_failed(err),
+                        }
+                    }
+                    None => {
+                        self.broken_frame = Some(frame.to_vec());
+                        self.events
+                            .push(Event::Error(EventError::ChecksumMismatch { seq }));
+                        self.pending_ack = Some(EscapeCode::IncorrectFrameData);
+                        self.track_error_rate(true);
+                    }
+                }
+            }
+            InputEvent::Control(ControlMsg::Echo(data)) => match Echo::from_bytes(&data) {
+                Some(echo) if echo.seq == CALIBRATION_ECHO_SEQ => {
+                    // a broken pattern is not replied to, so the rate is found too fast
+                    if let Some(rate) = calibration_rate(&echo) {
+                        if !echo.reply {
+                            self.send_echo(calibration_echo(rate, true));
+                        } else if self
+                            .calibration
+                            .as_mut()
+                            .is_some_and(|calibration| calibration.replied(rate))
+                        {
+                            self.try_calibration_rate();
+                        }
+                    }
+                }
+                Some(echo) if echo.seq == PRIORITY_ECHO_SEQ => match priority::decode(&data) {
+                    Some(message) => {
+                        if let Some(handler) = &mut self.on_priority {
+                            handler(message);
+                        }
+                        self.events.push(Event::PriorityReceived { len: message.len() });
+                    }
+                    None => self.events.push(Event::Error(EventError::InvalidEcho)),
+                },
+                Some(echo) if echo.seq == OFFSET_ECHO_SEQ => self.peer_resumed_at(echo.timestamp),
+                Some(echo) if echo.seq == FEATURES_ECHO_SEQ => {
+                    if echo.reply {
+                        self.unanswered_features = None;
+                    } else {
+                        // answered with our features, so that a side that uses none notices too
+                        self.send_echo(Echo {
+                            reply: true,
+                            seq: FEATURES_ECHO_SEQ,
+                            timestamp: self.features.to_bits(),
+                        });
+                    }
+                    self.peer_features(Features::from_bits(echo.timestamp))
+                }
+                Some(echo) if echo.seq == SESSION_ECHO_SEQ => {
+                    self.peer_announced(echo.timestamp);
+                    if echo.reply {
+                        self.events.push(Event::EchoReply { seq: echo.seq });
+                    } else {
+                        self.events.push(Event::EchoRequest { seq: echo.seq });
+                        // answered with our id instead of theirs
+                        self.send_echo(Echo {
+                            reply: true,
+                            seq: SESSION_ECHO_SEQ,
+                            timestamp: self.progress.id,
+                        });
+                    }
+                }
+                Some(echo) if echo.reply => {
+                    self.events.push(Event::EchoReply { seq: echo.seq });
+                    self.echo_replies.push(echo);
+                }
+                Some(echo) => {
+                    self.events.push(Event::EchoRequest { seq: echo.seq });
+                    self.send_echo(Echo {
+                        reply: true,
+                        ..echo
+                    });
+                }
+                None => self.events.push(Event::Error(EventError::InvalidEcho)),
+            },
+            // nothing is sent anymore, after ABT
+            InputEvent::Ack { .. } | InputEvent::Nak { .. } if self.cancelling => (),
+            #[cfg(test)]
+            InputEvent::Ack { .. } | InputEvent::Nak { .. } if self.inject_fault(&event) => (),
+            InputEvent::Ack { seq } => {
+                if self.live_timeline {
+                    let text = self.timeline.flush_text();
+                    self.log.text(text);
+                }
+                let acked_after = self.awaiting_ack_since.take().map(|sent| sent.elapsed());
+                // it is unknown which transmission of a resent frame has been acked
+                if let Some(rtt) = acked_after.filter(|_| self.retries == 0) {
+                    self.ack_timeout.sample(rtt);
+                }
+                // the first ack only asks for the first frame
+                if seq > 0 {
+                    self.latency.acked(seq);
+                    self.sent_frames.ack_through(seq);
+                    self.progress.sent_frames = seq;
+                    self.progress.sent_bytes += std::mem::take(&mut self.unacked_bytes);
+                    self.events.push(Event::Acked { seq });
+                }
+                self.send_next_frame();
+            }
+            InputEvent::Nak { .. } => self.resend(),
+            InputEvent::Control(ControlMsg::FinishedSending) => {
+                self.done_receiving = true;
+                self.events.push(Event::PeerFinished);
+            }
+            InputEvent::Control(ControlMsg::FrameOverrun) => {
+                self.events.push(Event::Error(EventError::FrameOverrun));
+                // requested again like a broken frame, the dropped data was never acked
+                self.pending_ack = Some(EscapeCode::IncorrectFrameData);
+                self.track_error_rate(true);
+            }
+            InputEvent::Control(ControlMsg::MalformedFrame) => {
+                self.events.push(Event::Error(EventError::MalformedFrame));
+                self.pending_ack = Some(EscapeCode::IncorrectFrameData);
+                self.track_error_rate(true);
+            }
+            InputEvent::Control(ControlMsg::SetFrameSize(len)) => {
+                self.tx_frame_data_len = len.clamp(1, FRAME_DATA_LEN);
+                // encoded again with the new size
+                if self.prepared_frame.take().is_some() {
+                    self.data.rollback();
+                }
+                self.events.push(Event::FrameSizeChanged {
+                    len: self.tx_frame_data_len,
+                });
+            }
+            InputEvent::Control(ControlMsg::Abort) => {
+                let idle = self.o_stream.idle_pattern().clone();
+                let framing = self.o_stream.framing();
+                self.o_stream = OutputStream::new();
+                self.o_stream.set_idle_pattern(idle);
+                self.o_stream.set_framing(framing);
+                self.o_stream.set_clocked(!self.edge_detection);
+                self.o_stream.set_nibble_order(self.i_stream.nibble_order());
+                self.discard();
+                self.events.push(Event::Aborted);
+            }
+            InputEvent::LinkIdle => (),
+        };
+        if !self.cancelling {
+            self.prepare_next_frame();
+        }
+
+        if idle {
+            self.log.idle();
+        } else if self.events.is_empty() {
+            self.log.busy();
+        }
+        for event in &self.events {
+            self.log.event(format_args!("{} {event}", D::NAME));
+        }
+        if let Some(session) = &mut self.session {
+            for event in &self.events {
+                session.record(
+                    self.seq,
+                    self.i_stream.received_frames(),
+                    Decision::Event(*event),
+                );
+            }
+        }
+        for event in &self.events {
+            self.queue.push(*event);
+        }
 
         self.device.debug_poll();
+        self.watchdog();
 
-        !(self.data.is_done() && self.done_receiving)
+        let cancelled =
+            self.cancelling && matches!(self.o_stream.state(), OutputState::WaitingForFrame);
+        !self.is_closed() && !self.is_stalled() && !cancelled
     }
 }
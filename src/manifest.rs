@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::checksum::crc32;
+
+/// Length and CRC-32 of the payload of one frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub seq: u32,
+    pub len: usize,
+    pub hash: u32,
+}
+
+/// # Manifest
+///
+/// Hashes of the payload of every frame, so that the receiver can make sure afterwards,
+/// that nothing has been corrupted without the per-frame checksum noticing.
+///
+/// Written as one `<seq> <len> <crc32>` line per frame.
+/// A frame that is sent or received again keeps the entry of its first time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    entries: BTreeMap<u32, Entry>,
+}
+
+/// A frame that does not match the manifest of the other side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The frame has been sent, but never received
+    Missing { seq: u32 },
+    /// The frame has been received with a different payload
+    Corrupted { seq: u32 },
+    /// The frame has been received, but never sent
+    Unexpected { seq: u32 },
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the payload of a frame, unless the frame has been recorded before
+    pub fn record(&mut self, seq: u32, payload: &[u8]) {
+        self.entries.entry(seq).or_insert(Entry {
+            seq,
+            len: payload.len(),
+            hash: crc32(payload),
+        });
+    }
+
+    /// Records the payload of the frame after the last recorded one,
+    /// the receiver only counts frames that passed the checksum, like the sender sent them
+    pub fn record_next(&mut self, payload: &[u8]) {
+        let seq = self.entries.last_key_value().map_or(1, |(seq, _)| seq + 1);
+        self.record(seq, payload);
+    }
+
+    pub fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        for entry in self.entries.values() {
+            writeln!(output, "{} {} {:08x}", entry.seq, entry.len, entry.hash)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(input: impl BufRead) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid manifest entry");
+        let mut entries = BTreeMap::new();
+        for line in input.lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let (Some(seq), Some(len), Some(hash), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let seq = seq.parse().map_err(|_| invalid())?;
+            entries.entry(seq).or_insert(Entry {
+                seq,
+                len: len.parse().map_err(|_| invalid())?,
+                hash: u32::from_str_radix(hash, 16).map_err(|_| invalid())?,
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        self.write(&mut File::create(path)?)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Compares the frames that have been received with this manifest of the sent frames
+    pub fn verify(&self, received: &Manifest) -> Vec<Mismatch> {
+        let mut mismatches = Vec::new();
+        for sent in self.entries.values() {
+            match received.entries.get(&sent.seq) {
+                None => mismatches.push(Mismatch::Missing { seq: sent.seq }),
+                Some(entry) if entry != sent => {
+                    mismatches.push(Mismatch::Corrupted { seq: sent.seq })
+                }
+                Some(_) => (),
+            }
+        }
+        for entry in received.entries.values() {
+            if !self.entries.contains_key(&entry.seq) {
+                mismatches.push(Mismatch::Unexpected { seq: entry.seq });
+            }
+        }
+        mismatches
+    }
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing { seq } => write!(f, "frame {seq} has never been received"),
+            Self::Corrupted { seq } => write!(f, "frame {seq} has been corrupted"),
+            Self::Unexpected { seq } => write!(f, "frame {seq} has never been sent"),
+        }
+    }
+}
+
+#[test]
+fn verify_manifest() {
+    let mut sent = Manifest::new();
+    sent.record(1, b"first");
+    sent.record(2, b"second");
+    sent.record(3, b"third");
+
+    let mut written = Vec::new();
+    sent.write(&mut written).unwrap();
+    let sent = Manifest::read(written.as_slice()).unwrap();
+
+    let mut received = Manifest::new();
+    received.record_next(b"first");
+    // resent after its ack got lost
+    received.record(1, b"first");
+    received.record_next(b"secoNd");
+    received.record(4, b"fourth");
+    assert_eq!(
+        sent.verify(&received),
+        [
+            Mismatch::Corrupted { seq: 2 },
+            Mismatch::Missing { seq: 3 },
+            Mismatch::Unexpected { seq: 4 }
+        ]
+    );
+    assert!(Manifest::read("1 5".as_bytes()).is_err());
+}
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::{SendError, SyncSender};
+
+use crate::sink::Sink;
+
+/// Starts every message, so that the zeros that fill up a frame
+/// while no message is queued can be skipped
+const MESSAGE_START: u8 = 0xa5;
+
+/// Start byte and big endian length in front of every message
+const HEADER_LEN: usize = 1 + 4;
+
+/// The message with its header, as it is sent
+pub fn encode_message(message: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + message.len());
+    bytes.push(MESSAGE_START);
+    bytes.extend((message.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(message);
+    bytes
+}
+
+/// # MessageSender
+///
+/// Queues messages for a connection created with [`crate::Connection::with_messages`],
+/// every message is received as one unit by [`MessageSink::recv_message`],
+/// no matter how many frames it spans.
+#[derive(Clone)]
+pub struct MessageSender {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl MessageSender {
+    pub fn new(sender: SyncSender<Vec<u8>>) -> Self {
+        Self { sender }
+    }
+
+    /// Blocks while the queue is full, fails once the connection has been dropped
+    pub fn send_message(&self, message: &[u8]) -> Result<(), SendError<Vec<u8>>> {
+        self.sender.send(encode_message(message))
+    }
+
+    /// Sends the value as one message, serialized with postcard.
+    ///
+    /// Typed messages are for applications that embed the connection,
+    /// the binary itself only sends raw messages.
+    #[cfg(feature = "serde")]
+    #[cfg_attr(not(test), expect(dead_code))]
+    pub fn send_msg<T: serde::Serialize>(&self, value: &T) -> Result<(), TypedMessageError> {
+        let message = postcard::to_allocvec(value).map_err(TypedMessageError::Postcard)?;
+        self.send_message(&message)
+            .map_err(|_| TypedMessageError::Disconnected)
+    }
+}
+
+/// Why a typed message could not be sent or received
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum TypedMessageError {
+    Postcard(postcard::Error),
+    /// The connection has been dropped
+    Disconnected,
+}
+
+#[cfg(feature = "serde")]
+impl std::fmt::Display for TypedMessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Postcard(err) => write!(f, "could not serialize the message: {err}"),
+            Self::Disconnected => write!(f, "the connection has been dropped"),
+        }
+    }
+}
+
+/// # MessageSink
+///
+/// Reassembles the messages sent by a [`MessageSender`] from the received bytes.
+///
+/// Bytes outside of a message are skipped,
+/// so a broken header only loses the messages up to the next intact one.
+#[derive(Debug, Default)]
+pub struct MessageSink {
+    /// Received bytes of the message that is not complete yet, starting with its header
+    buffer: Vec<u8>,
+    messages: VecDeque<Vec<u8>>,
+}
+
+impl MessageSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The oldest complete message
+    pub fn recv_message(&mut self) -> Option<Vec<u8>> {
+        self.messages.pop_front()
+    }
+
+    /// The oldest complete message, deserialized with postcard
+    #[cfg(feature = "serde")]
+    pub fn recv_msg<T: serde::de::DeserializeOwned>(
+        &mut self,
+    ) -> Option<Result<T, TypedMessageError>> {
+        let message = self.recv_message()?;
+        Some(postcard::from_bytes(&message).map_err(TypedMessageError::Postcard))
+    }
+}
+
+impl Sink for MessageSink {
+    fn receive(&mut self, data: &[u8]) -> io::Result<()> {
+        for &byte in data {
+            if self.buffer.is_empty() && byte != MESSAGE_START {
+                continue;
+            }
+            self.buffer.push(byte);
+            let Some(len) = self.buffer.get(1..HEADER_LEN) else {
+                continue;
+            };
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            if self.buffer.len() == HEADER_LEN + len {
+                let message = self.buffer.split_off(HEADER_LEN);
+                self.buffer.clear();
+                self.messages.push_back(message);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn messages_across_frames() {
+    let mut bytes = encode_message(b"read sensor 3");
+    bytes.extend([0; 7]);
+    bytes.extend(encode_message(&[]));
+    bytes.extend(encode_message(&[MESSAGE_START, 0]));
+
+    let mut sink = MessageSink::new();
+    for chunk in bytes.chunks(4) {
+        sink.receive(chunk).unwrap();
+    }
+    assert_eq!(sink.recv_message().as_deref(), Some(&b"read sensor 3"[..]));
+    assert_eq!(sink.recv_message().as_deref(), Some(&[][..]));
+    assert_eq!(
+        sink.recv_message().as_deref(),
+        Some(&[MESSAGE_START, 0][..])
+    );
+    assert_eq!(sink.recv_message(), None);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn typed_messages() {
+    let (sender, mut source) = crate::source::ChannelSource::new(2);
+    let sender = MessageSender::new(sender);
+    sender
+        .send_msg(&(3u8, String::from("temperature")))
+        .unwrap();
+    sender.send_msg(&-21i32).unwrap();
+
+    let mut sink = MessageSink::new();
+    let bytes: Vec<u8> = source.by_ref().map(Result::unwrap).collect();
+    sink.receive(&bytes).unwrap();
+    let request: (u8, String) = sink.recv_msg().unwrap().unwrap();
+    assert_eq!(request, (3, "temperature".into()));
+    assert_eq!(sink.recv_msg::<i32>().unwrap().unwrap(), -21);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn typed_messages_over_a_connection() {
+    use crate::sim::SimPort;
+    use crate::Connection;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Reading {
+        sensor: u8,
+        name: String,
+        celsius: i32,
+    }
+
+    let (port, other_port) = SimPort::pair(true);
+    let (mut sender_side, sender) = Connection::with_messages(port, 2);
+    let (mut receiver_side, _) = Connection::with_messages(other_port, 2);
+    let reading = Reading {
+        sensor: 3,
+        name: "temperature".into(),
+        celsius: -21,
+    };
+    sender.send_msg(&reading).unwrap();
+
+    let mut received = None;
+    for _ in 0..100_000 {
+        sender_side.poll();
+        receiver_side.poll();
+        received = receiver_side.recv_msg::<Reading>();
+        if received.is_some() {
+            break;
+        }
+    }
+    assert_eq!(received.unwrap().unwrap(), reading);
+}
@@ -0,0 +1,129 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::latency::LatencyTracker;
+use crate::retransmit::FrameCache;
+#[cfg(test)]
+use crate::retransmit::RetransmitCache;
+use crate::rtt::AckTimeout;
+use crate::schedule::SchedulePolicy;
+
+/// # Metrics
+///
+/// Counters and estimates of a [`crate::Connection`] after a transfer,
+/// written as JSON with `--metrics`, so that runs can be compared by scripts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    pub frames: usize,
+    pub retransmissions: u32,
+    /// `None` until the first ack has been measured, or if the ack timeout is not adaptive
+    pub rtt_smoothed: Option<Duration>,
+    pub rtt_variation: Option<Duration>,
+    /// `None` if frames are only resent once the other side asks for it
+    pub ack_timeout: Option<Duration>,
+    pub schedule: SchedulePolicy,
+    /// Frames the retransmit cache can hold
+    pub retransmit_capacity: usize,
+    /// Memory reserved for the retransmit cache
+    pub retransmit_bytes: usize,
+}
+
+impl Metrics {
+    pub fn collect(
+        latency: &LatencyTracker,
+        ack_timeout: &AckTimeout,
+        schedule: SchedulePolicy,
+        retransmit_cache: &dyn FrameCache,
+    ) -> Self {
+        let estimator = ack_timeout.estimator();
+        Self {
+            frames: latency.frames().len(),
+            retransmissions: latency
+                .frames()
+                .iter()
+                .map(|timing| timing.retransmissions)
+                .sum(),
+            rtt_smoothed: estimator.and_then(|estimator| estimator.smoothed()),
+            rtt_variation: estimator
+                .filter(|estimator| estimator.smoothed().is_some())
+                .map(|estimator| estimator.variation()),
+            ack_timeout: ack_timeout.timeout(),
+            schedule,
+            retransmit_capacity: retransmit_cache.capacity(),
+            retransmit_bytes: retransmit_cache.bytes_reserved(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let micros = |duration: Option<Duration>| match duration {
+            Some(duration) => duration.as_micros().to_string(),
+            None => "null".to_string(),
+        };
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"frames\": {},", self.frames);
+        let _ = writeln!(json, "  \"retransmissions\": {},", self.retransmissions);
+        let _ = writeln!(
+            json,
+            "  \"rtt_smoothed_us\": {},",
+            micros(self.rtt_smoothed)
+        );
+        let _ = writeln!(
+            json,
+            "  \"rtt_variation_us\": {},",
+            micros(self.rtt_variation)
+        );
+        let _ = writeln!(json, "  \"ack_timeout_us\": {},", micros(self.ack_timeout));
+        let _ = writeln!(json, "  \"schedule\": \"{}\",", self.schedule.name());
+        let _ = writeln!(
+            json,
+            "  \"retransmit_capacity\": {},",
+            self.retransmit_capacity
+        );
+        let _ = writeln!(json, "  \"retransmit_bytes\": {}", self.retransmit_bytes);
+        json.push_str("}\n");
+        json
+    }
+}
+
+#[test]
+fn metrics_of_acked_frames() {
+    let mut latency = LatencyTracker::new();
+    latency.encoded(1);
+    latency.nibble_sent(false);
+    latency.resent(1);
+    latency.acked(1);
+    let mut ack_timeout = AckTimeout::default();
+    let unmeasured = Metrics::collect(
+        &latency,
+        &ack_timeout,
+        SchedulePolicy::default(),
+        &RetransmitCache::default(),
+    );
+    assert_eq!(unmeasured.rtt_smoothed, None);
+    assert_eq!(unmeasured.rtt_variation, None);
+
+    ack_timeout.sample(Duration::from_millis(100));
+    ack_timeout.sample(Duration::from_millis(200));
+    let metrics = Metrics::collect(
+        &latency,
+        &ack_timeout,
+        SchedulePolicy::default(),
+        &RetransmitCache::default(),
+    );
+    assert_eq!(metrics.frames, 1);
+    assert_eq!(metrics.retransmissions, 1);
+    assert_eq!(metrics.rtt_smoothed, Some(Duration::from_micros(112_500)));
+    assert_eq!(metrics.rtt_variation, Some(Duration::from_micros(62_500)));
+    assert!(metrics.to_json().contains("\"rtt_smoothed_us\": 112500,"));
+
+    let fixed = Metrics::collect(
+        &latency,
+        &AckTimeout::Off,
+        SchedulePolicy::Ratio { data: 3 },
+        &RetransmitCache::new(2),
+    );
+    assert!(fixed.to_json().contains("\"ack_timeout_us\": null,"));
+    assert!(fixed.to_json().contains("\"schedule\": \"ratio:3\","));
+    assert_eq!(fixed.retransmit_capacity, 2);
+    assert!(fixed.retransmit_bytes >= 2 * crate::FRAME_LEN);
+}
@@ -0,0 +1,85 @@
+/// # FrameMiddleware
+///
+/// Transforms the payload of every data frame, e.g. to compress, encrypt or log it,
+/// without the connection knowing about the combination of stages.
+///
+/// Stages are chained, when sending they run in the order they have been added,
+/// when receiving in the reverse order. The payload is not escaped yet,
+/// it has to fit into the frame after escaping, a longer payload is truncated.
+///
+/// Received payloads are padded with zeros to the frame size,
+/// so stages that change the length have to record it in the payload themselves.
+pub trait FrameMiddleware {
+    /// Payload of a frame that is about to be sent
+    fn on_send(&mut self, _payload: &mut Vec<u8>) {}
+
+    /// Payload of a frame that has been received, before it is written to the output
+    fn on_receive(&mut self, _payload: &mut Vec<u8>) {}
+
+    /// Number of bytes the stage adds to a payload at most,
+    /// which are kept free in the frame
+    fn max_growth(&self) -> usize {
+        0
+    }
+}
+
+/// Runs every stage on the payload of a sent frame
+pub fn on_send(stages: &mut [Box<dyn FrameMiddleware>], payload: &mut Vec<u8>) {
+    for stage in stages.iter_mut() {
+        stage.on_send(payload);
+    }
+}
+
+/// Runs every stage on the payload of a received frame, undoing [`on_send`]
+pub fn on_receive(stages: &mut [Box<dyn FrameMiddleware>], payload: &mut Vec<u8>) {
+    for stage in stages.iter_mut().rev() {
+        stage.on_receive(payload);
+    }
+}
+
+#[cfg(test)]
+struct XorKey(u8);
+
+#[cfg(test)]
+impl FrameMiddleware for XorKey {
+    fn on_send(&mut self, payload: &mut Vec<u8>) {
+        payload.iter_mut().for_each(|byte| *byte ^= self.0);
+    }
+
+    fn on_receive(&mut self, payload: &mut Vec<u8>) {
+        self.on_send(payload);
+    }
+}
+
+#[cfg(test)]
+struct Reverse;
+
+#[cfg(test)]
+impl FrameMiddleware for Reverse {
+    fn on_send(&mut self, payload: &mut Vec<u8>) {
+        payload.reverse();
+    }
+
+    fn on_receive(&mut self, payload: &mut Vec<u8>) {
+        payload.reverse();
+    }
+}
+
+#[test]
+fn middleware_chain() {
+    use crate::escape::{EscapeCode, Escaped};
+
+    let mut stages: Vec<Box<dyn FrameMiddleware>> = vec![Box::new(XorKey(0x01)), Box::new(Reverse)];
+    // 0x13 ^ 0x01 is SOF, so the transformed payload has to be escaped
+    let data = [0xc0, 0x13, 0xc2];
+    let mut source = Escaped::new(data.into_iter().map(Ok));
+    let (frame, len) = crate::encode_transformed_frame(&mut source, 4, &mut stages).unwrap();
+    assert_eq!(
+        frame[..len],
+        [0x12, 0xc3, 0x12, 0x12, 0xc1, 0x00, EscapeCode::EndOfFrame as u8]
+    );
+
+    let mut received = vec![0xc3, 0x12, 0xc1];
+    on_receive(&mut stages, &mut received);
+    assert_eq!(received, data);
+}
@@ -0,0 +1,236 @@
+#[cfg(test)]
+use std::collections::VecDeque;
+
+/// # Deque
+///
+/// A double ended queue with a fixed capacity of `2 * N` nibbles, packed into `N` bytes.
+///
+/// Nibbles are pushed at the back and indexed from the back,
+/// so [`Deque::get`] with index 0 returns the nibble that has been pushed last.
+///
+/// ## Layout
+///
+/// byte index:   <  0,   1,   2,   3    >
+/// nibble index: <  7 6, 5 4, 3 2, 1 0  >
+/// data:         [  x x, x x, x x, x x  ]
+///
+/// push(1): [   xx,  xx,  xx,   x1  ]
+/// push(2): [   xx,  xx,  xx,   12  ]
+/// push(3): [   xx,  xx,  x1,   23  ]
+/// push(4): [   xx,  xx,  12,   34  ]
+/// push(5): [   xx,  x1,  23,   45  ]
+///
+/// ## Overflow
+///
+/// Pushing into a full deque drops the nibble at the front and returns it,
+/// like a shift register. Popping from an empty deque returns `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deque<const N: usize> {
+    // underlying bytes, nibbles outside of len are unspecified
+    data: [u8; N],
+    // length in nibbles
+    len: usize,
+}
+
+impl<const N: usize> Deque<N> {
+    pub fn new() -> Self {
+        Self {
+            data: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Number of nibbles in the deque
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Maximum number of nibbles
+    pub const fn capacity(&self) -> usize {
+        2 * N
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Pushes the lower nibble of the byte,
+    /// returns the nibble at the front if the deque was already full.
+    pub fn push_back(&mut self, nibble: u8) -> Option<u8> {
+        let nibble = nibble & 0x0f;
+        let prev = self.data;
+
+        // Shift every nibble 4 bits to the left
+        for (index, byte) in self.data.iter_mut().enumerate() {
+            *byte <<= 4;
+            *byte |= prev
+                .get(index + 1)
+                .map(|byte_to_right| byte_to_right >> 4)
+                .unwrap_or(nibble);
+        }
+
+        let filled = self.len == self.capacity();
+        if !filled {
+            self.len += 1;
+        }
+
+        filled.then(|| prev[0] >> 4)
+    }
+
+    /// Removes the nibble that has been pushed first
+    pub fn pop_front(&mut self) -> Option<u8> {
+        let index = self.len.checked_sub(1)?;
+        let result = self.get(index);
+        self.len = index;
+        result
+    }
+
+    /// Removes the nibble that has been pushed last
+    #[cfg(test)]
+    pub fn pop_back(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let prev = self.data;
+
+        // Shift every nibble 4 bits to the right
+        for (index, byte) in self.data.iter_mut().enumerate() {
+            *byte >>= 4;
+            *byte |= index
+                .checked_sub(1)
+                .map(|index_to_left| prev[index_to_left] << 4)
+                .unwrap_or(0x00);
+        }
+        self.len -= 1;
+
+        prev.last().map(|byte| byte & 0x0f)
+    }
+
+    /// Returns the nibble `index` positions away from the back
+    pub fn get(&self, index: usize) -> Option<u8> {
+        if index >= self.len {
+            return None;
+        }
+
+        let byte = self.data[N - 1 - index / 2];
+        let nibble = if index.is_multiple_of(2) {
+            byte & 0x0f
+        } else {
+            byte >> 4
+        };
+        Some(nibble)
+    }
+
+    /// Iterates from the front to the back, in the order the nibbles have been pushed
+    #[cfg(test)]
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = u8> + ExactSizeIterator + '_ {
+        (0..self.len)
+            .rev()
+            .map(|index| self.get(index).expect("index within len"))
+    }
+}
+
+impl<const N: usize> Default for Deque<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn deque() {
+    let mut deque = Deque::<2>::new();
+    assert_eq!(
+        [None, None, None, None, Some(0x01)],
+        [
+            deque.push_back(0x01),
+            deque.push_back(0x02),
+            deque.push_back(0x03),
+            deque.push_back(0x04),
+            deque.push_back(0x05)
+        ]
+    );
+    assert_eq!([0x23, 0x45], deque.data);
+    assert_eq!(
+        [Some(0x05), Some(0x04), Some(0x03), Some(0x02), None],
+        [
+            deque.get(0),
+            deque.get(1),
+            deque.get(2),
+            deque.get(3),
+            deque.get(4)
+        ]
+    );
+    assert_eq!(deque.iter().collect::<Vec<_>>(), [0x02, 0x03, 0x04, 0x05]);
+    assert_eq!(
+        [Some(0x05), Some(0x04), Some(0x03), Some(0x02), None],
+        [
+            deque.pop_back(),
+            deque.pop_back(),
+            deque.pop_back(),
+            deque.pop_back(),
+            deque.pop_back()
+        ]
+    );
+    assert_eq!(deque.pop_front(), None);
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, Copy)]
+enum Operation {
+    Push(u8),
+    PopFront,
+    PopBack,
+    Clear,
+}
+
+/// Compares every sequence of up to 7 operations with a [`VecDeque`]
+#[test]
+fn deque_matches_vec_deque() {
+    const OPERATIONS: [Operation; 6] = [
+        Operation::Push(0x3),
+        Operation::Push(0xc),
+        Operation::Push(0xf),
+        Operation::PopFront,
+        Operation::PopBack,
+        Operation::Clear,
+    ];
+    const STEPS: u32 = 7;
+
+    for sequence in 0..OPERATIONS.len().pow(STEPS) {
+        let mut deque = Deque::<2>::new();
+        let mut model = VecDeque::new();
+        let mut rest = sequence;
+        for _ in 0..STEPS {
+            let operation = OPERATIONS[rest % OPERATIONS.len()];
+            rest /= OPERATIONS.len();
+            match operation {
+                Operation::Push(nibble) => {
+                    let dropped = (model.len() == deque.capacity())
+                        .then(|| model.pop_front())
+                        .flatten();
+                    model.push_back(nibble);
+                    assert_eq!(deque.push_back(nibble), dropped, "{operation:?}");
+                }
+                Operation::PopFront => assert_eq!(deque.pop_front(), model.pop_front()),
+                Operation::PopBack => assert_eq!(deque.pop_back(), model.pop_back()),
+                Operation::Clear => {
+                    deque.clear();
+                    model.clear();
+                }
+            }
+            assert_eq!(deque.len(), model.len());
+            assert!(deque.iter().eq(model.iter().copied()));
+            assert!(deque.iter().rev().eq(model.iter().rev().copied()));
+            for index in 0..=deque.capacity() {
+                let expected = model.len().checked_sub(index + 1).map(|index| model[index]);
+                assert_eq!(deque.get(index), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::escape::{EscapeCode, Escaped};
+use crate::{encode_frame, Frame};
+
+pub const ECHO_LEN: usize = 1 + 4 + 8;
+
+/// # Echo
+///
+/// Payload of an echo frame.
+/// Requests are sent back by the other side with `reply` set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Echo {
+    pub reply: bool,
+    pub seq: u32,
+    /// Microseconds since the sender started pinging
+    pub timestamp: u64,
+}
+
+impl Echo {
+    pub fn to_bytes(self) -> [u8; ECHO_LEN] {
+        let mut bytes = [0; ECHO_LEN];
+        bytes[0] = self.reply as u8;
+        bytes[1..5].copy_from_slice(&self.seq.to_be_bytes());
+        bytes[5..].copy_from_slice(&self.timestamp.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; ECHO_LEN] = bytes.get(..ECHO_LEN)?.try_into().ok()?;
+        Some(Self {
+            reply: bytes[0] != 0,
+            seq: u32::from_be_bytes(bytes[1..5].try_into().ok()?),
+            timestamp: u64::from_be_bytes(bytes[5..].try_into().ok()?),
+        })
+    }
+
+    /// Encodes the echo like a normal frame, but starts it with SOE instead of SOF,
+    /// returns the frame and the number of its bytes that have to be sent
+    pub fn encode(&self) -> (Frame, usize) {
+        let (mut frame, len) = encode_frame(&mut Escaped::new(self.to_bytes().into_iter().map(Ok)))
+            .expect("echo is in memory");
+        frame[0] = EscapeCode::StartOfEcho as u8;
+        (frame, len)
+    }
+}
+
+/// Round trip time statistics
+pub struct RttStats {
+    sent: u32,
+    samples: Vec<Duration>,
+}
+
+impl RttStats {
+    pub fn new() -> Self {
+        Self {
+            sent: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sent(&mut self) {
+        self.sent += 1;
+    }
+
+    pub fn received(&mut self, rtt: Duration) {
+        self.samples.push(rtt);
+    }
+}
+
+impl Display for RttStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lost = self.sent.saturating_sub(self.samples.len() as u32);
+        let loss = if self.sent == 0 {
+            0.0
+        } else {
+            100.0 * lost as f64 / self.sent as f64
+        };
+        write!(f, "{} sent, {lost} lost ({loss:.1}% loss)", self.sent)?;
+
+        let (Some(min), Some(max)) = (self.samples.iter().min(), self.samples.iter().max()) else {
+            return Ok(());
+        };
+        let avg = self.samples.iter().sum::<Duration>() / self.samples.len() as u32;
+        write!(f, ", rtt min/avg/max = {min:?}/{avg:?}/{max:?}")
+    }
+}
+
+#[test]
+fn echo_bytes_roundtrip() {
+    let echo = Echo {
+        reply: true,
+        seq: 0x12345678,
+        timestamp: 0x2323_5656_6565_0000,
+    };
+    assert_eq!(Echo::from_bytes(&echo.to_bytes()), Some(echo));
+}
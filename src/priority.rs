@@ -0,0 +1,72 @@
+use crate::escape::{EscapeCode, Escaped};
+use crate::ping::{Echo, ECHO_LEN};
+use crate::{encode_frame, Frame, FRAME_DATA_LEN};
+
+/// Sequence number of the echo frames that carry a priority message,
+/// next to [`crate::calibrate::CALIBRATION_ECHO_SEQ`]
+pub const PRIORITY_ECHO_SEQ: u32 = u32::MAX - 2;
+
+/// The message does not fit into a single frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageTooLong {
+    /// Number of data bytes the message and its header would need
+    pub len: usize,
+}
+
+/// Encodes a priority message as an echo frame.
+///
+/// Priority messages are sent before the next data frame and handed to a separate callback
+/// by the other side, see [`crate::Connection::send_priority`].
+/// The header is an [`Echo`] request with [`PRIORITY_ECHO_SEQ`] and the length of the message
+/// as its timestamp, so that the frame never gets mixed up with the data stream.
+/// Like every echo they are neither acked nor resent.
+pub fn encode(message: &[u8]) -> Result<(Frame, usize), MessageTooLong> {
+    let header = Echo {
+        reply: false,
+        seq: PRIORITY_ECHO_SEQ,
+        timestamp: message.len() as u64,
+    };
+    let bytes: Vec<u8> = header.to_bytes().into_iter().chain(message.iter().copied()).collect();
+    if bytes.len() > FRAME_DATA_LEN {
+        return Err(MessageTooLong { len: bytes.len() });
+    }
+
+    let (mut frame, len) =
+        encode_frame(&mut Escaped::new(bytes.into_iter().map(Ok))).expect("message is in memory");
+    frame[0] = EscapeCode::StartOfEcho as u8;
+    Ok((frame, len))
+}
+
+/// The message of a received echo frame, `None` if it is not a priority message
+pub fn decode(data: &[u8]) -> Option<&[u8]> {
+    let header = Echo::from_bytes(data)?;
+    if header.reply || header.seq != PRIORITY_ECHO_SEQ {
+        return None;
+    }
+    data.get(ECHO_LEN..ECHO_LEN.checked_add(header.timestamp as usize)?)
+}
+
+#[test]
+fn priority_message_roundtrip() {
+    let message = b"stop the transfer \x12\x23";
+    let (frame, len) = encode(message).unwrap();
+    assert_eq!(frame[0], EscapeCode::StartOfEcho as u8);
+
+    // the input stream unescapes the echo data, the zeros after the message are ignored
+    let data = crate::tap::unescape(&frame[1..(len - 1)]);
+    assert_eq!(decode(&data), Some(&message[..]));
+
+    assert_eq!(
+        encode(&[0x12; FRAME_DATA_LEN]),
+        Err(MessageTooLong {
+            len: ECHO_LEN + FRAME_DATA_LEN
+        })
+    );
+    // a normal echo is not a priority message
+    let echo = Echo {
+        reply: false,
+        seq: 1,
+        timestamp: 0,
+    };
+    assert_eq!(decode(&echo.to_bytes()), None);
+}
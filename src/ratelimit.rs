@@ -0,0 +1,78 @@
+use std::time::Instant;
+
+use crate::bits::SYMBOL_BITS;
+
+/// # RateLimiter
+///
+/// Token bucket that limits how many bits are sent per second,
+/// e.g. to slow the transfer down to a speed that can be followed on LEDs.
+///
+/// The bucket holds at most one symbol, so that the symbols are spread out evenly
+/// instead of being sent in bursts after a pause.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bits_per_second: f64,
+    /// Bits that may be sent right now
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bits_per_second: u64) -> Self {
+        Self {
+            bits_per_second: bits_per_second.max(1) as f64,
+            tokens: SYMBOL_BITS as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Parses a rate like `500bps`, `2kbps` or `1mbps`
+    pub fn parse(text: &str) -> Option<Self> {
+        let units = [("mbps", 1_000_000), ("kbps", 1_000), ("bps", 1)];
+        for (unit, factor) in units {
+            if let Some(number) = text.strip_suffix(unit) {
+                return number
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|number| *number > 0)
+                    .map(|number| Self::new(number * factor));
+            }
+        }
+        None
+    }
+
+    /// Whether a symbol may be sent now, takes its bits from the bucket if it may
+    pub fn try_send_symbol(&mut self) -> bool {
+        self.try_send_symbol_at(Instant::now())
+    }
+
+    fn try_send_symbol_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        let bits = SYMBOL_BITS as f64;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.bits_per_second).min(bits);
+        if self.tokens < bits {
+            return false;
+        }
+        self.tokens -= bits;
+        true
+    }
+}
+
+#[test]
+fn rate_limiter() {
+    use std::time::Duration;
+
+    assert!(RateLimiter::parse("0bps").is_none());
+    assert!(RateLimiter::parse("fast").is_none());
+
+    // one symbol every 10ms
+    let mut limiter = RateLimiter::parse("400bps").unwrap();
+    let start = limiter.last_refill;
+    assert!(limiter.try_send_symbol_at(start));
+    assert!(!limiter.try_send_symbol_at(start + Duration::from_millis(5)));
+    assert!(limiter.try_send_symbol_at(start + Duration::from_millis(10)));
+    // a long pause does not allow a burst
+    assert!(limiter.try_send_symbol_at(start + Duration::from_secs(1)));
+    assert!(!limiter.try_send_symbol_at(start + Duration::from_secs(1)));
+}
@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bits::NibbleOrder;
+
+/// Sequence number of the echo frames that announce the session id,
+/// never used by [`crate::ping::Echo`] requests of `protocol ping`
+pub const SESSION_ECHO_SEQ: u32 = u32::MAX;
+
+/// Sequence number of the echo frames that announce the byte offset a resumed side sends from,
+/// next to [`crate::priority::PRIORITY_ECHO_SEQ`]
+pub const OFFSET_ECHO_SEQ: u32 = u32::MAX - 3;
+
+/// # ResumeToken
+///
+/// Negotiated parameters and progress of a transfer, saved to a `.session` file
+/// so that it can be continued after either side has been restarted.
+///
+/// Both sides announce their id when the connection starts,
+/// a resumed side only continues if the other side announces the id it has saved.
+///
+/// Written as one `<key> <value>` line per field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeToken {
+    pub id: u64,
+    pub peer_id: Option<u64>,
+    pub tx_frame_data_len: usize,
+    pub rx_frame_data_len: usize,
+    pub nibble_order: NibbleOrder,
+    /// Frames and payload bytes the other side has acknowledged
+    pub sent_frames: u32,
+    pub sent_bytes: u64,
+    /// Frames and payload bytes that have been written to the output
+    pub received_frames: u32,
+    pub received_bytes: u64,
+}
+
+impl ResumeToken {
+    /// A token for a new session with a random id
+    pub fn new(frame_data_len: usize) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        Self {
+            // the process id tells apart both sides started at the same time
+            id: nanos ^ (std::process::id() as u64).rotate_left(32),
+            peer_id: None,
+            tx_frame_data_len: frame_data_len,
+            rx_frame_data_len: frame_data_len,
+            nibble_order: NibbleOrder::default(),
+            sent_frames: 0,
+            sent_bytes: 0,
+            received_frames: 0,
+            received_bytes: 0,
+        }
+    }
+
+    pub fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "id {:016x}", self.id)?;
+        if let Some(peer_id) = self.peer_id {
+            writeln!(output, "peer-id {peer_id:016x}")?;
+        }
+        writeln!(output, "tx-frame-data-len {}", self.tx_frame_data_len)?;
+        writeln!(output, "rx-frame-data-len {}", self.rx_frame_data_len)?;
+        let order = match self.nibble_order {
+            NibbleOrder::HighFirst => "high-first",
+            NibbleOrder::LowFirst => "low-first",
+        };
+        writeln!(output, "nibble-order {order}")?;
+        writeln!(output, "sent-frames {}", self.sent_frames)?;
+        writeln!(output, "sent-bytes {}", self.sent_bytes)?;
+        writeln!(output, "received-frames {}", self.received_frames)?;
+        writeln!(output, "received-bytes {}", self.received_bytes)
+    }
+
+    pub fn read(input: impl BufRead) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid session token");
+        let mut token = Self::new(0);
+        let mut has_id = false;
+        for line in input.lines() {
+            let line = line?;
+            let (key, value) = line.split_once(' ').ok_or_else(invalid)?;
+            let number = || value.parse::<u64>().map_err(|_| invalid());
+            let id = || u64::from_str_radix(value, 16).map_err(|_| invalid());
+            match key {
+                "id" => {
+                    token.id = id()?;
+                    has_id = true;
+                }
+                "peer-id" => token.peer_id = Some(id()?),
+                "tx-frame-data-len" => token.tx_frame_data_len = number()? as usize,
+                "rx-frame-data-len" => token.rx_frame_data_len = number()? as usize,
+                "nibble-order" => {
+                    token.nibble_order = NibbleOrder::from_name(value).ok_or_else(invalid)?
+                }
+                "sent-frames" => token.sent_frames = number()? as u32,
+                "sent-bytes" => token.sent_bytes = number()?,
+                "received-frames" => token.received_frames = number()? as u32,
+                "received-bytes" => token.received_bytes = number()?,
+                _ => return Err(invalid()),
+            }
+        }
+        if !has_id {
+            return Err(invalid());
+        }
+        Ok(token)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        // written next to the token and renamed, so that a crash never leaves half a token
+        let temporary = format!("{path}.tmp");
+        self.write(&mut File::create(&temporary)?)?;
+        std::fs::rename(temporary, path)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+}
+
+#[test]
+fn resume_token_round_trip() {
+    let mut token = ResumeToken::new(8);
+    token.peer_id = Some(0x1234);
+    token.nibble_order = NibbleOrder::LowFirst;
+    token.sent_frames = 3;
+    token.sent_bytes = 24;
+    token.received_frames = 1;
+    token.received_bytes = 5;
+
+    let mut text = Vec::new();
+    token.write(&mut text).unwrap();
+    assert_eq!(ResumeToken::read(&text[..]).unwrap(), token);
+    assert!(ResumeToken::read(&b"sent-frames 3\n"[..]).is_err());
+    assert!(ResumeToken::read(&b"id xyz\n"[..]).is_err());
+}
+
+#[test]
+fn resumed_session_rejects_other_peer() {
+    let device = crate::device::DebugDevice::new();
+    let mut connection = crate::Connection::new(device, std::iter::empty());
+    let mut token = ResumeToken::new(8);
+    token.peer_id = Some(1);
+    token.sent_frames = 2;
+    connection.resume(token);
+    assert_eq!(connection.seq, 2);
+    assert_eq!(connection.resume_token().tx_frame_data_len, 8);
+
+    connection.peer_announced(1);
+    assert!(!connection.resume_rejected());
+    connection.peer_announced(2);
+    assert!(connection.resume_rejected());
+}
@@ -0,0 +1,257 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::Frame;
+
+/// Number of frames that are kept by default
+pub const DEFAULT_CAPACITY: usize = 8;
+
+/// The cache is full, the oldest frame has to be acked before another one can be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheFull {
+    pub oldest_seq: u32,
+}
+
+/// Frames that have been sent and might have to be sent again, until they are acked
+pub trait FrameCache {
+    /// Stores the first `len` bytes of the frame, replacing an older frame with the same seq
+    fn insert(&mut self, seq: u32, frame: Frame, len: usize) -> Result<(), CacheFull>;
+
+    fn get(&self, seq: u32) -> Option<(Frame, usize)>;
+
+    /// Drops every frame up to and including `seq`
+    fn ack_through(&mut self, seq: u32);
+
+    fn clear(&mut self);
+
+    /// Maximum number of frames
+    fn capacity(&self) -> usize;
+
+    fn len(&self) -> usize;
+
+    /// Bytes of the frames that have to be sent
+    fn bytes_used(&self) -> usize;
+
+    /// Memory reserved for the cache, whether it is used or not
+    fn bytes_reserved(&self) -> usize;
+}
+
+/// # RetransmitCache
+///
+/// Keeps encoded frames by sequence number until they are acked,
+/// so that any of them can be resent, not just the last one.
+///
+/// Bounded to `capacity` frames, inserting into a full cache fails instead of dropping
+/// a frame that might still be needed, which applies backpressure to the sender.
+#[derive(Debug)]
+pub struct RetransmitCache {
+    /// Ordered by sequence number
+    frames: VecDeque<(u32, Frame, usize)>,
+    capacity: usize,
+}
+
+impl RetransmitCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+}
+
+impl FrameCache for RetransmitCache {
+    fn insert(&mut self, seq: u32, frame: Frame, len: usize) -> Result<(), CacheFull> {
+        if let Some(entry) = self.frames.iter_mut().find(|(cached, ..)| *cached == seq) {
+            *entry = (seq, frame, len);
+            return Ok(());
+        }
+        if let Some((oldest_seq, ..)) = self.frames.front() {
+            if self.frames.len() >= self.capacity {
+                return Err(CacheFull {
+                    oldest_seq: *oldest_seq,
+                });
+            }
+        }
+        let index = self.frames.partition_point(|(cached, ..)| *cached < seq);
+        self.frames.insert(index, (seq, frame, len));
+        Ok(())
+    }
+
+    fn get(&self, seq: u32) -> Option<(Frame, usize)> {
+        self.frames
+            .iter()
+            .find(|(cached, ..)| *cached == seq)
+            .map(|(_, frame, len)| (*frame, *len))
+    }
+
+    fn ack_through(&mut self, seq: u32) {
+        self.frames.retain(|(cached, ..)| *cached > seq);
+    }
+
+    fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn bytes_used(&self) -> usize {
+        self.frames.iter().map(|(_, _, len)| len).sum()
+    }
+
+    fn bytes_reserved(&self) -> usize {
+        self.capacity * mem::size_of::<(u32, Frame, usize)>()
+    }
+}
+
+impl Default for RetransmitCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// # FixedRetransmitCache
+///
+/// [`RetransmitCache`] for at most `N` frames, stored inline so that it never allocates,
+/// for builds that have to stay within a [`crate::budget::MemoryBudget`].
+#[derive(Debug)]
+pub struct FixedRetransmitCache<const N: usize> {
+    /// Unordered, free slots are `None`
+    frames: [Option<(u32, Frame, usize)>; N],
+}
+
+impl<const N: usize> FixedRetransmitCache<N> {
+    /// Memory used by the cache, which does not change while it is used
+    pub const BYTES_RESERVED: usize = mem::size_of::<Self>();
+
+    pub const fn new() -> Self {
+        Self { frames: [None; N] }
+    }
+
+    fn slot(&self, seq: u32) -> Option<usize> {
+        self.frames
+            .iter()
+            .position(|slot| slot.is_some_and(|(cached, ..)| cached == seq))
+    }
+}
+
+impl<const N: usize> FrameCache for FixedRetransmitCache<N> {
+    fn insert(&mut self, seq: u32, frame: Frame, len: usize) -> Result<(), CacheFull> {
+        let slot = match self.slot(seq) {
+            Some(index) => Some(index),
+            None => self.frames.iter().position(Option::is_none),
+        };
+        match slot {
+            Some(index) => {
+                self.frames[index] = Some((seq, frame, len));
+                Ok(())
+            }
+            None => Err(CacheFull {
+                oldest_seq: self
+                    .frames
+                    .iter()
+                    .flatten()
+                    .map(|(seq, ..)| *seq)
+                    .min()
+                    .unwrap_or(seq),
+            }),
+        }
+    }
+
+    fn get(&self, seq: u32) -> Option<(Frame, usize)> {
+        self.frames
+            .iter()
+            .flatten()
+            .find(|(cached, ..)| *cached == seq)
+            .map(|(_, frame, len)| (*frame, *len))
+    }
+
+    fn ack_through(&mut self, seq: u32) {
+        for slot in &mut self.frames {
+            if slot.is_some_and(|(cached, ..)| cached <= seq) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.frames = [None; N];
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn len(&self) -> usize {
+        self.frames.iter().flatten().count()
+    }
+
+    fn bytes_used(&self) -> usize {
+        self.frames.iter().flatten().map(|(_, _, len)| len).sum()
+    }
+
+    fn bytes_reserved(&self) -> usize {
+        Self::BYTES_RESERVED
+    }
+}
+
+impl<const N: usize> Default for FixedRetransmitCache<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn retransmit_cache() {
+    let frame = |byte| [byte; crate::FRAME_LEN];
+    let mut cache = RetransmitCache::new(3);
+    for seq in 1..=3 {
+        cache.insert(seq, frame(seq as u8), 10).unwrap();
+    }
+    assert_eq!(
+        cache.insert(4, frame(4), 10),
+        Err(CacheFull { oldest_seq: 1 })
+    );
+    assert_eq!(cache.bytes_used(), 30);
+
+    // frame 1 can still be resent after frame 3 has been sent
+    assert_eq!(cache.get(1), Some((frame(1), 10)));
+    cache.ack_through(1);
+    assert_eq!(cache.get(2), Some((frame(2), 10)));
+    cache.insert(4, frame(4), 5).unwrap();
+    assert_eq!(cache.get(4), Some((frame(4), 5)));
+
+    cache.ack_through(3);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.get(2), None);
+    assert_eq!(cache.bytes_used(), 5);
+}
+
+#[test]
+fn fixed_retransmit_cache() {
+    let frame = |byte| [byte; crate::FRAME_LEN];
+    let mut cache = FixedRetransmitCache::<2>::new();
+    cache.insert(2, frame(2), 10).unwrap();
+    cache.insert(1, frame(1), 10).unwrap();
+    assert_eq!(
+        cache.insert(3, frame(3), 10),
+        Err(CacheFull { oldest_seq: 1 })
+    );
+    cache.insert(2, frame(4), 4).unwrap();
+    assert_eq!(cache.get(2), Some((frame(4), 4)));
+
+    cache.ack_through(1);
+    assert_eq!(cache.len(), 1);
+    cache.insert(3, frame(3), 10).unwrap();
+    assert_eq!(cache.get(1), None);
+    assert_eq!(cache.bytes_used(), 14);
+    assert_eq!(
+        cache.bytes_reserved(),
+        crate::budget::MemoryBudget::fixed::<2>().retransmit_cache
+    );
+}
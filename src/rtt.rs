@@ -0,0 +1,166 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Timeout before the first ack has been measured
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(1);
+/// Lower bound, so that a single fast ack does not cause spurious retransmissions
+const MIN_TIMEOUT: Duration = Duration::from_millis(200);
+const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// # RttEstimator
+///
+/// Smoothed round trip time and its variation, estimated from the time between the last nibble
+/// of a data frame and its ack, like the retransmission timer of TCP (RFC 6298).
+///
+/// Frames that have been resent are not measured, since it is unknown which transmission
+/// has been acked (Karn's algorithm). Each timeout doubles the timeout until the next ack.
+#[derive(Debug, Clone, Default)]
+pub struct RttEstimator {
+    smoothed: Option<Duration>,
+    variation: Duration,
+    /// How often the timeout has been doubled since the last ack
+    backoff: u32,
+    samples: u64,
+}
+
+impl RttEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.smoothed {
+            None => {
+                self.smoothed = Some(rtt);
+                self.variation = rtt / 2;
+            }
+            Some(smoothed) => {
+                let deviation = smoothed.abs_diff(rtt);
+                self.variation = (self.variation * 3 + deviation) / 4;
+                self.smoothed = Some((smoothed * 7 + rtt) / 8);
+            }
+        }
+        self.backoff = 0;
+        self.samples += 1;
+    }
+
+    /// The frame has not been acked in time
+    pub fn timed_out(&mut self) {
+        self.backoff = (self.backoff + 1).min(16);
+    }
+
+    pub fn smoothed(&self) -> Option<Duration> {
+        self.smoothed
+    }
+
+    pub fn variation(&self) -> Duration {
+        self.variation
+    }
+
+    pub fn timeout(&self) -> Duration {
+        let timeout = match self.smoothed {
+            Some(smoothed) => (smoothed + self.variation * 4).max(MIN_TIMEOUT),
+            None => INITIAL_TIMEOUT,
+        };
+        timeout.saturating_mul(1 << self.backoff).min(MAX_TIMEOUT)
+    }
+}
+
+impl Display for RttEstimator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.smoothed {
+            Some(smoothed) => writeln!(
+                f,
+                "rtt {smoothed:?} +- {:?} from {} acks, ack timeout {:?}",
+                self.variation,
+                self.samples,
+                self.timeout()
+            ),
+            None => writeln!(f, "rtt unknown, ack timeout {:?}", self.timeout()),
+        }
+    }
+}
+
+/// How long the connection waits for the ack of a data frame, before it is resent
+#[derive(Debug, Clone)]
+pub enum AckTimeout {
+    /// Follows the measured round trip time
+    Adaptive(RttEstimator),
+    /// The same timeout for every frame, e.g. for deterministic tests
+    Fixed(Duration),
+    /// Frames are only resent once the other side asks for it
+    Off,
+}
+
+impl Default for AckTimeout {
+    fn default() -> Self {
+        Self::Adaptive(RttEstimator::new())
+    }
+}
+
+impl AckTimeout {
+    pub fn timeout(&self) -> Option<Duration> {
+        match self {
+            Self::Adaptive(estimator) => Some(estimator.timeout()),
+            Self::Fixed(timeout) => Some(*timeout),
+            Self::Off => None,
+        }
+    }
+
+    pub fn sample(&mut self, rtt: Duration) {
+        if let Self::Adaptive(estimator) = self {
+            estimator.sample(rtt);
+        }
+    }
+
+    pub fn timed_out(&mut self) {
+        if let Self::Adaptive(estimator) = self {
+            estimator.timed_out();
+        }
+    }
+
+    /// The estimate, if the timeout is adaptive
+    pub fn estimator(&self) -> Option<&RttEstimator> {
+        match self {
+            Self::Adaptive(estimator) => Some(estimator),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn rtt_estimation() {
+    let ms = Duration::from_millis;
+
+    let mut estimator = RttEstimator::new();
+    assert_eq!(estimator.timeout(), INITIAL_TIMEOUT);
+    estimator.sample(ms(400));
+    assert_eq!(estimator.smoothed(), Some(ms(400)));
+    assert_eq!(estimator.variation(), ms(200));
+    assert_eq!(estimator.timeout(), ms(1200));
+
+    estimator.sample(ms(800));
+    assert_eq!(estimator.smoothed(), Some(ms(450)));
+    assert_eq!(estimator.variation(), ms(250));
+    assert_eq!(estimator.timeout(), ms(1450));
+
+    estimator.timed_out();
+    estimator.timed_out();
+    assert_eq!(estimator.timeout(), ms(5800));
+    // an ack ends the backoff
+    estimator.sample(ms(450));
+    assert_eq!(estimator.timeout(), ms(1200));
+
+    // a steady fast link is kept at the minimum
+    let mut estimator = RttEstimator::new();
+    for _ in 0..100 {
+        estimator.sample(Duration::from_micros(50));
+    }
+    assert_eq!(estimator.timeout(), MIN_TIMEOUT);
+
+    let mut fixed = AckTimeout::Fixed(ms(30));
+    fixed.sample(ms(900));
+    fixed.timed_out();
+    assert_eq!(fixed.timeout(), Some(ms(30)));
+    assert_eq!(AckTimeout::Off.timeout(), None);
+}
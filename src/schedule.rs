@@ -0,0 +1,131 @@
+/// What the [`TxScheduler`] sends next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    /// CFD or IFD for a frame the other side has sent
+    Ack,
+    /// Our next data frame, or a retransmission of it
+    Data,
+}
+
+/// How acks and data frames share the line, when both directions have payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchedulePolicy {
+    /// Acks are always sent before data, so the other side never waits on us
+    #[default]
+    AcksFirst,
+    /// Alternates between acks and data, whichever was not sent last goes first
+    RoundRobin,
+    /// Sends up to `data` data frames before an ack has to go out
+    Ratio { data: u32 },
+}
+
+impl SchedulePolicy {
+    /// Parses "acks-first", "round-robin" or a ratio like "ratio:3"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "acks-first" => Some(Self::AcksFirst),
+            "round-robin" => Some(Self::RoundRobin),
+            _ => {
+                let data = name.strip_prefix("ratio:")?.parse().ok()?;
+                (data > 0).then_some(Self::Ratio { data })
+            }
+        }
+    }
+
+    /// The name that [`SchedulePolicy::from_name`] parses
+    pub fn name(self) -> String {
+        match self {
+            Self::AcksFirst => "acks-first".to_string(),
+            Self::RoundRobin => "round-robin".to_string(),
+            Self::Ratio { data } => format!("ratio:{data}"),
+        }
+    }
+}
+
+/// # TxScheduler
+///
+/// Decides whether a pending ack or a pending data frame is sent next.
+///
+/// In full duplex both sides send data and ack the frames of the other side,
+/// if acks always waited for our data, the other direction would stall.
+#[derive(Debug, Default)]
+pub struct TxScheduler {
+    policy: SchedulePolicy,
+    /// What has been sent last, if both were pending
+    last: Option<Slot>,
+    /// Data frames that have been sent, while an ack was pending
+    data_streak: u32,
+}
+
+impl TxScheduler {
+    pub fn new(policy: SchedulePolicy) -> Self {
+        Self {
+            policy,
+            last: None,
+            data_streak: 0,
+        }
+    }
+
+    pub fn policy(&self) -> SchedulePolicy {
+        self.policy
+    }
+
+    /// What to send next, `None` if nothing is pending
+    pub fn next(&mut self, ack_pending: bool, data_pending: bool) -> Option<Slot> {
+        let slot = match (ack_pending, data_pending) {
+            (false, false) => return None,
+            (true, false) => Slot::Ack,
+            (false, true) => Slot::Data,
+            (true, true) => match self.policy {
+                SchedulePolicy::AcksFirst => Slot::Ack,
+                SchedulePolicy::RoundRobin if self.last == Some(Slot::Ack) => Slot::Data,
+                SchedulePolicy::RoundRobin => Slot::Ack,
+                SchedulePolicy::Ratio { data } if self.data_streak < data => Slot::Data,
+                SchedulePolicy::Ratio { .. } => Slot::Ack,
+            },
+        };
+        match slot {
+            Slot::Ack => self.data_streak = 0,
+            Slot::Data if ack_pending => self.data_streak += 1,
+            Slot::Data => (),
+        }
+        if ack_pending && data_pending {
+            self.last = Some(slot);
+        }
+        Some(slot)
+    }
+}
+
+#[test]
+fn schedule_policies() {
+    use Slot::{Ack, Data};
+
+    let run = |policy| {
+        let mut scheduler = TxScheduler::new(policy);
+        [0; 6].map(|_| scheduler.next(true, true).unwrap())
+    };
+    assert_eq!(run(SchedulePolicy::AcksFirst), [Ack; 6]);
+    assert_eq!(
+        run(SchedulePolicy::RoundRobin),
+        [Ack, Data, Ack, Data, Ack, Data]
+    );
+    assert_eq!(
+        run(SchedulePolicy::Ratio { data: 2 }),
+        [Data, Data, Ack, Data, Data, Ack]
+    );
+
+    let mut scheduler = TxScheduler::new(SchedulePolicy::RoundRobin);
+    assert_eq!(scheduler.next(false, false), None);
+    assert_eq!(scheduler.next(false, true), Some(Data));
+    assert_eq!(scheduler.next(true, false), Some(Ack));
+
+    assert_eq!(
+        SchedulePolicy::from_name("ratio:3"),
+        Some(SchedulePolicy::Ratio { data: 3 })
+    );
+    assert_eq!(SchedulePolicy::from_name("ratio:0"), None);
+    assert_eq!(SchedulePolicy::from_name("data-first"), None);
+    for policy in [SchedulePolicy::RoundRobin, SchedulePolicy::Ratio { data: 3 }] {
+        assert_eq!(SchedulePolicy::from_name(&policy.name()), Some(policy));
+    }
+}
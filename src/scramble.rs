@@ -0,0 +1,162 @@
+use std::fmt::Display;
+
+use crate::middleware::FrameMiddleware;
+use crate::ping::Echo;
+use crate::Frame;
+
+/// Sequence number of the echo frames that announce the [`Features`] of a side,
+/// next to [`crate::resume::OFFSET_ECHO_SEQ`]
+pub const FEATURES_ECHO_SEQ: u32 = u32::MAX - 4;
+
+/// State the shift register starts with for every frame, neither all zeros nor all ones,
+/// which payloads of only zeros or only ones would keep unchanged
+const SEED: u8 = 0x55;
+
+/// # Features
+///
+/// Transformations of the payload, that both sides have to agree on.
+///
+/// A side that uses any of them announces them with an echo before its first frame,
+/// the other side aborts if it does not use the same ones,
+/// instead of writing payloads it can not read to its sink.
+/// Like every echo the announcement is not resent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    /// Payloads are scrambled, see [`Scrambler`]
+    pub scramble: bool,
+    /// Data is compressed, see [`crate::compress::Compressed`]
+    pub compress: bool,
+}
+
+impl Features {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn to_bits(self) -> u64 {
+        self.scramble as u64 | (self.compress as u64) << 1
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self {
+            scramble: bits & 1 != 0,
+            compress: bits & 2 != 0,
+        }
+    }
+
+    /// Echo frame that tells the other side which features are used
+    pub fn announcement(self) -> (Frame, usize) {
+        Echo {
+            reply: false,
+            seq: FEATURES_ECHO_SEQ,
+            timestamp: self.to_bits(),
+        }
+        .encode()
+    }
+}
+
+impl Display for Features {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = [
+            (self.scramble, "scrambling"),
+            (self.compress, "compression"),
+        ]
+        .into_iter()
+        .filter_map(|(used, name)| used.then_some(name))
+        .collect();
+        if names.is_empty() {
+            write!(f, "no features")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
+
+/// # Scrambler
+///
+/// Self-synchronizing scrambler with the polynomial x^7 + x^4 + 1, so that payloads
+/// with long runs of the same value, like all zeros, do not turn into long runs
+/// of equal nibbles, which each need a buffer code in between.
+///
+/// Only the payload is scrambled, escape codes and buffer codes stay as they are,
+/// so that the framing can still be decoded. The shift register starts from the same seed
+/// for every frame, a lost frame does not affect the next one.
+/// Within a frame, a corrupted bit only corrupts itself and the bits 4 and 7 after it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Scrambler;
+
+impl FrameMiddleware for Scrambler {
+    fn on_send(&mut self, payload: &mut Vec<u8>) {
+        scramble(payload);
+    }
+
+    fn on_receive(&mut self, payload: &mut Vec<u8>) {
+        descramble(payload);
+    }
+}
+
+/// Bit that is added to the next bit, from the scrambled bits 4 and 7 before it
+fn feedback(state: u8) -> u8 {
+    (state >> 3 ^ state >> 6) & 1
+}
+
+pub fn scramble(payload: &mut [u8]) {
+    // the last 7 scrambled bits, the latest one in bit 0
+    let mut state = SEED;
+    for byte in payload {
+        let mut scrambled = 0;
+        for shift in (0..u8::BITS).rev() {
+            let bit = (*byte >> shift & 1) ^ feedback(state);
+            state = (state << 1 | bit) & 0x7f;
+            scrambled = scrambled << 1 | bit;
+        }
+        *byte = scrambled;
+    }
+}
+
+pub fn descramble(payload: &mut [u8]) {
+    let mut state = SEED;
+    for byte in payload {
+        let mut descrambled = 0;
+        for shift in (0..u8::BITS).rev() {
+            let bit = *byte >> shift & 1;
+            descrambled = descrambled << 1 | (bit ^ feedback(state));
+            state = (state << 1 | bit) & 0x7f;
+        }
+        *byte = descrambled;
+    }
+}
+
+#[test]
+fn scrambler_breaks_up_constant_payloads() {
+    use crate::cost::frame_nibbles;
+
+    for constant in [0x00, 0xff, 0x55] {
+        let payload = [constant; 64];
+        let mut scrambled = payload;
+        scramble(&mut scrambled);
+        // every pair of equal nibbles costs a buffer code
+        assert!(frame_nibbles(&scrambled) < frame_nibbles(&payload));
+        descramble(&mut scrambled);
+        assert_eq!(scrambled, payload);
+    }
+
+    // a flipped bit is only repeated by the two taps
+    let mut scrambled = [0xa5; 8];
+    scramble(&mut scrambled);
+    scrambled[2] ^= 0b0000_0001;
+    descramble(&mut scrambled);
+    let flipped: Vec<u32> = scrambled
+        .iter()
+        .map(|byte| (byte ^ 0xa5).count_ones())
+        .collect();
+    assert_eq!(flipped, [0, 0, 1, 2, 0, 0, 0, 0]);
+
+    let features = Features {
+        scramble: true,
+        compress: true,
+    };
+    assert_eq!(Features::from_bits(features.to_bits()), features);
+    assert_eq!(features.to_string(), "scrambling, compression");
+    assert!(Features::default().is_empty());
+}
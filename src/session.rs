@@ -0,0 +1,284 @@
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::event::{Event, EventError};
+
+/// A decision the connection made, together with the reason for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Event(Event),
+    /// Smaller frames were requested, because too many frames in a row were broken
+    RequestFrameSize {
+        len: usize,
+        errors: u32,
+    },
+    /// No frame has been acked or received for this many polls, so the connection gave up
+    Stalled {
+        polls: u32,
+    },
+}
+
+/// One line of the session log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Time since the session started
+    pub elapsed: Duration,
+    /// Number of frames sent so far
+    pub tx_seq: u32,
+    /// Number of frames received so far
+    pub rx_seq: u32,
+    pub decision: Decision,
+}
+
+/// # SessionLog
+///
+/// Append-only log of every decision of a connection, one [`Entry`] per line,
+/// that can be narrated afterwards with [`explain`].
+///
+/// A line looks like `1520 tx=3 rx=0 resend seq=3 retries=1`,
+/// starting with the microseconds since the session started.
+pub struct SessionLog<W: Write = File> {
+    output: W,
+    start: Instant,
+}
+
+impl SessionLog {
+    pub fn append(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::with_output(file))
+    }
+}
+
+impl<W: Write> SessionLog<W> {
+    pub fn with_output(output: W) -> Self {
+        Self {
+            output,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, tx_seq: u32, rx_seq: u32, decision: Decision) {
+        let entry = Entry {
+            elapsed: self.start.elapsed(),
+            tx_seq,
+            rx_seq,
+            decision,
+        };
+        // the log must never get in the way of the transfer
+        let _ = writeln!(self.output, "{}", format_entry(&entry));
+    }
+}
+
+fn format_entry(entry: &Entry) -> String {
+    let mut line = format!(
+        "{} tx={} rx={} ",
+        entry.elapsed.as_micros(),
+        entry.tx_seq,
+        entry.rx_seq
+    );
+    let _ = match entry.decision {
+        Decision::Event(Event::Received { seq, len }) => {
+            write!(line, "received seq={seq} len={len}")
+        }
+        Decision::Event(Event::Acked { seq }) => write!(line, "acked seq={seq}"),
+        Decision::Event(Event::FrameSent { seq }) => write!(line, "sent seq={seq}"),
+        Decision::Event(Event::Resend { seq, retries }) => {
+            write!(line, "resend seq={seq} retries={retries}")
+        }
+        Decision::Event(Event::EchoRequest { seq }) => write!(line, "echo-request seq={seq}"),
+        Decision::Event(Event::EchoReply { seq }) => write!(line, "echo-reply seq={seq}"),
+        Decision::Event(Event::PriorityReceived { len }) => write!(line, "priority len={len}"),
+        Decision::Event(Event::PeerFinished) => write!(line, "peer-finished"),
+        Decision::Event(Event::Aborted) => write!(line, "aborted"),
+        Decision::Event(Event::Cancelled) => write!(line, "cancelled"),
+        Decision::Event(Event::FrameSizeChanged { len }) => write!(line, "frame-size len={len}"),
+        Decision::Event(Event::Error(EventError::FrameOverrun)) => write!(line, "overrun"),
+        Decision::Event(Event::Error(EventError::MalformedFrame)) => write!(line, "malformed"),
+        Decision::Event(Event::Error(EventError::ChecksumMismatch { seq })) => {
+            write!(line, "checksum-mismatch seq={seq}")
+        }
+        Decision::Event(Event::Error(EventError::InvalidEcho)) => write!(line, "invalid-echo"),
+        Decision::Event(Event::Error(EventError::UnknownSession)) => {
+            write!(line, "unknown-session")
+        }
+        Decision::Event(Event::Error(EventError::FeatureMismatch)) => {
+            write!(line, "feature-mismatch")
+        }
+        Decision::Event(Event::Error(EventError::SinkFailed)) => write!(line, "sink-failed"),
+        Decision::RequestFrameSize { len, errors } => {
+            write!(line, "request-frame-size len={len} errors={errors}")
+        }
+        Decision::Stalled { polls } => write!(line, "stalled polls={polls}"),
+    };
+    line
+}
+
+pub fn parse_entry(line: &str) -> Option<Entry> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let [elapsed, tx_seq, rx_seq, kind, fields @ ..] = &words[..] else {
+        return None;
+    };
+    let field = |name: &str| -> Option<u64> {
+        fields
+            .iter()
+            .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))?
+            .parse()
+            .ok()
+    };
+    let seq = || field("seq").map(|seq| seq as u32);
+    let len = || field("len").map(|len| len as usize);
+
+    let decision = match *kind {
+        "received" => Decision::Event(Event::Received {
+            seq: seq()?,
+            len: len()?,
+        }),
+        "acked" => Decision::Event(Event::Acked { seq: seq()? }),
+        "sent" => Decision::Event(Event::FrameSent { seq: seq()? }),
+        "resend" => Decision::Event(Event::Resend {
+            seq: seq()?,
+            retries: field("retries")? as u32,
+        }),
+        "echo-request" => Decision::Event(Event::EchoRequest { seq: seq()? }),
+        "echo-reply" => Decision::Event(Event::EchoReply { seq: seq()? }),
+        "priority" => Decision::Event(Event::PriorityReceived { len: len()? }),
+        "peer-finished" => Decision::Event(Event::PeerFinished),
+        "aborted" => Decision::Event(Event::Aborted),
+        "cancelled" => Decision::Event(Event::Cancelled),
+        "frame-size" => Decision::Event(Event::FrameSizeChanged { len: len()? }),
+        "overrun" => Decision::Event(Event::Error(EventError::FrameOverrun)),
+        "malformed" => Decision::Event(Event::Error(EventError::MalformedFrame)),
+        "checksum-mismatch" => {
+            Decision::Event(Event::Error(EventError::ChecksumMismatch { seq: seq()? }))
+        }
+        "invalid-echo" => Decision::Event(Event::Error(EventError::InvalidEcho)),
+        "unknown-session" => Decision::Event(Event::Error(EventError::UnknownSession)),
+        "feature-mismatch" => Decision::Event(Event::Error(EventError::FeatureMismatch)),
+        "sink-failed" => Decision::Event(Event::Error(EventError::SinkFailed)),
+        "request-frame-size" => Decision::RequestFrameSize {
+            len: len()?,
+            errors: field("errors")? as u32,
+        },
+        "stalled" => Decision::Stalled {
+            polls: field("polls")? as u32,
+        },
+        _ => return None,
+    };
+    Some(Entry {
+        elapsed: Duration::from_micros(elapsed.parse().ok()?),
+        tx_seq: tx_seq.strip_prefix("tx=")?.parse().ok()?,
+        rx_seq: rx_seq.strip_prefix("rx=")?.parse().ok()?,
+        decision,
+    })
+}
+
+fn narrate(decision: &Decision) -> String {
+    match decision {
+        Decision::Event(Event::Resend { seq, retries }) => {
+            format!("the other side rejected frame {seq}, sending it again (retry {retries})")
+        }
+        Decision::Event(Event::Error(EventError::ChecksumMismatch { seq })) => {
+            format!("received frame {seq} was corrupted, it has to be sent again")
+        }
+        Decision::Event(Event::Error(EventError::FrameOverrun)) => {
+            "received frame was too long, probably noise on the line".into()
+        }
+        Decision::Event(Event::Error(EventError::MalformedFrame)) => {
+            "received frame was incomplete, it has to be sent again".into()
+        }
+        Decision::Event(event) => event.to_string(),
+        Decision::RequestFrameSize { len, errors } => {
+            format!("{errors} broken frames in a row, asking for frames of {len} bytes")
+        }
+        Decision::Stalled { polls } => {
+            format!("nothing has been received for {polls} polls, giving up")
+        }
+    }
+}
+
+/// Narrates a session log, followed by a summary of where the time went.
+pub fn explain(log: &str) -> String {
+    let mut text = String::new();
+    let mut last = None;
+    let mut sent = 0;
+    let mut resends = 0;
+    let mut broken = 0;
+    // time from first sending a frame until it was acknowledged
+    let mut sent_at = None;
+    let mut slowest: Option<(u32, Duration)> = None;
+
+    for (number, line) in log.lines().enumerate() {
+        let Some(entry) = parse_entry(line) else {
+            let _ = writeln!(text, "line {}: could not be read", number + 1);
+            continue;
+        };
+        let _ = writeln!(
+            text,
+            "[{:>10.3?}] {}",
+            entry.elapsed,
+            narrate(&entry.decision)
+        );
+
+        match entry.decision {
+            Decision::Event(Event::FrameSent { seq }) => {
+                sent += 1;
+                sent_at = Some((seq, entry.elapsed));
+            }
+            Decision::Event(Event::Acked { seq }) => {
+                if let Some((sent_seq, at)) = sent_at.filter(|(sent_seq, _)| *sent_seq == seq) {
+                    let took = entry.elapsed.saturating_sub(at);
+                    if slowest.is_none_or(|(_, slowest)| took > slowest) {
+                        slowest = Some((sent_seq, took));
+                    }
+                }
+            }
+            Decision::Event(Event::Resend { .. }) => resends += 1,
+            Decision::Event(Event::Error(_)) => broken += 1,
+            _ => (),
+        }
+        last = Some(entry);
+    }
+
+    if let Some(last) = last {
+        let _ = writeln!(
+            text,
+            "\n{:.3?} in total, {sent} frames sent, {resends} resends, {broken} broken frames received",
+            last.elapsed
+        );
+    }
+    if let Some((seq, took)) = slowest {
+        let _ = writeln!(
+            text,
+            "frame {seq} took the longest to be acknowledged ({took:.3?})"
+        );
+    }
+    text
+}
+
+#[test]
+fn session_log_roundtrip() {
+    let decisions = [
+        Decision::Event(Event::FrameSent { seq: 1 }),
+        Decision::Event(Event::Resend { seq: 1, retries: 2 }),
+        Decision::Event(Event::Error(EventError::ChecksumMismatch { seq: 4 })),
+        Decision::Event(Event::Error(EventError::MalformedFrame)),
+        Decision::RequestFrameSize { len: 32, errors: 3 },
+        Decision::Stalled { polls: 10_000 },
+    ];
+    let mut log = SessionLog::with_output(Vec::new());
+    for decision in decisions {
+        log.record(1, 4, decision);
+    }
+
+    let text = String::from_utf8(log.output).unwrap();
+    let parsed: Vec<Decision> = text
+        .lines()
+        .map(|line| parse_entry(line).unwrap())
+        .inspect(|entry| assert_eq!((entry.tx_seq, entry.rx_seq), (1, 4)))
+        .map(|entry| entry.decision)
+        .collect();
+    assert_eq!(parsed, decisions);
+    assert!(explain(&text).contains("1 frames sent, 1 resends, 2 broken frames received"));
+}
@@ -0,0 +1,46 @@
+//! Cancels a [`CancellationToken`] when the process receives SIGINT (Ctrl-C).
+
+use std::sync::OnceLock;
+
+use crate::cancel::CancellationToken;
+
+static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::c_int;
+
+    const SIGINT: c_int = 2;
+    /// Returned by `signal` if the handler could not be installed
+    const SIG_ERR: usize = usize::MAX;
+
+    extern "C" {
+        // provided by the libc std links against
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn handle_sigint(_signum: c_int) {
+        // only an atomic store, which is safe to do in a signal handler
+        if let Some(token) = super::TOKEN.get() {
+            token.cancel();
+        }
+    }
+
+    pub fn install() -> bool {
+        /* SAFETY: the handler does not allocate or lock */
+        unsafe { signal(SIGINT, handle_sigint) != SIG_ERR }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    pub fn install() -> bool {
+        false
+    }
+}
+
+/// Installs the handler, returns false if it could not be installed,
+/// e.g. because it already has been or the platform is not supported.
+pub fn cancel_on_ctrl_c(token: CancellationToken) -> bool {
+    TOKEN.set(token).is_ok() && sys::install()
+}
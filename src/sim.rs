@@ -0,0 +1,453 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io;
+use std::rc::Rc;
+
+use crate::device::{DeviceName, DeviceRx, DeviceTx};
+use crate::sink::Sink;
+use crate::soak::Prbs;
+use crate::Connection;
+
+/// One direction of a simulated cable
+#[derive(Default)]
+struct Line {
+    /// Nibbles that have been sent, but not read yet
+    sent: VecDeque<u8>,
+    /// The nibble that has been read last, which stays on the line
+    value: u8,
+}
+
+/// # SimPort
+///
+/// One end of a simulated cable, every nibble that is sent stays on the line
+/// until the other end has read it, however often one end is polled in a row,
+/// so that no nibble is lost like between two ports that run at the same speed.
+pub struct SimPort {
+    tx: Rc<RefCell<Line>>,
+    rx: Rc<RefCell<Line>>,
+    /// Whether both ends are polled in turn, so that every read is a new nibble
+    lockstep: bool,
+}
+
+impl SimPort {
+    /// Both ends of a cable, `lockstep` if the ends are always polled in turn
+    pub fn pair(lockstep: bool) -> (Self, Self) {
+        let lines = [Rc::default(), Rc::default()];
+        let first = Self {
+            tx: Rc::clone(&lines[0]),
+            rx: Rc::clone(&lines[1]),
+            lockstep,
+        };
+        let second = Self {
+            tx: Rc::clone(&lines[1]),
+            rx: Rc::clone(&lines[0]),
+            lockstep,
+        };
+        (first, second)
+    }
+}
+
+impl SimPort {
+    /// A cable from the port to itself, every read returns the nibble that has been sent last
+    pub fn loopback() -> Self {
+        let line: Rc<RefCell<Line>> = Rc::default();
+        Self {
+            tx: Rc::clone(&line),
+            rx: line,
+            lockstep: true,
+        }
+    }
+}
+
+impl DeviceName for SimPort {
+    const NAME: &'static str = "Sim";
+}
+
+impl DeviceTx for SimPort {
+    fn send(&mut self, data: u8) {
+        self.tx.borrow_mut().sent.push_back(data & 0x0f);
+    }
+}
+
+impl DeviceRx for SimPort {
+    fn read(&self) -> u8 {
+        let mut line = self.rx.borrow_mut();
+        if let Some(nibble) = line.sent.pop_front() {
+            line.value = nibble;
+        }
+        line.value
+    }
+
+    fn detects_edges(&self) -> bool {
+        !self.lockstep
+    }
+}
+
+/// One of the two simulated connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+}
+
+/// # Interleaving
+///
+/// Decides which side is polled next, from a seed or a recorded schedule,
+/// so that a failing simulation can be reproduced exactly.
+///
+/// The schedule is written as one `a` or `b` per poll.
+pub struct Interleaving {
+    source: Source,
+    schedule: Vec<Side>,
+}
+
+enum Source {
+    Seeded(Prbs),
+    Replay(std::vec::IntoIter<Side>),
+}
+
+impl Interleaving {
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            source: Source::Seeded(Prbs::new(seed)),
+            schedule: Vec::new(),
+        }
+    }
+
+    /// Replays a schedule like `abba`, `None` if it contains anything else
+    pub fn replay(schedule: &str) -> Option<Self> {
+        let sides = schedule
+            .chars()
+            .map(|side| match side {
+                'a' => Some(Side::A),
+                'b' => Some(Side::B),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            source: Source::Replay(sides.into_iter()),
+            schedule: Vec::new(),
+        })
+    }
+
+    /// The side to poll next, `None` once a replayed schedule has ended
+    pub fn next_side(&mut self) -> Option<Side> {
+        let side = match &mut self.source {
+            Source::Seeded(prbs) => {
+                if prbs.next_byte() & 1 == 0 {
+                    Side::A
+                } else {
+                    Side::B
+                }
+            }
+            Source::Replay(sides) => sides.next()?,
+        };
+        self.schedule.push(side);
+        Some(side)
+    }
+}
+
+impl Display for Interleaving {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for side in &self.schedule {
+            match side {
+                Side::A => write!(f, "a")?,
+                Side::B => write!(f, "b")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// # Simulator
+///
+/// Two connections over a simulated cable in a single thread,
+/// polled one at a time in the order of the [`Interleaving`].
+pub struct Simulator<IA, IB, SA, SB>
+where
+    IA: Iterator<Item = io::Result<u8>>,
+    IB: Iterator<Item = io::Result<u8>>,
+    SA: Sink,
+    SB: Sink,
+{
+    pub a: Connection<SimPort, IA, SA>,
+    pub b: Connection<SimPort, IB, SB>,
+    interleaving: Interleaving,
+    running: [bool; 2],
+}
+
+impl<IA, IB, SA, SB> Simulator<IA, IB, SA, SB>
+where
+    IA: Iterator<Item = io::Result<u8>>,
+    IB: Iterator<Item = io::Result<u8>>,
+    SA: Sink,
+    SB: Sink,
+{
+    pub fn new(a: (IA, SA), b: (IB, SB), interleaving: Interleaving) -> Self {
+        let (port_a, port_b) = SimPort::pair(false);
+        Self {
+            a: Connection::with_output(port_a, a.0, a.1),
+            b: Connection::with_output(port_b, b.0, b.1),
+            interleaving,
+            running: [true; 2],
+        }
+    }
+
+    /// Polls the next side, returns false once both sides are done or the schedule has ended
+    pub fn step(&mut self) -> bool {
+        let Some(side) = self.interleaving.next_side() else {
+            return false;
+        };
+        match side {
+            Side::A if self.running[0] => self.running[0] = self.a.poll(),
+            Side::B if self.running[1] => self.running[1] = self.b.poll(),
+            _ => (),
+        }
+        self.running.iter().any(|running| *running)
+    }
+
+    /// Steps at most `polls` times, returns how many polls happened
+    pub fn run(&mut self, polls: usize) -> usize {
+        (0..polls).take_while(|_| self.step()).count()
+    }
+
+    pub fn interleaving(&self) -> &Interleaving {
+        &self.interleaving
+    }
+}
+
+#[test]
+fn seeded_interleaving_replays_exactly() {
+    let data = || (0..16u8).map(|byte| Ok(0xc0 | byte));
+    let simulate = |interleaving| {
+        let mut simulator = Simulator::new(
+            (data(), Vec::new()),
+            (std::iter::empty(), Vec::new()),
+            interleaving,
+        );
+        simulator.run(2_000);
+        let timelines = [
+            simulator.a.timeline.render_svg(),
+            simulator.b.timeline.render_svg(),
+        ];
+        (timelines, simulator.interleaving().to_string())
+    };
+
+    let (first, schedule) = simulate(Interleaving::seeded(7));
+    let (second, _) = simulate(Interleaving::seeded(7));
+    assert_eq!(first, second);
+    assert_eq!(schedule.len(), 2_000);
+
+    let (replayed, replayed_schedule) = simulate(Interleaving::replay(&schedule).unwrap());
+    assert_eq!(replayed, first);
+    assert_eq!(replayed_schedule, schedule);
+    assert!(Interleaving::replay("abc").is_none());
+}
+
+#[test]
+fn frames_encoded_ahead_of_time() {
+    let data: Vec<u8> = (0..4 * crate::FRAME_DATA_LEN)
+        .map(|index| 0xa0 | (index as u8 & 0x0f))
+        .collect();
+    let mut simulator = Simulator::new(
+        (data.clone().into_iter().map(Ok), Vec::new()),
+        (std::iter::empty(), Vec::new()),
+        Interleaving::seeded(3),
+    );
+
+    let mut prepared = false;
+    for _ in 0..200_000 {
+        prepared |= simulator.a.prepared_frame.is_some();
+        simulator.step();
+    }
+    assert!(prepared);
+    assert!(simulator.b.output.starts_with(&data));
+}
+
+#[test]
+fn loopback_receives_own_data() {
+    let data: Vec<u8> = (0..2 * crate::FRAME_DATA_LEN)
+        .map(|index| 0xb0 | (index as u8 & 0x0f))
+        .collect();
+    let mut connection = Connection::with_output(
+        SimPort::loopback(),
+        data.clone().into_iter().map(Ok),
+        Vec::new(),
+    );
+    for _ in 0..100_000 {
+        connection.poll();
+    }
+    assert!(connection.output.starts_with(&data));
+}
+
+#[test]
+fn reader_over_loopback() {
+    let data: Vec<u8> = (0..3 * crate::FRAME_DATA_LEN)
+        .map(|index| 0xd0 | (index as u8 & 0x0f))
+        .collect();
+    let mut connection = Connection::send_reader(
+        SimPort::loopback(),
+        io::Cursor::new(data.clone()),
+        Vec::new(),
+    );
+    for _ in 0..100_000 {
+        connection.poll();
+    }
+    assert!(connection.output.starts_with(&data));
+}
+
+#[test]
+fn split_devices_transfer() {
+    use crate::device::Split;
+
+    // every side sends on one cable and receives on the other
+    let (a_tx, b_rx) = SimPort::pair(true);
+    let (b_tx, a_rx) = SimPort::pair(true);
+    let data: Vec<u8> = (0..2 * crate::FRAME_DATA_LEN)
+        .map(|index| 0xd0 | (index as u8 & 0x0f))
+        .collect();
+    let mut a = Connection::with_output(
+        Split::new(a_tx, a_rx),
+        data.clone().into_iter().map(Ok),
+        Vec::new(),
+    );
+    let mut b = Connection::with_output(Split::new(b_tx, b_rx), std::iter::empty(), Vec::new());
+    for _ in 0..100_000 {
+        a.poll();
+        b.poll();
+    }
+    assert!(b.output.starts_with(&data));
+}
+
+#[test]
+fn unwritable_sink_aborts_the_transfer() {
+    use crate::event::{Event, EventError};
+
+    /// Like a rotating file sink on a full disk
+    struct FullDisk;
+    impl std::io::Write for FullDisk {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let data = (0..crate::FRAME_DATA_LEN as u8).map(Ok);
+    let mut simulator = Simulator::new(
+        (data, Vec::new()),
+        (std::iter::empty(), FullDisk),
+        Interleaving::seeded(3),
+    );
+    let mut events = [Vec::new(), Vec::new()];
+    for _ in 0..50_000 {
+        if !simulator.step() {
+            break;
+        }
+        events[0].extend(simulator.a.drain_events());
+        events[1].extend(simulator.b.drain_events());
+    }
+    assert_eq!(
+        simulator.b.sink_error().map(ToString::to_string).as_deref(),
+        Some("disk full")
+    );
+    assert!(events[1].contains(&Event::Error(EventError::SinkFailed)));
+    assert!(!events[1]
+        .iter()
+        .any(|event| matches!(event, Event::Received { .. })));
+    // the frame that could not be written is never acked
+    assert!(!events[0]
+        .iter()
+        .any(|event| matches!(event, Event::Acked { seq } if *seq > 0)));
+    assert!(!simulator.running[1], "the receiver did not stop");
+}
+
+#[test]
+fn scrambling_used_by_both_sides() {
+    let data: Vec<u8> = vec![0x00; 2 * crate::FRAME_DATA_LEN];
+    let simulate = |scramble_b: bool| {
+        let mut simulator = Simulator::new(
+            (data.clone().into_iter().map(Ok), Vec::new()),
+            (std::iter::empty(), Vec::new()),
+            Interleaving::seeded(5),
+        );
+        simulator.a.enable_scrambling();
+        if scramble_b {
+            simulator.b.enable_scrambling();
+        }
+        simulator.run(200_000);
+        simulator
+    };
+
+    let both = simulate(true);
+    assert!(both.b.output.starts_with(&data));
+    assert!(!both.b.features_rejected());
+
+    // the other side does not descramble, so it refuses the frames
+    let one = simulate(false);
+    assert!(one.b.features_rejected());
+    assert!(!one.b.output.starts_with(&data));
+}
+
+#[test]
+fn features_announced_until_answered() {
+    use crate::escape::EscapeCode;
+    use crate::stream::{ControlMsg, InputEvent, InputStream};
+
+    let (port, mut other) = SimPort::pair(false);
+    let mut connection = Connection::new(port, [0x00; 16].into_iter().map(Ok));
+    connection.enable_scrambling();
+    // asks for the first frame, but never answers the announcement
+    let request = [0xf0, EscapeCode::CorrectFrameData as u8, 0xf0];
+    for nibble in crate::conformance::wire_nibbles(&request) {
+        other.send(nibble);
+    }
+
+    let mut input_stream = InputStream::new();
+    let mut announcements = 0;
+    for _ in 0..3 * crate::FEATURES_RETRY_POLLS {
+        connection.poll();
+        match input_stream.push(other.read()) {
+            InputEvent::Control(ControlMsg::Echo(_)) => announcements += 1,
+            InputEvent::DataFrame { .. } => panic!("data is sent before the features are answered"),
+            _ => (),
+        }
+    }
+    assert!(announcements >= 2, "{announcements}");
+}
+
+#[test]
+fn compression_used_by_both_sides() {
+    let mut data: Vec<u8> = vec![0x00; 3 * crate::FRAME_DATA_LEN];
+    data.extend((0..crate::FRAME_DATA_LEN).map(|index| 0xa0 | (index as u8 & 0x0f)));
+    let simulate = |compress_a: bool, compress_b: bool| {
+        let mut simulator = Simulator::new(
+            (data.clone().into_iter().map(Ok), Vec::new()),
+            (std::iter::empty(), Vec::new()),
+            Interleaving::seeded(11),
+        );
+        if compress_a {
+            simulator.a.enable_compression();
+        }
+        if compress_b {
+            simulator.b.enable_compression();
+        }
+        simulator.run(200_000);
+        simulator
+    };
+
+    // the padding of the last frame decompresses to nothing
+    let both = simulate(true, true);
+    assert_eq!(both.b.output, data);
+    assert!(!both.a.features_rejected() && !both.b.features_rejected());
+
+    // neither side writes data it can not read to its sink
+    for (compress_a, compress_b) in [(true, false), (false, true)] {
+        let one = simulate(compress_a, compress_b);
+        assert!(one.a.features_rejected() || one.b.features_rejected());
+        // no frame is sent before the features have been answered
+        assert!(one.b.output.is_empty(), "{compress_a} {compress_b}");
+    }
+}
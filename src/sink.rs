@@ -0,0 +1,287 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Where the [`crate::Connection`] writes received data to.
+pub trait Sink {
+    fn receive(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Called once all data has been received
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after the received data has been verified, see [`RotatingSink`]
+    fn commit(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Drops everything after the first `len` bytes that have been received,
+    /// so that data which is sent again after resuming is not written twice
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        let _ = len;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl<W: Write> Sink for W {
+    fn receive(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_all(data)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotate {
+    /// Start a new file once the current one has reached this many bytes
+    Size(u64),
+    /// Start a new file once the current one is this old
+    Time(Duration),
+}
+
+impl Rotate {
+    /// Parses sizes like `512B`, `64KB`, `10MB`, `1GB` or durations like `30s`, `15m`, `1h`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let units = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)];
+        for (unit, factor) in units {
+            if let Some(number) = text.strip_suffix(unit) {
+                return number
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|number| number.checked_mul(factor))
+                    .map(Self::Size);
+            }
+        }
+        crate::soak::parse_duration(text).map(Self::Time)
+    }
+}
+
+/// # RotatingSink
+///
+/// Writes into a numbered series of files, e.g. `data-%03d.bin`
+/// becomes `data-000.bin`, `data-001.bin`, ...
+///
+/// If the sink is atomic, the files are written as `data-000.bin.part`
+/// and only renamed by [`Sink::commit`], so that an interrupted transfer
+/// never leaves a truncated file that looks complete.
+pub struct RotatingSink {
+    pattern: String,
+    rotate: Rotate,
+    atomic: bool,
+    index: usize,
+    file: File,
+    written: u64,
+    opened_at: Instant,
+}
+
+impl RotatingSink {
+    pub fn new(pattern: &str, rotate: Rotate, atomic: bool) -> io::Result<Self> {
+        Ok(Self {
+            pattern: pattern.into(),
+            rotate,
+            atomic,
+            index: 0,
+            file: File::create(written_name(pattern, 0, atomic))?,
+            written: 0,
+            opened_at: Instant::now(),
+        })
+    }
+
+    /// Continues writing after the files of an interrupted transfer, instead of replacing them
+    pub fn resume(pattern: &str, rotate: Rotate, atomic: bool) -> io::Result<Self> {
+        let mut index = 0;
+        while std::path::Path::new(&written_name(pattern, index + 1, atomic)).exists() {
+            index += 1;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(written_name(pattern, index, atomic))?;
+        Ok(Self {
+            pattern: pattern.into(),
+            rotate,
+            atomic,
+            index,
+            written: file.metadata()?.len(),
+            file,
+            opened_at: Instant::now(),
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.index += 1;
+        self.file = File::create(written_name(&self.pattern, self.index, self.atomic))?;
+        self.written = 0;
+        self.opened_at = Instant::now();
+        Ok(())
+    }
+}
+
+impl Sink for RotatingSink {
+    fn receive(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let len = match self.rotate {
+                Rotate::Size(max) => {
+                    let max = max.max(1);
+                    if self.written >= max {
+                        self.rotate()?;
+                    }
+                    data.len().min((max - self.written) as usize)
+                }
+                Rotate::Time(max) => {
+                    if self.opened_at.elapsed() >= max {
+                        self.rotate()?;
+                    }
+                    data.len()
+                }
+            };
+
+            let (current, rest) = data.split_at(len);
+            self.file.write_all(current)?;
+            self.written += len as u64;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        if !self.atomic {
+            return Ok(());
+        }
+        for index in 0..=self.index {
+            let name = file_name(&self.pattern, index);
+            std::fs::rename(written_name(&self.pattern, index, true), name)?;
+        }
+        // committing twice would not find the parts anymore
+        self.atomic = false;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.file.flush()?;
+        let mut start = 0;
+        for index in 0..=self.index {
+            let name = written_name(&self.pattern, index, self.atomic);
+            let file_len = std::fs::metadata(&name)?.len();
+            if len > start + file_len {
+                start += file_len;
+                continue;
+            }
+
+            // the later files only contain data after `len`
+            for later in (index + 1)..=self.index {
+                std::fs::remove_file(written_name(&self.pattern, later, self.atomic))?;
+            }
+            let file = OpenOptions::new().append(true).open(&name)?;
+            file.set_len(len - start)?;
+            self.file = file;
+            self.index = index;
+            self.written = len - start;
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("only {start} bytes have been written, not {len}"),
+        ))
+    }
+}
+
+/// Name of the file that is written to, before it is committed
+fn written_name(pattern: &str, index: usize, atomic: bool) -> String {
+    let name = file_name(pattern, index);
+    if atomic {
+        format!("{name}.part")
+    } else {
+        name
+    }
+}
+
+/// Replaces the first `%d` (optionally zero padded like `%03d`) with the index,
+/// or appends the index if there is no placeholder.
+fn file_name(pattern: &str, index: usize) -> String {
+    let Some(start) = pattern.find('%') else {
+        return format!("{pattern}.{index}");
+    };
+    let Some(len) = pattern[start..].find('d') else {
+        return format!("{pattern}.{index}");
+    };
+
+    let width = match &pattern[(start + 1)..(start + len)] {
+        "" => 0,
+        width => match width.parse::<usize>() {
+            Ok(width) => width,
+            Err(_) => return format!("{pattern}.{index}"),
+        },
+    };
+    format!(
+        "{}{index:0width$}{}",
+        &pattern[..start],
+        &pattern[(start + len + 1)..]
+    )
+}
+
+#[test]
+fn rotating_file_names() {
+    assert_eq!(file_name("data-%03d.bin", 7), "data-007.bin");
+    assert_eq!(file_name("data-%d.bin", 12), "data-12.bin");
+    assert_eq!(file_name("data.bin", 3), "data.bin.3");
+    assert_eq!(Rotate::parse("10MB"), Some(Rotate::Size(10 << 20)));
+    assert_eq!(Rotate::parse("99999999999GB"), None);
+    assert_eq!(
+        Rotate::parse("1h"),
+        Some(Rotate::Time(Duration::from_secs(3600)))
+    );
+}
+
+#[test]
+fn atomic_sink_renames_on_commit() {
+    let dir = std::env::temp_dir().join(format!("protocol-sink-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pattern = dir.join("data-%d.bin").to_string_lossy().into_owned();
+
+    let mut sink = RotatingSink::new(&pattern, Rotate::Size(2), true).unwrap();
+    sink.receive(&[1, 2, 3]).unwrap();
+    sink.finish().unwrap();
+    assert!(dir.join("data-1.bin.part").exists());
+    assert!(!dir.join("data-0.bin").exists());
+
+    sink.commit().unwrap();
+    assert_eq!(std::fs::read(dir.join("data-0.bin")).unwrap(), [1, 2]);
+    assert_eq!(std::fs::read(dir.join("data-1.bin")).unwrap(), [3]);
+    assert!(!dir.join("data-1.bin.part").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn resumed_sink_drops_data_sent_again() {
+    let dir = std::env::temp_dir().join(format!("protocol-resume-sink-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pattern = dir.join("data-%d.bin").to_string_lossy().into_owned();
+
+    let mut sink = RotatingSink::new(&pattern, Rotate::Size(2), false).unwrap();
+    sink.receive(&[1, 2, 3, 4, 5]).unwrap();
+    sink.finish().unwrap();
+    drop(sink);
+
+    // the sender only got acks for the first 3 bytes and sends from there again
+    let mut sink = RotatingSink::resume(&pattern, Rotate::Size(2), false).unwrap();
+    sink.truncate(3).unwrap();
+    sink.receive(&[4, 5, 6]).unwrap();
+    sink.finish().unwrap();
+    assert!(sink.truncate(7).is_err());
+
+    assert_eq!(std::fs::read(dir.join("data-0.bin")).unwrap(), [1, 2]);
+    assert_eq!(std::fs::read(dir.join("data-1.bin")).unwrap(), [3, 4]);
+    assert_eq!(std::fs::read(dir.join("data-2.bin")).unwrap(), [5, 6]);
+    // plain writers can not take back what has been written
+    assert!(Sink::truncate(&mut Vec::<u8>::new(), 0).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
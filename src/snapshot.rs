@@ -0,0 +1,168 @@
+use std::io;
+
+use crate::bits::NibbleOrder;
+use crate::resume::ResumeToken;
+use crate::{Frame, FRAME_LEN};
+
+/// Identifies the format, the last byte is the version
+const MAGIC: [u8; 4] = *b"PSN1";
+
+/// # Snapshot
+///
+/// Complete state of a connection, so that it can continue after a power loss
+/// without announcing its session again, unlike a [`ResumeToken`].
+///
+/// The frame that has been sent but not acked yet is kept and sent again on restore,
+/// the data source has to continue at [`Snapshot::source_offset`].
+///
+/// ## Layout
+///
+/// All numbers are little endian.
+///
+/// | Field                           | Bytes |
+/// | ------------------------------- | ----- |
+/// | `PSN1`                          | 4     |
+/// | id                              | 8     |
+/// | has peer id, peer id            | 1 + 8 |
+/// | tx and rx frame data length     | 2 + 2 |
+/// | nibble order, 0 is high first   | 1     |
+/// | sent frames, sent bytes         | 4 + 8 |
+/// | received frames, received bytes | 4 + 8 |
+/// | retries                         | 4     |
+/// | done receiving                  | 1     |
+/// | pending payload bytes           | 8     |
+/// | pending frame length, frame     | 2 + n |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub token: ResumeToken,
+    /// How often the pending frame has been resent
+    pub retries: u32,
+    pub done_receiving: bool,
+    /// Frame that has not been acked yet and the number of its bytes that are sent
+    pub pending: Option<(Frame, usize)>,
+    /// Payload bytes of the pending frame
+    pub pending_bytes: u64,
+}
+
+impl Snapshot {
+    /// Bytes of the data source that have already been put into frames
+    pub fn source_offset(&self) -> u64 {
+        self.token.sent_bytes + self.pending_bytes
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let token = &self.token;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(token.id.to_le_bytes());
+        bytes.push(token.peer_id.is_some() as u8);
+        bytes.extend(token.peer_id.unwrap_or(0).to_le_bytes());
+        bytes.extend((token.tx_frame_data_len as u16).to_le_bytes());
+        bytes.extend((token.rx_frame_data_len as u16).to_le_bytes());
+        bytes.push(matches!(token.nibble_order, NibbleOrder::LowFirst) as u8);
+        bytes.extend(token.sent_frames.to_le_bytes());
+        bytes.extend(token.sent_bytes.to_le_bytes());
+        bytes.extend(token.received_frames.to_le_bytes());
+        bytes.extend(token.received_bytes.to_le_bytes());
+        bytes.extend(self.retries.to_le_bytes());
+        bytes.push(self.done_receiving as u8);
+        bytes.extend(self.pending_bytes.to_le_bytes());
+        match &self.pending {
+            Some((frame, len)) => {
+                bytes.extend((*len as u16).to_le_bytes());
+                bytes.extend(&frame[..*len]);
+            }
+            None => bytes.extend(0u16.to_le_bytes()),
+        }
+        bytes
+    }
+
+    /// `None` if the bytes are not a snapshot or have been cut off
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut bytes = bytes.strip_prefix(&MAGIC)?;
+        let mut take = |len: usize| {
+            let (taken, rest) = bytes.split_at_checked(len)?;
+            bytes = rest;
+            Some(taken)
+        };
+        macro_rules! read {
+            ($ty:ty) => {
+                <$ty>::from_le_bytes(take(std::mem::size_of::<$ty>())?.try_into().ok()?)
+            };
+        }
+
+        let mut token = ResumeToken::new(0);
+        token.id = read!(u64);
+        let has_peer_id = read!(u8) != 0;
+        let peer_id = read!(u64);
+        token.peer_id = has_peer_id.then_some(peer_id);
+        token.tx_frame_data_len = read!(u16) as usize;
+        token.rx_frame_data_len = read!(u16) as usize;
+        token.nibble_order = match read!(u8) {
+            0 => NibbleOrder::HighFirst,
+            _ => NibbleOrder::LowFirst,
+        };
+        token.sent_frames = read!(u32);
+        token.sent_bytes = read!(u64);
+        token.received_frames = read!(u32);
+        token.received_bytes = read!(u64);
+        let retries = read!(u32);
+        let done_receiving = read!(u8) != 0;
+        let pending_bytes = read!(u64);
+        let pending = match read!(u16) as usize {
+            0 => None,
+            len if len <= FRAME_LEN => {
+                let mut frame = [0; FRAME_LEN];
+                frame[..len].copy_from_slice(take(len)?);
+                Some((frame, len))
+            }
+            _ => return None,
+        };
+        Some(Self {
+            token,
+            retries,
+            done_receiving,
+            pending,
+            pending_bytes,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        // like ResumeToken::save, never leaves half a snapshot behind
+        let temporary = format!("{path}.tmp");
+        std::fs::write(&temporary, self.to_bytes())?;
+        std::fs::rename(temporary, path)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid snapshot"))
+    }
+}
+
+#[test]
+fn snapshot_restores_pending_frame() {
+    let mut connection =
+        crate::Connection::new(crate::device::DebugDevice::new(), std::iter::empty());
+    let mut token = ResumeToken::new(8);
+    token.peer_id = Some(7);
+    token.sent_frames = 3;
+    token.sent_bytes = 24;
+    let mut frame = [0; FRAME_LEN];
+    frame[..4].copy_from_slice(&[0x12, 0xab, 0xcd, 0x23]);
+    let snapshot = Snapshot {
+        token,
+        retries: 2,
+        done_receiving: false,
+        pending: Some((frame, 4)),
+        pending_bytes: 2,
+    };
+
+    let bytes = snapshot.to_bytes();
+    assert_eq!(Snapshot::from_bytes(&bytes), Some(snapshot));
+    assert_eq!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]), None);
+    assert_eq!(snapshot.source_offset(), 26);
+
+    connection.restore(snapshot);
+    assert_eq!(connection.seq, 4);
+    assert_eq!(connection.snapshot(), snapshot);
+}
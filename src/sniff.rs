@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use crate::device::DeviceRx;
+use crate::ping::Echo;
+use crate::stream::{ControlMsg, FrameKind, InputEvent, Strictness};
+use crate::wire::WireDecoder;
+use crate::{FRAME_DATA_LEN, MINI_FRAME_DATA_LEN};
+
+/// Which side of the connection most likely sent a decoded value.
+///
+/// Both peers share the same lines, so the direction can not be measured,
+/// it is guessed from what has been sent instead:
+/// frames carry data of the sender, control codes are replies of the receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sender,
+    Receiver,
+    Unknown,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sender => write!(f, "sender  "),
+            Self::Receiver => write!(f, "receiver"),
+            Self::Unknown => write!(f, "unknown "),
+        }
+    }
+}
+
+/// The four lines one of the peers sends on, the sniffer listens to both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lines {
+    /// PA0-PA3
+    Lower,
+    /// PA4-PA7
+    Upper,
+}
+
+impl Display for Lines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lower => write!(f, "PA0-3"),
+            Self::Upper => write!(f, "PA4-7"),
+        }
+    }
+}
+
+/// Something that has been decoded from the traffic on the lines
+#[derive(Debug, PartialEq, Eq)]
+pub struct Sniffed {
+    pub direction: Direction,
+    pub description: String,
+}
+
+impl Display for Sniffed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.direction, self.description)
+    }
+}
+
+/// # Sniffer
+///
+/// Passively decodes the traffic of both peers, without ever driving the lines.
+/// Each peer sends on its own four lines, which are decoded separately,
+/// so that the traffic of one side does not break up the values of the other.
+/// Broken and interrupted frames are still shown, as far as they could be decoded.
+pub struct Sniffer {
+    lower: WireDecoder,
+    upper: WireDecoder,
+}
+
+impl Sniffer {
+    pub fn new() -> Self {
+        Self {
+            lower: WireDecoder::with_strictness(Strictness::Promiscuous),
+            upper: WireDecoder::with_strictness(Strictness::Promiscuous),
+        }
+    }
+
+    /// Decodes the levels of all eight lines, what both peers have completed in this read
+    pub fn push(&mut self, pins: u8) -> Vec<(Lines, Sniffed)> {
+        let lower = describe(self.lower.push(pins & 0x0f)).map(|sniffed| (Lines::Lower, sniffed));
+        let upper = describe(self.upper.push(pins >> 4)).map(|sniffed| (Lines::Upper, sniffed));
+        lower.into_iter().chain(upper).collect()
+    }
+}
+
+/// What an event of the [`WireDecoder`] means on the lines, `None` for [`InputEvent::LinkIdle`]
+pub fn describe(event: InputEvent) -> Option<Sniffed> {
+    let (direction, description) = match event {
+        InputEvent::DataFrame {
+            kind: FrameKind::Full,
+            payload,
+            ..
+        } => (
+            Direction::Sender,
+            format!("frame {}", hex(&payload[..FRAME_DATA_LEN])),
+        ),
+        InputEvent::DataFrame {
+            kind: FrameKind::Mini,
+            payload,
+            ..
+        } => (
+            Direction::Sender,
+            format!("mini frame {}", hex(&payload[..MINI_FRAME_DATA_LEN])),
+        ),
+        // the sniffer does not know which frame is in flight
+        InputEvent::Ack { .. } => (Direction::Receiver, "CFD".into()),
+        InputEvent::Nak { .. } => (Direction::Receiver, "IFD".into()),
+        InputEvent::Control(ControlMsg::Echo(data)) => match Echo::from_bytes(&data) {
+            Some(echo) if echo.reply => (Direction::Unknown, format!("echo reply {}", echo.seq)),
+            Some(echo) => (Direction::Unknown, format!("echo request {}", echo.seq)),
+            None => (Direction::Unknown, format!("invalid echo {}", hex(&data))),
+        },
+        InputEvent::Control(ControlMsg::FinishedSending) => (Direction::Sender, "FS".into()),
+        InputEvent::Control(ControlMsg::SetFrameSize(len)) => {
+            (Direction::Receiver, format!("SFS {len}"))
+        }
+        InputEvent::Control(ControlMsg::Abort) => (Direction::Unknown, "ABT".into()),
+        InputEvent::Control(ControlMsg::FrameOverrun) => {
+            (Direction::Unknown, "frame overrun".into())
+        }
+        InputEvent::Control(ControlMsg::MalformedFrame) => {
+            (Direction::Unknown, "malformed frame".into())
+        }
+        InputEvent::LinkIdle => return None,
+    };
+    Some(Sniffed {
+        direction,
+        description,
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Prints everything that is decoded, until the process is stopped.
+pub fn run<D: DeviceRx>(device: &D, pacing: std::time::Duration) {
+    let mut sniffer = Sniffer::new();
+    loop {
+        for (lines, sniffed) in sniffer.push(device.read()) {
+            println!("{lines} {sniffed}");
+        }
+        std::thread::sleep(pacing);
+    }
+}
+
+#[test]
+fn sniff_frame_and_ack() {
+    use crate::escape::{EscapeCode, Escaped};
+
+    let (frame, len) = crate::encode_frame(&mut Escaped::new([0xab].into_iter().map(Ok))).unwrap();
+    let mut bytes = frame[..len].to_vec();
+    bytes.push(0xf0);
+    let lower = crate::conformance::wire_nibbles(&bytes);
+    // the ack is sent on the other lines, while the frame is still being sent
+    let ack = [0xf0, EscapeCode::CorrectFrameData as u8, 0xf0];
+    let mut upper = crate::conformance::wire_nibbles(&ack);
+    upper.resize(lower.len(), *upper.last().unwrap());
+
+    let mut sniffer = Sniffer::new();
+    let sniffed: Vec<(Lines, Direction)> = lower
+        .iter()
+        .zip(&upper)
+        .flat_map(|(lower, upper)| sniffer.push(upper << 4 | lower))
+        .map(|(lines, sniffed)| (lines, sniffed.direction))
+        .collect();
+    assert_eq!(
+        sniffed,
+        [
+            (Lines::Upper, Direction::Receiver),
+            (Lines::Lower, Direction::Sender)
+        ]
+    );
+}
@@ -0,0 +1,141 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// # Prbs
+///
+/// Pseudo random byte stream (xorshift64) that both sides
+/// can regenerate from the same seed.
+pub struct Prbs {
+    state: u64,
+}
+
+impl Prbs {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Self {
+            state: seed.max(1),
+        }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 56) as u8
+    }
+}
+
+/// Sender side of the soak test, yields bytes until the duration has passed.
+pub struct Source {
+    prbs: Prbs,
+    end: Instant,
+}
+
+impl Source {
+    pub fn new(seed: u64, duration: Duration) -> Self {
+        Self {
+            prbs: Prbs::new(seed),
+            end: Instant::now() + duration,
+        }
+    }
+}
+
+impl Iterator for Source {
+    type Item = io::Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        (Instant::now() < self.end).then(|| Ok(self.prbs.next_byte()))
+    }
+}
+
+/// Receiver side of the soak test, compares received bytes with the regenerated stream.
+pub struct Verifier {
+    prbs: Prbs,
+    /// Number of bytes that have been compared
+    offset: u64,
+    first_divergence: Option<u64>,
+    byte_errors: u64,
+    bit_errors: u64,
+}
+
+impl Verifier {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            prbs: Prbs::new(seed),
+            offset: 0,
+            first_divergence: None,
+            byte_errors: 0,
+            bit_errors: 0,
+        }
+    }
+
+    /// Number of bytes that did not match the regenerated stream
+    pub fn byte_errors(&self) -> u64 {
+        self.byte_errors
+    }
+
+    pub fn report(&self) -> String {
+        let divergence = match self.first_divergence {
+            Some(offset) => format!("first divergence at byte {offset}"),
+            None => String::from("no divergence"),
+        };
+        format!(
+            "{} bytes verified, {} byte errors, {} bit errors, {divergence}",
+            self.offset, self.byte_errors, self.bit_errors
+        )
+    }
+}
+
+impl Write for Verifier {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            let expected = self.prbs.next_byte();
+            if byte != expected {
+                if self.first_divergence.is_none() {
+                    eprintln!(
+                        "Soak: first divergence at byte {}, expected {expected:02x}, got {byte:02x}",
+                        self.offset
+                    );
+                    self.first_divergence = Some(self.offset);
+                }
+                self.byte_errors += 1;
+                self.bit_errors += (byte ^ expected).count_ones() as u64;
+            }
+            self.offset += 1;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Parses durations like `90`, `30s`, `15m` or `1h`.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => text.split_at(index),
+        None => (text, "s"),
+    };
+    let number: u64 = number.parse().ok()?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(seconds))
+}
+
+#[test]
+fn verifier_detects_divergence() {
+    let mut expected = Prbs::new(42);
+    let mut bytes: Vec<u8> = (0..16).map(|_| expected.next_byte()).collect();
+    bytes[5] ^= 0b0000_0101;
+
+    let mut verifier = Verifier::new(42);
+    verifier.write_all(&bytes).unwrap();
+    assert_eq!(verifier.first_divergence, Some(5));
+    assert_eq!(verifier.byte_errors, 1);
+    assert_eq!(verifier.bit_errors, 2);
+}
@@ -0,0 +1,173 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+
+/// # ChannelSource
+///
+/// Data source that is fed from other threads through a [`SyncSender`],
+/// while the poll loop owns the device.
+///
+/// Never blocks, if no data is queued the current frame is sent as it is.
+pub struct ChannelSource {
+    receiver: Receiver<Vec<u8>>,
+    /// Remaining bytes of the message that is currently being sent
+    current: std::vec::IntoIter<u8>,
+    disconnected: bool,
+}
+
+impl ChannelSource {
+    /// Creates a source and a sender, that can queue up to `bound` messages.
+    pub fn new(bound: usize) -> (SyncSender<Vec<u8>>, Self) {
+        let (sender, receiver) = mpsc::sync_channel(bound);
+        let source = Self {
+            receiver,
+            current: Vec::new().into_iter(),
+            disconnected: false,
+        };
+        (sender, source)
+    }
+
+    /// Whether all senders have been dropped and every message has been sent
+    #[cfg(test)]
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected && self.current.len() == 0
+    }
+}
+
+impl Iterator for ChannelSource {
+    type Item = io::Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(byte) = self.current.next() {
+                return Some(Ok(byte));
+            }
+            match self.receiver.try_recv() {
+                Ok(message) => self.current = message.into_iter(),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Data source of a connection, whose type does not have to be named,
+/// see [`crate::Connection::boxed`]
+pub type BoxedSource = Box<dyn Iterator<Item = io::Result<u8>>>;
+
+/// # DataSource
+///
+/// Bytes to send, that can be read again from the last checkpoint,
+/// so that a frame which got lost can be encoded again.
+pub trait DataSource: Iterator<Item = io::Result<u8>> {
+    /// Forgets every byte before the current position, they are never read again
+    fn checkpoint(&mut self);
+    /// Continues with the first byte after the last checkpoint
+    fn rollback(&mut self);
+}
+
+/// # ReplaySource
+///
+/// Keeps the bytes any source has returned since the last checkpoint,
+/// usually the data of the frame that has not been acked yet.
+///
+/// Errors are passed on, but not replayed.
+pub struct ReplaySource<I: Iterator<Item = io::Result<u8>>> {
+    bytes: I,
+    /// Bytes since the last checkpoint
+    buffer: Vec<u8>,
+    /// Index of the next byte in the buffer, which is read from the source at the end of it
+    position: usize,
+}
+
+impl<I: Iterator<Item = io::Result<u8>>> ReplaySource<I> {
+    pub fn new(bytes: I) -> Self {
+        Self {
+            bytes,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// The source the bytes are read from
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.bytes
+    }
+}
+
+impl<I: Iterator<Item = io::Result<u8>>> Iterator for ReplaySource<I> {
+    type Item = io::Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(byte) = self.buffer.get(self.position) {
+            self.position += 1;
+            return Some(Ok(*byte));
+        }
+        let result = self.bytes.next()?;
+        if let Ok(byte) = result {
+            self.buffer.push(byte);
+            self.position += 1;
+        }
+        Some(result)
+    }
+}
+
+impl<S: DataSource + ?Sized> DataSource for Box<S> {
+    fn checkpoint(&mut self) {
+        (**self).checkpoint();
+    }
+
+    fn rollback(&mut self) {
+        (**self).rollback();
+    }
+}
+
+impl<I: Iterator<Item = io::Result<u8>>> DataSource for ReplaySource<I> {
+    fn checkpoint(&mut self) {
+        self.buffer.drain(..self.position);
+        self.position = 0;
+    }
+
+    fn rollback(&mut self) {
+        self.position = 0;
+    }
+}
+
+#[test]
+fn channel_source_from_thread() {
+    let (sender, mut source) = ChannelSource::new(4);
+    std::thread::spawn(move || {
+        sender.send(vec![1, 2]).unwrap();
+        sender.send(vec![3]).unwrap();
+    })
+    .join()
+    .unwrap();
+
+    let bytes: Vec<u8> = source.by_ref().map(Result::unwrap).collect();
+    assert_eq!(bytes, [1, 2, 3]);
+    assert!(source.is_disconnected());
+}
+
+#[test]
+fn replay_source_rollback() {
+    use crate::escape::Escaped;
+
+    let bytes = [0x01, 0x12, 0x02].into_iter().map(Ok);
+    let mut source = Escaped::new(ReplaySource::new(bytes));
+    let mut next = || source.next().unwrap().unwrap();
+    assert_eq!([next(), next()], [0x01, 0x12]);
+    source.checkpoint();
+
+    // the second half of the escaped value belongs to the next frame
+    assert_eq!(source.next().unwrap().unwrap(), 0x12);
+    assert_eq!(source.next().unwrap().unwrap(), 0x02);
+    source.rollback();
+    let replayed: Vec<u8> = source.by_ref().map(Result::unwrap).collect();
+    assert_eq!(replayed, [0x12, 0x02]);
+
+    source.checkpoint();
+    source.rollback();
+    assert!(source.next().is_none());
+}
@@ -0,0 +1,273 @@
+//! # Frame grammar
+//!
+//! What is sent on the wire, written as rules instead of tables in the docs,
+//! so that the encoder and the decoder can be checked against it.
+//!
+//! ```text
+//! value      = byte that is no escape code | escape code twice
+//! data(n)    = value{n}
+//! checksum   = value* (BU1 | BU2)*             spanning exactly ESCAPED_CHECKSUM_LEN bytes
+//! frame      = SOF data(frame size) checksum EOF
+//! mini frame = SOM data(MINI_FRAME_DATA_LEN) checksum EOF
+//! echo       = SOE data(FRAME_DATA_LEN) checksum EOF
+//! frame size = SFS value value checksum EOF
+//! control    = CFD | IFD | FS | ABT
+//! ```
+//!
+//! Buffer codes between equal nibbles belong to the nibble layer, see
+//! [`crate::conformance::wire_nibbles`], and are not part of the grammar.
+
+use crate::escape::EscapeCode;
+use crate::{ESCAPED_CHECKSUM_LEN, FRAME_DATA_LEN, MINI_FRAME_DATA_LEN};
+
+#[derive(Debug, Clone)]
+pub enum Rule {
+    Code(EscapeCode),
+    /// A data byte, escape codes are written twice
+    Value,
+    /// The rules one after the other
+    Seq(Vec<Rule>),
+    /// Any of the rules
+    Alt(Vec<Rule>),
+    /// The rule any number of times, including none
+    Many(Box<Rule>),
+    /// The rule has to span exactly this many bytes
+    Len(usize, Box<Rule>),
+    /// The rule exactly this many times
+    Times(usize, Box<Rule>),
+}
+
+impl Rule {
+    /// Whether the rule matches the whole input
+    pub fn accepts(&self, input: &[u8]) -> bool {
+        self.ends(input, 0).contains(&input.len())
+    }
+
+    /// Every position where a match that starts at `at` can end
+    fn ends(&self, input: &[u8], at: usize) -> Vec<usize> {
+        match self {
+            Self::Code(code) => match input.get(at) {
+                Some(byte) if *byte == *code as u8 => vec![at + 1],
+                _ => Vec::new(),
+            },
+            Self::Value => match input.get(at) {
+                Some(byte) if EscapeCode::from_byte(*byte).is_none() => vec![at + 1],
+                Some(byte) if input.get(at + 1) == Some(byte) => vec![at + 2],
+                _ => Vec::new(),
+            },
+            Self::Seq(rules) => rules.iter().fold(vec![at], |starts, rule| {
+                let mut ends: Vec<usize> = starts
+                    .into_iter()
+                    .flat_map(|start| rule.ends(input, start))
+                    .collect();
+                ends.sort_unstable();
+                ends.dedup();
+                ends
+            }),
+            Self::Alt(rules) => {
+                let mut ends: Vec<usize> =
+                    rules.iter().flat_map(|rule| rule.ends(input, at)).collect();
+                ends.sort_unstable();
+                ends.dedup();
+                ends
+            }
+            Self::Many(rule) => {
+                let mut ends = vec![at];
+                let mut index = 0;
+                while let Some(&start) = ends.get(index) {
+                    for end in rule.ends(input, start) {
+                        // matches that do not consume anything would repeat forever
+                        if end > start && !ends.contains(&end) {
+                            ends.push(end);
+                        }
+                    }
+                    index += 1;
+                }
+                ends
+            }
+            Self::Len(len, rule) => rule
+                .ends(input, at)
+                .into_iter()
+                .filter(|end| *end == at + len)
+                .collect(),
+            Self::Times(count, rule) => (0..*count).fold(vec![at], |starts, _| {
+                let mut ends: Vec<usize> = starts
+                    .into_iter()
+                    .flat_map(|start| rule.ends(input, start))
+                    .collect();
+                ends.sort_unstable();
+                ends.dedup();
+                ends
+            }),
+        }
+    }
+}
+
+fn many(rule: Rule) -> Rule {
+    Rule::Many(Box::new(rule))
+}
+
+pub fn data(len: usize) -> Rule {
+    Rule::Times(len, Box::new(Rule::Value))
+}
+
+/// The checksum, filled up with alternating buffer codes
+pub fn checksum() -> Rule {
+    let padding = Rule::Alt(vec![
+        Rule::Code(EscapeCode::Buffer1),
+        Rule::Code(EscapeCode::Buffer2),
+    ]);
+    Rule::Len(
+        ESCAPED_CHECKSUM_LEN,
+        Box::new(Rule::Seq(vec![many(Rule::Value), many(padding)])),
+    )
+}
+
+pub fn frame(data_len: usize) -> Rule {
+    Rule::Seq(vec![
+        Rule::Code(EscapeCode::StartOfFrame),
+        data(data_len),
+        checksum(),
+        Rule::Code(EscapeCode::EndOfFrame),
+    ])
+}
+
+pub fn mini_frame() -> Rule {
+    Rule::Seq(vec![
+        Rule::Code(EscapeCode::StartOfMiniFrame),
+        data(MINI_FRAME_DATA_LEN),
+        checksum(),
+        Rule::Code(EscapeCode::EndOfFrame),
+    ])
+}
+
+pub fn echo() -> Rule {
+    Rule::Seq(vec![
+        Rule::Code(EscapeCode::StartOfEcho),
+        data(FRAME_DATA_LEN),
+        checksum(),
+        Rule::Code(EscapeCode::EndOfFrame),
+    ])
+}
+
+pub fn frame_size() -> Rule {
+    Rule::Seq(vec![
+        Rule::Code(EscapeCode::SetFrameSize),
+        Rule::Value,
+        Rule::Value,
+        checksum(),
+        Rule::Code(EscapeCode::EndOfFrame),
+    ])
+}
+
+pub fn control() -> Rule {
+    Rule::Alt(vec![
+        Rule::Code(EscapeCode::CorrectFrameData),
+        Rule::Code(EscapeCode::IncorrectFrameData),
+        Rule::Code(EscapeCode::FinishedSending),
+        Rule::Code(EscapeCode::Abort),
+    ])
+}
+
+/// Payloads with escape codes at the start, in the middle and right before the end of the data
+fn payloads() -> Vec<Vec<u8>> {
+    let plain: Vec<u8> = (0..FRAME_DATA_LEN)
+        .map(|index| 0xa0 | (index as u8 & 0x0f))
+        .collect();
+    let mut escaped = plain.clone();
+    escaped[0] = EscapeCode::StartOfFrame as u8;
+    escaped[20] = EscapeCode::EndOfFrame as u8;
+    escaped[21] = EscapeCode::Buffer1 as u8;
+    let short = vec![
+        EscapeCode::Abort as u8,
+        0x01,
+        EscapeCode::SetFrameSize as u8,
+    ];
+    vec![plain, escaped, short, Vec::new()]
+}
+
+#[test]
+fn encoder_follows_grammar() {
+    use crate::escape::Escaped;
+    use crate::ping::Echo;
+    use crate::stream::{frame_size_payload, FRAME_SIZE_LEN};
+
+    for payload in payloads() {
+        let (encoded, len) =
+            crate::encode_frame(&mut Escaped::new(payload.iter().copied().map(Ok))).unwrap();
+        assert!(frame(FRAME_DATA_LEN).accepts(&encoded[..len]), "{payload:02x?}");
+
+        let (encoded, len) =
+            crate::encode_partial_frame(&mut Escaped::new(payload.iter().copied().map(Ok)), 16)
+                .unwrap();
+        assert!(frame(16).accepts(&encoded[..len]), "{payload:02x?}");
+        // frames of another size are not accepted
+        assert!(!frame(17).accepts(&encoded[..len]));
+    }
+
+    let request = Echo {
+        reply: false,
+        seq: 7,
+        timestamp: 0x1223_3445,
+    };
+    let (encoded, len) = request.encode();
+    assert!(echo().accepts(&encoded[..len]));
+
+    let payload = frame_size_payload(0x12);
+    let (mut encoded, len) = crate::encode_partial_frame(
+        &mut Escaped::new(payload.into_iter().map(Ok)),
+        FRAME_SIZE_LEN,
+    )
+    .unwrap();
+    encoded[0] = EscapeCode::SetFrameSize as u8;
+    assert!(frame_size().accepts(&encoded[..len]));
+
+    let (mut encoded, len) = crate::encode_partial_frame(
+        &mut Escaped::new([0xc1, 0xc2].into_iter().map(Ok)),
+        MINI_FRAME_DATA_LEN,
+    )
+    .unwrap();
+    encoded[0] = EscapeCode::StartOfMiniFrame as u8;
+    assert!(mini_frame().accepts(&encoded[..len]));
+
+    for code in [
+        EscapeCode::CorrectFrameData,
+        EscapeCode::IncorrectFrameData,
+        EscapeCode::FinishedSending,
+        EscapeCode::Abort,
+    ] {
+        assert!(control().accepts(&[code as u8]));
+    }
+    assert!(!control().accepts(&[EscapeCode::Buffer1 as u8]));
+    // a single escape code inside of the data ends it
+    assert!(!data(2).accepts(&[0xa0, EscapeCode::EndOfFrame as u8]));
+}
+
+#[test]
+fn decoder_accepts_what_grammar_accepts() {
+    use crate::conformance::wire_nibbles;
+    use crate::escape::Escaped;
+    use crate::stream::{InputEvent, InputStream};
+
+    let payload = &payloads()[0];
+    let (encoded, len) =
+        crate::encode_frame(&mut Escaped::new(payload.iter().copied().map(Ok))).unwrap();
+    let mut short = encoded[..len].to_vec();
+    short.remove(10);
+    let mut long = encoded[..len].to_vec();
+    long.insert(10, 0xa5);
+
+    for candidate in [encoded[..len].to_vec(), short, long] {
+        let mut bytes = candidate.clone();
+        bytes.extend([0xf0, 0xf0]);
+        let mut i_stream = InputStream::new();
+        let decoded = wire_nibbles(&bytes)
+            .into_iter()
+            .any(|nibble| matches!(i_stream.push(nibble), InputEvent::DataFrame { .. }));
+        assert_eq!(
+            decoded,
+            frame(FRAME_DATA_LEN).accepts(&candidate),
+            "{candidate:02x?}"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::device::{DeviceName, DeviceRx, DeviceTx};
+
+/// Nibbles a [`StdioDevice`] sends and reads in one call
+const STDIO_BATCH: usize = 1024;
+
+/// Writes the bytes prefixed with their length as big endian u16.
+pub fn write_frame(output: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(bytes.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too long"))?;
+    output.write_all(&len.to_be_bytes())?;
+    output.write_all(bytes)
+}
+
+/// Reads a length prefixed frame, returns `None` if the input has ended between frames.
+pub fn read_frame(input: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 2];
+    match input.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let mut bytes = vec![0; u16::from_be_bytes(len) as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+/// # StdioDevice
+///
+/// Carries the nibbles of a [`crate::Connection`] over a pair of byte streams,
+/// like stdin and stdout, so that the protocol can be piped through other tools.
+///
+/// Every batch of nibbles is written as a length prefixed frame, one nibble per byte.
+/// Like on the cable, reading returns the last nibble once every received one has been read.
+pub struct StdioDevice<W: Write> {
+    output: W,
+    /// Frames that have been read from the input by a background thread
+    input: Receiver<Vec<u8>>,
+    /// Nibbles of the received frames that have not been read yet
+    received: RefCell<VecDeque<u8>>,
+    last_read: Cell<u8>,
+}
+
+impl<W: Write> StdioDevice<W> {
+    pub fn new(mut input: impl Read + Send + 'static, output: W) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        // reading blocks, but the connection has to keep sending in the meantime
+        thread::spawn(move || {
+            while let Ok(Some(frame)) = read_frame(&mut input) {
+                if sender.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            output,
+            input: receiver,
+            received: RefCell::new(VecDeque::new()),
+            last_read: Cell::new(0),
+        }
+    }
+
+    /// Moves the frames that have arrived so far into the queue of received nibbles
+    fn receive(&self) {
+        let mut received = self.received.borrow_mut();
+        for frame in self.input.try_iter() {
+            received.extend(frame.into_iter().map(|byte| byte & 0x0f));
+        }
+    }
+}
+
+impl<W: Write> DeviceName for StdioDevice<W> {
+    const NAME: &'static str = "Stdio";
+}
+
+impl<W: Write> DeviceTx for StdioDevice<W> {
+    fn send(&mut self, data: u8) {
+        self.send_many(&[data]);
+    }
+
+    fn send_many(&mut self, data: &[u8]) {
+        let nibbles: Vec<u8> = data.iter().map(|nibble| nibble & 0x0f).collect();
+        for chunk in nibbles.chunks(u16::MAX as usize) {
+            // the other side has gone away, which is noticed by the protocol
+            let _ = write_frame(&mut self.output, chunk);
+        }
+        let _ = self.output.flush();
+    }
+
+    fn max_batch(&self) -> usize {
+        STDIO_BATCH
+    }
+}
+
+impl<W: Write> DeviceRx for StdioDevice<W> {
+    fn read(&self) -> u8 {
+        self.receive();
+        if let Some(nibble) = self.received.borrow_mut().pop_front() {
+            self.last_read.set(nibble);
+        }
+        self.last_read.get()
+    }
+
+    fn read_many(&self, buffer: &mut [u8]) -> usize {
+        self.receive();
+        let mut received = self.received.borrow_mut();
+        let len = buffer.len().min(received.len());
+        for (cell, nibble) in buffer.iter_mut().zip(received.drain(..len)) {
+            *cell = nibble;
+        }
+        if let Some(last) = buffer[..len].last() {
+            self.last_read.set(*last);
+        }
+        len
+    }
+}
+
+#[test]
+fn connections_over_pipes() {
+    use crate::Connection;
+
+    // escape codes within and across bytes, and more than a frame
+    let data: Vec<u8> = (0..3 * crate::FRAME_DATA_LEN)
+        .map(|index| (index * 7) as u8)
+        .collect();
+    let (a_input, b_output) = io::pipe().unwrap();
+    let (b_input, a_output) = io::pipe().unwrap();
+    let mut a = Connection::with_output(
+        StdioDevice::new(a_input, a_output),
+        data.clone().into_iter().map(Ok),
+        Vec::new(),
+    );
+    let mut b = Connection::with_output(
+        StdioDevice::new(b_input, b_output),
+        std::iter::empty(),
+        Vec::new(),
+    );
+    for _ in 0..1_000_000 {
+        let running = [a.poll(), b.poll()];
+        if b.output.len() >= data.len() || !running.contains(&true) {
+            break;
+        }
+    }
+    assert!(b.output.starts_with(&data));
+}
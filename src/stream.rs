@@ -1,6 +1,22 @@
-use crate::escape::EscapeCode;
-use crate::{Frame, CHECKSUM_LEN, FRAME_DATA_LEN, FRAME_LEN};
-use std::fmt::Debug;
+use crate::bits::{self, NibbleOrder};
+use crate::checksum;
+use crate::debugfmt;
+use crate::diagnostics::BackgroundWriter;
+use crate::escape::{EscapeCode, EscapeStats};
+use crate::framing::{EscapeFraming, Framing};
+use crate::nibble::Deque;
+use crate::{Frame, CHECKSUM_LEN, FRAME_DATA_LEN, FRAME_LEN, MINI_FRAME_DATA_LEN};
+use std::fmt::{Debug, Display};
+use std::io::Write;
+
+/// Number of bytes in a frame size frame, the length and its CRC-8
+pub const FRAME_SIZE_LEN: usize = 2;
+
+/// Data of a frame size frame, the CRC makes sure that a corrupted length is never used
+pub fn frame_size_payload(len: usize) -> [u8; FRAME_SIZE_LEN] {
+    let len = len as u8;
+    [len, checksum::crc8(&[len])]
+}
 
 pub struct InputStream {
     state: InputState,
@@ -8,120 +24,575 @@ pub struct InputStream {
     window: u16,
     // how many nibbles have been pushed into the window
     window_length: u8,
+    // how many of the next nibbles belong to an escaped value that has been decoded already
+    skip_nibbles: u8,
     data: [u8; FRAME_DATA_LEN + CHECKSUM_LEN],
     // index of nibble in the frame to write to next
     data_index: usize,
+    // how many nibble-phase slips have been detected
+    slips: u32,
+    // number of data bytes in the frames that are expected
+    frame_data_len: usize,
+    // how often every escape code has been received
+    escape_stats: EscapeStats,
+    // how many nibbles have been received since the frame started or the last escape code,
+    // buffer codes do not fill up the frame, so they are only limited by this
+    frame_nibbles: usize,
+    // how unexpected escape codes and broken frames are handled
+    strictness: Strictness,
+    // order in which the nibbles of a byte are received
+    nibble_order: NibbleOrder,
+    // how the other side sends values that are equal to escape codes
+    framing: &'static dyn Framing,
+    // whether the nibble order has been confirmed by a received escape code
+    negotiated: bool,
+    // whether nibbles are only told apart by a change of value,
+    // without it every pushed nibble is a new value, e.g. because a clock line marks them
+    edge_detection: bool,
+    // how long the line has to be idle before anything is decoded
+    squelch: Squelch,
+    // idle pattern of the other side, counts as an idle line
+    peer_idle: IdlePattern,
+    // the last nibble that has been pushed, whether it was used or not
+    last_raw: u8,
+    // for how many pushes the line has been idle
+    quiet: u32,
+    // whether the line has been idle long enough
+    open: bool,
+    // number of data frames that have been decoded, the last one's sequence number
+    received_frames: u32,
+    // sequence number of the frame the other side replies to next
+    in_flight: u32,
+    // what has been decoded, written in the background so that it does not hold up a poll
+    trace: BackgroundWriter,
+}
+
+/// How long the line has to be idle before the [`InputStream`] decodes anything,
+/// so that noise on a floating bus is not taken for escape codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Squelch {
+    /// Everything is decoded
+    #[default]
+    Off,
+    /// The line has to hold a stable value or follow the idle pattern of the other side
+    /// for this many pushes, again after every frame overrun or stray escape code
+    Polls(u32),
+}
+
+/// How the [`InputStream`] reacts to anomalies like unexpected escape codes
+/// or frames with the wrong length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Every anomaly requests the frame again
+    #[default]
+    Strict,
+    /// Escape codes outside of a frame are ignored and an unexpected SOF starts the frame again,
+    /// broken frames are still requested again
+    Tolerant,
+    /// Everything that can be decoded is emitted, even broken frames, for sniffing
+    Promiscuous,
+}
+
+impl Strictness {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "strict" => Some(Self::Strict),
+            "tolerant" => Some(Self::Tolerant),
+            "promiscuous" => Some(Self::Promiscuous),
+            _ => None,
+        }
+    }
 }
 
 impl InputStream {
     pub fn new() -> Self {
+        Self::with_strictness(Strictness::default())
+    }
+
+    pub fn with_strictness(strictness: Strictness) -> Self {
         Self {
             state: InputState::WaitingForFrame,
             window: 0x0000,
             window_length: 0,
+            skip_nibbles: 0,
             data: [0; FRAME_DATA_LEN + CHECKSUM_LEN],
             data_index: 0,
+            slips: 0,
+            frame_data_len: FRAME_DATA_LEN,
+            escape_stats: EscapeStats::default(),
+            frame_nibbles: 0,
+            strictness,
+            nibble_order: NibbleOrder::default(),
+            framing: &EscapeFraming,
+            negotiated: false,
+            edge_detection: true,
+            squelch: Squelch::Off,
+            peer_idle: IdlePattern::default(),
+            last_raw: 0,
+            quiet: 0,
+            open: true,
+            received_frames: 0,
+            in_flight: 0,
+            trace: BackgroundWriter::stderr(),
+        }
+    }
+
+    /// Sets how long the line has to be idle, and which idle pattern the other side sends
+    pub fn set_squelch(&mut self, squelch: Squelch, peer_idle: IdlePattern) {
+        self.squelch = squelch;
+        self.peer_idle = peer_idle;
+        self.open = squelch == Squelch::Off;
+        self.quiet = 0;
+    }
+
+    pub fn squelch(&self) -> (Squelch, &IdlePattern) {
+        (self.squelch, &self.peer_idle)
+    }
+
+    /// Without edge detection every pushed nibble is used, even if it is equal to the previous one,
+    /// see [`crate::device::DeviceRx::detects_edges`].
+    pub fn set_edge_detection(&mut self, edge_detection: bool) {
+        self.edge_detection = edge_detection;
+    }
+
+    /// Sets the nibble order that is expected, until the first escape code
+    /// shows which order the other side actually uses.
+    pub fn set_nibble_order(&mut self, order: NibbleOrder) {
+        self.nibble_order = order;
+        self.negotiated = false;
+    }
+
+    pub fn nibble_order(&self) -> NibbleOrder {
+        self.nibble_order
+    }
+
+    pub fn set_framing(&mut self, framing: &'static dyn Framing) {
+        self.framing = framing;
+    }
+
+    pub fn framing(&self) -> &'static dyn Framing {
+        self.framing
+    }
+
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    /// Sets the number of data bytes the following frames are expected to have
+    pub fn set_frame_data_len(&mut self, len: usize) {
+        self.frame_data_len = len.clamp(1, FRAME_DATA_LEN);
+    }
+
+    /// Number of data and checksum bytes in the frame that is being read
+    fn frame_len(&self) -> usize {
+        match self.state {
+            InputState::ReadingMiniFrame => MINI_FRAME_DATA_LEN + CHECKSUM_LEN,
+            _ => self.frame_data_len + CHECKSUM_LEN,
+        }
+    }
+
+    /// Number of frames that ended in the middle of a byte, because a nibble went missing
+    pub fn slips(&self) -> u32 {
+        self.slips
+    }
+
+    pub fn escape_stats(&self) -> &EscapeStats {
+        &self.escape_stats
+    }
+
+    pub fn state(&self) -> &InputState {
+        &self.state
+    }
+
+    /// Number of data frames that have been decoded, broken ones included
+    pub fn received_frames(&self) -> u32 {
+        self.received_frames
+    }
+
+    /// Continues numbering the data frames after `frames`, e.g. for a resumed transfer
+    pub fn set_received_frames(&mut self, frames: u32) {
+        self.received_frames = frames;
+    }
+
+    /// Sets the sequence number of the frame that has been sent last,
+    /// which the next [`InputEvent::Ack`] or [`InputEvent::Nak`] refers to
+    pub fn set_in_flight(&mut self, seq: u32) {
+        self.in_flight = seq;
+    }
+
+    /// Numbers a data frame that has been completed
+    fn data_frame(
+        &mut self,
+        kind: FrameKind,
+        payload: [u8; FRAME_DATA_LEN + CHECKSUM_LEN],
+    ) -> InputEvent {
+        self.received_frames = self.received_frames.wrapping_add(1);
+        InputEvent::DataFrame {
+            seq: self.received_frames,
+            kind,
+            payload,
         }
     }
 
-    pub fn push(&mut self, nibble: u8) -> Command {
+    pub fn push(&mut self, nibble: u8) -> InputEvent {
+        if !self.squelch_push(nibble) {
+            return InputEvent::LinkIdle;
+        }
         match self.state {
             InputState::WaitingForFrame => self.waiting_for_frame(nibble),
-            InputState::ReadingFrame => self.reading_frame(nibble),
+            InputState::ReadingFrame
+            | InputState::ReadingMiniFrame
+            | InputState::ReadingEcho
+            | InputState::ReadingFrameSize => self.reading_frame(nibble),
         }
     }
 
-    fn waiting_for_frame(&mut self, nibble: u8) -> Command {
+    fn waiting_for_frame(&mut self, nibble: u8) -> InputEvent {
+        self.frame_nibbles = 0;
         let should_read_window = self.window_push(nibble);
         if !should_read_window {
-            return Command::None;
+            return InputEvent::LinkIdle;
         }
 
-        match self.window_decode_value() {
-            DecodedValue::EscapeCode(escape_code) => match escape_code {
+        if let DecodedValue::EscapeCode(escape_code) = self.window_decode_value() {
+            match escape_code {
                 EscapeCode::StartOfFrame => {
                     self.state = InputState::ReadingFrame;
-                    eprintln!("State is now {:?}", self.state);
+                    self.trace_state();
+                }
+                EscapeCode::StartOfEcho => {
+                    self.state = InputState::ReadingEcho;
+                    self.trace_state();
+                }
+                EscapeCode::SetFrameSize => {
+                    self.state = InputState::ReadingFrameSize;
+                    self.trace_state();
+                }
+                EscapeCode::StartOfMiniFrame => {
+                    self.state = InputState::ReadingMiniFrame;
+                    self.trace_state();
                 }
-                EscapeCode::CorrectFrameData => return Command::SendNextFrame,
-                EscapeCode::IncorrectFrameData => return Command::ResendLastFrame,
-                EscapeCode::FinishedSending => return Command::StopReceivingData,
-                EscapeCode::Buffer1 | EscapeCode::Buffer2 => eprintln!("Unexpected value"),
-                EscapeCode::EndOfFrame => eprintln!("Unexpected value"),
-            },
-            _ => (),
+                EscapeCode::CorrectFrameData => {
+                    return InputEvent::Ack {
+                        seq: self.in_flight,
+                    }
+                }
+                EscapeCode::IncorrectFrameData => {
+                    return InputEvent::Nak {
+                        seq: self.in_flight,
+                    }
+                }
+                EscapeCode::FinishedSending => {
+                    return InputEvent::Control(ControlMsg::FinishedSending)
+                }
+                EscapeCode::Abort => return self.abort(),
+                // buffers and EOF only appear inside of frames,
+                // so the start of a frame has been missed
+                EscapeCode::Buffer1 | EscapeCode::Buffer2 | EscapeCode::EndOfFrame => {
+                    self.escape_stats.record_misparsed(escape_code);
+                    // or it was noise, which is not decoded again until the line is idle
+                    self.squelch_close();
+                    if self.strictness == Strictness::Strict {
+                        return InputEvent::Control(ControlMsg::MalformedFrame);
+                    }
+                }
+            }
         }
 
-        Command::None
+        InputEvent::LinkIdle
     }
 
-    fn reading_frame(&mut self, nibble: u8) -> Command {
+    fn reading_frame(&mut self, nibble: u8) -> InputEvent {
         let changed = self.window_push(nibble);
+        // no encoder sends that many nibbles for a single frame
+        if self.frame_nibbles > MAX_FRAME_NIBBLES {
+            return self.frame_overrun();
+        }
         if !changed {
-            return Command::None;
+            return InputEvent::LinkIdle;
         }
 
         let value = self.window_decode_value();
-        eprintln!("decoded: {:?}, index: {}", value, self.data_index);
+        let _ = writeln!(self.trace, "decoded: {:?}, index: {}", value, self.data_index);
+
+        // more data than fits into a frame, probably noise
+        let is_data = matches!(value, DecodedValue::Nibble(..) | DecodedValue::Byte(..));
+        // the frame size is requested again right away,
+        // instead of waiting for an EOF that might never arrive
+        if is_data
+            && matches!(self.state, InputState::ReadingFrameSize)
+            && self.data_index / 2 >= FRAME_SIZE_LEN
+        {
+            self.state = InputState::WaitingForFrame;
+            self.trace_state();
+            self.data = [0; FRAME_DATA_LEN + CHECKSUM_LEN];
+            self.data_index = 0;
+            return InputEvent::Control(ControlMsg::MalformedFrame);
+        }
+        if is_data && self.data_index / 2 >= self.frame_len() {
+            return self.frame_overrun();
+        }
+
         match value {
+            DecodedValue::Separator => InputEvent::LinkIdle,
             DecodedValue::Nibble(value) => {
                 // eprintln!("_{:01x}", value);
-                self.data[self.data_index / 2] |= value << ((1 + self.data_index) % 2) * 4;
+                self.data[self.data_index / 2] |= value << self.nibble_order.shift(self.data_index);
                 self.data_index += 1;
-                Command::None
+                InputEvent::LinkIdle
             }
             DecodedValue::Byte(value) => {
                 // eprintln!("{:02x}", value);
                 self.data[self.data_index / 2] = value;
                 self.data_index += 2;
-                Command::None
+                InputEvent::LinkIdle
             }
             DecodedValue::EscapeCode(escape_code) => {
-                if !matches!(escape_code, EscapeCode::StartOfFrame) {
+                if !matches!(escape_code, EscapeCode::Buffer1 | EscapeCode::Buffer2) {
+                    self.frame_nibbles = 0;
+                }
+                let echo = matches!(self.state, InputState::ReadingEcho);
+                let frame_size = matches!(self.state, InputState::ReadingFrameSize);
+                let mini = matches!(self.state, InputState::ReadingMiniFrame);
+                // buffers can appear inside of every kind of frame,
+                // the idle pattern after an EOF is not read as the data of another frame
+                if escape_code == EscapeCode::EndOfFrame {
+                    self.state = InputState::WaitingForFrame;
+                    self.trace_state();
+                } else if !matches!(
+                    escape_code,
+                    EscapeCode::StartOfFrame | EscapeCode::Buffer1 | EscapeCode::Buffer2
+                ) {
                     self.state = InputState::ReadingFrame;
-                    eprintln!("State is now {:?}", self.state);
+                    self.trace_state();
                 }
 
-                match dbg!(&escape_code) {
-                    EscapeCode::StartOfFrame if self.data_index != 0 => Command::ResendLastFrame,
-                    EscapeCode::EndOfFrame => {
-                        if dbg!(dbg!(self.data_index / 2) == self.data.len()) {
+                let _ = writeln!(self.trace, "escape code: {escape_code:?}");
+                match escape_code {
+                    EscapeCode::StartOfFrame if self.data_index != 0 => match self.strictness {
+                        Strictness::Strict => InputEvent::Control(ControlMsg::MalformedFrame),
+                        // the interrupted frame is dropped, it has no checksum to tell
+                        // whether its data belongs together with the data of the new one
+                        Strictness::Tolerant => {
+                            self.take_data();
+                            self.state = InputState::ReadingFrame;
+                            InputEvent::LinkIdle
+                        }
+                        // the interrupted frame is emitted and a new one is started
+                        Strictness::Promiscuous => {
                             self.data_index = 0;
-                            Command::Received(self.data)
+                            let data = std::mem::replace(
+                                &mut self.data,
+                                [0; FRAME_DATA_LEN + CHECKSUM_LEN],
+                            );
+                            self.data_frame(FrameKind::Full, data)
+                        }
+                    },
+                    // echoes are filled up like data frames, but only their first bytes are read
+                    EscapeCode::EndOfFrame if echo => {
+                        InputEvent::Control(ControlMsg::Echo(self.take_data()))
+                    }
+                    EscapeCode::EndOfFrame if frame_size => {
+                        let complete = self.data_index / 2 == FRAME_SIZE_LEN;
+                        let [len, crc] = self.take_data()[..2] else {
+                            unreachable!("frames hold more than two bytes");
+                        };
+                        if complete && crc == checksum::crc8(&[len]) {
+                            InputEvent::Control(ControlMsg::SetFrameSize(len as usize))
                         } else {
-                            self.data_index = 0;
-                            Command::ResendLastFrame
+                            InputEvent::Control(ControlMsg::MalformedFrame)
                         }
                     }
-                    EscapeCode::CorrectFrameData => Command::SendNextFrame,
-                    EscapeCode::IncorrectFrameData => Command::ResendLastFrame,
-                    EscapeCode::FinishedSending => Command::StopReceivingData,
+                    EscapeCode::EndOfFrame if self.strictness == Strictness::Promiscuous => {
+                        let data = self.take_data();
+                        self.data_frame(FrameKind::Full, data)
+                    }
+                    // frames only ever contain whole bytes, so a nibble went missing
+                    EscapeCode::EndOfFrame if !self.data_index.is_multiple_of(2) => {
+                        let _ = writeln!(self.trace, "Nibble slip detected at index {}", self.data_index);
+                        self.take_data();
+                        self.slips += 1;
+                        InputEvent::Control(ControlMsg::MalformedFrame)
+                    }
+                    EscapeCode::EndOfFrame if mini => {
+                        let complete = self.data_index / 2 == MINI_FRAME_DATA_LEN + CHECKSUM_LEN;
+                        let data = self.take_data();
+                        if complete {
+                            self.data_frame(FrameKind::Mini, data)
+                        } else {
+                            InputEvent::Control(ControlMsg::MalformedFrame)
+                        }
+                    }
+                    EscapeCode::EndOfFrame => {
+                        let complete = self.data_index / 2 == self.frame_len();
+                        let data = self.take_data();
+                        if complete {
+                            self.data_frame(FrameKind::Full, data)
+                        } else {
+                            InputEvent::Control(ControlMsg::MalformedFrame)
+                        }
+                    }
+                    EscapeCode::CorrectFrameData => InputEvent::Ack {
+                        seq: self.in_flight,
+                    },
+                    EscapeCode::IncorrectFrameData => InputEvent::Nak {
+                        seq: self.in_flight,
+                    },
+                    EscapeCode::FinishedSending => InputEvent::Control(ControlMsg::FinishedSending),
+                    EscapeCode::Abort => self.abort(),
+                    EscapeCode::StartOfEcho => {
+                        self.state = InputState::ReadingEcho;
+                        self.data_index = 0;
+                        InputEvent::LinkIdle
+                    }
+                    EscapeCode::SetFrameSize => {
+                        self.state = InputState::ReadingFrameSize;
+                        self.data_index = 0;
+                        InputEvent::LinkIdle
+                    }
+                    EscapeCode::StartOfMiniFrame => {
+                        self.state = InputState::ReadingMiniFrame;
+                        self.data_index = 0;
+                        InputEvent::LinkIdle
+                    }
                     EscapeCode::StartOfFrame | EscapeCode::Buffer1 | EscapeCode::Buffer2 => {
-                        Command::None
+                        InputEvent::LinkIdle
                     }
                 }
             }
         }
     }
 
+    fn trace_state(&mut self) {
+        let _ = writeln!(self.trace, "State is now {:?}", self.state);
+    }
+
+    /// Takes the data of the frame that has ended, so that the next one starts out empty
+    fn take_data(&mut self) -> [u8; FRAME_DATA_LEN + CHECKSUM_LEN] {
+        self.data_index = 0;
+        std::mem::replace(&mut self.data, [0; FRAME_DATA_LEN + CHECKSUM_LEN])
+    }
+
+    /// Drops everything that has been received and waits for the next start of frame
+    fn abort(&mut self) -> InputEvent {
+        self.state = InputState::WaitingForFrame;
+        self.trace_state();
+        self.data = [0; FRAME_DATA_LEN + CHECKSUM_LEN];
+        self.data_index = 0;
+        InputEvent::Control(ControlMsg::Abort)
+    }
+
+    /// Drops the frame and waits for the next start of frame
+    fn frame_overrun(&mut self) -> InputEvent {
+        self.squelch_close();
+        self.state = InputState::WaitingForFrame;
+        self.trace_state();
+        self.data = [0; FRAME_DATA_LEN + CHECKSUM_LEN];
+        self.data_index = 0;
+        InputEvent::Control(ControlMsg::FrameOverrun)
+    }
+
+    /// Returns whether the nibble should be decoded,
+    /// only once the line has been idle long enough.
+    fn squelch_push(&mut self, nibble: u8) -> bool {
+        let nibble = bits::lower_nibble(nibble);
+        let previous = std::mem::replace(&mut self.last_raw, nibble);
+        let Squelch::Polls(polls) = self.squelch else {
+            return true;
+        };
+        if self.open {
+            return true;
+        }
+
+        let idle = previous == nibble
+            || match &self.peer_idle {
+                IdlePattern::Alternating => {
+                    [previous, nibble] == [0x0, 0xf] || [previous, nibble] == [0xf, 0x0]
+                }
+                IdlePattern::Sequence(nibbles) => nibbles
+                    .iter()
+                    .zip(nibbles.iter().cycle().skip(1))
+                    .any(|pair| pair == (&previous, &nibble)),
+                IdlePattern::HoldLast | IdlePattern::TriState => false,
+            };
+        self.quiet = if idle { self.quiet + 1 } else { 0 };
+        if self.quiet >= polls {
+            self.open = true;
+            // the idle nibbles are not part of the next value
+            self.window = nibble as u16;
+            self.window_length = 0;
+            let _ = writeln!(self.trace, "Line is idle, squelch opened");
+        }
+        false
+    }
+
+    fn squelch_close(&mut self) {
+        if self.squelch != Squelch::Off {
+            self.open = false;
+            self.quiet = 0;
+        }
+    }
+
     fn window_decode_value(&mut self) -> DecodedValue {
-        let higher_byte = (self.window >> u8::BITS) as u8;
-        let lower_byte = self.window as u8;
+        let [first, second] = bits::split((self.window >> u8::BITS) as u8);
+        let [third, fourth] = bits::split(self.window as u8);
+        let higher_byte = self.nibble_order.join(first, second);
+        let lower_byte = self.nibble_order.join(third, fourth);
+
+        // the first escape code tells in which order the other side sends nibbles,
+        // only the buffer codes, which are never sent first, stay escape codes when swapped
+        if !self.negotiated {
+            if EscapeCode::from_byte(higher_byte).is_some() {
+                self.negotiated = true;
+            } else if EscapeCode::from_byte(bits::swap_nibbles(higher_byte)).is_some() {
+                self.nibble_order = self.nibble_order.swapped();
+                self.negotiated = true;
+                let _ = writeln!(self.trace, "Nibble order is now {:?}", self.nibble_order);
+                return self.window_decode_value();
+            }
+        }
+
+        // values are only escaped inside of a frame, like a SOF followed by an escaped 0x12,
+        // and start at a byte, `0xa1 0x21 0x2b` contains `0x12 0x12` across its bytes
+        if EscapeCode::from_byte(higher_byte).is_some()
+            && !matches!(self.state, InputState::WaitingForFrame)
+            && self.data_index.is_multiple_of(2)
+        {
+            let escaped = self.framing.escaped(higher_byte);
+            // the second byte starts with the nibble the value ends with, like `0x12 0x21`,
+            // so only the separator in between fits into the window, the rest is skipped
+            let separated = self.edge_detection
+                && self.nibble_order.split(escaped)[0] == second
+                && [third, fourth] == separator(second, self.nibble_order);
+            if lower_byte == escaped || separated {
+                self.escape_stats.record_escaped(higher_byte);
+                self.window_length = 0;
+                self.skip_nibbles = if separated { 2 } else { 0 };
+                return DecodedValue::Byte(higher_byte);
+            }
+        }
+
+        if self.is_separator(higher_byte, third) {
+            if let Some(escape_code) = EscapeCode::from_byte(higher_byte) {
+                self.escape_stats.record_seen(escape_code);
+            }
+            self.window_length = 2;
+            return DecodedValue::Separator;
+        }
 
         // detect escape codes and shrink the window,
         // so that the data is not decoded again in the next iteration
         match EscapeCode::from_byte(higher_byte) {
-            Some(_) if higher_byte == lower_byte => {
-                self.window_length = 0;
-                let byte = self.window >> u8::BITS;
-                DecodedValue::Byte(byte as u8)
-            }
-            Some(escape_code) => {
-                eprintln!("window = {:04x}", self.window);
+            Some(escape_code) if !self.is_misaligned(&escape_code) => {
+                let _ = writeln!(self.trace, "window = {:04x}", self.window);
+                self.escape_stats.record_seen(escape_code);
                 self.window_length = 2;
                 DecodedValue::EscapeCode(escape_code)
             }
-            None => {
+            misaligned => {
+                if let Some(escape_code) = misaligned {
+                    self.escape_stats.record_misparsed(escape_code);
+                }
                 self.window_length = 3;
                 let nibble = self.window >> (u8::BITS + u8::BITS / 2);
                 DecodedValue::Nibble(nibble as u8)
@@ -129,15 +600,54 @@ impl InputStream {
         }
     }
 
+    /// Whether the byte is the [`separator`] between the last data nibble and the next one,
+    /// instead of an escape code that interrupts the frame
+    fn is_separator(&self, byte: u8, next: u8) -> bool {
+        if matches!(self.state, InputState::WaitingForFrame)
+            || self.data_index == 0
+            || !self.edge_detection
+        {
+            return false;
+        }
+        let index = self.data_index - 1;
+        let mut last = bits::lower_nibble(self.data[index / 2] >> self.nibble_order.shift(index));
+        // an escaped value is followed by its second byte on the wire, which ends differently
+        // with the legacy scheme, like `0x65 0x56`
+        if !index.is_multiple_of(2) && EscapeCode::from_byte(self.data[index / 2]).is_some() {
+            last = self.nibble_order.split(self.framing.escaped(self.data[index / 2]))[1];
+        }
+        last == next && separator(last, self.nibble_order) == self.nibble_order.split(byte)
+    }
+
+    /// Whether the escape code starts in the middle of a data byte, like `0x12`
+    /// in the data `0xa1 0x2b`, so that it is really the low and high nibble of two bytes.
+    ///
+    /// A buffer code inside of a byte is only skipped as a [`separator`],
+    /// and an EOF can arrive at either alignment when the frame is a nibble short or not filled up.
+    fn is_misaligned(&self, escape_code: &EscapeCode) -> bool {
+        if matches!(self.state, InputState::WaitingForFrame) || self.data_index.is_multiple_of(2) {
+            return false;
+        }
+        match escape_code {
+            EscapeCode::EndOfFrame => {
+                matches!(
+                    self.state,
+                    InputState::ReadingFrame | InputState::ReadingMiniFrame
+                ) && self.data_index + 1 < 2 * self.frame_len()
+            }
+            _ => true,
+        }
+    }
+
     /// Pushes the nibble into the window and
     /// returns whether the window should be looked at or not
     fn window_push(&mut self, nibble: u8) -> bool {
         // ensures that the unused nibble is 0
-        let nibble = nibble & 0x0f;
+        let nibble = bits::lower_nibble(nibble);
         // truncates the u16, so that only the least significant nibble is left
-        let previous_nibble = (self.window as u8) & 0x0f;
-        // whether value has changed
-        if previous_nibble == nibble {
+        let previous_nibble = bits::lower_nibble(self.window as u8);
+        // whether value has changed, which is the only way to tell nibbles apart without a clock
+        if previous_nibble == nibble && self.edge_detection {
             return false;
         }
 
@@ -145,6 +655,11 @@ impl InputStream {
         self.window <<= 4;
         self.window |= nibble as u16;
         self.window_length += 1;
+        self.frame_nibbles += 1;
+        if self.skip_nibbles > 0 {
+            self.skip_nibbles -= 1;
+            self.window_length -= 1;
+        }
 
         // whether enough data has been pushed into the window
         self.window_length == 4
@@ -155,6 +670,8 @@ enum DecodedValue {
     Nibble(u8),
     Byte(u8),
     EscapeCode(EscapeCode),
+    /// A [`separator`] that is not a buffer code
+    Separator,
 }
 
 impl Debug for DecodedValue {
@@ -169,37 +686,115 @@ impl Debug for DecodedValue {
                 .field(&format!("{:02x}", arg0))
                 .finish(),
             Self::EscapeCode(arg0) => f.debug_tuple("EscapeCode").field(arg0).finish(),
+            Self::Separator => write!(f, "Separator"),
         }
     }
 }
 
 #[derive(Debug)]
-enum InputState {
+#[non_exhaustive]
+pub enum InputState {
     WaitingForFrame,
     ReadingFrame,
+    ReadingMiniFrame,
+    ReadingEcho,
+    ReadingFrameSize,
 }
 
-#[derive(PartialEq, Eq)]
-pub enum Command {
-    Received([u8; FRAME_DATA_LEN + CHECKSUM_LEN]),
-    SendNextFrame,
-    ResendLastFrame,
+impl Display for InputState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WaitingForFrame => write!(f, "waiting for frame"),
+            Self::ReadingFrame => write!(f, "reading frame"),
+            Self::ReadingMiniFrame => write!(f, "reading mini frame"),
+            Self::ReadingEcho => write!(f, "reading echo"),
+            Self::ReadingFrameSize => write!(f, "reading frame size"),
+        }
+    }
+}
+
+/// Whether a data frame has the negotiated size or is a mini frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Full,
+    /// Only the first [`MINI_FRAME_DATA_LEN`] bytes are used
+    Mini,
+}
+
+/// Everything the other side sends that is neither data nor the reply to a data frame
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ControlMsg {
+    /// Data of an echo frame, padded with zeros
+    Echo([u8; FRAME_DATA_LEN + CHECKSUM_LEN]),
     /// From now on the other side will only send escape codes
-    StopReceivingData,
-    None,
+    FinishedSending,
+    /// The other side wants to receive frames with this many data bytes
+    SetFrameSize(usize),
+    /// The frame was longer than allowed and has been dropped
+    FrameOverrun,
+    /// The frame was incomplete or interrupted and has been dropped
+    MalformedFrame,
+    /// The other side has discarded everything and starts over
+    Abort,
 }
 
-impl Debug for Command {
+impl Debug for ControlMsg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Received(arg0) => f
-                .debug_tuple("Received")
-                .field(&bytes_to_debug_string(arg0))
+            Self::Echo(data) => f
+                .debug_tuple("Echo")
+                .field(&debugfmt::hex_list(data))
                 .finish(),
-            Self::SendNextFrame => write!(f, "SendNextFrame"),
-            Self::ResendLastFrame => write!(f, "ResendLastFrame"),
-            Self::StopReceivingData => write!(f, "StopReceivingData"),
-            Self::None => write!(f, "None"),
+            Self::FinishedSending => write!(f, "FinishedSending"),
+            Self::SetFrameSize(len) => write!(f, "SetFrameSize({len})"),
+            Self::FrameOverrun => write!(f, "FrameOverrun"),
+            Self::MalformedFrame => write!(f, "MalformedFrame"),
+            Self::Abort => write!(f, "Abort"),
+        }
+    }
+}
+
+/// What the [`InputStream`] has decoded from the other side
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    /// Data and checksum of a frame, numbered in the order frames have been received,
+    /// whether their checksum matches or not
+    DataFrame {
+        seq: u32,
+        kind: FrameKind,
+        payload: [u8; FRAME_DATA_LEN + CHECKSUM_LEN],
+    },
+    /// The other side has received the frame in flight, see [`InputStream::set_in_flight`]
+    Ack {
+        seq: u32,
+    },
+    /// The other side has received the frame in flight broken and wants it again
+    Nak {
+        seq: u32,
+    },
+    Control(ControlMsg),
+    /// Nothing has been completed by the nibble
+    LinkIdle,
+}
+
+impl Debug for InputEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DataFrame { seq, kind, payload } => {
+                let len = match kind {
+                    FrameKind::Full => payload.len(),
+                    FrameKind::Mini => MINI_FRAME_DATA_LEN + CHECKSUM_LEN,
+                };
+                f.debug_struct("DataFrame")
+                    .field("seq", seq)
+                    .field("kind", kind)
+                    .field("payload", &debugfmt::hex_list(&payload[..len]))
+                    .finish()
+            }
+            Self::Ack { seq } => write!(f, "Ack {{ seq: {seq} }}"),
+            Self::Nak { seq } => write!(f, "Nak {{ seq: {seq} }}"),
+            Self::Control(msg) => f.debug_tuple("Control").field(msg).finish(),
+            Self::LinkIdle => write!(f, "LinkIdle"),
         }
     }
 }
@@ -212,9 +807,13 @@ fn read_alternating() {
     assert_eq!(
         commands
             .iter()
-            .filter(|command| matches!(command, Command::Received(..)))
+            .filter(|command| matches!(command, InputEvent::DataFrame { .. }))
             .collect::<Vec<_>>(),
-        vec![&Command::Received([0xf0; 64])],
+        vec![&InputEvent::DataFrame {
+            seq: 1,
+            kind: FrameKind::Full,
+            payload: [0xf0; 64],
+        }],
     );
 }
 
@@ -226,9 +825,13 @@ fn read_zeros() {
     assert_eq!(
         commands
             .iter()
-            .filter(|command| matches!(command, Command::Received(..)))
+            .filter(|command| matches!(command, InputEvent::DataFrame { .. }))
             .collect::<Vec<_>>(),
-        vec![&Command::Received([0x00; 64])],
+        vec![&InputEvent::DataFrame {
+            seq: 1,
+            kind: FrameKind::Full,
+            payload: [0x00; 64],
+        }],
     );
 }
 
@@ -246,72 +849,403 @@ fn read_random() {
     assert_eq!(
         commands
             .iter()
-            .filter(|command| matches!(command, Command::Received(..)))
+            .filter(|command| matches!(command, InputEvent::DataFrame { .. }))
             .collect::<Vec<_>>(),
-        vec![&Command::Received([
-            0xa0, 0x8e, 0x4f, 0x24, 0x68, 0x53, 0x13, 0xcb, 0x17, 0xeb, 0xa1, 0xf2, 0x7e, 0xb3,
-            0xab, 0x07, 0x00, 0x4c, 0xac, 0x54, 0x34, 0x34, 0x5b, 0x72, 0x96, 0x09, 0xc0, 0xda,
-            0xbc, 0x17, 0xbc, 0xef, 0xa9, 0x7f, 0x65, 0x39, 0x58, 0x21, 0x72, 0xdd, 0x0b, 0xba,
-            0x9a, 0x75, 0xcd, 0x5f, 0xa2, 0x44, 0x43, 0x1b, 0xd2, 0x0d, 0x5b, 0x7c, 0x65, 0xbb,
-            0xc9, 0x4f, 0x78, 0xfe, 0x08, 0x6e, 0x23, 0x23,
-        ])],
+        vec![&InputEvent::DataFrame {
+            seq: 1,
+            kind: FrameKind::Full,
+            payload: [
+                0xa0, 0x8e, 0x4f, 0x24, 0x68, 0x53, 0x13, 0xcb, 0x17, 0xeb, 0xa1, 0xf2, 0x7e, 0xb3,
+                0xab, 0x07, 0x00, 0x4c, 0xac, 0x54, 0x34, 0x5b, 0x72, 0x96, 0x09, 0xc0, 0xda, 0xbc,
+                0x17, 0xbc, 0xef, 0xa9, 0x7f, 0x65, 0x39, 0x58, 0x21, 0x72, 0xdd, 0x0b, 0xba, 0x9a,
+                0x75, 0xcd, 0x5f, 0xa2, 0x44, 0x43, 0x1b, 0xd2, 0x0d, 0x5b, 0x7c, 0x65, 0xbb, 0xc9,
+                0x4f, 0x78, 0xfe, 0x08, 0x6e, 0x23, 0xce, 0x40,
+            ]
+        }],
     );
 }
 
 #[cfg(test)]
-fn use_input_stream(data: impl Iterator<Item = u8>) -> (Vec<Command>, InputStream) {
-    use crate::{encode_frame, Escaped};
-    let mut iter = Escaped::new(data.map(|byte| Ok(byte)));
-
+fn use_input_stream(data: impl Iterator<Item = u8>) -> (Vec<InputEvent>, InputStream) {
     let mut output_stream = OutputStream::new();
     let mut input_stream = InputStream::new();
     let mut commands = Vec::new();
 
-    while !iter.is_done() {
-        let frame = encode_frame(&mut iter);
-        eprintln!("{}", bytes_to_debug_string(&frame));
-
-        // TODO Use output stream
-        for byte in [&[0xf0; 5], frame.as_slice(), &[0xf0; 5]].concat() {
-            let higher_nibble = byte >> 4;
-            let lowher_nibble = byte & 0x0f;
-            commands.push(input_stream.push(higher_nibble));
-            if higher_nibble == lowher_nibble {
-                commands.push(input_stream.push(EscapeCode::Buffer1 as u8 >> 4));
-                commands.push(input_stream.push(EscapeCode::Buffer1 as u8));
-            }
-            commands.push(input_stream.push(lowher_nibble));
+    for (frame, len) in crate::encode_frames(data.map(Ok)).map(Result::unwrap) {
+        eprintln!("{}", debugfmt::hex_list(&frame[..len]));
+
+        // the idle pattern around the frame completes its SOF and EOF
+        for _ in 0..4 {
+            commands.push(input_stream.push(output_stream.next()));
+        }
+        output_stream.send_frame(frame, len);
+        while matches!(output_stream.state(), OutputState::WritingFrame) {
+            commands.push(input_stream.push(output_stream.next()));
+        }
+        for _ in 0..4 {
+            commands.push(input_stream.push(output_stream.next()));
+        }
+    }
+
+    (commands, input_stream)
+}
+
+#[cfg(test)]
+fn push_bytes(input_stream: &mut InputStream, bytes: &[u8]) -> Vec<InputEvent> {
+    let mut commands = Vec::new();
+    for byte in bytes {
+        let higher_nibble = byte >> 4;
+        let lowher_nibble = byte & 0x0f;
+        commands.push(input_stream.push(higher_nibble));
+        if higher_nibble == lowher_nibble {
             commands.push(input_stream.push(EscapeCode::Buffer1 as u8 >> 4));
             commands.push(input_stream.push(EscapeCode::Buffer1 as u8));
         }
+        commands.push(input_stream.push(lowher_nibble));
+        commands.push(input_stream.push(EscapeCode::Buffer1 as u8 >> 4));
+        commands.push(input_stream.push(EscapeCode::Buffer1 as u8));
     }
+    commands
+}
 
-    return (commands, input_stream);
+#[test]
+fn detect_missing_nibble() {
+    let mut input_stream = InputStream::new();
+    input_stream.set_frame_data_len(2);
+    // SOF, 0xf0, 0xf0 missing its lower nibble, EOF, followed by a good frame
+    let nibbles = [0x1, 0x2, 0xf, 0x0, 0xf, 0x2, 0x3];
+    let mut commands: Vec<InputEvent> = nibbles
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .collect();
+    commands.extend(push_bytes(
+        &mut input_stream,
+        &[
+            EscapeCode::StartOfFrame as u8,
+            0xc7,
+            0xc8,
+            EscapeCode::EndOfFrame as u8,
+        ],
+    ));
+    commands.retain(|command| *command != InputEvent::LinkIdle);
+
+    assert_eq!(input_stream.slips(), 1);
+    let mut payload = [0; FRAME_DATA_LEN + CHECKSUM_LEN];
+    payload[..2].copy_from_slice(&[0xc7, 0xc8]);
+    assert_eq!(
+        commands,
+        [
+            InputEvent::Control(ControlMsg::MalformedFrame),
+            InputEvent::DataFrame {
+                seq: 1,
+                kind: FrameKind::Full,
+                payload,
+            }
+        ]
+    );
 }
 
-fn bytes_to_debug_string(bytes: &[u8]) -> String {
-    let mut result = bytes
+#[test]
+fn read_frame_size_request() {
+    let mut input_stream = InputStream::new();
+    // SFS, 0x08, CRC, EOF
+    let nibbles = [0x9, 0xa, 0x0, 0x8, 0x3, 0x8, 0x2, 0x3, 0xf, 0x0];
+
+    let commands: Vec<InputEvent> = nibbles
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .collect();
+    assert_eq!(frame_size_payload(8), [0x08, 0x38]);
+    assert_eq!(
+        commands.last(),
+        Some(&InputEvent::Control(ControlMsg::SetFrameSize(8)))
+    );
+
+    // the length has been corrupted to 0x09
+    let mut input_stream = InputStream::new();
+    let nibbles = [0x9, 0xa, 0x0, 0x9, 0x3, 0x8, 0x2, 0x3, 0xf, 0x0];
+    let commands: Vec<InputEvent> = nibbles
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .collect();
+    assert_eq!(
+        commands.last(),
+        Some(&InputEvent::Control(ControlMsg::MalformedFrame))
+    );
+}
+
+#[test]
+fn read_mini_frame() {
+    let data: Vec<u8> = (0..MINI_FRAME_DATA_LEN as u8)
+        .map(|index| 0xc0 | index)
+        .collect();
+    let mut bytes = vec![0xf0, EscapeCode::StartOfMiniFrame as u8];
+    bytes.extend(&data);
+    bytes.extend([EscapeCode::EndOfFrame as u8, 0xf0]);
+
+    let mut input_stream = InputStream::new();
+    let commands: Vec<InputEvent> = crate::conformance::wire_nibbles(&bytes)
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .filter(|command| *command != InputEvent::LinkIdle)
+        .collect();
+    let [InputEvent::DataFrame {
+        kind: FrameKind::Mini,
+        payload: frame,
+        ..
+    }] = commands.as_slice()
+    else {
+        panic!("{commands:?}");
+    };
+    assert_eq!(frame[..MINI_FRAME_DATA_LEN], data);
+    // the next frame has the normal size again
+    assert_eq!(input_stream.frame_len(), FRAME_DATA_LEN + CHECKSUM_LEN);
+}
+
+#[test]
+fn read_escaped_checksum() {
+    // CHECKSUM_LEN is still 0, so a two byte checksum behind four data bytes
+    // is read like a frame of six data bytes
+    let checksum = [EscapeCode::EndOfFrame as u8, 0xc1];
+    let mut cells = [0; 4];
+    crate::write_checksum(&mut cells, &checksum);
+    assert_eq!(cells, [0x23, 0x23, 0xc1, EscapeCode::Buffer1 as u8]);
+
+    let mut bytes = vec![0xf0, EscapeCode::StartOfFrame as u8, 0xc2, 0xc3, 0xc4, 0xc5];
+    bytes.extend(cells);
+    bytes.extend([EscapeCode::EndOfFrame as u8, 0xf0]);
+
+    let mut input_stream = InputStream::new();
+    input_stream.set_frame_data_len(6);
+    let commands: Vec<InputEvent> = crate::conformance::wire_nibbles(&bytes)
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .filter(|command| *command != InputEvent::LinkIdle)
+        .collect();
+    let [InputEvent::DataFrame {
+        kind: FrameKind::Full,
+        payload,
+        ..
+    }] = commands.as_slice()
+    else {
+        panic!("{commands:?}");
+    };
+    assert_eq!(payload[..4], [0xc2, 0xc3, 0xc4, 0xc5]);
+    assert_eq!(payload[4..6], checksum);
+}
+
+#[test]
+fn detect_low_nibble_first() {
+    let mut input_stream = InputStream::new();
+    // SFS, 0x10, CRC, EOF with the lower nibble sent first
+    let nibbles = [0xa, 0x9, 0x0, 0x1, 0x0, 0x7, 0x3, 0x2, 0x0, 0xf];
+
+    let commands: Vec<InputEvent> = nibbles
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .collect();
+    assert_eq!(input_stream.nibble_order(), NibbleOrder::LowFirst);
+    assert_eq!(
+        commands.last(),
+        Some(&InputEvent::Control(ControlMsg::SetFrameSize(16)))
+    );
+}
+
+#[test]
+fn clocked_equal_nibbles() {
+    let mut input_stream = InputStream::new();
+    input_stream.set_edge_detection(false);
+    input_stream.set_frame_data_len(2);
+    // SOF, 0x44, 0x44 without buffers in between, EOF
+    let nibbles = [0x1, 0x2, 0x4, 0x4, 0x4, 0x4, 0x2, 0x3, 0xf, 0x0];
+    let payloads: Vec<_> = nibbles
+        .into_iter()
+        .filter_map(|nibble| match input_stream.push(nibble) {
+            InputEvent::DataFrame { payload, .. } => Some(payload),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(payloads.len(), 1);
+    assert_eq!(payloads[0][..2], [0x44, 0x44]);
+}
+
+#[test]
+fn squelch_floating_line() {
+    let mut input_stream = InputStream::new();
+    input_stream.set_squelch(Squelch::Polls(4), IdlePattern::Alternating);
+    // noise that contains EOF and CFD, then the alternating idle pattern
+    let noise = [
+        0x7, 0x2, 0x3, 0x9, 0x4, 0x5, 0x1, 0x6, 0xf, 0x0, 0xf, 0x0, 0xf,
+    ];
+    // SOF, 0xc7, start of EOF
+    let frame = [0x1, 0x2, 0xc, 0x7, 0x2, 0x3, 0xf];
+    let commands: Vec<InputEvent> = noise
+        .into_iter()
+        .chain(frame)
+        .map(|nibble| input_stream.push(nibble))
+        .collect();
+    assert!(commands[..noise.len()]
         .iter()
-        .map(|byte| format!("{byte:02x}"))
-        .zip(std::iter::repeat(", "))
-        .fold(String::from("["), |accum, (l, r)| accum + &l + r);
-    result.pop();
-    result.pop();
-    result + "]"
+        .all(|command| *command == InputEvent::LinkIdle));
+    assert!(matches!(input_stream.state(), InputState::ReadingFrame));
+    assert_eq!(input_stream.data[0], 0xc7);
+
+    // stray codes close the squelch again, instead of requesting frames over and over
+    let mut input_stream = InputStream::new();
+    input_stream.set_squelch(Squelch::Polls(2), IdlePattern::Alternating);
+    let stray_eofs = [
+        0x0, 0x0, 0x0, 0x2, 0x3, 0xf, 0x0, 0x2, 0x3, 0xf, 0x0, 0x2, 0x3, 0xf, 0x0,
+    ];
+    let resends = stray_eofs
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .filter(|command| *command == InputEvent::Control(ControlMsg::MalformedFrame))
+        .count();
+    assert_eq!(resends, 1);
+}
+
+#[test]
+fn read_overlong_frame() {
+    let mut input_stream = InputStream::new();
+    let mut bytes = vec![EscapeCode::StartOfFrame as u8];
+    bytes.extend([0xf0; FRAME_DATA_LEN + CHECKSUM_LEN + 16]);
+    bytes.push(EscapeCode::EndOfFrame as u8);
+
+    let commands = push_bytes(&mut input_stream, &bytes);
+    assert!(commands.contains(&InputEvent::Control(ControlMsg::FrameOverrun)));
+    assert!(!commands
+        .iter()
+        .any(|command| matches!(command, InputEvent::DataFrame { .. })));
+    assert!(input_stream.data_index / 2 <= input_stream.data.len());
 }
 
-enum OutputState {
+#[test]
+fn strictness_on_unexpected_sof() {
+    // SOF, 0xf0, SOF, 0xc1, EOF, with frames of a single byte
+    let bytes = [0x12, 0xf0, 0x12, 0xc1, 0x23, 0xf0];
+
+    let mut strict = InputStream::with_strictness(Strictness::Strict);
+    assert!(
+        push_bytes(&mut strict, &bytes).contains(&InputEvent::Control(ControlMsg::MalformedFrame))
+    );
+
+    let mut tolerant = InputStream::with_strictness(Strictness::Tolerant);
+    tolerant.set_frame_data_len(1);
+    let received: Vec<InputEvent> = push_bytes(&mut tolerant, &bytes)
+        .into_iter()
+        .filter(|command| *command != InputEvent::LinkIdle)
+        .collect();
+    // the frame starts again at the second SOF, without the data of the first one
+    let [InputEvent::DataFrame { payload, .. }] = &received[..] else {
+        panic!("a single frame is received: {received:?}");
+    };
+    assert_eq!(payload[..2], [0xc1, 0x00]);
+
+    let mut promiscuous = InputStream::with_strictness(Strictness::Promiscuous);
+    promiscuous.set_frame_data_len(1);
+    let received: Vec<InputEvent> = push_bytes(&mut promiscuous, &bytes)
+        .into_iter()
+        .filter(|command| matches!(command, InputEvent::DataFrame { .. }))
+        .collect();
+    assert_eq!(received.len(), 2);
+}
+
+#[test]
+fn idle_patterns() {
+    let mut output_stream = OutputStream::new();
+    assert_eq!([output_stream.next(), output_stream.next()], [0x0f, 0x00]);
+
+    output_stream.set_idle_pattern(IdlePattern::from_name("0c5").unwrap());
+    assert_eq!(
+        [0; 4].map(|_| output_stream.next()),
+        [0x05, 0x00, 0x0c, 0x05]
+    );
+    output_stream.set_idle_pattern(IdlePattern::HoldLast);
+    assert_eq!([output_stream.next(), output_stream.next()], [0x05, 0x05]);
+    output_stream.set_idle_pattern(IdlePattern::TriState);
+    assert!(output_stream.is_released());
+
+    // 0x1 and 0x2 would form SOF
+    assert_eq!(IdlePattern::from_name("f12"), None);
+    assert_eq!(IdlePattern::from_name("2f1"), None);
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum OutputState {
     WaitingForFrame,
     WritingFrame,
 }
 
+impl Display for OutputState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WaitingForFrame => write!(f, "waiting for frame"),
+            Self::WritingFrame => write!(f, "writing frame"),
+        }
+    }
+}
+
+/// Nibbles the [`OutputStream`] sends while there is no frame to send
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum IdlePattern {
+    /// Alternates between 0xf and 0x0
+    #[default]
+    Alternating,
+    /// Repeats the nibbles in order
+    Sequence(Vec<u8>),
+    /// Keeps sending the last nibble, which the other side does not see as a new value
+    HoldLast,
+    /// Stops driving the data lines, see [`crate::device::DeviceTx::release`]
+    TriState,
+}
+
+impl IdlePattern {
+    /// Parses "alternating", "hold", "tristate" or a sequence of hex nibbles like "0f5",
+    /// sequences that contain an escape code in any nibble order are rejected.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "alternating" => Some(Self::Alternating),
+            "hold" => Some(Self::HoldLast),
+            "tristate" => Some(Self::TriState),
+            _ => {
+                let nibbles = name
+                    .chars()
+                    .map(|char| char.to_digit(16).map(|digit| digit as u8))
+                    .collect::<Option<Vec<_>>>()?;
+                let is_escape_code = |first: u8, second: u8| {
+                    EscapeCode::from_byte(first << 4 | second).is_some()
+                        || EscapeCode::from_byte(second << 4 | first).is_some()
+                };
+                // the sequence repeats, so the last nibble is followed by the first
+                let safe = nibbles
+                    .iter()
+                    .zip(nibbles.iter().cycle().skip(1))
+                    .all(|(first, second)| !is_escape_code(*first, *second));
+                (!nibbles.is_empty() && safe).then_some(Self::Sequence(nibbles))
+            }
+        }
+    }
+}
+
 pub struct OutputStream {
     state: OutputState,
     /// Data to send
     frame: Frame,
+    /// Number of bytes of the frame that are sent
+    len: usize,
+    nibble_order: NibbleOrder,
+    /// How values that are equal to escape codes are sent
+    framing: &'static dyn Framing,
+    /// Whether every nibble is read on its own, like with a clock line,
+    /// so that equal nibbles need no buffer in between
+    clocked: bool,
+    idle: IdlePattern,
+    /// The nibble that has been sent last
+    last: u8,
     /// Index of the nibble to send
     index: usize,
-    window: Window<4>,
+    window: Deque<4>,
 }
 
 impl OutputStream {
@@ -319,26 +1253,77 @@ impl OutputStream {
         Self {
             state: OutputState::WaitingForFrame,
             frame: [0; FRAME_LEN],
+            len: FRAME_LEN,
+            nibble_order: NibbleOrder::default(),
+            framing: &EscapeFraming,
+            clocked: false,
+            idle: IdlePattern::default(),
+            last: 0x00,
             index: 0,
-            window: Window::new(),
+            window: Deque::new(),
         }
     }
 
-    pub fn send_frame(&mut self, frame: Frame) {
+    pub fn state(&self) -> &OutputState {
+        &self.state
+    }
+
+    pub fn set_nibble_order(&mut self, order: NibbleOrder) {
+        self.nibble_order = order;
+    }
+
+    pub fn set_framing(&mut self, framing: &'static dyn Framing) {
+        self.framing = framing;
+    }
+
+    pub fn framing(&self) -> &'static dyn Framing {
+        self.framing
+    }
+
+    pub fn set_clocked(&mut self, clocked: bool) {
+        self.clocked = clocked;
+    }
+
+    pub fn idle_pattern(&self) -> &IdlePattern {
+        &self.idle
+    }
+
+    pub fn set_idle_pattern(&mut self, idle: IdlePattern) {
+        self.idle = idle;
+    }
+
+    /// Whether the data lines should be released instead of sending the next nibble
+    pub fn is_released(&self) -> bool {
+        self.idle == IdlePattern::TriState && matches!(self.state, OutputState::WaitingForFrame)
+    }
+
+    /// Only sends the first `len` bytes of the frame, which end with its EOF.
+    ///
+    /// The escaped values of the frame are doubled, and sent with the [`Framing`].
+    pub fn send_frame(&mut self, frame: Frame, len: usize) {
         self.state = OutputState::WritingFrame;
         self.frame = frame;
+        self.len = len.min(FRAME_LEN);
+        // between the escape code that starts the frame and its EOF
+        if let Some(body) = self.frame.get_mut(1..self.len.saturating_sub(1)) {
+            self.framing.rewrite(body);
+        }
         self.index = 0;
+        self.window.clear();
     }
 
-    /// Resets the internal state, but keeps the frame data.
-    pub fn resend_frame(&mut self) {
-        self.state = OutputState::WaitingForFrame;
+    /// Sends a single escape code instead of a frame
+    pub fn send_control(&mut self, escape_code: EscapeCode) {
+        self.state = OutputState::WritingFrame;
+        self.frame[0] = escape_code as u8;
+        self.len = 1;
         self.index = 0;
+        self.window.clear();
     }
 
     /// returns the next nibble to send
     pub fn next(&mut self) -> u8 {
-        match self.state {
+        self.last = match self.state {
             OutputState::WaitingForFrame => self.waiting_for_frame(),
             OutputState::WritingFrame => {
                 if let Some(nibble) = self.writing_frame() {
@@ -348,177 +1333,395 @@ impl OutputStream {
                     self.waiting_for_frame()
                 }
             }
-        }
+        };
+        self.last
     }
 
     fn waiting_for_frame(&mut self) -> u8 {
-        let nibble = if self.index % 2 == 0 { 0x0f } else { 0x00 };
+        let nibble = match &self.idle {
+            IdlePattern::Alternating if self.index.is_multiple_of(2) => 0x0f,
+            IdlePattern::Alternating => 0x00,
+            IdlePattern::Sequence(nibbles) => nibbles[self.index % nibbles.len()],
+            IdlePattern::HoldLast | IdlePattern::TriState => self.last,
+        };
         self.index += 1;
         nibble
     }
 
+    /// Sends the frame nibble by nibble, with a [`separator`] between equal neighbours,
+    /// so that a frame of `n` bytes takes at most [`max_wire_nibbles`] nibbles.
     fn writing_frame(&mut self) -> Option<u8> {
-        if let Some(byte) = self.frame.get(self.index / 2) {
-            let nibble = if self.index % 2 == 0 {
-                byte >> 4
-            } else {
-                byte & 0x0f
-            };
-            self.window.push_back(nibble);
-        }
-
-        if self.window.len > 2 {
-            self.window.pop_front()
-        } else if self.window.len == 2 {
-            let higher = self.window.get(1).expect("upper nibble");
-            let lower = self.window.get(0).expect("lower nibble");
-            let escape_code = if higher == EscapeCode::Buffer1 as u8 >> 4 {
-                EscapeCode::Buffer2 as u8
-            } else {
-                EscapeCode::Buffer1 as u8 >> 4
-            };
-
-            if higher == lower {
-                self.window.pop_back();
-                self.window.push_back(escape_code >> 4);
-                self.window.push_back(escape_code & 0x0f);
-                self.window.push_back(lower);
+        // the rest of a buffer code and the nibble after it
+        if let Some(nibble) = self.window.pop_front() {
+            return Some(nibble);
+        }
+        let byte = self.frame[..self.len].get(self.index / 2)?;
+        let nibble = self.nibble_order.split(*byte)[self.index % 2];
+        // the nibble before the frame belongs to the idle pattern, which is never a buffer
+        let first = self.index == 0;
+        let previous = self.index.checked_sub(2).map(|index| self.frame[index / 2]);
+        self.index += 1;
+        if let Some(previous) = previous.filter(|_| self.index % 2 == 1 && !self.clocked) {
+            if let Some([buffer_first, buffer_second]) =
+                boundary_buffer(previous, *byte, self.nibble_order)
+            {
+                self.window.push_back(buffer_second);
+                self.window.push_back(nibble);
+                return Some(buffer_first);
             }
-            self.window.pop_front()
-        } else {
-            None
         }
+        if first || nibble != self.last || self.clocked {
+            return Some(nibble);
+        }
+
+        let [separator_first, separator_second] = separator(nibble, self.nibble_order);
+        self.window.push_back(separator_second);
+        self.window.push_back(nibble);
+        Some(separator_first)
     }
 }
 
-/// # Window
-///
-/// A vector-like data structure with a maximum length, that stores nibbles.
-///
-/// ## Layout
+/// Nibbles of the escape code that is sent between two equal nibbles, so that the second one
+/// is seen as well.
 ///
-/// byte index:   <  0,   1,   2,   3    >
-/// nibble index: <  7 6, 5 4, 3 2, 1 0  >
-/// data:         [  x x, x x, x x, x x  ]
-///
-/// push(1): [   xx,  xx,  xx,   x1  ]
-/// push(2): [   xx,  xx,  xx,   12  ]
-/// push(3): [   xx,  xx,  x1,   23  ]
-/// push(4): [   xx,  xx,  12,   34  ]
-/// push(5): [   xx,  x1,  23,   45  ]
-///
-/// N is the underlying maximum size in bytes
-struct Window<const N: usize> {
-    // underlying bytes
-    data: [u8; N],
-    // length in nibbles
-    len: usize,
+/// Neither of them may be equal to the repeated nibble, and the first one may not form
+/// an escape code with it, like `0x4` followed by a [`EscapeCode::Buffer1`] reads as IFD.
+/// The buffer codes are made of 0x5 and 0x6 themselves, so those nibbles are separated
+/// by a start of mini frame. The [`InputStream`] only takes a code as a separator
+/// when the same nibble follows it again.
+pub fn separator(nibble: u8, order: NibbleOrder) -> [u8; 2] {
+    [
+        EscapeCode::Buffer1,
+        EscapeCode::Buffer2,
+        EscapeCode::StartOfMiniFrame,
+    ]
+    .map(|code| order.split(code as u8))
+    .into_iter()
+    .find(|&[first, second]| {
+        first != nibble
+            && second != nibble
+            && EscapeCode::from_byte(order.join(nibble, first)).is_none()
+    })
+    .expect("a start of mini frame separates the nibbles of the buffer codes")
 }
 
-impl<const N: usize> Window<N> {
-    fn new() -> Self {
-        Self {
-            data: [0; N],
-            len: 0,
-        }
+/// Buffer code that is sent between two bytes whose nibbles would otherwise read
+/// as a single byte with a [`separator`] in between, like `0x15 0x61` and `0x11`.
+///
+/// A buffer code at the start of a byte is always skipped, it never has to separate
+/// data from the escape codes in it, which are doubled.
+pub fn boundary_buffer(previous: u8, next: u8, order: NibbleOrder) -> Option<[u8; 2]> {
+    let [high, low] = order.split(previous);
+    let [next_high, next_low] = order.split(next);
+    if next_low != high || separator(high, order) != [low, next_high] {
+        return None;
     }
+    [EscapeCode::Buffer1, EscapeCode::Buffer2]
+        .map(|code| order.split(code as u8))
+        .into_iter()
+        .find(|&[first, second]| first != low && second != next_high)
+}
 
-    fn push_back(&mut self, nibble: u8) -> Option<u8> {
-        let prev = self.data;
+/// Most nibbles a frame of `bytes` bytes takes on the wire,
+/// if every pair of neighbouring nibbles is equal and gets a buffer code in between.
+pub const fn max_wire_nibbles(bytes: usize) -> usize {
+    let symbols = bits::symbols(bytes);
+    symbols + SYMBOLS_PER_BUFFER * symbols.saturating_sub(1)
+}
 
-        // Shift every nibble 4 bits to the left
-        for (index, byte) in self.data.iter_mut().enumerate() {
-            *byte <<= 4;
-            *byte |= prev
-                .get(index + 1)
-                .map(|byte_to_right| byte_to_right >> 4)
-                .unwrap_or(nibble);
-        }
+/// Number of nibbles of a buffer code
+const SYMBOLS_PER_BUFFER: usize = bits::symbols(1);
 
-        let filled = self.len / 2 == self.data.len();
-        if !filled {
-            self.len += 1;
-        }
+/// Nibbles the [`OutputStream`] sends with the framing for every byte value from 0x00 to 0xff,
+/// as the only data byte of a frame, indexed by the value.
+pub fn encoding_table(framing: &'static dyn Framing) -> Vec<Vec<u8>> {
+    (0..=u8::MAX)
+        .map(|byte| {
+            let (frame, len) =
+                crate::encode_partial_frame(&mut crate::Escaped::new([Ok(byte)].into_iter()), 1)
+                    .unwrap();
+            let mut output_stream = OutputStream::new();
+            output_stream.set_framing(framing);
+            output_stream.send_frame(frame, len);
+            std::iter::from_fn(|| {
+                let nibble = output_stream.next();
+                matches!(output_stream.state(), OutputState::WritingFrame).then_some(nibble)
+            })
+            .collect()
+        })
+        .collect()
+}
 
-        filled.then_some(prev[0] >> 4)
+/// Formats the [`encoding_table`] one value per line, like `0x12: 1 2 1 2 1 2 5 6 2 3`
+pub fn encoding_table_text(framing: &'static dyn Framing) -> String {
+    let mut text = String::new();
+    for (byte, nibbles) in framing.encoding_table().iter().enumerate() {
+        let nibbles: Vec<String> = nibbles.iter().map(|nibble| format!("{nibble:x}")).collect();
+        text.push_str(&format!("0x{byte:02x}: {}\n", nibbles.join(" ")));
     }
+    text
+}
+
+/// Most nibbles after a start of frame, before the frame counts as overrun,
+/// even if the data has not filled up the frame yet, e.g. because of endless buffer codes
+pub const MAX_FRAME_NIBBLES: usize = max_wire_nibbles(FRAME_LEN);
 
-    fn pop_front(&mut self) -> Option<u8> {
-        let result = self.get(self.len - 1);
-        if self.len > 0 {
-            self.len -= 1;
+#[cfg(test)]
+fn shifted_frame_commands(data: &[u8], prefix: &[u8]) -> Vec<InputEvent> {
+    let mut bytes = vec![0xf0, EscapeCode::StartOfFrame as u8];
+    for &byte in data {
+        bytes.push(byte);
+        if EscapeCode::from_byte(byte).is_some() {
+            bytes.push(byte);
         }
-        result
     }
+    bytes.extend([EscapeCode::EndOfFrame as u8, 0xf0, 0xf0]);
 
-    fn pop_back(&mut self) -> Option<u8> {
-        let prev = self.data;
-
-        // Shift every nibble 4 bits to the right
-        for (index, byte) in self.data.iter_mut().enumerate() {
-            *byte >>= 4;
-            *byte |= index
-                .checked_sub(1)
-                .map(|index_to_left| prev[index_to_left] << 4)
-                .unwrap_or(0x00);
-        }
+    let mut input_stream = InputStream::new();
+    input_stream.set_frame_data_len(data.len());
+    prefix
+        .iter()
+        .chain(&crate::conformance::wire_nibbles(&bytes))
+        .map(|nibble| input_stream.push(*nibble))
+        .filter(|command| *command != InputEvent::LinkIdle)
+        .collect()
+}
 
-        let not_empty = self.len > 0;
-        if not_empty {
-            self.len -= 1;
+#[test]
+fn escape_codes_across_bytes() {
+    // 0x12 and 0x23 made up of the low and the high nibble of two bytes
+    let payloads: [&[u8]; 5] = [
+        &[0xa1, 0x2b],
+        &[0x01, 0x23],
+        &[0x12, 0x3c],
+        &[0xab, 0xcd],
+        // a doubled 0x12 across three bytes
+        &[0xa1, 0x21, 0x2b],
+    ];
+    for data in payloads {
+        for prefix in [&[][..], &[0x0], &[0x3], &[0xf, 0x0, 0x5]] {
+            let commands = shifted_frame_commands(data, prefix);
+            let [InputEvent::DataFrame { payload: frame, .. }] = commands.as_slice() else {
+                panic!("{data:02x?} shifted by {prefix:?}: {commands:?}");
+            };
+            assert_eq!(frame[..data.len()], *data);
         }
+    }
+}
 
-        not_empty.then_some(prev[prev.len() - 1] & 0x0f)
+#[test]
+fn eof_after_missing_nibble() {
+    // SOF, 0xab, 0xc missing its lower nibble, EOF
+    let nibbles = [0xf, 0x0, 0x1, 0x2, 0xa, 0xb, 0xc, 0x2, 0x3, 0xf, 0x0, 0xf];
+    for prefix in [&[][..], &[0x5]] {
+        let mut input_stream = InputStream::new();
+        input_stream.set_frame_data_len(2);
+        let commands: Vec<_> = prefix
+            .iter()
+            .chain(&nibbles)
+            .map(|nibble| input_stream.push(*nibble))
+            .filter(|command| *command != InputEvent::LinkIdle)
+            .collect();
+        assert!(
+            matches!(
+                commands.as_slice(),
+                [InputEvent::Control(ControlMsg::MalformedFrame)]
+            ),
+            "{commands:?}"
+        );
     }
+}
 
-    fn get(&self, index: usize) -> Option<u8> {
-        if index >= self.len {
-            return None;
+#[test]
+fn constant_payloads_within_wire_limit() {
+    // 0x44 would form an IFD with buffer code 1, 0x55 and 0x66 are made of buffer code nibbles
+    for constant in [0x00, 0xff, 0x44, 0x55, 0x66] {
+        let (frame, len) = crate::encode_frames([constant; FRAME_DATA_LEN].into_iter().map(Ok))
+            .next()
+            .expect("one frame")
+            .unwrap();
+        let mut output_stream = OutputStream::new();
+        let mut input_stream = InputStream::new();
+        for _ in 0..4 {
+            input_stream.push(output_stream.next());
         }
 
-        let byte_index = self.data.len() - 1 - index / 2;
-        let byte = self.data[byte_index];
-        let nibble = if index % 2 == 0 {
-            byte & 0x0f
-        } else {
-            byte >> 4
-        };
-        Some(nibble)
+        output_stream.send_frame(frame, len);
+        let mut nibbles = 0;
+        let mut commands = Vec::new();
+        // the idle pattern after the frame completes the EOF
+        for _ in 0..MAX_FRAME_NIBBLES + 4 {
+            let nibble = output_stream.next();
+            if matches!(output_stream.state(), OutputState::WritingFrame) {
+                nibbles += 1;
+            }
+            commands.push(input_stream.push(nibble));
+        }
+
+        // a separator between every pair of equal nibbles, but never more
+        assert_eq!(nibbles, crate::cost::frame_nibbles(&frame[..len]));
+        assert!(nibbles <= MAX_FRAME_NIBBLES);
+        let received: Vec<&InputEvent> = commands
+            .iter()
+            .filter(|command| **command != InputEvent::LinkIdle)
+            .collect();
+        assert_eq!(
+            received,
+            [&InputEvent::DataFrame {
+                seq: 1,
+                kind: FrameKind::Full,
+                payload: [constant; FRAME_DATA_LEN],
+            }],
+            "{constant:02x}"
+        );
     }
 }
 
 #[test]
-fn window() {
-    let mut window = Window::<2>::new();
-    assert_eq!(
-        [None, None, None, None, Some(0x01)],
-        [
-            window.push_back(0x01),
-            window.push_back(0x02),
-            window.push_back(0x03),
-            window.push_back(0x04),
-            window.push_back(0x05)
-        ]
-    );
-    assert_eq!([0x23, 0x45], window.data);
+fn endless_buffer_codes_overrun() {
+    // on a clocked line alternating buffer codes are never taken for escaped data
+    let mut input_stream = InputStream::new();
+    input_stream.set_edge_detection(false);
+    // SOF, 0xc7, then only buffer codes
+    let mut nibbles = vec![0x1, 0x2, 0xc, 0x7];
+    let buffers = [EscapeCode::Buffer1, EscapeCode::Buffer2].map(|code| bits::split(code as u8));
+    nibbles.extend(buffers.iter().cycle().take(MAX_FRAME_NIBBLES / 2).flatten());
+
+    let commands: Vec<InputEvent> = nibbles
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .filter(|command| *command != InputEvent::LinkIdle)
+        .collect();
     assert_eq!(
-        [Some(0x05), Some(0x04), Some(0x03), Some(0x02), None],
-        [
-            window.get(0),
-            window.get(1),
-            window.get(2),
-            window.get(3),
-            window.get(4)
-        ]
+        commands.first(),
+        Some(&InputEvent::Control(ControlMsg::FrameOverrun)),
+        "{commands:?}"
     );
+    assert!(!commands
+        .iter()
+        .any(|command| matches!(command, InputEvent::DataFrame { .. })));
+}
+
+#[test]
+fn escape_code_statistics() {
+    // SOF, escaped SOF, 0xa1 0x2b which contain a misaligned SOF, 0xcc with a buffer, EOF
+    let bytes = [0xf0, 0x12, 0x12, 0x12, 0xa1, 0x2b, 0xcc, 0x23, 0xf0, 0xf0];
+    let mut input_stream = InputStream::new();
+    input_stream.set_frame_data_len(4);
+    let received = crate::conformance::wire_nibbles(&bytes)
+        .into_iter()
+        .map(|nibble| input_stream.push(nibble))
+        .filter(|command| matches!(command, InputEvent::DataFrame { .. }))
+        .count();
+    assert_eq!(received, 1);
+
+    let stats = input_stream.escape_stats();
+    assert_eq!(stats.counts(EscapeCode::StartOfFrame), [1, 1, 1]);
+    assert_eq!(stats.counts(EscapeCode::EndOfFrame), [1, 0, 0]);
+    assert_eq!(stats.counts(EscapeCode::Buffer1), [1, 0, 0]);
+    // an encoder that never sends the second buffer code stands out
+    assert_eq!(stats.counts(EscapeCode::Buffer2), [0, 0, 0]);
+    assert!(stats
+        .to_string()
+        .lines()
+        .any(|line| line.split_whitespace().eq(["SOF", "1", "1", "1"])));
+}
+
+#[test]
+fn encoding_table_roundtrip() {
+    use crate::framing::FramingKind;
+
+    for kind in [FramingKind::Escape, FramingKind::Legacy] {
+        let framing = kind.framing();
+        for (byte, nibbles) in framing.encoding_table().iter().enumerate() {
+            let mut input_stream = InputStream::new();
+            input_stream.set_frame_data_len(1);
+            input_stream.set_framing(framing);
+            let idle = [0xf, 0x0, 0xf, 0x0];
+            let payloads: Vec<_> = idle
+                .into_iter()
+                .chain(nibbles.iter().copied())
+                .chain(idle)
+                .filter_map(|nibble| match input_stream.push(nibble) {
+                    InputEvent::DataFrame { payload, .. } => Some(payload),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(payloads.len(), 1, "{kind:?} 0x{byte:02x}: {nibbles:x?}");
+            assert_eq!(payloads[0][0], byte as u8, "{kind:?} 0x{byte:02x}: {nibbles:x?}");
+        }
+    }
+
+    // an escaped value is sent twice, but still fits into a frame of a single data byte
+    let table = FramingKind::Escape.framing().encoding_table();
+    assert_eq!(table[0x12], [0x1, 0x2, 0x1, 0x2, 0x1, 0x2, 0x5, 0x6, 0x2, 0x3]);
+    let text = encoding_table_text(FramingKind::Escape.framing());
+    assert_eq!(text.lines().nth(0x12), Some("0x12: 1 2 1 2 1 2 5 6 2 3"));
+    // the legacy framing follows it with its swapped nibbles instead
+    let text = encoding_table_text(FramingKind::Legacy.framing());
+    assert_eq!(text.lines().nth(0x12), Some("0x12: 1 2 1 2 5 6 2 1 2 3"));
+}
+
+#[test]
+fn buffer_codes_inside_of_data() {
+    // buffer codes across two bytes are data, `0x15 0x61` is not `0x11` with a separator
+    let data = [0x15, 0x61, 0x25, 0x64, 0x46, 0x54, 0x5d, 0xe5];
+    let (commands, _) = use_input_stream(data.into_iter());
+    let payloads: Vec<_> = commands
+        .into_iter()
+        .filter_map(|command| match command {
+            InputEvent::DataFrame { payload, .. } => Some(payload),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(payloads.len(), 1);
+    assert_eq!(payloads[0][..data.len()], data);
+    assert!(payloads[0][data.len()..].iter().all(|byte| *byte == 0));
+
     assert_eq!(
-        [Some(0x05), Some(0x04), Some(0x03), Some(0x02), None],
-        [
-            window.pop_back(),
-            window.pop_back(),
-            window.pop_back(),
-            window.pop_back(),
-            window.pop_back()
-        ]
+        crate::conformance::wire_nibbles(&[0x15, 0x61]),
+        [0x1, 0x5, 0x6, 0x5, 0x6, 0x1]
     );
 }
+
+#[test]
+fn legacy_framing_roundtrip() {
+    use crate::framing::FramingKind;
+
+    // escaped values next to each other, and next to their swapped nibbles
+    let mut payload = [0; FRAME_DATA_LEN];
+    let values = [0x12, 0x21, 0x56, 0x65, 0x65, 0x12, 0xde, 0x44, 0x89, 0x98];
+    for (byte, value) in payload.iter_mut().zip(values.into_iter().cycle()) {
+        *byte = value;
+    }
+    for edge_detection in [true, false] {
+        let (frame, len) = crate::encode_frames(payload.into_iter().map(Ok))
+            .next()
+            .expect("one frame")
+            .unwrap();
+        let mut output_stream = OutputStream::new();
+        output_stream.set_framing(FramingKind::Legacy.framing());
+        output_stream.set_clocked(!edge_detection);
+        let mut input_stream = InputStream::new();
+        input_stream.set_framing(FramingKind::Legacy.framing());
+        input_stream.set_edge_detection(edge_detection);
+        for _ in 0..4 {
+            input_stream.push(output_stream.next());
+        }
+
+        output_stream.send_frame(frame, len);
+        let received: Vec<_> = (0..MAX_FRAME_NIBBLES + 4)
+            .map(|_| input_stream.push(output_stream.next()))
+            .filter(|event| *event != InputEvent::LinkIdle)
+            .collect();
+        assert_eq!(
+            received,
+            [InputEvent::DataFrame {
+                seq: 1,
+                kind: FrameKind::Full,
+                payload,
+            }],
+            "edge detection {edge_detection}"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::Write;
+
+use crate::bits;
+use crate::diagnostics::BackgroundWriter;
+use crate::escape::{EscapeCode, Escaped};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// # PipelineTap
+///
+/// Observes how data is transformed on its way through the protocol layers.
+///
+/// When sending the stages are called in the order
+/// raw bytes → escaped frame → nibbles → wire,
+/// when receiving in the reverse order wire → nibbles → escaped frame → raw bytes.
+/// Received frames are unescaped while they are decoded,
+/// so their escaped form and nibbles are rebuilt from the received data.
+pub trait PipelineTap {
+    /// Data bytes of a frame, before escaping
+    fn raw(&mut self, _direction: Direction, _bytes: &[u8]) {}
+
+    /// The whole frame with SOF, escaped values, padding and EOF
+    fn escaped(&mut self, _direction: Direction, _bytes: &[u8]) {}
+
+    /// The escaped frame split into nibbles, without buffer codes
+    fn nibbles(&mut self, _direction: Direction, _nibbles: &[u8]) {}
+
+    /// A single nibble on the wire, including buffer codes and idle nibbles
+    fn wire(&mut self, _direction: Direction, _nibble: u8) {}
+}
+
+/// Hands a sent frame to every stage of the tap
+pub fn tap_sent(tap: &mut dyn PipelineTap, frame: &[u8]) {
+    let data = &frame[1..(frame.len() - 1)];
+    tap.raw(Direction::Send, &unescape(data));
+    tap.escaped(Direction::Send, frame);
+    tap.nibbles(Direction::Send, &split(frame));
+}
+
+/// Hands the data of a received frame to every stage of the tap
+pub fn tap_received(tap: &mut dyn PipelineTap, data: &[u8]) {
+    let mut frame = vec![EscapeCode::StartOfFrame as u8];
+    frame.extend(Escaped::new(data.iter().map(|byte| Ok(*byte))).map(Result::unwrap));
+    frame.push(EscapeCode::EndOfFrame as u8);
+
+    tap.nibbles(Direction::Receive, &split(&frame));
+    tap.escaped(Direction::Receive, &frame);
+    tap.raw(Direction::Receive, data);
+}
+
+fn split(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().flat_map(|byte| bits::split(*byte)).collect()
+}
+
+/// Drops the second byte of escaped values and the buffer codes used as padding
+pub fn unescape(bytes: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes[index];
+        if EscapeCode::from_byte(byte).is_some() {
+            if bytes.get(index + 1) != Some(&byte) {
+                // padding
+                index += 1;
+                continue;
+            }
+            index += 1;
+        }
+        raw.push(byte);
+        index += 1;
+    }
+    raw
+}
+
+/// Prints every stage except the wire as a line of hex values,
+/// the wire is already shown by the [`crate::viz::Timeline`].
+pub struct TextTap<W: Write = BackgroundWriter> {
+    output: W,
+}
+
+impl TextTap {
+    pub fn new() -> Self {
+        Self::with_output(BackgroundWriter::stderr())
+    }
+}
+
+impl<W: Write> TextTap<W> {
+    pub fn with_output(output: W) -> Self {
+        Self { output }
+    }
+
+    fn line(&mut self, direction: Direction, stage: &str, values: &[u8], width: usize) {
+        let arrow = match direction {
+            Direction::Send => "->",
+            Direction::Receive => "<-",
+        };
+        let _ = write!(self.output, "{arrow} {stage:<8}");
+        for value in values {
+            let _ = write!(self.output, " {value:0width$x}");
+        }
+        let _ = writeln!(self.output);
+    }
+}
+
+impl<W: Write> PipelineTap for TextTap<W> {
+    fn raw(&mut self, direction: Direction, bytes: &[u8]) {
+        self.line(direction, "raw", bytes, 2);
+    }
+
+    fn escaped(&mut self, direction: Direction, bytes: &[u8]) {
+        self.line(direction, "escaped", bytes, 2);
+    }
+
+    fn nibbles(&mut self, direction: Direction, nibbles: &[u8]) {
+        self.line(direction, "nibbles", nibbles, 1);
+    }
+}
+
+#[test]
+fn tap_stages() {
+    let mut output = Vec::new();
+    let mut tap = TextTap::with_output(&mut output);
+    tap_sent(&mut tap, &[0x12, 0x01, 0x23, 0x23, 0x56, 0x23]);
+    tap_received(&mut tap, &[0x01, 0x23]);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "-> raw      01 23\n\
+         -> escaped  12 01 23 23 56 23\n\
+         -> nibbles  1 2 0 1 2 3 2 3 5 6 2 3\n\
+         <- nibbles  1 2 0 1 2 3 2 3 2 3\n\
+         <- escaped  12 01 23 23 23\n\
+         <- raw      01 23\n"
+    );
+}
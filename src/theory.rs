@@ -0,0 +1,190 @@
+use std::fmt::Display;
+
+use crate::cost::ChecksumAudit;
+use crate::event::Event;
+use crate::session::{parse_entry, Decision};
+use crate::{CHECKSUM_LEN, ESCAPE_CODE_LEN, FRAME_DATA_LEN};
+
+/// Nibbles of an ack, CFD is a single escape code
+const ACK_NIBBLES: f64 = 2.0;
+
+/// # LossModel
+///
+/// Expected behavior of stop-and-wait, if every nibble is corrupted
+/// independently with the same probability.
+///
+/// A frame only gets through if none of its nibbles and none of the nibbles of its ack
+/// are corrupted, every other attempt is a retransmission.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LossModel {
+    pub nibble_error_rate: f64,
+}
+
+impl LossModel {
+    pub fn new(nibble_error_rate: f64) -> Self {
+        Self {
+            nibble_error_rate: nibble_error_rate.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Expected nibbles of a frame with random data, including escaped bytes and buffer codes
+    pub fn frame_nibbles(data_len: usize) -> f64 {
+        let escaped_data = data_len as f64 * (1.0 + ChecksumAudit::random_rate());
+        let bytes = (2 * ESCAPE_CODE_LEN) as f64 + escaped_data + (2 * CHECKSUM_LEN) as f64;
+        let nibbles = 2.0 * bytes;
+        // neighbouring random nibbles are equal one time in 16, each time 2 buffer nibbles are sent
+        nibbles + 2.0 * (nibbles - 1.0) / 16.0
+    }
+
+    /// Probability that a frame and its ack arrive intact
+    pub fn success(&self, data_len: usize) -> f64 {
+        let nibbles = Self::frame_nibbles(data_len) + ACK_NIBBLES;
+        (1.0 - self.nibble_error_rate).powf(nibbles)
+    }
+
+    /// Expected retransmissions per sent frame
+    pub fn retransmit_rate(&self, data_len: usize) -> f64 {
+        // every attempt but the successful one is a retransmission
+        1.0 - self.success(data_len)
+    }
+
+    /// Payload bytes per byte on the wire, counting every attempt and its ack
+    pub fn efficiency(&self, data_len: usize) -> f64 {
+        let wire_bytes = (Self::frame_nibbles(data_len) + ACK_NIBBLES) / 2.0;
+        data_len as f64 * self.success(data_len) / wire_bytes
+    }
+
+    /// Frame size with the highest [`LossModel::efficiency`]
+    pub fn optimal_frame_size(&self) -> usize {
+        (1..=FRAME_DATA_LEN)
+            .max_by(|a, b| self.efficiency(*a).total_cmp(&self.efficiency(*b)))
+            .expect("at least one frame size")
+    }
+}
+
+/// What actually happened, counted from a session log, e.g. one written by `protocol soak`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Measured {
+    /// Frames that were sent, including retransmissions
+    pub frames: u32,
+    pub retransmissions: u32,
+    /// Size of the frames that were sent last
+    pub tx_frame_size: Option<usize>,
+    /// Frame size that was last requested, because received frames kept breaking
+    pub adapted_frame_size: Option<usize>,
+}
+
+impl Measured {
+    pub fn from_log(log: &str) -> Self {
+        let mut measured = Self::default();
+        for entry in log.lines().filter_map(parse_entry) {
+            match entry.decision {
+                Decision::Event(Event::FrameSent { .. }) => measured.frames += 1,
+                Decision::Event(Event::Resend { .. }) => {
+                    measured.frames += 1;
+                    measured.retransmissions += 1;
+                }
+                Decision::Event(Event::FrameSizeChanged { len }) => {
+                    measured.tx_frame_size = Some(len)
+                }
+                Decision::RequestFrameSize { len, .. } => measured.adapted_frame_size = Some(len),
+                _ => (),
+            }
+        }
+        measured
+    }
+
+    pub fn retransmit_rate(&self) -> f64 {
+        if self.frames == 0 {
+            0.0
+        } else {
+            self.retransmissions as f64 / self.frames as f64
+        }
+    }
+}
+
+/// Theory next to the measurement, printed by `protocol theory`
+pub struct Report {
+    pub model: LossModel,
+    pub measured: Option<Measured>,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "nibble error rate {}, frames of up to {FRAME_DATA_LEN} bytes",
+            self.model.nibble_error_rate
+        )?;
+        writeln!(
+            f,
+            "{:>5} {:>8} {:>11} {:>10}",
+            "frame", "nibbles", "retransmits", "efficiency"
+        )?;
+        for data_len in [8, 16, 32, 64]
+            .into_iter()
+            .filter(|len| *len <= FRAME_DATA_LEN)
+        {
+            writeln!(
+                f,
+                "{:>5} {:>8.1} {:>10.2}% {:>9.1}%",
+                data_len,
+                LossModel::frame_nibbles(data_len),
+                100.0 * self.model.retransmit_rate(data_len),
+                100.0 * self.model.efficiency(data_len)
+            )?;
+        }
+        let optimal = self.model.optimal_frame_size();
+        writeln!(
+            f,
+            "optimal frame size: {optimal} bytes ({:.1}% efficiency)",
+            100.0 * self.model.efficiency(optimal)
+        )?;
+
+        let Some(measured) = self.measured else {
+            return Ok(());
+        };
+        let frame_size = measured.tx_frame_size.unwrap_or(FRAME_DATA_LEN);
+        writeln!(
+            f,
+            "measured: {} frames, {} retransmits ({:.2}%), expected {:.2}% for {frame_size} byte frames",
+            measured.frames,
+            measured.retransmissions,
+            100.0 * measured.retransmit_rate(),
+            100.0 * self.model.retransmit_rate(frame_size)
+        )?;
+        if let Some(adapted) = measured.adapted_frame_size {
+            writeln!(
+                f,
+                "adapted frame size: {adapted} bytes, optimal {optimal} bytes"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn loss_model_against_measurement() {
+    let clean = LossModel::new(0.0);
+    assert_eq!(clean.retransmit_rate(64), 0.0);
+    assert_eq!(clean.optimal_frame_size(), FRAME_DATA_LEN);
+
+    // larger frames break more often, so noisy lines need smaller ones
+    let noisy = LossModel::new(0.01);
+    assert!(noisy.retransmit_rate(64) > noisy.retransmit_rate(8));
+    assert!(noisy.optimal_frame_size() < FRAME_DATA_LEN);
+
+    let log = "10 tx=1 rx=0 sent seq=1\n\
+               20 tx=1 rx=0 resend seq=1 retries=1\n\
+               30 tx=2 rx=0 sent seq=2\n\
+               40 tx=2 rx=3 request-frame-size len=32 errors=3\n";
+    let measured = Measured::from_log(log);
+    assert_eq!(measured.frames, 3);
+    assert_eq!(measured.retransmissions, 1);
+    assert_eq!(measured.adapted_frame_size, Some(32));
+    let report = Report {
+        model: noisy,
+        measured: Some(measured),
+    };
+    assert!(report.to_string().contains("1 retransmits (33.33%)"));
+}
@@ -0,0 +1,393 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+
+use crate::escape::EscapeCode;
+use crate::{CHECKSUM_LEN, FRAME_DATA_LEN};
+
+const HIGH: &str = "◻️";
+const LOW: &str = "◼";
+
+/// Width and height of a single bit in the svg output
+const SVG_CELL: usize = 8;
+
+/// Identifiers of the TX and RX signals in the vcd output
+const VCD_TX: char = '!';
+const VCD_RX: char = '"';
+
+const RESET: &str = "\x1b[0m";
+
+/// What a nibble on the wire is part of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NibbleKind {
+    Idle,
+    /// SOF, SOE, SFS and EOF
+    Header,
+    Payload,
+    Checksum,
+    /// Buffer codes and escaped values
+    Escape,
+    /// CFD and IFD
+    Ack,
+    /// FS and ABT
+    Control,
+}
+
+impl NibbleKind {
+    pub const ALL: [Self; 7] = [
+        Self::Header,
+        Self::Payload,
+        Self::Checksum,
+        Self::Escape,
+        Self::Ack,
+        Self::Control,
+        Self::Idle,
+    ];
+
+    fn label(self) -> char {
+        match self {
+            Self::Idle => '.',
+            Self::Header => 'H',
+            Self::Payload => 'P',
+            Self::Checksum => 'C',
+            Self::Escape => 'E',
+            Self::Ack => 'A',
+            Self::Control => 'X',
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Self::Idle => "\x1b[2m",
+            Self::Header => "\x1b[1;34m",
+            Self::Payload => "\x1b[32m",
+            Self::Checksum => "\x1b[33m",
+            Self::Escape => "\x1b[35m",
+            Self::Ack => "\x1b[36m",
+            Self::Control => "\x1b[1;31m",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Header => "header",
+            Self::Payload => "payload",
+            Self::Checksum => "checksum",
+            Self::Escape => "escape",
+            Self::Ack => "ack",
+            Self::Control => "control",
+        }
+    }
+}
+
+/// Tells for every nibble which part of the protocol it belongs to,
+/// by decoding escape codes the same way as the [`crate::stream::InputStream`].
+///
+/// With edge detection repeated nibbles are a single symbol and get the same kind.
+pub fn classify(
+    nibbles: &[u8],
+    frame_data_len: usize,
+    checksum_len: usize,
+    edge_detection: bool,
+) -> Vec<NibbleKind> {
+    // value of every symbol and the index of its first nibble
+    let mut symbols: Vec<(u8, usize)> = Vec::new();
+    for (index, nibble) in nibbles.iter().enumerate() {
+        if !(edge_detection && symbols.last().is_some_and(|(last, _)| last == nibble)) {
+            symbols.push((*nibble, index));
+        }
+    }
+    let byte_at = |index: usize| {
+        let [(higher, _), (lower, _)] = [symbols.get(index)?, symbols.get(index + 1)?];
+        Some(higher << 4 | lower)
+    };
+
+    let mut kinds = vec![NibbleKind::Idle; symbols.len()];
+    let mut in_frame = false;
+    // data and checksum nibbles of the current frame
+    let mut frame_nibbles = 0;
+    let mut index = 0;
+    while index < symbols.len() {
+        let byte = byte_at(index);
+        let Some(code) = byte.and_then(EscapeCode::from_byte) else {
+            // inside a frame the data is aligned to whole bytes
+            if in_frame && byte.is_some() {
+                kinds[index..(index + 2)].fill(if frame_nibbles < 2 * frame_data_len {
+                    NibbleKind::Payload
+                } else if frame_nibbles < 2 * (frame_data_len + checksum_len) {
+                    NibbleKind::Checksum
+                } else {
+                    NibbleKind::Escape
+                });
+                frame_nibbles += 2;
+                index += 2;
+            } else {
+                index += 1;
+            }
+            continue;
+        };
+        if in_frame && byte_at(index + 2) == byte {
+            kinds[index..(index + 4)].fill(NibbleKind::Escape);
+            frame_nibbles += 2;
+            index += 4;
+            continue;
+        }
+        let kind = match code {
+            EscapeCode::StartOfFrame
+            | EscapeCode::StartOfEcho
+            | EscapeCode::SetFrameSize
+            | EscapeCode::StartOfMiniFrame => {
+                in_frame = true;
+                frame_nibbles = 0;
+                NibbleKind::Header
+            }
+            EscapeCode::EndOfFrame => {
+                in_frame = false;
+                NibbleKind::Header
+            }
+            EscapeCode::Buffer1 | EscapeCode::Buffer2 => NibbleKind::Escape,
+            EscapeCode::CorrectFrameData | EscapeCode::IncorrectFrameData => NibbleKind::Ack,
+            EscapeCode::FinishedSending | EscapeCode::Abort => NibbleKind::Control,
+        };
+        kinds[index..(index + 2)].fill(kind);
+        index += 2;
+    }
+
+    let mut nibble_kinds = vec![NibbleKind::Idle; nibbles.len()];
+    for (position, ((_, start), kind)) in symbols.iter().zip(&kinds).enumerate() {
+        let end = symbols
+            .get(position + 1)
+            .map_or(nibbles.len(), |(_, start)| *start);
+        nibble_kinds[*start..end].fill(*kind);
+    }
+    nibble_kinds
+}
+
+/// # Timeline
+///
+/// Records the outgoing (TX) and incoming (RX) nibble of every poll,
+/// so that both directions can be rendered aligned below each other.
+pub struct Timeline {
+    name: &'static str,
+    tx: Vec<u8>,
+    rx: Vec<u8>,
+    /// When each poll happened, relative to `start`
+    times: Vec<Duration>,
+    start: Instant,
+    /// Index of the first nibble that has not been printed by [`Timeline::flush_text`]
+    printed: usize,
+    /// Data and checksum bytes of a frame, to tell them apart
+    frame_data_len: usize,
+    checksum_len: usize,
+    edge_detection: bool,
+    /// Whether the kinds of nibbles are colored in the text output
+    color: bool,
+}
+
+impl Timeline {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            tx: Vec::new(),
+            rx: Vec::new(),
+            times: Vec::new(),
+            start: Instant::now(),
+            printed: 0,
+            frame_data_len: FRAME_DATA_LEN,
+            checksum_len: CHECKSUM_LEN,
+            edge_detection: true,
+            // the text output is printed to stderr
+            color: std::io::stderr().is_terminal(),
+        }
+    }
+
+    /// Sets how the recorded nibbles are split into frames, see [`classify`]
+    pub fn set_layout(&mut self, frame_data_len: usize, checksum_len: usize, edge_detection: bool) {
+        self.frame_data_len = frame_data_len;
+        self.checksum_len = checksum_len;
+        self.edge_detection = edge_detection;
+    }
+
+    /// Kinds of the sent and of the received nibbles
+    fn kinds(&self) -> [Vec<NibbleKind>; 2] {
+        [&self.tx, &self.rx].map(|nibbles| {
+            classify(
+                nibbles,
+                self.frame_data_len,
+                self.checksum_len,
+                self.edge_detection,
+            )
+        })
+    }
+
+    /// Number of nibbles of every kind, for both directions
+    pub fn statistics(&self) -> String {
+        let mut text = String::new();
+        for (direction, kinds) in ["TX", "RX"].into_iter().zip(self.kinds()) {
+            let counts: Vec<_> = NibbleKind::ALL
+                .iter()
+                .map(|kind| {
+                    let count = kinds.iter().filter(|nibble| *nibble == kind).count();
+                    format!("{count} {}", kind.name())
+                })
+                .collect();
+            let _ = writeln!(text, "{} {direction}: {}", self.name, counts.join(", "));
+        }
+        text
+    }
+
+    pub fn record(&mut self, tx: u8, rx: u8) {
+        self.tx.push(tx & 0x0f);
+        self.rx.push(rx & 0x0f);
+        self.times.push(self.start.elapsed());
+    }
+
+    /// Renders the nibbles recorded since the last call as eight lines of blocks,
+    /// four for each direction with the most significant bit first.
+    pub fn flush_text(&mut self) -> String {
+        let text = self.render_text(self.printed..self.tx.len());
+        self.printed = self.tx.len();
+        text
+    }
+
+    /// Followed by a line that labels the kind of every nibble, see [`NibbleKind`]
+    pub fn render_text(&self, range: std::ops::Range<usize>) -> String {
+        let mut text = String::new();
+        let [tx_kinds, rx_kinds] = self.kinds();
+        let directions = [
+            ("TX", &self.tx[range.clone()], &tx_kinds[range.clone()]),
+            ("RX", &self.rx[range.clone()], &rx_kinds[range]),
+        ];
+        for (direction, nibbles, kinds) in directions {
+            for bit in (0..4).rev() {
+                let _ = write!(text, "{} {direction}{bit} ", self.name);
+                for nibble in nibbles {
+                    text.push_str(if nibble >> bit & 1 == 1 { HIGH } else { LOW });
+                }
+                text.push('\n');
+            }
+            let _ = write!(text, "{} {direction}  ", self.name);
+            for kind in kinds {
+                if self.color {
+                    let _ = write!(text, "{}{}{RESET}", kind.color(), kind.label());
+                } else {
+                    text.push(kind.label());
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Renders the whole recording as a standalone svg image.
+    pub fn render_svg(&self) -> String {
+        let width = (self.tx.len() + 4) * SVG_CELL;
+        let height = 9 * SVG_CELL;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\n"
+        );
+
+        let rows = (0..4)
+            .rev()
+            .map(|bit| ("TX", &self.tx, bit))
+            .chain((0..4).rev().map(|bit| ("RX", &self.rx, bit)));
+        for (row, (direction, nibbles, bit)) in rows.enumerate() {
+            // leave an empty row between both directions
+            let y = (row + row / 4) * SVG_CELL;
+            let _ = writeln!(
+                svg,
+                "<text x=\"0\" y=\"{}\" font-size=\"{SVG_CELL}\">{direction}{bit}</text>",
+                y + SVG_CELL
+            );
+            for (index, nibble) in nibbles.iter().enumerate() {
+                if nibble >> bit & 1 == 1 {
+                    let _ = writeln!(
+                        svg,
+                        "<rect x=\"{}\" y=\"{y}\" width=\"{SVG_CELL}\" height=\"{SVG_CELL}\"/>",
+                        (index + 4) * SVG_CELL
+                    );
+                }
+            }
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Renders the whole recording as a value change dump with microsecond timestamps,
+    /// which can be opened in GTKWave next to a logic analyzer capture of the port pins.
+    pub fn render_vcd(&self) -> String {
+        let mut vcd = String::new();
+        let _ = writeln!(vcd, "$version protocol $end");
+        let _ = writeln!(vcd, "$timescale 1us $end");
+        let _ = writeln!(vcd, "$scope module {} $end", self.name);
+        let _ = writeln!(vcd, "$var wire 4 {VCD_TX} tx [3:0] $end");
+        let _ = writeln!(vcd, "$var wire 4 {VCD_RX} rx [3:0] $end");
+        let _ = writeln!(vcd, "$upscope $end");
+        let _ = writeln!(vcd, "$enddefinitions $end");
+
+        let mut previous = None;
+        for ((tx, rx), time) in self.tx.iter().zip(&self.rx).zip(&self.times) {
+            let changes = [
+                (VCD_TX, *tx, previous.map(|(tx, _)| tx)),
+                (VCD_RX, *rx, previous.map(|(_, rx)| rx)),
+            ];
+            let mut changes = changes
+                .into_iter()
+                .filter(|(_, value, previous)| Some(*value) != *previous)
+                .peekable();
+            if changes.peek().is_some() {
+                let _ = writeln!(vcd, "#{}", time.as_micros());
+            }
+            for (id, value, _) in changes {
+                let _ = writeln!(vcd, "b{value:04b} {id}");
+            }
+            previous = Some((*tx, *rx));
+        }
+        // keeps the last values visible until the end of the recording
+        if let Some(end) = self.times.last() {
+            let _ = writeln!(vcd, "#{}", end.as_micros() + 1);
+        }
+        vcd
+    }
+}
+
+#[test]
+fn vcd_only_lists_changes() {
+    let mut timeline = Timeline::new("Test");
+    for (tx, rx, millis) in [(0x0, 0x0, 0), (0x1, 0x0, 1), (0x1, 0x0, 2), (0x1, 0xa, 3)] {
+        timeline.tx.push(tx);
+        timeline.rx.push(rx);
+        timeline.times.push(Duration::from_millis(millis));
+    }
+    let vcd = timeline.render_vcd();
+    let (header, changes) = vcd.split_once("$enddefinitions $end\n").unwrap();
+    assert!(header.contains("$var wire 4 ! tx [3:0] $end"));
+    assert_eq!(
+        changes,
+        "#0\nb0000 !\nb0000 \"\n#1000\nb0001 !\n#3000\nb1010 \"\n#3001\n"
+    );
+}
+
+#[test]
+fn classify_frame_nibbles() {
+    use NibbleKind::*;
+    // idle, SOF, 0xc1, escaped SOF, BU1, 0xc1, EOF, CFD
+    let nibbles = [
+        0xf, 0x1, 0x2, 0xc, 0x1, 0x1, 0x2, 0x1, 0x2, 0x5, 0x6, 0xc, 0x1, 0x2, 0x3, 0x3, 0x4,
+    ];
+    let kinds = classify(&nibbles, 3, 0, false);
+    assert_eq!(
+        kinds,
+        [
+            Idle, Header, Header, Payload, Payload, Escape, Escape, Escape, Escape, Escape, Escape,
+            Payload, Payload, Header, Header, Ack, Ack
+        ]
+    );
+    // repeated reads belong to the same nibble
+    assert_eq!(
+        classify(&[0x1, 0x1, 0x2, 0x3, 0x4], 1, 0, true),
+        [Header, Header, Header, Ack, Ack]
+    );
+}
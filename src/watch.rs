@@ -0,0 +1,245 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::hash::hash;
+
+/// Starts the message that announces a file
+const METADATA_TAG: u8 = b'M';
+/// Starts the message with the content of the announced file
+const CONTENT_TAG: u8 = b'C';
+
+/// Tag, big endian length and hash in front of the name
+const METADATA_HEADER_LEN: usize = 1 + 8 + 4;
+
+/// Name, length and [whole-file hash](crate::hash) of a file, sent in a message ahead of its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub name: String,
+    pub len: u64,
+    pub hash: u32,
+}
+
+impl FileMetadata {
+    pub fn of(name: &str, content: &[u8]) -> Self {
+        Self {
+            name: name.into(),
+            len: content.len() as u64,
+            hash: hash(content),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(METADATA_HEADER_LEN + self.name.len());
+        message.push(METADATA_TAG);
+        message.extend(self.len.to_be_bytes());
+        message.extend(self.hash.to_be_bytes());
+        message.extend_from_slice(self.name.as_bytes());
+        message
+    }
+
+    /// `None` if the message is not a metadata message
+    pub fn decode(message: &[u8]) -> Option<Self> {
+        if message.len() < METADATA_HEADER_LEN || message[0] != METADATA_TAG {
+            return None;
+        }
+        Some(Self {
+            len: u64::from_be_bytes(message[1..9].try_into().unwrap()),
+            hash: u32::from_be_bytes(message[9..13].try_into().unwrap()),
+            name: String::from_utf8(message[METADATA_HEADER_LEN..].to_vec()).ok()?,
+        })
+    }
+}
+
+/// The metadata and the content message that transfer the file
+pub fn file_messages(path: &Path) -> io::Result<[Vec<u8>; 2]> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "file name is not UTF-8"))?;
+    let content = std::fs::read(path)?;
+    let metadata = FileMetadata::of(name, &content).encode();
+
+    let mut message = Vec::with_capacity(1 + content.len());
+    message.push(CONTENT_TAG);
+    message.extend(content);
+    Ok([metadata, message])
+}
+
+/// Only plain names are written, so the other side can not write outside of the directory
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+/// Why a received file has not been written
+#[derive(Debug)]
+pub enum ReceiveError {
+    Io(io::Error),
+    /// A content message without metadata, or a message that is neither
+    Unexpected,
+    /// The name contains a path instead of a plain file name
+    InvalidName(String),
+    /// The length or hash of the content does not match its metadata
+    Corrupted(String),
+}
+
+impl fmt::Display for ReceiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Unexpected => write!(f, "unexpected message"),
+            Self::InvalidName(name) => write!(f, "{name:?} is not a plain file name"),
+            Self::Corrupted(name) => write!(f, "{name} does not match its metadata"),
+        }
+    }
+}
+
+impl From<io::Error> for ReceiveError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// # FileReceiver
+///
+/// Writes the files sent by the watching side into a directory.
+///
+/// Every file is written as a `.part` file first and only renamed
+/// once it matches its metadata, so files are never seen half written.
+#[derive(Debug)]
+pub struct FileReceiver {
+    dir: PathBuf,
+    /// Metadata of the file, whose content is received next
+    pending: Option<FileMetadata>,
+}
+
+impl FileReceiver {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            pending: None,
+        }
+    }
+
+    /// Returns the name of the file, once its content has been written
+    pub fn receive(&mut self, message: &[u8]) -> Result<Option<String>, ReceiveError> {
+        if let Some(metadata) = FileMetadata::decode(message) {
+            if !is_plain_name(&metadata.name) {
+                return Err(ReceiveError::InvalidName(metadata.name));
+            }
+            self.pending = Some(metadata);
+            return Ok(None);
+        }
+
+        let (Some(&CONTENT_TAG), Some(metadata)) = (message.first(), self.pending.take()) else {
+            return Err(ReceiveError::Unexpected);
+        };
+        let content = &message[1..];
+        if content.len() as u64 != metadata.len || hash(content) != metadata.hash {
+            return Err(ReceiveError::Corrupted(metadata.name));
+        }
+        let path = self.dir.join(&metadata.name);
+        let part = self.dir.join(format!("{}.part", metadata.name));
+        std::fs::write(&part, content)?;
+        std::fs::rename(part, path)?;
+        Ok(Some(metadata.name))
+    }
+}
+
+/// # DirWatcher
+///
+/// Files of a directory that have been created or modified, without its subdirectories.
+#[cfg(feature = "watch")]
+pub struct DirWatcher {
+    // stops watching once dropped
+    _watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+#[cfg(feature = "watch")]
+impl DirWatcher {
+    pub fn new(dir: &Path) -> notify::Result<Self> {
+        use notify::Watcher;
+
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Blocks until files have changed, every file is only returned once,
+    /// even if an editor wrote it several times in a row, `None` once watching failed
+    pub fn changed(&self) -> Option<Vec<PathBuf>> {
+        let first = self.events.recv().ok()?;
+        let mut paths = Vec::new();
+        for event in std::iter::once(first)
+            .chain(self.events.try_iter())
+            .flatten()
+        {
+            if !matches!(
+                event.kind,
+                notify::EventKind::Create(_) | notify::EventKind::Modify(_)
+            ) {
+                continue;
+            }
+            for path in event.paths {
+                if path.is_file() && !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+        Some(paths)
+    }
+}
+
+#[test]
+fn receive_watched_files() {
+    let dir = std::env::temp_dir().join(format!("protocol-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("firmware.hex");
+    std::fs::write(&source, b":00000001FF\n").unwrap();
+    let target = dir.join("received");
+    std::fs::create_dir_all(&target).unwrap();
+
+    let [metadata, content] = file_messages(&source).unwrap();
+    assert_eq!(
+        FileMetadata::decode(&metadata),
+        Some(FileMetadata::of("firmware.hex", b":00000001FF\n"))
+    );
+    assert_eq!(FileMetadata::decode(&content), None);
+
+    let mut receiver = FileReceiver::new(&target);
+    assert!(matches!(
+        receiver.receive(&content),
+        Err(ReceiveError::Unexpected)
+    ));
+    assert_eq!(receiver.receive(&metadata).unwrap(), None);
+    assert_eq!(
+        receiver.receive(&content).unwrap().as_deref(),
+        Some("firmware.hex")
+    );
+    assert_eq!(
+        std::fs::read(target.join("firmware.hex")).unwrap(),
+        b":00000001FF\n"
+    );
+
+    let mut corrupted = content.clone();
+    corrupted[1] ^= 1;
+    receiver.receive(&metadata).unwrap();
+    assert!(matches!(
+        receiver.receive(&corrupted),
+        Err(ReceiveError::Corrupted(_))
+    ));
+
+    let escape = FileMetadata::of("../firmware.hex", b"").encode();
+    let err = receiver.receive(&escape).unwrap_err();
+    assert!(matches!(err, ReceiveError::InvalidName(_)));
+    assert_eq!(
+        err.to_string(),
+        "\"../firmware.hex\" is not a plain file name"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
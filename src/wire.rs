@@ -0,0 +1,98 @@
+use crate::bits::NibbleOrder;
+use crate::stream::{InputEvent, InputStream, Strictness};
+
+/// # WireDecoder
+///
+/// Decodes nibbles that have already been captured, from files, sockets or logic analyzers,
+/// into the same events the [`InputStream`] of a live connection produces,
+/// without a device or a connection.
+///
+/// Like on the cable, a nibble is only new once the value changes,
+/// captures that contain every nibble exactly once disable the edge detection.
+pub struct WireDecoder {
+    i_stream: InputStream,
+}
+
+impl Default for WireDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WireDecoder {
+    pub fn new() -> Self {
+        Self::with_strictness(Strictness::default())
+    }
+
+    pub fn with_strictness(strictness: Strictness) -> Self {
+        Self {
+            i_stream: InputStream::with_strictness(strictness),
+        }
+    }
+
+    pub fn set_edge_detection(&mut self, edge_detection: bool) {
+        self.i_stream.set_edge_detection(edge_detection);
+    }
+
+    /// The nibble order that is expected, until the first escape code shows the actual one
+    pub fn set_nibble_order(&mut self, order: NibbleOrder) {
+        self.i_stream.set_nibble_order(order);
+    }
+
+    /// Number of data bytes of the captured frames, if the sides agreed on a different size
+    pub fn set_frame_data_len(&mut self, len: usize) {
+        self.i_stream.set_frame_data_len(len);
+    }
+
+    /// Nibble slips that have been detected and skipped so far
+    pub fn slips(&self) -> u32 {
+        self.i_stream.slips()
+    }
+
+    /// Decodes a single nibble, [`InputEvent::LinkIdle`] while nothing has been completed
+    pub fn push(&mut self, nibble: u8) -> InputEvent {
+        self.i_stream.push(nibble)
+    }
+
+    /// Decodes the nibbles in order, values that span several calls are decoded once complete
+    pub fn feed(&mut self, nibbles: &[u8]) -> Vec<InputEvent> {
+        nibbles
+            .iter()
+            .map(|nibble| self.push(*nibble))
+            .filter(|event| *event != InputEvent::LinkIdle)
+            .collect()
+    }
+}
+
+#[test]
+fn decode_captured_nibbles() {
+    use crate::escape::{EscapeCode, Escaped};
+
+    let (frame, len) =
+        crate::encode_frame(&mut Escaped::new([0xab, 0x12].into_iter().map(Ok))).unwrap();
+    let mut bytes = frame[..len].to_vec();
+    bytes.extend([0xf0, EscapeCode::CorrectFrameData as u8, 0xf0, 0xf0]);
+    let nibbles = crate::conformance::wire_nibbles(&bytes);
+
+    // split at an arbitrary point, like two reads from a socket
+    let mut decoder = WireDecoder::new();
+    let (first, second) = nibbles.split_at(nibbles.len() / 3);
+    let mut events = decoder.feed(first);
+    events.extend(decoder.feed(second));
+
+    let [InputEvent::DataFrame { payload: data, .. }, InputEvent::Ack { .. }] = events.as_slice()
+    else {
+        panic!("{events:?}");
+    };
+    assert_eq!(data[..2], [0xab, 0x12]);
+    assert_eq!(decoder.slips(), 0);
+
+    // the live input stream decodes exactly the same
+    let mut i_stream = InputStream::new();
+    let live: Vec<InputEvent> = nibbles
+        .iter()
+        .map(|nibble| i_stream.push(*nibble))
+        .filter(|event| *event != InputEvent::LinkIdle)
+        .collect();
+    assert_eq!(live, events);
+}
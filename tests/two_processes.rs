@@ -0,0 +1,57 @@
+//! Runs sender and receiver as separate processes, connected through a `TcpDevice`,
+//! to catch bugs that the in-process `DebugDevice` hides (buffering, timing).
+//!
+//! Ignored by default, run with `cargo test -- --ignored`.
+
+use std::fs;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn wait_with_timeout(child: &mut Child, deadline: Instant) -> bool {
+    while Instant::now() < deadline {
+        if let Ok(Some(status)) = child.try_wait() {
+            return status.success();
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let _ = child.kill();
+    false
+}
+
+#[test]
+#[ignore = "spawns processes and takes a long time"]
+fn transfer_between_processes() {
+    let dir = std::env::temp_dir().join(format!("protocol-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = "data/random-256.bin";
+    let output = dir.join("received.bin");
+    let addr = "127.0.0.1:47611";
+
+    let mut receiver = Command::new(env!("CARGO_BIN_EXE_protocol"))
+        .args(["--device", &format!("listen:{addr}"), "--output"])
+        .arg(&output)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut sender = Command::new(env!("CARGO_BIN_EXE_protocol"))
+        .args(["--device", addr])
+        .stdin(fs::File::open(input).unwrap())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + TIMEOUT;
+    let sender_ok = wait_with_timeout(&mut sender, deadline);
+    let receiver_ok = wait_with_timeout(&mut receiver, deadline);
+    assert!(sender_ok && receiver_ok, "transfer did not finish in time");
+
+    // rotation is disabled, so everything ends up in the first file
+    let received = fs::read(format!("{}.0", output.display())).unwrap();
+    assert_eq!(received, fs::read(input).unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}